@@ -0,0 +1,80 @@
+//! Contention of the block state under concurrent block announcements.
+//!
+//! Compares the single-owner [`SyncState`] fed through a command channel against the previous
+//! design with one mutex per field, with several threads recording received blocks at once.
+use bscpeer::peer::sync::{SyncCommand, SyncState};
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex, mpsc},
+    thread,
+};
+
+const ANNOUNCEMENTS_PER_THREAD: u64 = 10_000;
+
+/// The previous mutex-per-field layout of the hot-path state.
+#[derive(Default)]
+struct MutexState {
+    current_height: Mutex<u64>,
+    received_blocks: Mutex<HashSet<u64>>,
+}
+
+impl MutexState {
+    fn process_received_block(&self, block_number: u64) {
+        if self.received_blocks.lock().unwrap().contains(&block_number) {
+            return;
+        }
+        self.received_blocks.lock().unwrap().insert(block_number);
+        let mut current = self.current_height.lock().unwrap();
+        if block_number > *current {
+            *current = block_number;
+        }
+    }
+}
+
+fn announce<F: Fn(u64) + Sync>(threads: u64, process: F) {
+    thread::scope(|scope| {
+        for thread in 0..threads {
+            let process = &process;
+            scope.spawn(move || {
+                for i in 0..ANNOUNCEMENTS_PER_THREAD {
+                    // peers mostly announce the same blocks
+                    process(i + thread % 2);
+                }
+            });
+        }
+    });
+}
+
+fn bench_announcements(c: &mut Criterion) {
+    let mut group = c.benchmark_group("announcements");
+    for threads in [1, 4, 16] {
+        group.bench_with_input(BenchmarkId::new("mutex", threads), &threads, |b, &threads| {
+            b.iter(|| {
+                let state = Arc::new(MutexState::default());
+                announce(threads, |number| state.process_received_block(number));
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("actor", threads), &threads, |b, &threads| {
+            b.iter(|| {
+                let (commands, receiver) = mpsc::channel();
+                let actor = thread::spawn(move || {
+                    let mut state = SyncState::new(0);
+                    for command in receiver {
+                        state.handle(command);
+                    }
+                    state.height()
+                });
+                announce(threads, |number| {
+                    commands.send(SyncCommand::BlockReceived(number)).unwrap();
+                });
+                drop(commands);
+                actor.join().unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_announcements);
+criterion_main!(benches);
@@ -0,0 +1,53 @@
+-- Hashes, addresses and peer ids are stored as raw bytes, times as unix milliseconds.
+
+CREATE TABLE IF NOT EXISTS blocks (
+    hash BYTEA PRIMARY KEY,
+    number BIGINT NOT NULL,
+    parent_hash BYTEA NOT NULL,
+    timestamp BIGINT NOT NULL,
+    miner BYTEA NOT NULL,
+    gas_used BIGINT NOT NULL,
+    gas_limit BIGINT NOT NULL,
+    base_fee BIGINT,
+    transaction_count INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS blocks_number_idx ON blocks (number);
+
+CREATE TABLE IF NOT EXISTS transactions (
+    block_hash BYTEA NOT NULL REFERENCES blocks (hash),
+    block_number BIGINT NOT NULL,
+    index INTEGER NOT NULL,
+    hash BYTEA NOT NULL,
+    "from" BYTEA,
+    "to" BYTEA,
+    value NUMERIC(78, 0) NOT NULL,
+    nonce BIGINT NOT NULL,
+    gas_limit BIGINT NOT NULL,
+    gas_price BIGINT NOT NULL,
+    tx_type SMALLINT NOT NULL,
+    input_len INTEGER NOT NULL,
+    selector BYTEA,
+    PRIMARY KEY (block_hash, index)
+);
+CREATE INDEX IF NOT EXISTS transactions_hash_idx ON transactions (hash);
+CREATE INDEX IF NOT EXISTS transactions_from_idx ON transactions ("from");
+CREATE INDEX IF NOT EXISTS transactions_to_idx ON transactions ("to");
+
+-- First time each peer delivered a block.
+CREATE TABLE IF NOT EXISTS block_peers (
+    block_hash BYTEA NOT NULL,
+    peer_id BYTEA NOT NULL,
+    seen_at BIGINT NOT NULL,
+    PRIMARY KEY (block_hash, peer_id)
+);
+
+CREATE TABLE IF NOT EXISTS sessions (
+    id BIGSERIAL PRIMARY KEY,
+    peer_id BYTEA NOT NULL,
+    remote_addr TEXT NOT NULL,
+    client_version TEXT NOT NULL,
+    connected_at BIGINT NOT NULL,
+    disconnected_at BIGINT,
+    disconnect_reason TEXT
+);
+CREATE INDEX IF NOT EXISTS sessions_open_idx ON sessions (peer_id) WHERE disconnected_at IS NULL;
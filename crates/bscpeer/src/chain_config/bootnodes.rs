@@ -1,22 +1,89 @@
+//! Built-in boot nodes and parsing of enode URLs.
 use reth_discv4::NodeRecord;
+use reth_network_peers::PeerId;
+use std::{net::SocketAddr, str::FromStr};
 
-pub fn bsc_mainnet_nodes() -> Vec<NodeRecord> {
-    parse_nodes(BSC_MAINNET_BOOTNODES)
+/// Reasons an enode URL is invalid.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum NodeUrlError {
+    #[error("expected an enode:// url")]
+    MissingScheme,
+    #[error("missing '@' between the node id and the address")]
+    MissingAddress,
+    #[error("node id must be 128 hex characters, got {0}")]
+    IdLength(usize),
+    #[error("node id is not valid hex")]
+    IdNotHex,
+    #[error("invalid address {0:?}, expected ip:port")]
+    InvalidAddress(String),
+    #[error("invalid discovery port {0:?}")]
+    InvalidDiscoveryPort(String),
 }
 
-/// Returns parsed bsc mainnet nodes
-pub fn bsc_testnet_nodes() -> Vec<NodeRecord> {
-    parse_nodes(BSC_TESTNET_BOOTNODES)
+/// An invalid entry of a boot node list.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid boot node #{index} {entry:?}: {source}")]
+pub struct BootnodeError {
+    /// Position of the entry in its list, starting at 1.
+    pub index: usize,
+    pub entry: String,
+    #[source]
+    pub source: NodeUrlError,
 }
 
-/// Parses all the nodes
-fn parse_nodes(nodes: impl IntoIterator<Item = impl AsRef<str>>) -> Vec<NodeRecord> {
+/// Parses an `enode://<id>@<ip>:<port>[?discport=<port>]` url.
+pub fn parse_node(url: &str) -> Result<NodeRecord, NodeUrlError> {
+    let url = url.trim();
+    let rest = url.strip_prefix("enode://").ok_or(NodeUrlError::MissingScheme)?;
+    let (id, address) = rest.split_once('@').ok_or(NodeUrlError::MissingAddress)?;
+    if id.len() != 128 {
+        return Err(NodeUrlError::IdLength(id.len()));
+    }
+    let id = PeerId::from_str(id).map_err(|_| NodeUrlError::IdNotHex)?;
+    let (address, query) = match address.split_once('?') {
+        Some((address, query)) => (address, Some(query)),
+        None => (address, None),
+    };
+    let addr: SocketAddr =
+        address.parse().map_err(|_| NodeUrlError::InvalidAddress(address.to_string()))?;
+    let udp_port = match query {
+        Some(query) => query
+            .strip_prefix("discport=")
+            .and_then(|port| port.parse().ok())
+            .ok_or_else(|| NodeUrlError::InvalidDiscoveryPort(query.to_string()))?,
+        None => addr.port(),
+    };
+    Ok(NodeRecord { address: addr.ip(), tcp_port: addr.port(), udp_port, id })
+}
+
+/// Parses a list of enode urls, failing on the first invalid entry.
+pub fn parse_nodes(
+    nodes: impl IntoIterator<Item = impl AsRef<str>>,
+) -> Result<Vec<NodeRecord>, BootnodeError> {
     nodes
         .into_iter()
-        .map(|s| s.as_ref().parse().unwrap())
+        .enumerate()
+        .map(|(i, entry)| {
+            let entry = entry.as_ref();
+            parse_node(entry).map_err(|source| BootnodeError {
+                index: i + 1,
+                entry: entry.to_string(),
+                source,
+            })
+        })
         .collect()
 }
 
+/// Returns parsed bsc mainnet nodes
+pub fn bsc_mainnet_nodes() -> Vec<NodeRecord> {
+    parse_nodes(BSC_MAINNET_BOOTNODES).expect("valid built-in boot nodes")
+}
+
+/// Returns parsed bsc testnet nodes
+pub fn bsc_testnet_nodes() -> Vec<NodeRecord> {
+    parse_nodes(BSC_TESTNET_BOOTNODES).expect("valid built-in boot nodes")
+}
+
 /// Bsc mainnet boot nodes.
 pub static BSC_MAINNET_BOOTNODES: &[&str] = &[
     "enode://433c8bfdf53a3e2268ccb1b829e47f629793291cbddf0c76ae626da802f90532251fc558e2e0d10d6725e759088439bf1cd4714716b03a259a35d4b2e4acfa7f@52.69.102.73:30311",
@@ -52,4 +119,29 @@ mod tests {
         assert!(!nodes.is_empty());
         assert_eq!(nodes.len(), 4);
     }
+
+    #[test]
+    fn test_parse_node() {
+        let id = &BSC_MAINNET_BOOTNODES[0][8..136];
+        let node = parse_node(&format!("enode://{id}@127.0.0.1:30311?discport=30312")).unwrap();
+        assert_eq!((node.tcp_port, node.udp_port), (30311, 30312));
+        assert_eq!(parse_node(BSC_MAINNET_BOOTNODES[0]), Ok(bsc_mainnet_nodes()[0]));
+
+        assert_eq!(parse_node("127.0.0.1:30311"), Err(NodeUrlError::MissingScheme));
+        assert_eq!(parse_node(&format!("enode://{id}")), Err(NodeUrlError::MissingAddress));
+        assert_eq!(parse_node("enode://abcd@127.0.0.1:30311"), Err(NodeUrlError::IdLength(4)));
+        let not_hex = "z".repeat(128);
+        assert_eq!(
+            parse_node(&format!("enode://{not_hex}@127.0.0.1:30311")),
+            Err(NodeUrlError::IdNotHex)
+        );
+        assert_eq!(
+            parse_node(&format!("enode://{id}@bootnode:30311")),
+            Err(NodeUrlError::InvalidAddress("bootnode:30311".to_string()))
+        );
+
+        let err = parse_nodes([BSC_MAINNET_BOOTNODES[0], "enode://abcd@127.0.0.1:1"]).unwrap_err();
+        assert_eq!(err.index, 2);
+        assert_eq!(err.source, NodeUrlError::IdLength(4));
+    }
 }
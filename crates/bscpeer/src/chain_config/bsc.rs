@@ -2,21 +2,26 @@ use alloy_primitives::{BlockHash, U256};
 use reth_chainspec::{
     make_genesis_header, BaseFeeParams, BaseFeeParamsKind, Chain, ChainSpec, Head, NamedChain,
 };
+use reth_ethereum_forks::ChainHardforks;
 use reth_primitives::SealedHeader;
 use std::str::FromStr;
 
 use crate::chain_config::hardfork::BscHardfork;
 
 pub fn bsc_mainnet() -> ChainSpec {
+    bsc_mainnet_with_hardforks(BscHardfork::bsc_mainnet())
+}
+
+/// Bsc mainnet spec with a custom hardfork schedule.
+pub fn bsc_mainnet_with_hardforks(hardforks: ChainHardforks) -> ChainSpec {
     let genesis = serde_json::from_str(include_str!("genesis.json"))
         .expect("Can't deserialize BSC Mainnet genesis json");
-    let hardforks = BscHardfork::bsc_mainnet();
     ChainSpec {
         chain: Chain::from_named(NamedChain::BinanceSmartChain),
         genesis: serde_json::from_str(include_str!("genesis.json"))
             .expect("Can't deserialize BSC Mainnet genesis json"),
         paris_block_and_final_difficulty: Some((0, U256::from(0))),
-        hardforks: BscHardfork::bsc_mainnet(),
+        hardforks: hardforks.clone(),
         deposit_contract: None,
         base_fee_params: BaseFeeParamsKind::Constant(BaseFeeParams::new(1, 1)),
         prune_delete_limit: 3500,
@@ -2,21 +2,26 @@ use alloy_primitives::{B256, BlockHash, U256};
 use reth_chainspec::{
     BaseFeeParams, BaseFeeParamsKind, Chain, ChainSpec, Head, NamedChain, make_genesis_header,
 };
+use reth_ethereum_forks::ChainHardforks;
 use reth_primitives::SealedHeader;
 use std::str::FromStr;
 
 use crate::chain_config::hardfork::BscHardfork;
 
 pub fn bsc_testnet() -> ChainSpec {
+    bsc_testnet_with_hardforks(BscHardfork::bsc_testnet())
+}
+
+/// Bsc testnet spec with a custom hardfork schedule.
+pub fn bsc_testnet_with_hardforks(hardforks: ChainHardforks) -> ChainSpec {
     let genesis = serde_json::from_str(include_str!("genesis_chapel.json"))
         .expect("Can't deserialize BSC Testnet genesis json");
-    let hardforks = BscHardfork::bsc_testnet();
     ChainSpec {
         chain: Chain::from_named(NamedChain::BinanceSmartChainTestnet),
         genesis: serde_json::from_str(include_str!("genesis_chapel.json"))
             .expect("Can't deserialize BSC Testnet genesis json"),
         paris_block_and_final_difficulty: Some((0, U256::from(0))),
-        hardforks: BscHardfork::bsc_testnet(),
+        hardforks: hardforks.clone(),
         deposit_contract: None,
         base_fee_params: BaseFeeParamsKind::Constant(BaseFeeParams::new(1, 1)),
         prune_delete_limit: 3500,
@@ -0,0 +1,140 @@
+//! Trusted checkpoints, i.e. known canonical blocks any followed chain has to contain.
+use crate::parlia::validation::HeaderError;
+use alloy_consensus::Header;
+use alloy_primitives::{B256, b256};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Built-in checkpoints of BSC mainnet.
+pub const MAINNET_CHECKPOINTS: &[Checkpoint] = &[Checkpoint {
+    number: 0,
+    hash: b256!("0x0d21840abff46b96c84b2ac9e10e4f5cdaeb5693cb665db62a2f3b02d2d57b5b"),
+}];
+
+/// Built-in checkpoints of the BSC testnet.
+pub const CHAPEL_CHECKPOINTS: &[Checkpoint] = &[Checkpoint {
+    number: 0,
+    hash: b256!("0x6d3c66c5357ec91d5c43af47e234a939b22557cbb552dc45bebbceeed90fbe34"),
+}];
+
+/// A canonical block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub number: u64,
+    pub hash: B256,
+}
+
+/// Set of checkpoints headers are verified against.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Checkpoints {
+    hashes: BTreeMap<u64, B256>,
+}
+
+impl Checkpoints {
+    pub fn new(checkpoints: impl IntoIterator<Item = Checkpoint>) -> Self {
+        Self {
+            hashes: checkpoints
+                .into_iter()
+                .map(|checkpoint| (checkpoint.number, checkpoint.hash))
+                .collect(),
+        }
+    }
+
+    /// Returns the hash of the checkpoint at `number`.
+    pub fn get(&self, number: u64) -> Option<B256> {
+        self.hashes.get(&number).copied()
+    }
+
+    /// Checks that neither the header nor its parent contradicts a checkpoint.
+    pub fn verify(&self, header: &Header, hash: B256) -> Result<(), HeaderError> {
+        check(header.number, self.get(header.number), hash)?;
+        if let Some(parent) = header.number.checked_sub(1) {
+            check(parent, self.get(parent), header.parent_hash)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the known ancestry of the header runs through the checkpoints below its
+    /// parent, walking back from the parent with `parent_of`, which returns the parent hash of a
+    /// known block, until an ancestor is unknown.
+    pub fn verify_ancestry(
+        &self,
+        header: &Header,
+        mut parent_of: impl FnMut(&B256) -> Option<B256>,
+    ) -> Result<(), HeaderError> {
+        let Some(mut number) = header.number.checked_sub(1) else { return Ok(()) };
+        let mut hash = header.parent_hash;
+        while let Some((&checkpoint, _)) = self.hashes.range(..number).next_back() {
+            while number > checkpoint {
+                let Some(parent) = parent_of(&hash) else { return Ok(()) };
+                (number, hash) = (number - 1, parent);
+            }
+            check(number, self.get(number), hash)?;
+        }
+        Ok(())
+    }
+}
+
+fn check(number: u64, expected: Option<B256>, got: B256) -> Result<(), HeaderError> {
+    match expected {
+        Some(expected) if expected != got => {
+            Err(HeaderError::CheckpointMismatch { number, expected, got })
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let checkpoint = B256::repeat_byte(1);
+        let checkpoints = Checkpoints::new([Checkpoint { number: 10, hash: checkpoint }]);
+
+        let header = Header { number: 10, ..Default::default() };
+        assert!(checkpoints.verify(&header, checkpoint).is_ok());
+        assert_eq!(
+            checkpoints.verify(&header, B256::ZERO),
+            Err(HeaderError::CheckpointMismatch {
+                number: 10,
+                expected: checkpoint,
+                got: B256::ZERO
+            })
+        );
+
+        let child = Header { number: 11, parent_hash: checkpoint, ..Default::default() };
+        assert!(checkpoints.verify(&child, B256::ZERO).is_ok());
+        let fork = Header { number: 11, parent_hash: B256::ZERO, ..Default::default() };
+        assert!(checkpoints.verify(&fork, B256::ZERO).is_err());
+
+        let unrelated = Header { number: 20, ..Default::default() };
+        assert!(checkpoints.verify(&unrelated, B256::ZERO).is_ok());
+    }
+
+    #[test]
+    fn test_verify_ancestry() {
+        let checkpoints =
+            Checkpoints::new([Checkpoint { number: 10, hash: B256::with_last_byte(10) }]);
+        // known blocks 11 to 13, whose hashes end with their numbers
+        let canonical = |hash: &B256| match hash[31] {
+            number @ 11..=13 => Some(B256::with_last_byte(number - 1)),
+            _ => None,
+        };
+        // the same blocks, but block 11 descends from another block 10
+        let forked = |hash: &B256| match hash[31] {
+            11 => Some(B256::ZERO),
+            number => canonical(&B256::with_last_byte(number)),
+        };
+        let header =
+            Header { number: 14, parent_hash: B256::with_last_byte(13), ..Default::default() };
+        assert!(checkpoints.verify_ancestry(&header, canonical).is_ok());
+        assert!(matches!(
+            checkpoints.verify_ancestry(&header, forked),
+            Err(HeaderError::CheckpointMismatch { number: 10, .. })
+        ));
+        // an ancestry that can't be followed back to the checkpoint isn't rejected
+        assert!(checkpoints.verify_ancestry(&header, |_| None).is_ok());
+    }
+}
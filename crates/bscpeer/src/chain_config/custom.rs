@@ -0,0 +1,123 @@
+//! Chain specs built from an arbitrary genesis file, e.g. for private BSC-compatible devnets.
+use crate::chain_config::hardfork::BscHardfork;
+use alloy_genesis::Genesis;
+use alloy_primitives::{B256, U256};
+use clap::ValueEnum;
+use reth_chainspec::{
+    BaseFeeParams, BaseFeeParamsKind, Chain, ChainSpec, Head, make_genesis_header,
+};
+use reth_ethereum_forks::ChainHardforks;
+use reth_primitives::SealedHeader;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Errors that can occur while loading a custom genesis file.
+#[derive(Debug, thiserror::Error)]
+pub enum GenesisError {
+    /// The genesis file could not be read.
+    #[error("failed to read genesis file {path}: {source}")]
+    Io {
+        /// Path of the genesis file.
+        path: PathBuf,
+        /// The underlying io error.
+        #[source]
+        source: std::io::Error,
+    },
+    /// The genesis file is not valid genesis JSON.
+    #[error("invalid genesis file {path}: {source}")]
+    Json {
+        /// Path of the genesis file.
+        path: PathBuf,
+        /// The underlying parse error.
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+/// Built-in hardfork schedules a custom genesis can be combined with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HardforkProfile {
+    /// The BSC mainnet schedule.
+    #[default]
+    Mainnet,
+    /// The BSC testnet (Chapel) schedule.
+    Testnet,
+}
+
+impl HardforkProfile {
+    /// Returns the hardfork schedule of this profile.
+    pub fn hardforks(self) -> ChainHardforks {
+        match self {
+            Self::Mainnet => BscHardfork::bsc_mainnet(),
+            Self::Testnet => BscHardfork::bsc_testnet(),
+        }
+    }
+}
+
+/// Builds a chain spec from the genesis file at `path` and the given hardfork schedule.
+///
+/// The chain id is taken from the genesis `config`. The genesis hash is computed from the
+/// resulting genesis header unless `genesis_hash` is given, which is needed when the remote
+/// clients hash their genesis header differently.
+pub fn chain_spec_from_genesis(
+    path: &Path,
+    hardforks: ChainHardforks,
+    genesis_hash: Option<B256>,
+) -> Result<ChainSpec, GenesisError> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|source| GenesisError::Io { path: path.to_path_buf(), source })?;
+    let genesis: Genesis = serde_json::from_str(&contents)
+        .map_err(|source| GenesisError::Json { path: path.to_path_buf(), source })?;
+
+    let header = make_genesis_header(&genesis, &hardforks);
+    let genesis_header = match genesis_hash {
+        Some(hash) => SealedHeader::new(header, hash),
+        None => SealedHeader::seal_slow(header),
+    };
+    Ok(ChainSpec {
+        chain: Chain::from_id(genesis.config.chain_id),
+        genesis,
+        paris_block_and_final_difficulty: Some((0, U256::from(0))),
+        hardforks,
+        deposit_contract: None,
+        base_fee_params: BaseFeeParamsKind::Constant(BaseFeeParams::new(1, 1)),
+        prune_delete_limit: 3500,
+        genesis_header,
+        ..Default::default()
+    })
+}
+
+/// Head of a freshly started chain, i.e. its genesis block.
+pub fn genesis_head(chain_spec: &ChainSpec) -> Head {
+    Head {
+        number: 0,
+        hash: chain_spec.genesis_hash(),
+        difficulty: chain_spec.genesis.difficulty,
+        total_difficulty: chain_spec.genesis.difficulty,
+        timestamp: chain_spec.genesis.timestamp,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain_config::bsc::bsc_mainnet;
+
+    #[test]
+    fn test_mainnet_genesis_roundtrip() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/chain_config/genesis.json");
+        let mainnet = bsc_mainnet();
+        let chain_spec = chain_spec_from_genesis(
+            &path,
+            HardforkProfile::Mainnet.hardforks(),
+            Some(mainnet.genesis_hash()),
+        )
+        .unwrap();
+        assert_eq!(chain_spec.chain, mainnet.chain);
+        assert_eq!(
+            chain_spec.fork_id(&genesis_head(&chain_spec)),
+            mainnet.fork_id(&Head::default())
+        );
+    }
+}
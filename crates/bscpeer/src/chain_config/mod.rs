@@ -1,4 +1,72 @@
 pub mod bootnodes;
 pub mod bsc;
 pub mod bsc_chapel;
-mod hardfork;
+pub mod checkpoints;
+pub mod custom;
+pub mod hardfork;
+pub mod remote;
+pub mod schedule;
+pub mod validators;
+
+use alloy_primitives::Address;
+use clap::ValueEnum;
+use reth_chainspec::{ChainSpec, Head};
+use reth_discv4::NodeRecord;
+use reth_ethereum_forks::ChainHardforks;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Built-in BSC networks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BscNetwork {
+    /// BSC mainnet.
+    #[default]
+    Mainnet,
+    /// The BSC testnet.
+    Chapel,
+}
+
+impl BscNetwork {
+    /// Returns the chain spec, optionally with a custom hardfork schedule.
+    pub fn chain_spec(self, hardforks: Option<ChainHardforks>) -> ChainSpec {
+        match (self, hardforks) {
+            (Self::Mainnet, Some(hardforks)) => bsc::bsc_mainnet_with_hardforks(hardforks),
+            (Self::Mainnet, None) => bsc::bsc_mainnet(),
+            (Self::Chapel, Some(hardforks)) => bsc_chapel::bsc_testnet_with_hardforks(hardforks),
+            (Self::Chapel, None) => bsc_chapel::bsc_testnet(),
+        }
+    }
+
+    /// Returns the head announced in the status message.
+    pub fn head(self) -> Head {
+        match self {
+            Self::Mainnet => bsc::head(),
+            Self::Chapel => bsc_chapel::head(),
+        }
+    }
+
+    /// Returns the built-in checkpoints of the network.
+    pub fn checkpoints(self) -> &'static [checkpoints::Checkpoint] {
+        match self {
+            Self::Mainnet => checkpoints::MAINNET_CHECKPOINTS,
+            Self::Chapel => checkpoints::CHAPEL_CHECKPOINTS,
+        }
+    }
+
+    /// Returns the bundled validator names of the network.
+    pub fn validators(self) -> BTreeMap<Address, String> {
+        match self {
+            Self::Mainnet => validators::bsc_mainnet_validators(),
+            Self::Chapel => BTreeMap::new(),
+        }
+    }
+
+    /// Returns the boot nodes of the network.
+    pub fn boot_nodes(self) -> Vec<NodeRecord> {
+        match self {
+            Self::Mainnet => bootnodes::bsc_mainnet_nodes(),
+            Self::Chapel => bootnodes::bsc_testnet_nodes(),
+        }
+    }
+}
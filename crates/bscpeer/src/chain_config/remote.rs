@@ -0,0 +1,64 @@
+//! Chain head fetched from a remote JSON-RPC node, replacing the built-in head at startup.
+use alloy_eips::BlockNumberOrTag;
+use alloy_primitives::B256;
+use alloy_provider::{Provider, ProviderBuilder};
+use reth_chainspec::{ChainSpec, Head};
+
+/// Errors that can occur while fetching the head from a remote node.
+#[derive(Debug, thiserror::Error)]
+pub enum RemoteHeadError {
+    /// The RPC url could not be parsed.
+    #[error("invalid rpc url {0}")]
+    InvalidUrl(String),
+    /// The request failed.
+    #[error("rpc request failed: {0}")]
+    Rpc(#[from] alloy_transport::TransportError),
+    /// The node doesn't know the block it reported as its latest.
+    #[error("remote node returned no block {0}")]
+    MissingBlock(u64),
+    /// The node is on a chain with another id.
+    #[error("remote node is on chain {got}, expected {expected}")]
+    ChainId { expected: u64, got: u64 },
+    /// The node is on a chain with another genesis.
+    #[error("remote node has genesis {got}, expected {expected}")]
+    Genesis { expected: B256, got: B256 },
+}
+
+/// Fetches the latest block of the node at `url`, after checking the node follows the chain of
+/// the spec, i.e. reports its chain id and genesis.
+///
+/// The total difficulty is only known if the node still reports it; otherwise the one of
+/// `fallback` is kept.
+pub async fn fetch_head(
+    url: &str,
+    chain_spec: &ChainSpec,
+    fallback: Head,
+) -> Result<Head, RemoteHeadError> {
+    let url = url.parse().map_err(|_| RemoteHeadError::InvalidUrl(url.to_string()))?;
+    let provider = ProviderBuilder::new().connect_http(url);
+    let (expected, got) = (chain_spec.chain.id(), provider.get_chain_id().await?);
+    if got != expected {
+        return Err(RemoteHeadError::ChainId { expected, got });
+    }
+    let genesis = provider
+        .get_block_by_number(BlockNumberOrTag::Number(0))
+        .await?
+        .ok_or(RemoteHeadError::MissingBlock(0))?;
+    let (expected, got) = (chain_spec.genesis_hash(), genesis.header.hash);
+    if got != expected {
+        return Err(RemoteHeadError::Genesis { expected, got });
+    }
+    let number = provider.get_block_number().await?;
+    let block = provider
+        .get_block_by_number(BlockNumberOrTag::Number(number))
+        .await?
+        .ok_or(RemoteHeadError::MissingBlock(number))?;
+    let header = block.header;
+    Ok(Head {
+        number: header.number,
+        hash: header.hash,
+        difficulty: header.difficulty,
+        total_difficulty: header.total_difficulty.unwrap_or(fallback.total_difficulty),
+        timestamp: header.timestamp,
+    })
+}
@@ -0,0 +1,145 @@
+//! Hardfork schedules loaded from a JSON or TOML file at runtime.
+//!
+//! A schedule lists the forks in activation order, each activated either at a block or at a
+//! timestamp:
+//!
+//! ```json
+//! { "forks": [
+//!     { "name": "Frontier", "block": 0 },
+//!     { "name": "Maxwell", "timestamp": 1751250600 }
+//! ] }
+//! ```
+//!
+//! Names are resolved against the Ethereum hardforks first, then the BSC ones.
+use crate::chain_config::hardfork::BscHardfork;
+use reth_chainspec::ForkCondition;
+use reth_ethereum_forks::{ChainHardforks, EthereumHardfork, Hardfork};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Errors that can occur while loading a hardfork schedule.
+#[derive(Debug, thiserror::Error)]
+pub enum ScheduleError {
+    /// The schedule file could not be read.
+    #[error("failed to read hardfork schedule {path}: {source}")]
+    Io {
+        /// Path of the schedule file.
+        path: PathBuf,
+        /// The underlying io error.
+        #[source]
+        source: std::io::Error,
+    },
+    /// The schedule file is not valid JSON.
+    #[error("invalid hardfork schedule: {0}")]
+    Json(#[from] serde_json::Error),
+    /// The schedule file is not valid TOML.
+    #[error("invalid hardfork schedule: {0}")]
+    Toml(#[from] toml::de::Error),
+    /// A fork name is neither an Ethereum nor a BSC hardfork.
+    #[error("unknown hardfork `{0}`")]
+    UnknownFork(String),
+    /// A fork does not specify exactly one of `block` and `timestamp`.
+    #[error("hardfork `{0}` must specify exactly one of `block` or `timestamp`")]
+    InvalidActivation(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct ScheduleFile {
+    forks: Vec<ForkEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ForkEntry {
+    name: String,
+    block: Option<u64>,
+    timestamp: Option<u64>,
+}
+
+impl ForkEntry {
+    fn into_fork(self) -> Result<(Box<dyn Hardfork>, ForkCondition), ScheduleError> {
+        let condition = match (self.block, self.timestamp) {
+            (Some(block), None) => ForkCondition::Block(block),
+            (None, Some(timestamp)) => ForkCondition::Timestamp(timestamp),
+            _ => return Err(ScheduleError::InvalidActivation(self.name)),
+        };
+        let fork = if let Ok(fork) = self.name.parse::<EthereumHardfork>() {
+            fork.boxed()
+        } else if let Ok(fork) = self.name.parse::<BscHardfork>() {
+            fork.boxed()
+        } else {
+            return Err(ScheduleError::UnknownFork(self.name));
+        };
+        Ok((fork, condition))
+    }
+}
+
+/// Loads a hardfork schedule, parsing it as TOML if the file has a `.toml` extension and as JSON
+/// otherwise.
+pub fn load(path: &Path) -> Result<ChainHardforks, ScheduleError> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|source| ScheduleError::Io { path: path.to_path_buf(), source })?;
+    if path.extension().is_some_and(|ext| ext == "toml") {
+        parse_toml(&contents)
+    } else {
+        parse_json(&contents)
+    }
+}
+
+/// Parses a JSON hardfork schedule.
+pub fn parse_json(contents: &str) -> Result<ChainHardforks, ScheduleError> {
+    into_hardforks(serde_json::from_str(contents)?)
+}
+
+/// Parses a TOML hardfork schedule.
+pub fn parse_toml(contents: &str) -> Result<ChainHardforks, ScheduleError> {
+    into_hardforks(toml::from_str(contents)?)
+}
+
+fn into_hardforks(file: ScheduleFile) -> Result<ChainHardforks, ScheduleError> {
+    let forks = file.forks.into_iter().map(ForkEntry::into_fork).collect::<Result<_, _>>()?;
+    Ok(ChainHardforks::new(forks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_json_schedule() {
+        let hardforks = parse_json(
+            r#"{ "forks": [
+                { "name": "Frontier", "block": 0 },
+                { "name": "Ramanujan", "block": 0 },
+                { "name": "Maxwell", "timestamp": 1751250600 }
+            ] }"#,
+        )
+        .unwrap();
+        assert_eq!(hardforks.fork(EthereumHardfork::Frontier), ForkCondition::Block(0));
+        assert_eq!(hardforks.fork(BscHardfork::Maxwell), ForkCondition::Timestamp(1751250600));
+    }
+
+    #[test]
+    fn test_parse_toml_schedule() {
+        let hardforks = parse_toml(
+            r#"
+            [[forks]]
+            name = "Lorentz"
+            timestamp = 1745903100
+            "#,
+        )
+        .unwrap();
+        assert_eq!(hardforks.fork(BscHardfork::Lorentz), ForkCondition::Timestamp(1745903100));
+    }
+
+    #[test]
+    fn test_invalid_schedule() {
+        assert!(matches!(
+            parse_json(r#"{ "forks": [{ "name": "Unknown", "block": 0 }] }"#),
+            Err(ScheduleError::UnknownFork(_))
+        ));
+        assert!(matches!(
+            parse_json(r#"{ "forks": [{ "name": "Maxwell", "block": 0, "timestamp": 0 }] }"#),
+            Err(ScheduleError::InvalidActivation(_))
+        ));
+    }
+}
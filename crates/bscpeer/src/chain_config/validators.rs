@@ -0,0 +1,79 @@
+//! Human-readable names of validators, attributed to the blocks they propose.
+use alloy_primitives::Address;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Returns the bundled names of the BSC mainnet validators, as published by the operators.
+pub fn bsc_mainnet_validators() -> BTreeMap<Address, String> {
+    serde_json::from_str(include_str!("validators.json"))
+        .expect("Can't deserialize BSC Mainnet validators json")
+}
+
+/// Validator that proposed a block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Validator {
+    /// Signer recovered from the seal.
+    pub address: Address,
+    /// Name from the registry, `None` for unknown validators.
+    pub name: Option<String>,
+}
+
+impl Validator {
+    /// Returns the name as used for metric labels, `unknown` for validators without one so that
+    /// arbitrary signers can't grow the label set.
+    pub fn label(&self) -> String {
+        self.name.clone().unwrap_or_else(|| "unknown".to_string())
+    }
+}
+
+/// Names of the validators by address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidatorRegistry {
+    names: HashMap<Address, String>,
+}
+
+impl ValidatorRegistry {
+    /// Creates the registry, later names of an address replacing earlier ones.
+    pub fn new(names: impl IntoIterator<Item = (Address, String)>) -> Self {
+        Self { names: names.into_iter().collect() }
+    }
+
+    /// Returns the name of the validator.
+    pub fn name(&self, address: &Address) -> Option<&str> {
+        self.names.get(address).map(String::as_str)
+    }
+
+    /// Attributes a proposer address to its validator.
+    pub fn resolve(&self, address: Address) -> Validator {
+        Validator { address, name: self.name(&address).map(str::to_string) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        let bundled = bsc_mainnet_validators();
+        assert!(!bundled.is_empty());
+        let (address, name) = bundled.iter().next().map(|(a, n)| (*a, n.clone())).unwrap();
+
+        let unknown = Address::repeat_byte(1);
+        let registry =
+            ValidatorRegistry::new(bundled.into_iter().chain([(address, "Renamed".to_string())]));
+        assert_ne!(name, "Renamed");
+        assert_eq!(registry.name(&address), Some("Renamed"));
+        assert_eq!(registry.resolve(unknown), Validator { address: unknown, name: None });
+        assert_eq!(registry.resolve(unknown).label(), "unknown");
+        assert_eq!(registry.resolve(address).label(), "Renamed");
+    }
+}
@@ -0,0 +1,228 @@
+//! Command line interface of the `bscpeer` binary.
+use bscpeer::{
+    chain_config::{BscNetwork, custom::HardforkProfile},
+    config::{self, Config, ConfigError},
+    export::ExportFormat,
+};
+use clap::{Parser, Subcommand};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+};
+
+/// A lightweight BSC p2p peer that follows the chain head over devp2p.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Path to a TOML config file. Repeat to run one node per config file in this process.
+    #[arg(long, value_name = "FILE")]
+    pub config: Vec<PathBuf>,
+
+    /// Built-in network to follow.
+    #[arg(long, value_enum)]
+    pub chain: Option<BscNetwork>,
+
+    /// Genesis file of a custom BSC-compatible chain to follow instead of BSC mainnet.
+    #[arg(long, value_name = "FILE")]
+    pub genesis: Option<PathBuf>,
+
+    /// Hardfork schedule used together with `--genesis`.
+    #[arg(long, value_enum, requires = "genesis")]
+    pub hardfork_profile: Option<HardforkProfile>,
+
+    /// JSON-RPC endpoint of a synced node to fetch the starting head from.
+    #[arg(long, value_name = "URL")]
+    pub head_rpc_url: Option<String>,
+
+    /// Comma-separated enode urls used instead of the built-in boot nodes.
+    #[arg(long, value_name = "ENODES", value_delimiter = ',')]
+    pub bootnodes: Vec<String>,
+
+    /// Interface the RLPx and discovery sockets bind to.
+    #[arg(long, value_name = "IP")]
+    pub addr: Option<IpAddr>,
+
+    /// RLPx TCP port.
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// Discovery v4 UDP port.
+    #[arg(long)]
+    pub discovery_port: Option<u16>,
+
+    /// Address advertised in the local node record, e.g. the public IPv6 address of a dual-stack
+    /// node listening on `::`.
+    #[arg(long, value_name = "IP")]
+    pub external_ip: Option<IpAddr>,
+
+    /// Address the Prometheus exporter listens on.
+    #[arg(long, value_name = "ADDR")]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Address the JSON-RPC server listens on.
+    #[arg(long, value_name = "ADDR")]
+    pub rpc_addr: Option<SocketAddr>,
+
+    /// Log every eth message the node sends or receives, requires the `trace-wire` feature.
+    #[arg(long)]
+    pub trace_wire: bool,
+
+    /// Bytes of each traced payload logged as hex, zero for none.
+    #[arg(long, value_name = "BYTES", requires = "trace_wire")]
+    pub trace_wire_payload: Option<usize>,
+
+    /// Capture every eth message the node sends or receives to a file, requires the
+    /// `trace-wire` feature.
+    #[arg(long, value_name = "FILE")]
+    pub capture: Option<PathBuf>,
+
+    /// Run under an init system: logs without colors and a PID file, by default `bscpeer.pid` in
+    /// the data directory of the first config. The process doesn't fork.
+    #[arg(long)]
+    pub daemon: bool,
+
+    /// File the pid of the process is written to while it runs.
+    #[arg(long, value_name = "FILE")]
+    pub pid_file: Option<PathBuf>,
+}
+
+/// Subcommands of the binary, which runs the nodes when none is given.
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print the enode url and ENR of each node, generating and persisting its key if it has none
+    /// yet.
+    ///
+    /// The advertised address is the external IP, else the address the node binds to, else the
+    /// one detected through NAT.
+    Enode,
+    /// Dial a single peer, run the RLPx and BSC handshake and print what the peer sent.
+    ///
+    /// The chain settings are those of the first config.
+    Ping {
+        /// Enode url of the peer.
+        enode: String,
+        /// Seconds to wait for the handshake.
+        #[arg(long, default_value_t = 15)]
+        timeout: u64,
+    },
+    /// Fetch a block range from the network, write it to disk and exit.
+    ///
+    /// The chain and peer settings are those of the first config, the Parquet layout that of its
+    /// Parquet sink.
+    Export {
+        /// First block of the range.
+        #[arg(long)]
+        from: u64,
+        /// Last block of the range, inclusive.
+        #[arg(long)]
+        to: u64,
+        /// Output format.
+        #[arg(long, value_enum, default_value_t)]
+        format: ExportFormat,
+        /// File the JSON lines are written to, or directory of the Parquet tables.
+        #[arg(long, value_name = "PATH")]
+        output: PathBuf,
+    },
+    /// Measure the header and body download throughput and request latency of peers.
+    ///
+    /// The chain settings are those of the first config.
+    Benchmark {
+        /// Enode urls of the peers.
+        #[arg(required = true)]
+        enodes: Vec<String>,
+        /// Blocks downloaded from every peer, counting back from its head.
+        #[arg(long, default_value_t = 1024)]
+        blocks: u64,
+        /// Headers per request.
+        #[arg(long, default_value_t = 128)]
+        batch: u64,
+        /// Seconds to wait for the sessions.
+        #[arg(long, default_value_t = 30)]
+        connect_timeout: u64,
+        /// Print the report as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+impl Cli {
+    /// Loads the config of every node to run, with the environment and command line overrides
+    /// applied, in that order, and validates the boot nodes.
+    ///
+    /// When running several nodes, unnamed ones are named after their config file.
+    pub fn configs(&self) -> Result<Vec<Config>, ConfigError> {
+        if self.config.is_empty() {
+            let mut config = Config::default();
+            config.apply_env()?;
+            self.apply(&mut config);
+            config.bootnodes.override_nodes()?;
+            return Ok(vec![config]);
+        }
+        let multiple = self.config.len() > 1;
+        let configs = self
+            .config
+            .iter()
+            .map(|path| {
+                let mut config = Config::load(path)?;
+                if multiple && config.name.is_none() {
+                    config.name = path.file_stem().map(|stem| stem.to_string_lossy().into_owned());
+                }
+                config.apply_env()?;
+                self.apply(&mut config);
+                config.bootnodes.override_nodes()?;
+                Ok(config)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        config::check_instances(&configs)?;
+        Ok(configs)
+    }
+
+    /// Applies the command line overrides to the loaded config.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(network) = self.chain {
+            config.chain.network = network;
+        }
+        if let Some(genesis) = &self.genesis {
+            config.chain.genesis = Some(genesis.clone());
+        }
+        if let Some(profile) = self.hardfork_profile {
+            config.chain.hardfork_profile = profile;
+        }
+        if let Some(url) = &self.head_rpc_url {
+            config.chain.head_rpc_url = Some(url.clone());
+        }
+        if !self.bootnodes.is_empty() {
+            config.bootnodes.nodes = self.bootnodes.clone();
+        }
+        if let Some(addr) = self.addr {
+            config.p2p.addr = addr;
+        }
+        if let Some(port) = self.port {
+            config.p2p.port = port;
+        }
+        if let Some(port) = self.discovery_port {
+            config.p2p.discovery_port = port;
+        }
+        if let Some(ip) = self.external_ip {
+            config.p2p.external_ip = Some(ip);
+        }
+        if let Some(addr) = self.metrics_addr {
+            config.metrics.addr = addr;
+        }
+        if let Some(addr) = self.rpc_addr {
+            config.rpc.addr = addr;
+        }
+        if self.trace_wire {
+            config.trace_wire.enabled = true;
+        }
+        if let Some(max_payload) = self.trace_wire_payload {
+            config.trace_wire.max_payload = max_payload;
+        }
+        if let Some(path) = &self.capture {
+            config.trace_wire.capture = Some(path.clone());
+        }
+    }
+}
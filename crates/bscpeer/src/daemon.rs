@@ -0,0 +1,292 @@
+//! Supervision of the process by an init system: the PID file, shutdown signals, exit codes and
+//! the systemd notification protocol.
+//!
+//! The process doesn't fork. It is meant to be started in the background by systemd, a runit or
+//! s6 service or similar, which learn of its pid from the PID file and stop it with a signal.
+//! On the signal the nodes disconnect and their sinks write out what they queued before the
+//! process exits, a second signal exits right away.
+//! Under a `Type=notify` systemd unit, readiness is reported once a peer session is active and
+//! the watchdog is kept alive while the head advances.
+use crate::peer::head::ChainHead;
+use futures::StreamExt;
+use reth_network::{
+    EthNetworkPrimitives, NetworkEvent, NetworkEventListenerProvider, NetworkHandle, PeersInfo,
+};
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// Exit code of an invalid config, `EX_CONFIG` of `sysexits.h`, so supervisors can tell it from
+/// runtime failures and avoid restarting in a loop.
+pub const EXIT_CONFIG: i32 = 78;
+/// Exit code of a failure at runtime.
+pub const EXIT_FAILURE: i32 = 1;
+
+/// Errors creating the PID file.
+#[derive(Debug, thiserror::Error)]
+pub enum PidFileError {
+    #[error("failed to write pid file {path}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("pid file {path} names the running process {pid}")]
+    Running { path: PathBuf, pid: u32 },
+}
+
+/// PID file of the process, removed when dropped.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Writes the pid of the process to the file, replacing a stale file but failing if it names
+    /// another running process.
+    ///
+    /// Whether a process runs is only checked on Linux, elsewhere an existing file is replaced.
+    pub fn create(path: impl Into<PathBuf>) -> Result<Self, PidFileError> {
+        let path = path.into();
+        let io_error = |source| PidFileError::Io { path: path.clone(), source };
+        if let Some(pid) = read_pid(&path).map_err(io_error)? {
+            if pid != std::process::id() && is_running(pid) {
+                return Err(PidFileError::Running { path: path.clone(), pid });
+            }
+            std::fs::remove_file(&path).map_err(io_error)?;
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        let mut file =
+            OpenOptions::new().write(true).create_new(true).open(&path).map_err(io_error)?;
+        writeln!(file, "{}", std::process::id()).map_err(io_error)?;
+        Ok(Self { path })
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::warn!(path = %self.path.display(), "failed to remove pid file: {}", e);
+        }
+    }
+}
+
+/// Reads the pid of an existing file, `None` if there is none or it holds garbage.
+fn read_pid(path: &Path) -> io::Result<Option<u32>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents.trim().parse().unwrap_or(0))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_running(pid: u32) -> bool {
+    pid != 0 && Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_running(_pid: u32) -> bool {
+    false
+}
+
+/// Waits for SIGTERM, SIGQUIT or SIGINT, returning the name of the signal received.
+#[cfg(unix)]
+pub async fn shutdown_signal() -> io::Result<&'static str> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut quit = signal(SignalKind::quit())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    Ok(tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = quit.recv() => "SIGQUIT",
+        _ = interrupt.recv() => "SIGINT",
+    })
+}
+
+/// Waits for ctrl-c.
+#[cfg(not(unix))]
+pub async fn shutdown_signal() -> io::Result<&'static str> {
+    tokio::signal::ctrl_c().await?;
+    Ok("ctrl-c")
+}
+
+/// Sends the state to the service manager through `$NOTIFY_SOCKET`, returning whether the
+/// variable is set.
+#[cfg(unix)]
+pub fn sd_notify(state: &str) -> io::Result<bool> {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else { return Ok(false) };
+    send_notify(&path, state)?;
+    Ok(true)
+}
+
+/// Sends the state to the notify socket at the path, or the abstract one if prefixed by `@`.
+#[cfg(unix)]
+fn send_notify(path: &std::ffi::OsStr, state: &str) -> io::Result<usize> {
+    let socket = std::os::unix::net::UnixDatagram::unbound()?;
+    match path.as_encoded_bytes().strip_prefix(b"@") {
+        Some(name) => send_abstract(&socket, name, state),
+        None => socket.send_to(state.as_bytes(), path),
+    }
+}
+
+/// Does nothing, there is no systemd.
+#[cfg(not(unix))]
+pub fn sd_notify(_state: &str) -> io::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(target_os = "linux")]
+fn send_abstract(
+    socket: &std::os::unix::net::UnixDatagram,
+    name: &[u8],
+    state: &str,
+) -> io::Result<usize> {
+    use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+
+    socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn send_abstract(
+    _socket: &std::os::unix::net::UnixDatagram,
+    _name: &[u8],
+    _state: &str,
+) -> io::Result<usize> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "abstract notify socket"))
+}
+
+/// Returns the interval of the systemd watchdog from `$WATCHDOG_USEC`, if it watches this
+/// process.
+pub fn watchdog_interval() -> Option<Duration> {
+    let pid = std::env::var("WATCHDOG_PID").ok();
+    if !pid.is_none_or(|pid| pid.trim().parse::<u32>().ok() == Some(std::process::id())) {
+        return None;
+    }
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.trim().parse::<u64>().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Reports readiness to systemd once the first peer session of any network is active, then
+/// runs the watchdog if enabled. Returns right away outside of a notify unit.
+pub async fn notify_systemd(
+    networks: Vec<NetworkHandle<EthNetworkPrimitives>>,
+    heads: Vec<watch::Receiver<Option<ChainHead>>>,
+) {
+    if std::env::var_os("NOTIFY_SOCKET").is_none() {
+        return;
+    }
+    let mut events =
+        futures::stream::select_all(networks.iter().map(|network| network.event_listener()));
+    // sessions established before listening aren't reported
+    if networks.iter().all(|network| network.num_connected_peers() == 0) {
+        while let Some(event) = events.next().await {
+            if let NetworkEvent::ActivePeerSession { .. } = event {
+                break;
+            }
+        }
+    }
+    drop(events);
+    notify("READY=1\nSTATUS=Peer session active");
+    info!("notified systemd of readiness");
+
+    if let Some(interval) = watchdog_interval() {
+        watchdog(heads, interval).await;
+    }
+}
+
+/// Keeps the watchdog alive while the head of any node advances, so a node stuck on its head is
+/// restarted. The keep-alive is sent at half the interval, which should be well above the block
+/// time.
+async fn watchdog(mut heads: Vec<watch::Receiver<Option<ChainHead>>>, interval: Duration) {
+    let period = interval / 2;
+    let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    loop {
+        ticks.tick().await;
+        let mut advanced = false;
+        for head in &mut heads {
+            if head.has_changed().unwrap_or(false) {
+                head.mark_unchanged();
+                advanced = true;
+            }
+        }
+        if advanced {
+            notify("WATCHDOG=1");
+        } else {
+            warn!(?period, "head didn't advance, withholding the watchdog keep-alive");
+        }
+    }
+}
+
+/// Sends the state to systemd, logging a failure.
+pub fn notify(state: &str) {
+    if let Err(e) = sd_notify(state) {
+        warn!("failed to notify systemd: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pid_file() {
+        let dir = std::env::temp_dir().join(format!("bscpeer-pidfile-{}", std::process::id()));
+        let path = dir.join("bscpeer.pid");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        // a file left behind by this process, e.g. after a restart in a container, is stale
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, format!("{}\n", std::process::id())).unwrap();
+        let pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(read_pid(&path).unwrap(), Some(std::process::id()));
+        drop(pid_file);
+        assert!(!path.exists());
+
+        std::fs::write(&path, "garbage").unwrap();
+        drop(PidFile::create(&path).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_send_notify() {
+        use std::os::unix::net::UnixDatagram;
+
+        let path = std::env::temp_dir().join(format!("bscpeer-notify-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        send_notify(path.as_os_str(), "READY=1").unwrap();
+
+        let mut buf = [0; 64];
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_pid_file_running() {
+        let dir =
+            std::env::temp_dir().join(format!("bscpeer-pidfile-running-{}", std::process::id()));
+        let path = dir.join("bscpeer.pid");
+        std::fs::create_dir_all(&dir).unwrap();
+        // pid 1 always runs
+        std::fs::write(&path, "1\n").unwrap();
+        assert!(matches!(PidFile::create(&path), Err(PidFileError::Running { pid: 1, .. })));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
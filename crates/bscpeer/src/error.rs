@@ -0,0 +1,106 @@
+//! Errors starting a node.
+use crate::{
+    chain_config::{bootnodes::BootnodeError, custom::GenesisError, schedule::ScheduleError},
+    parlia::snapshot::SnapshotError,
+    peer::{banlist::BanListError, nodekey::NodeKeyError, watchlist::WatchlistError},
+    sink::SinkError,
+};
+use maxminddb::MaxMindDBError;
+use metrics_exporter_prometheus::BuildError;
+use reth_network::error::NetworkError;
+use std::{io, net::SocketAddr, path::PathBuf};
+
+/// Errors starting a node, each naming the file or setting to fix.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(
+        "failed to load node key {path}: {source}, remove the file to generate a new key, which \
+         changes the node id"
+    )]
+    NodeKey {
+        path: PathBuf,
+        #[source]
+        source: NodeKeyError,
+    },
+    #[error("invalid `bootnodes.nodes`: {0}")]
+    Bootnode(#[from] BootnodeError),
+    #[error("invalid `peers.trusted_nodes` entry {node}: {reason}")]
+    TrustedNode { node: String, reason: String },
+    #[error("failed to load `chain.hardforks`: {0}")]
+    Schedule(#[from] ScheduleError),
+    #[error("failed to load `chain.genesis`: {0}")]
+    Genesis(#[from] GenesisError),
+    #[error("failed to load ban list {path}: {source}, fix or remove the file")]
+    BanList {
+        path: PathBuf,
+        #[source]
+        source: BanListError,
+    },
+    #[error("failed to load watchlist {path}: {source}, fix or remove the file")]
+    Watchlist {
+        path: PathBuf,
+        #[source]
+        source: WatchlistError,
+    },
+    #[error("failed to open the GeoIP databases of `geoip`: {0}")]
+    GeoIp(#[from] MaxMindDBError),
+    #[error(
+        "failed to load parlia snapshot {path}: {source}, remove the file to rebuild it from the \
+         chain"
+    )]
+    Snapshot {
+        path: PathBuf,
+        #[source]
+        source: SnapshotError,
+    },
+    #[error("failed to start sinks: {0}")]
+    Sink(#[from] SinkError),
+    #[cfg(feature = "postgres")]
+    #[error("invalid `sinks.postgres.url`: {0}")]
+    Postgres(#[from] crate::sink::postgres::PostgresSinkError),
+    #[cfg(feature = "webhook")]
+    #[error("failed to build the webhook http client: {0}")]
+    Webhook(#[from] reqwest::Error),
+    #[error("failed to create `trace_wire.capture` file: {0}")]
+    Capture(#[source] io::Error),
+    #[error("failed to start network, check `p2p.port` and `p2p.discovery_port` are free: {0}")]
+    Network(#[from] NetworkError),
+    #[error("failed to start RPC server on {addr}, check `rpc.addr` is free: {source}")]
+    Rpc {
+        addr: SocketAddr,
+        #[source]
+        source: io::Error,
+    },
+    #[error(
+        "failed to start the metrics exporter on {addr}, check `metrics.addr` is free: {source}"
+    )]
+    Metrics {
+        addr: SocketAddr,
+        #[source]
+        source: BuildError,
+    },
+}
+
+impl Error {
+    /// Returns whether the error is caused by the config or the files it names, rather than the
+    /// environment, so retrying without changing them fails again.
+    pub fn is_config(&self) -> bool {
+        match self {
+            Self::NodeKey { .. }
+            | Self::Bootnode(_)
+            | Self::TrustedNode { .. }
+            | Self::Schedule(_)
+            | Self::Genesis(_)
+            | Self::BanList { .. }
+            | Self::Watchlist { .. }
+            | Self::GeoIp(_)
+            | Self::Snapshot { .. }
+            | Self::Capture(_) => true,
+            #[cfg(feature = "postgres")]
+            Self::Postgres(_) => true,
+            Self::Sink(_) | Self::Network(_) | Self::Rpc { .. } | Self::Metrics { .. } => false,
+            #[cfg(feature = "webhook")]
+            Self::Webhook(_) => false,
+        }
+    }
+}
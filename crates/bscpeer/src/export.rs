@@ -0,0 +1,378 @@
+//! One-off export of a block range, behind the `export` subcommand.
+//!
+//! The blocks are fetched from the connected peers, several at a time and each from the next
+//! peer on failure. Each header is checked on its own and against its seal, each body against
+//! its header, and a block not linking to the one before is fetched again from the other peers.
+//! The blocks are written in order, as newline-delimited JSON or as Parquet files laid out like
+//! the Parquet sink's.
+use crate::{
+    BscPeerBuilder,
+    config::{Config, ParquetSinkConfig, SinksConfig},
+    parlia::{Parlia, validation},
+    peer::fetch::{self, FetchError},
+};
+use alloy_consensus::{Header, Transaction, transaction::SignerRecoverable};
+use alloy_eips::Typed2718;
+use alloy_primitives::{Address, B256, Bytes, U256};
+use clap::ValueEnum;
+use futures::StreamExt;
+use reth_ethereum_primitives::Block;
+use reth_network::{EthNetworkPrimitives, NetworkHandle, PeersInfo};
+use reth_network_api::Peers;
+use secp256k1::{SecretKey, rand};
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{sync::mpsc, time::Instant};
+use tracing::{debug, info};
+
+/// Blocks fetched concurrently.
+const CONCURRENCY: usize = 16;
+/// Peers tried per block before the export fails.
+const MAX_ATTEMPTS: usize = 5;
+/// Delay between attempts while no peer is connected.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+/// How long to wait for the first peer.
+const PEER_TIMEOUT: Duration = Duration::from_secs(120);
+/// Blocks queued for the writer.
+const WRITE_QUEUE: usize = 256;
+
+/// Output format of an export.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// One JSON object per block and line.
+    #[default]
+    Json,
+    /// Parquet `blocks` and `transactions` tables, requires the `parquet` feature.
+    Parquet,
+}
+
+/// Errors exporting blocks.
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+    #[error("invalid block range {0}-{1}")]
+    Range(u64, u64),
+    #[error("no peer connected within {0:?}")]
+    NoPeers(Duration),
+    #[error("failed to fetch block {number}: {source}")]
+    Fetch {
+        number: u64,
+        #[source]
+        source: FetchError,
+    },
+    #[error("failed to fetch block {0}: no connected peers")]
+    Unavailable(u64),
+    #[error(transparent)]
+    Node(#[from] crate::Error),
+    #[error("failed to write {path}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[cfg(feature = "parquet")]
+    #[error(transparent)]
+    Parquet(#[from] crate::sink::parquet::ParquetSinkError),
+    #[error("the `{0}` feature is disabled")]
+    FeatureDisabled(&'static str),
+    #[error("writer stopped")]
+    WriterStopped,
+}
+
+/// Starts a node with the chain and peer settings of the config, exports the blocks `from..=to`
+/// to `output` and returns the number of blocks written.
+///
+/// The node uses a random key and ports and a temporary datadir, so it can run next to a node
+/// with the same config, and none of the configured sinks or the RPC server.
+pub async fn run(
+    mut config: Config,
+    from: u64,
+    to: u64,
+    format: ExportFormat,
+    output: PathBuf,
+) -> Result<u64, ExportError> {
+    if from > to {
+        return Err(ExportError::Range(from, to));
+    }
+    let parquet = config.sinks.parquet.clone().unwrap_or_default();
+    config.p2p.port = 0;
+    config.p2p.discovery_port = 0;
+    config.rpc.enabled = false;
+    config.transactions.enabled = false;
+    config.sinks = SinksConfig::default();
+    config.datadir = std::env::temp_dir().join(format!("bscpeer-export-{}", std::process::id()));
+    let datadir = config.datadir.clone();
+    let result = async {
+        let node = BscPeerBuilder::new(config)
+            .secret_key(SecretKey::new(&mut rand::thread_rng()))
+            .build()
+            .await?;
+        let network = node.network();
+
+        let deadline = Instant::now() + PEER_TIMEOUT;
+        while network.num_connected_peers() == 0 {
+            if Instant::now() >= deadline {
+                return Err(ExportError::NoPeers(PEER_TIMEOUT));
+            }
+            tokio::time::sleep(RETRY_DELAY).await;
+        }
+        let parlia = node.parlia().clone();
+        let result = export_blocks(network, &parlia, from..=to, format, output, parquet).await;
+        node.shutdown().await;
+        result
+    };
+    let result = result.await;
+    if let Err(e) = std::fs::remove_dir_all(&datadir) {
+        debug!(datadir = %datadir.display(), "failed to remove the export datadir: {}", e);
+    }
+    result
+}
+
+/// Exports the blocks of the range from the peers of the network, see [`run`].
+pub async fn export_blocks(
+    network: &NetworkHandle<EthNetworkPrimitives>,
+    parlia: &Parlia,
+    range: RangeInclusive<u64>,
+    format: ExportFormat,
+    output: PathBuf,
+    parquet: ParquetSinkConfig,
+) -> Result<u64, ExportError> {
+    let (blocks_tx, mut blocks_rx) = mpsc::channel(WRITE_QUEUE);
+    let writer = tokio::task::spawn_blocking(move || {
+        let blocks = std::iter::from_fn(move || blocks_rx.blocking_recv());
+        match format {
+            ExportFormat::Json => write_json(&output, blocks),
+            ExportFormat::Parquet => write_parquet(output, parquet, blocks),
+        }
+    });
+
+    let (first, last) = (*range.start(), *range.end());
+    let mut blocks = futures::stream::iter(range)
+        .map(|number| fetch_block(network, parlia, number, None))
+        .buffered(CONCURRENCY);
+    let mut parent: Option<(B256, Header)> = None;
+    let mut written = 0;
+    while let Some(block) = blocks.next().await {
+        let (mut hash, mut block) = block?;
+        let number = block.header.number;
+        let link =
+            parent.as_ref().map(|parent| (parent, validate_link(parlia, &block.header, parent)));
+        if let Some((parent, Err(e))) = link {
+            // the peer served another branch, ask the others for the child of the parent
+            debug!(number, "block not linked to the previous block: {}", e);
+            (hash, block) = fetch_block(network, parlia, number, Some(parent)).await?;
+        }
+        parent = Some((hash, block.header.clone()));
+        if blocks_tx.send(block).await.is_err() {
+            break;
+        }
+        written += 1;
+        if number % 1000 == 0 {
+            info!(number, first, last, "exporting blocks");
+        }
+    }
+    drop(blocks_tx);
+    writer.await.map_err(|_| ExportError::WriterStopped)??;
+    Ok(written)
+}
+
+/// Checks that a header is the child of the parent with the given hash.
+fn validate_link(
+    parlia: &Parlia,
+    header: &Header,
+    (parent_hash, parent): &(B256, Header),
+) -> Result<(), FetchError> {
+    if header.parent_hash != *parent_hash {
+        return Err(FetchError::OtherBranch(parent.number));
+    }
+    parlia.validate_against_parent(header, parent)?;
+    Ok(())
+}
+
+/// Fetches and verifies the block at `number`, and its hash, trying another peer after each
+/// failure. If a parent is given, the block must be its child.
+async fn fetch_block(
+    network: &NetworkHandle<EthNetworkPrimitives>,
+    parlia: &Parlia,
+    number: u64,
+    parent: Option<&(B256, Header)>,
+) -> Result<(B256, Block), ExportError> {
+    let mut last_error = None;
+    for attempt in 0..MAX_ATTEMPTS {
+        let peers = network.get_all_peers().await?;
+        if peers.is_empty() {
+            tokio::time::sleep(RETRY_DELAY).await;
+            continue;
+        }
+        let peer_id = peers[(number as usize + attempt) % peers.len()].remote_id;
+        let result = async {
+            let header = fetch::fetch_header(network, peer_id, number).await?;
+            let hash = header.hash_slow();
+            // the genesis block carries no seal
+            if number > 0 {
+                parlia.validate_header(&header)?;
+                validation::validate_seal(&header, &parlia.proposer(&header, hash))?;
+            }
+            if let Some(parent) = parent {
+                validate_link(parlia, &header, parent)?;
+            }
+            let body = fetch::fetch_body(network, peer_id, hash, &header).await?;
+            Ok::<_, FetchError>((hash, Block { header, body }))
+        };
+        match result.await {
+            Ok(block) => return Ok(block),
+            Err(e) => {
+                debug!(number, %peer_id, "failed to fetch block: {}", e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(match last_error {
+        Some(source) => ExportError::Fetch { number, source },
+        None => ExportError::Unavailable(number),
+    })
+}
+
+/// A block as exported to JSON.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonBlock {
+    number: u64,
+    hash: B256,
+    parent_hash: B256,
+    timestamp: u64,
+    miner: Address,
+    gas_used: u64,
+    gas_limit: u64,
+    base_fee: Option<u64>,
+    extra_data: Bytes,
+    transactions: Vec<JsonTransaction>,
+}
+
+/// A transaction as exported to JSON.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonTransaction {
+    hash: B256,
+    from: Option<Address>,
+    to: Option<Address>,
+    value: U256,
+    nonce: u64,
+    gas_limit: u64,
+    /// Effective gas price in wei.
+    gas_price: u128,
+    tx_type: u8,
+    input: Bytes,
+}
+
+impl From<&Block> for JsonBlock {
+    fn from(block: &Block) -> Self {
+        let header = &block.header;
+        Self {
+            number: header.number,
+            hash: header.hash_slow(),
+            parent_hash: header.parent_hash,
+            timestamp: header.timestamp,
+            miner: header.beneficiary,
+            gas_used: header.gas_used,
+            gas_limit: header.gas_limit,
+            base_fee: header.base_fee_per_gas,
+            extra_data: header.extra_data.clone(),
+            transactions: block
+                .body
+                .transactions
+                .iter()
+                .map(|tx| JsonTransaction {
+                    hash: *tx.tx_hash(),
+                    from: tx.recover_signer().ok(),
+                    to: tx.to(),
+                    value: tx.value(),
+                    nonce: tx.nonce(),
+                    gas_limit: tx.gas_limit(),
+                    gas_price: tx.effective_gas_price(header.base_fee_per_gas),
+                    tx_type: tx.ty(),
+                    input: tx.input().clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn write_json(path: &Path, blocks: impl Iterator<Item = Block>) -> Result<(), ExportError> {
+    let io_error = |source| ExportError::Io { path: path.to_path_buf(), source };
+    let mut output = BufWriter::new(File::create(path).map_err(io_error)?);
+    for block in blocks {
+        serde_json::to_writer(&mut output, &JsonBlock::from(&block))
+            .map_err(|e| io_error(e.into()))?;
+        writeln!(output).map_err(io_error)?;
+    }
+    output.flush().map_err(io_error)
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet(
+    dir: PathBuf,
+    config: ParquetSinkConfig,
+    blocks: impl Iterator<Item = Block>,
+) -> Result<(), ExportError> {
+    Ok(crate::sink::parquet::write_blocks(ParquetSinkConfig { dir, ..config }, blocks)?)
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet(
+    _dir: PathBuf,
+    _config: ParquetSinkConfig,
+    _blocks: impl Iterator<Item = Block>,
+) -> Result<(), ExportError> {
+    Err(ExportError::FeatureDisabled("parquet"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chain_config::{BscNetwork, custom::genesis_head},
+        testing::{MockPeer, canned_blocks, local_network},
+    };
+    use std::sync::Arc;
+    use tokio::time::timeout;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_export_json() {
+        let chain_spec = Arc::new(BscNetwork::Chapel.chain_spec(None));
+        let head = genesis_head(&chain_spec);
+        let blocks = canned_blocks(&chain_spec, 8);
+        let peer = MockPeer::spawn(chain_spec.clone(), head, blocks.clone()).await;
+        let parlia = Parlia::new(chain_spec.clone());
+        let network = local_network(chain_spec, head).await;
+        timeout(Duration::from_secs(10), peer.connect(&network)).await.unwrap();
+
+        let output =
+            std::env::temp_dir().join(format!("bscpeer-export-{}.ndjson", std::process::id()));
+        let written = export_blocks(
+            &network,
+            &parlia,
+            3..=6,
+            ExportFormat::Json,
+            output.clone(),
+            ParquetSinkConfig::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(written, 4);
+
+        let contents = std::fs::read_to_string(&output).unwrap();
+        std::fs::remove_file(&output).unwrap();
+        let lines: Vec<serde_json::Value> =
+            contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["number"], 3);
+        assert_eq!(lines[3]["hash"], blocks[5].0.to_string());
+        assert_eq!(lines[3]["parentHash"], blocks[4].0.to_string());
+    }
+}
@@ -0,0 +1,88 @@
+//! Namespacing of the metrics and logs of a node, so several nodes can run in one process.
+//!
+//! Tasks of a named node run within its instance scope: their log lines carry an `instance` span
+//! and, with [`InstanceRecorder`] installed, their metrics an `instance` label.
+use metrics::{
+    Counter, Gauge, Histogram, Key, KeyName, Label, Metadata, Recorder, SharedString, Unit,
+};
+use std::{future::Future, sync::Arc};
+use tokio::task::JoinHandle;
+use tracing::{Instrument, info_span};
+
+tokio::task_local! {
+    static INSTANCE: Arc<str>;
+}
+
+/// Returns the name of the instance the current task belongs to.
+pub fn current() -> Option<Arc<str>> {
+    INSTANCE.try_with(Clone::clone).ok()
+}
+
+/// Runs the future as part of the named instance, or unchanged if there is no name.
+pub async fn scope<F: Future>(name: Option<Arc<str>>, future: F) -> F::Output {
+    match name {
+        Some(name) => {
+            let span = info_span!("instance", name = %name);
+            INSTANCE.scope(name, future.instrument(span)).await
+        }
+        None => future.await,
+    }
+}
+
+/// Spawns a task belonging to the same instance as the current task.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let future = future.in_current_span();
+    match current() {
+        Some(name) => tokio::spawn(INSTANCE.scope(name, future)),
+        None => tokio::spawn(future),
+    }
+}
+
+/// Recorder adding the `instance` label to metrics recorded within an instance scope.
+#[derive(Debug)]
+pub struct InstanceRecorder<R> {
+    inner: R,
+}
+
+impl<R> InstanceRecorder<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+
+    fn key(key: &Key) -> Key {
+        match current() {
+            Some(name) => key.with_extra_labels(vec![Label::new("instance", name.to_string())]),
+            None => key.clone(),
+        }
+    }
+}
+
+impl<R: Recorder> Recorder for InstanceRecorder<R> {
+    fn describe_counter(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_counter(key, unit, description)
+    }
+
+    fn describe_gauge(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_gauge(key, unit, description)
+    }
+
+    fn describe_histogram(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_histogram(key, unit, description)
+    }
+
+    fn register_counter(&self, key: &Key, metadata: &Metadata<'_>) -> Counter {
+        self.inner.register_counter(&Self::key(key), metadata)
+    }
+
+    fn register_gauge(&self, key: &Key, metadata: &Metadata<'_>) -> Gauge {
+        self.inner.register_gauge(&Self::key(key), metadata)
+    }
+
+    fn register_histogram(&self, key: &Key, metadata: &Metadata<'_>) -> Histogram {
+        self.inner.register_histogram(&Self::key(key), metadata)
+    }
+}
@@ -1,2 +1,23 @@
+//! A lightweight BSC p2p peer following the chain head over devp2p.
+//!
+//! The node is embedded through [`BscPeerBuilder`], see the [`node`] module.
 pub mod chain_config;
-pub mod peer;
\ No newline at end of file
+pub mod config;
+pub mod daemon;
+pub mod error;
+pub mod export;
+pub mod instance;
+pub mod node;
+pub mod parlia;
+pub mod peer;
+pub mod reload;
+pub mod rpc;
+pub mod sink;
+pub mod status;
+pub mod supervisor;
+pub mod telemetry;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
+
+pub use error::Error;
+pub use node::{BscPeerBuilder, BscPeerHandle};
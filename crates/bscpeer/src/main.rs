@@ -1,166 +1,300 @@
-use reth_discv4::Discv4ConfigBuilder;
-use reth_network::{
-    EthNetworkPrimitives, NetworkConfig, NetworkEvent, NetworkEventListenerProvider,
-    NetworkManager, PeersInfo,
+use bscpeer::{
+    BscPeerBuilder, BscPeerHandle,
+    chain_config::bootnodes::parse_node,
+    config::Config,
+    daemon, export,
+    instance::InstanceRecorder,
+    peer::{benchmark, nodekey, ping},
+    supervisor, telemetry,
 };
-use reth_network_api::events::{PeerEvent, SessionInfo};
-use reth_provider::noop::NoopProvider;
-use reth_tracing::{
-    LayerInfo, LogFormat, RethTracer, Tracer, tracing_subscriber::filter::LevelFilter,
+use clap::Parser;
+use metrics_exporter_prometheus::PrometheusBuilder;
+use reth_tracing::tracing_subscriber::{
+    self, EnvFilter, fmt, layer::SubscriberExt, reload, util::SubscriberInitExt,
 };
-use secp256k1::{SecretKey, rand};
-use std::{
-    net::{Ipv4Addr, SocketAddr},
-    sync::Arc,
-    time::Duration,
-};
-use tokio::sync::mpsc;
-use tokio::time::interval;
-use tokio_stream::StreamExt;
-use tracing::{info, warn};
+use std::{sync::Arc, time::Duration};
+use tracing::{error, info, warn};
 
-mod chain_config;
-mod peer;
+mod cli;
 
 #[tokio::main]
 async fn main() {
-    let _ = RethTracer::new()
-        .with_stdout(LayerInfo::new(
-            LogFormat::Terminal,
-            LevelFilter::INFO.to_string(),
-            "".to_string(),
-            Some("always".to_string()),
-        ))
-        .init();
-
-    let local_addr = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 30303);
-
-    let secret_key = SecretKey::new(&mut rand::thread_rng());
-
-    let bsc_boot_nodes = chain_config::bootnodes::bsc_mainnet_nodes();
-
-    let state_manager = peer::blockstate::BlockStateManager::new(0);
-
-    let (event_sender, mut event_receiver) =
-        mpsc::unbounded_channel::<peer::blockstate::BlockEvent>();
-
-    let block_importer = peer::blockstate::SmartBlockImporter::new(event_sender);
-
-    let net_cfg = NetworkConfig::builder(secret_key)
-        .boot_nodes(bsc_boot_nodes.clone())
-        .set_head(chain_config::bsc::head())
-        .with_pow()
-        .listener_addr(local_addr)
-        .eth_rlpx_handshake(Arc::new(peer::handshake::BscHandshake::default()))
-        .block_import(Box::new(block_importer))
-        .build(NoopProvider::eth(
-            Arc::new(chain_config::bsc::bsc_mainnet()),
-        ));
-
-    let net_cfg = net_cfg.set_discovery_v4(
-        Discv4ConfigBuilder::default()
-            .add_boot_nodes(bsc_boot_nodes)
-            .lookup_interval(Duration::from_millis(500))
-            .build(),
-    );
-    let net_manager = NetworkManager::<EthNetworkPrimitives>::new(net_cfg)
-        .await
-        .unwrap();
-
-    let net_handle = net_manager.handle().clone();
-    let mut network_events = net_handle.event_listener();
-
-    tokio::spawn(net_manager);
+    let cli = Arc::new(cli::Cli::parse());
+    let configs = match cli.configs() {
+        Ok(configs) => configs,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(daemon::EXIT_CONFIG);
+        }
+    };
+    let result = match &cli.command {
+        Some(cli::Command::Enode) => Some(print_enodes(&configs).await),
+        Some(cli::Command::Ping { enode, timeout }) => {
+            Some(ping_peer(&configs[0], enode, Duration::from_secs(*timeout)).await)
+        }
+        Some(cli::Command::Benchmark { enodes, blocks, batch, connect_timeout, json }) => {
+            let settings = benchmark::BenchmarkSettings {
+                blocks: *blocks,
+                batch: *batch,
+                connect_timeout: Duration::from_secs(*connect_timeout),
+            };
+            Some(benchmark_peers(&configs[0], enodes, &settings, *json).await)
+        }
+        _ => None,
+    };
+    if let Some(result) = result {
+        exit_on_error(result);
+        return;
+    }
 
-    info!("BSC P2P network started, listening and requesting blocks...");
+    // logging is shared by all nodes, its filter is set from the first config and replaced by the
+    // filter of whichever node reloads its config
+    let filter = match EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(&configs[0].log.filter))
+    {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("error: invalid log filter: {e}");
+            std::process::exit(daemon::EXIT_CONFIG);
+        }
+    };
+    let (filter, filter_handle) = reload::Layer::new(filter);
+    let (otlp, telemetry_guard) = match telemetry::layer(&configs[0].otlp) {
+        Ok(otlp) => otlp.unzip(),
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(daemon::EXIT_CONFIG);
+        }
+    };
+    tracing_subscriber::registry()
+        .with(otlp)
+        .with(filter)
+        .with(fmt::layer().with_ansi(!cli.daemon))
+        .init();
+    #[cfg(not(feature = "otel"))]
+    if configs[0].otlp.enabled {
+        warn!("otlp export is enabled but the `otel` feature is disabled");
+    }
+    supervisor::install_panic_hook();
+    let reporting_guard = supervisor::init_reporting(&configs[0].supervisor);
+    let log_filter = move |directives: &str| {
+        let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+        filter_handle.reload(filter).map_err(|e| e.to_string())
+    };
 
-    let state_for_timer = state_manager.clone();
-    let handle_for_timer = net_handle.clone();
-    tokio::spawn(async move {
-        let mut interval = interval(Duration::from_secs(10));
-        loop {
-            interval.tick().await;
+    if let Some(cli::Command::Export { from, to, format, output }) = &cli.command {
+        let config = configs.into_iter().next().expect("at least one config");
+        let result = export::run(config, *from, *to, *format, output.clone()).await;
+        exit_on_error(result.map(|written| info!(written, "export finished")));
+        return;
+    }
 
-            state_for_timer.cleanup_expired_requests();
+    let pid_file = cli.pid_file.clone().or_else(|| cli.daemon.then(|| configs[0].pid_file_path()));
+    let pid_file = match pid_file.map(daemon::PidFile::create).transpose() {
+        Ok(pid_file) => pid_file,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(daemon::EXIT_FAILURE);
+        }
+    };
 
-            let connected_peers = state_for_timer.peerset.lock().unwrap();
-            if !connected_peers.is_empty() {
-                drop(connected_peers);
-                state_for_timer.request_next_block(&handle_for_timer);
+    // the exporter is shared by all nodes
+    let metrics_config = &configs[0].metrics;
+    if metrics_config.enabled {
+        let addr = metrics_config.addr;
+        match PrometheusBuilder::new().with_http_listener(addr).build() {
+            Ok((recorder, exporter)) => {
+                tokio::spawn(exporter);
+                if let Err(e) = metrics::set_global_recorder(InstanceRecorder::new(recorder)) {
+                    warn!("failed to install the metrics recorder: {}", e);
+                }
+            }
+            Err(source) => {
+                error!("{}", bscpeer::Error::Metrics { addr, source });
+                drop(reporting_guard);
+                drop(telemetry_guard);
+                drop(pid_file);
+                std::process::exit(daemon::EXIT_FAILURE);
             }
         }
-    });
-
-    loop {
-        tokio::select! {
-            network_event = network_events.next() => {
-                match network_event {
-                    Some(NetworkEvent::ActivePeerSession { info, .. }) => {
-                        let SessionInfo { status, client_version, peer_id, .. } = info;
+    }
 
-                        state_manager.add_peer(peer_id);
+    let mut nodes = Vec::with_capacity(configs.len());
+    for (index, config) in configs.into_iter().enumerate() {
+        // a reload rereads the config files and environment, with the same overrides
+        let cli = cli.clone();
+        let builder = BscPeerBuilder::new(config)
+            .config_source(move || cli.configs().map(|mut configs| configs.swap_remove(index)))
+            .log_filter(log_filter.clone());
+        match builder.build().await {
+            Ok(node) => nodes.push(node),
+            Err(e) => {
+                error!("failed to start node: {}", e);
+                futures::future::join_all(nodes.into_iter().map(BscPeerHandle::shutdown)).await;
+                drop(reporting_guard);
+                drop(telemetry_guard);
+                drop(pid_file);
+                let code = if e.is_config() { daemon::EXIT_CONFIG } else { daemon::EXIT_FAILURE };
+                std::process::exit(code);
+            }
+        }
+    }
 
-                        info!(
-                            peers = %net_handle.num_connected_peers(),
-                            %peer_id,
-                            chain = %status.chain,
-                            best_block = %status.blockhash,
-                            ?status.total_difficulty,
-                            ?client_version,
-                            "new node connected"
-                        );
+    // readiness and watchdog of a systemd notify unit
+    let networks = nodes.iter().map(|node| node.network().clone()).collect();
+    let heads = nodes.iter().map(BscPeerHandle::subscribe_head).collect();
+    let systemd = tokio::spawn(daemon::notify_systemd(networks, heads));
 
-                        state_manager.request_next_block(&net_handle);
-                    }
-                    Some(NetworkEvent::Peer(PeerEvent::SessionClosed { peer_id, reason })) => {
-                        state_manager.remove_peer(&peer_id);
+    let exit_code = tokio::select! {
+        // the process stops with any of its nodes, so an init system restarts them all
+        _ = futures::future::select_all(nodes.iter_mut().map(|node| Box::pin(node.stopped()))) => {
+            error!("node stopped");
+            daemon::EXIT_FAILURE
+        }
+        signal = shutdown_signal() => {
+            info!(signal, "shutting down");
+            0
+        }
+    };
+    systemd.abort();
+    daemon::notify("STOPPING=1");
+    // the sinks write out what they queued, unless another signal asks not to wait
+    let exit_code = tokio::select! {
+        _ = futures::future::join_all(nodes.into_iter().map(BscPeerHandle::shutdown)) => exit_code,
+        signal = shutdown_signal() => {
+            warn!(signal, "exiting without waiting for the nodes to shut down");
+            daemon::EXIT_FAILURE
+        }
+    };
+    // exiting skips destructors
+    drop(reporting_guard);
+    drop(telemetry_guard);
+    drop(pid_file);
+    std::process::exit(exit_code);
+}
 
-                        info!(
-                            peers = %net_handle.num_connected_peers(),
-                            %peer_id,
-                            ?reason,
-                            "node connection closed"
-                        );
-                    }
-                    Some(_) => {
-                    }
-                    None => {
-                        warn!("network event stream ended");
-                        break;
-                    }
-                }
-            }
+/// Waits for a shutdown signal, forever if they can't be listened for.
+async fn shutdown_signal() -> &'static str {
+    match daemon::shutdown_signal().await {
+        Ok(signal) => signal,
+        Err(e) => {
+            warn!("failed to listen for shutdown signals: {}", e);
+            std::future::pending().await
+        }
+    }
+}
 
-            block_event = event_receiver.recv() => {
-                match block_event {
-                    Some(peer::blockstate::BlockEvent::NewBlock { peer_id, block_number, block_hash, transaction_count }) => {
-                        info!(
-                            %peer_id,
-                            block_number = block_number,
-                            block_hash = %block_hash,
-                            transaction_count = transaction_count,
-                            current_height = %state_manager.get_current_height(),
-                            "process new block event"
-                        );
+/// Exits the process with the error, if any.
+fn exit_on_error<E: std::fmt::Display>(result: Result<(), E>) {
+    if let Err(e) = result {
+        eprintln!("error: {e}");
+        std::process::exit(daemon::EXIT_FAILURE);
+    }
+}
 
-                        state_manager.process_received_block(block_number);
-                    }
-                    Some(peer::blockstate::BlockEvent::NewBlockHashes { peer_id, block_numbers }) => {
-                        info!(
-                            %peer_id,
-                            block_count = block_numbers.len(),
-                            current_height = %state_manager.get_current_height(),
-                            "process block hashes event"
-                        );
+/// Prints the enode url and ENR of every node.
+async fn print_enodes(configs: &[Config]) -> Result<(), Box<dyn std::error::Error>> {
+    for config in configs {
+        let secret_key = nodekey::load_or_generate(&config.node_key_path())?;
+        let p2p = &config.p2p;
+        let ip = match p2p.external_ip {
+            Some(ip) => ip,
+            None if !p2p.addr.is_unspecified() => p2p.addr,
+            None => p2p
+                .nat_resolver()
+                .external_addr()
+                .await
+                .ok_or("failed to detect the external address, set `p2p.external_ip`")?,
+        };
+        if let Some(name) = &config.name {
+            println!("{name}:");
+        }
+        println!("{}", nodekey::node_record(&secret_key, ip, p2p.port, p2p.discovery_port));
+        println!("{}", nodekey::enr(&secret_key, ip, p2p.port, p2p.discovery_port)?.to_base64());
+    }
+    Ok(())
+}
 
-                        state_manager.process_block_hashes(&block_numbers, &net_handle);
-                    }
-                    None => {
-                        warn!("block event stream ended");
-                        break;
-                    }
-                }
+/// Runs the handshake with a single peer and prints its outcome, failing if it didn't succeed.
+async fn ping_peer(
+    config: &Config,
+    enode: &str,
+    timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let peer = parse_node(enode)?;
+    let report = ping::ping(config, peer, timeout).await?;
+    if let Some(session) = &report.session {
+        println!("client:         {}", session.client_version);
+        println!("address:        {}", session.remote_addr);
+        let capabilities: Vec<_> =
+            session.capabilities.capabilities().iter().map(ToString::to_string).collect();
+        println!("capabilities:   {}", capabilities.join(", "));
+    }
+    if let Some(status) = &report.handshake.status {
+        println!("eth version:    eth/{}", status.version as u8);
+        println!("chain:          {}", status.chain);
+        println!("genesis:        {}", status.genesis);
+        println!("head:           {}", status.blockhash);
+        if let Some(total_difficulty) = status.total_difficulty {
+            println!("difficulty:     {total_difficulty}");
+        }
+        println!("fork id:        {:?}", status.forkid);
+        match &report.handshake.upgrade_status {
+            Some(upgrade) => println!(
+                "upgrade status: ok, disable_peer_tx_broadcast={}",
+                upgrade.extension.disable_peer_tx_broadcast
+            ),
+            None if status.version as u8 <= 66 => {
+                println!("upgrade status: not exchanged below eth/67")
             }
+            None => {}
+        }
+    }
+    match report.handshake.error {
+        Some(error) => Err(format!("handshake failed: {error}").into()),
+        None => Ok(()),
+    }
+}
+
+/// Benchmarks the peers and prints the report, fastest body download first.
+async fn benchmark_peers(
+    config: &Config,
+    enodes: &[String],
+    settings: &benchmark::BenchmarkSettings,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let peers = enodes.iter().map(|enode| parse_node(enode)).collect::<Result<Vec<_>, _>>()?;
+    let mut results = benchmark::run(config, peers, settings).await?;
+    results.sort_by(|a, b| b.bodies.bytes_per_sec.total_cmp(&a.bodies.bytes_per_sec));
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+        return Ok(());
+    }
+    let ms =
+        |latency: Option<f64>| latency.map_or_else(|| "-".to_string(), |ms| format!("{ms:.1}"));
+    for result in &results {
+        println!("peer {}", result.peer_id);
+        if let Some(client_version) = &result.client_version {
+            println!("  client   {client_version}");
+        }
+        for (phase, summary) in [("headers", &result.headers), ("bodies", &result.bodies)] {
+            println!(
+                "  {phase:<8} {} items, {:.1} items/s, {:.1} KiB/s, {} requests ({} failed), \
+                 latency ms p50 {} p90 {} p99 {} max {}",
+                summary.items,
+                summary.items_per_sec,
+                summary.bytes_per_sec / 1024.0,
+                summary.requests,
+                summary.failures,
+                ms(summary.p50_ms),
+                ms(summary.p90_ms),
+                ms(summary.p99_ms),
+                ms(summary.max_ms),
+            );
+        }
+        if let Some(error) = &result.error {
+            println!("  error    {error}");
         }
     }
+    Ok(())
 }
@@ -0,0 +1,1117 @@
+//! Embeddable BSC p2p follower.
+//!
+//! [`BscPeerBuilder`] wires the network, block importer, sync actor and RPC server together and
+//! starts them, returning a [`BscPeerHandle`] to interact with the running node.
+use crate::{
+    chain_config::{
+        self,
+        checkpoints::{Checkpoint, Checkpoints},
+        validators::{Validator, ValidatorRegistry},
+    },
+    config::{ChainConfig, Config, ConfigError},
+    error::Error,
+    instance, parlia,
+    peer::{
+        self,
+        blockstate::{BlockEvent, BlockImportHook, BlockStateManager},
+        events::EventEnvelope,
+        head::ChainHead,
+    },
+    reload::{self, ConfigReloader, ConfigSource, LogFilterReloader, ReloadError, ReloadReport},
+    rpc::{self, admin::AdminApiServer, bsc::BscApiServer, parlia::ParliaApiServer},
+    sink::{BlockSink, SinkTask},
+    status,
+    supervisor::{Supervisor, TaskFailure},
+};
+use alloy_consensus::Header;
+use alloy_primitives::B256;
+use metrics::counter;
+use reth_chainspec::{ChainSpec, Head};
+use reth_eth_wire::HelloMessageWithProtocols;
+use reth_eth_wire_types::DisconnectReason;
+use reth_ethereum_primitives::BlockBody;
+use reth_network::{
+    EthNetworkPrimitives, NetworkConfig, NetworkEvent, NetworkEventListenerProvider,
+    NetworkHandle, NetworkManager, PeersInfo,
+};
+use reth_network_api::{
+    Peers,
+    events::{PeerEvent, SessionInfo},
+};
+use reth_network_peers::{NodeRecord, PeerId, pk2id};
+use reth_provider::noop::NoopProvider;
+use secp256k1::{SECP256K1, SecretKey};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinHandle,
+    time::interval,
+};
+use tokio_stream::StreamExt;
+use tracing::{debug, error, info, warn};
+
+/// Builder of a [`BscPeerHandle`].
+pub struct BscPeerBuilder {
+    config: Config,
+    secret_key: Option<SecretKey>,
+    sinks: Vec<Box<dyn BlockSink>>,
+    hooks: Vec<BlockImportHook>,
+    config_source: Option<ConfigSource>,
+    log_filter: Option<LogFilterReloader>,
+}
+
+impl std::fmt::Debug for BscPeerBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BscPeerBuilder")
+            .field("config", &self.config)
+            .field("sinks", &self.sinks.len())
+            .field("hooks", &self.hooks.len())
+            .field("reloadable", &self.config_source.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl BscPeerBuilder {
+    /// Creates a builder from the node configuration.
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            secret_key: None,
+            sinks: Vec::new(),
+            hooks: Vec::new(),
+            config_source: None,
+            log_filter: None,
+        }
+    }
+
+    /// Sets the name of the node, added to its metrics and logs.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.config.name = Some(name.into());
+        self
+    }
+
+    /// Sets the chain to follow, BSC mainnet by default.
+    pub fn chain(mut self, chain: ChainConfig) -> Self {
+        self.config.chain = chain;
+        self
+    }
+
+    /// Sets the node key. If unset, the key persisted in the datadir is used, generated on first
+    /// start.
+    pub fn secret_key(mut self, secret_key: SecretKey) -> Self {
+        self.secret_key = Some(secret_key);
+        self
+    }
+
+    /// Sets the interface and TCP port the RLPx listener binds to.
+    pub fn listener_addr(mut self, addr: SocketAddr) -> Self {
+        self.config.p2p.addr = addr.ip();
+        self.config.p2p.port = addr.port();
+        self
+    }
+
+    /// Sets the UDP port of the discovery socket.
+    pub fn discovery_port(mut self, port: u16) -> Self {
+        self.config.p2p.discovery_port = port;
+        self
+    }
+
+    /// Sets the address advertised in the local node record, e.g. a public IPv6 address of a
+    /// node listening on `::`.
+    pub fn external_ip(mut self, ip: IpAddr) -> Self {
+        self.config.p2p.external_ip = Some(ip);
+        self
+    }
+
+    /// Adds a consumer of block events, run on its own task with its own event queue.
+    pub fn sink(mut self, sink: impl BlockSink) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Adds a hook called with every block that passed validation, before its event is emitted.
+    pub fn block_import_hook(
+        mut self,
+        hook: impl Fn(PeerId, &reth_eth_wire::NewBlock) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Sets how the config is reread when reloaded on SIGHUP or through the admin API, see
+    /// [`reload`].
+    pub fn config_source(
+        mut self,
+        source: impl Fn() -> Result<Config, ConfigError> + Send + Sync + 'static,
+    ) -> Self {
+        self.config_source = Some(Arc::new(source));
+        self
+    }
+
+    /// Sets how a reloaded log filter is applied to the log subscriber.
+    pub fn log_filter(
+        mut self,
+        reload: impl Fn(&str) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.log_filter = Some(Arc::new(reload));
+        self
+    }
+
+    /// Starts the node, failing if a file named by the config can't be loaded or a listener
+    /// can't be bound.
+    ///
+    /// All tasks of a named node run within its [`instance`] scope.
+    pub async fn build(self) -> Result<BscPeerHandle, Error> {
+        let name = self.config.name.as_deref().map(Arc::from);
+        instance::scope(name, self.start()).await
+    }
+
+    async fn start(self) -> Result<BscPeerHandle, Error> {
+        let Self { config, secret_key, sinks, hooks, config_source, log_filter } = self;
+        let secret_key = match secret_key {
+            Some(secret_key) => secret_key,
+            None => {
+                let path = config.node_key_path();
+                peer::nodekey::load_or_generate(&path)
+                    .map_err(|source| Error::NodeKey { path, source })?
+            }
+        };
+
+        let (chain_spec, head, mut boot_nodes) = resolve_chain(&config.chain)?;
+        if let Some(nodes) = config.bootnodes.override_nodes()? {
+            boot_nodes = nodes;
+        }
+        if !config.bootnodes.sources.is_empty() {
+            let fetched = peer::bootnodes::fetch_all(&config.bootnodes.sources).await;
+            let added = peer::bootnodes::merge(&mut boot_nodes, fetched);
+            info!(added, total = boot_nodes.len(), "merged remote boot nodes");
+        }
+        let head = resolve_head(&config.chain, &chain_spec, head).await;
+        let chain_spec = Arc::new(chain_spec);
+        let fork_id_policy = peer::forkid::ForkIdPolicy::new(&chain_spec, head, &config.fork_id);
+
+        let path = config.ban_list_path();
+        let ban_list = peer::banlist::BanList::load(&path)
+            .map_err(|source| Error::BanList { path, source })?;
+        let ban_list = Arc::new(Mutex::new(ban_list));
+        let path = config.watchlist_path();
+        let watchlist = peer::watchlist::Watchlist::load(&path)
+            .map_err(|source| Error::Watchlist { path, source })?;
+        let watchlist = Arc::new(Mutex::new(watchlist));
+
+        let geo_resolver = peer::geo::GeoIpResolver::open(&config.geoip)?;
+        let peer_geo = Arc::new(Mutex::new(peer::geo::PeerGeoTracker::new(geo_resolver)));
+
+        let path = config.snapshot_path();
+        let snapshots = parlia::snapshot::SnapshotStore::load(&path)
+            .map_err(|source| Error::Snapshot { path, source })?;
+        let event_sender = peer::events::EventSender::default();
+        let event_receiver = event_sender.subscribe(
+            "node",
+            config.event_buffer,
+            peer::events::OverflowPolicy::CoalesceHashes,
+        );
+
+        let mut checkpoints = vec![Checkpoint { number: 0, hash: chain_spec.genesis_hash() }];
+        if config.chain.genesis.is_none() {
+            checkpoints.extend_from_slice(config.chain.network.checkpoints());
+        }
+        checkpoints.extend_from_slice(&config.chain.checkpoints);
+        let checkpoints = Arc::new(Checkpoints::new(checkpoints));
+        let mut validators = BTreeMap::new();
+        if config.chain.genesis.is_none() {
+            validators = config.chain.network.validators();
+        }
+        validators.extend(config.chain.validators.clone());
+        let validators = Arc::new(ValidatorRegistry::new(validators));
+
+        #[cfg(feature = "parquet")]
+        let sinks: Vec<_> = sinks
+            .into_iter()
+            .chain(config.sinks.parquet.clone().map(|parquet| -> Box<dyn BlockSink> {
+                Box::new(crate::sink::parquet::ParquetSink::spawn(parquet))
+            }))
+            .collect();
+        #[cfg(not(feature = "parquet"))]
+        if config.sinks.parquet.is_some() {
+            warn!("parquet sink configured, but the `parquet` feature is disabled");
+        }
+        #[cfg(feature = "postgres")]
+        let postgres = config
+            .sinks
+            .postgres
+            .clone()
+            .map(crate::sink::postgres::PostgresSink::spawn)
+            .transpose()?;
+        #[cfg(feature = "postgres")]
+        let sinks: Vec<_> = sinks
+            .into_iter()
+            .chain(postgres.clone().map(|postgres| -> Box<dyn BlockSink> { Box::new(postgres) }))
+            .collect();
+        #[cfg(not(feature = "postgres"))]
+        if config.sinks.postgres.is_some() {
+            warn!("postgres sink configured, but the `postgres` feature is disabled");
+        }
+        let event_sinks = crate::sink::event_sinks(&config.sinks).await?;
+        #[cfg(not(feature = "nats"))]
+        if config.sinks.nats.is_some() {
+            warn!("nats sink configured, but the `nats` feature is disabled");
+        }
+        #[cfg(not(feature = "redis"))]
+        if config.sinks.redis.is_some() {
+            warn!("redis sink configured, but the `redis` feature is disabled");
+        }
+        #[cfg(feature = "webhook")]
+        let webhook = config
+            .sinks
+            .webhook
+            .clone()
+            .map(crate::sink::webhook::WebhookSink::spawn)
+            .transpose()?;
+        #[cfg(feature = "webhook")]
+        let sinks: Vec<_> = sinks
+            .into_iter()
+            .chain(webhook.clone().map(crate::sink::webhook::WebhookSink::sink))
+            .collect();
+        #[cfg(not(feature = "webhook"))]
+        if config.sinks.webhook.is_some() {
+            warn!("webhook sink configured, but the `webhook` feature is disabled");
+        }
+        let spawn_sink = |sink| crate::sink::spawn(&event_sender, sink, config.event_buffer);
+        let sinks: Vec<_> = sinks.into_iter().map(spawn_sink).collect();
+        let event_sinks: Vec<_> = event_sinks.into_iter().map(spawn_sink).collect();
+        // sinks needing the full blocks are handed the imported ones
+        let hooks: Vec<_> =
+            hooks.into_iter().chain(sinks.iter().filter_map(SinkTask::hook)).collect();
+        peer::wire::configure(&config.trace_wire).map_err(Error::Capture)?;
+        #[cfg(not(feature = "trace-wire"))]
+        if config.trace_wire.enabled || config.trace_wire.capture.is_some() {
+            warn!("wire tracing enabled, but the `trace-wire` feature is disabled");
+        }
+
+        let consensus = peer::blockstate::ConsensusState::new(snapshots);
+        let bandwidth = peer::bandwidth::Bandwidth::new(&config.bandwidth);
+        let parlia = parlia::Parlia::new(chain_spec.clone());
+        let deployments = peer::deployments::DeploymentDetector::default();
+        let peer_duplicates = Arc::new(Mutex::new(peer::duplicates::DuplicateTracker::default()));
+        let (state_manager, sync_actor) =
+            BlockStateManager::new(head.number, parlia.clone(), consensus.subscribe());
+        let mut sync_actor = sync_actor
+            .with_events(event_sender.clone())
+            .with_checkpoints(checkpoints.clone())
+            .with_monitor(peer::alerts::SyncMonitor::new(&config.alerts, Instant::now()))
+            .with_token_transfers(peer::tokens::TokenTransferDecoder::new(&config.token_transfers))
+            .with_watchlist(watchlist.clone())
+            .with_deployments(deployments.clone())
+            .with_duplicate_tracker(peer_duplicates.clone())
+            .with_ancestor_depth(config.sync.ancestor_depth)
+            .with_request_ttl(Duration::from_secs(config.sync.request_ttl_secs))
+            .with_bandwidth(bandwidth.clone());
+
+        let peer_heads = Arc::new(Mutex::new(peer::peer_heads::PeerHeads::default()));
+        let validator_stats =
+            Arc::new(Mutex::new(peer::validator_stats::ValidatorStats::default()));
+        let mut block_importer = peer::blockstate::SmartBlockImporter::new(
+            event_sender.clone(),
+            parlia.clone(),
+            consensus,
+        )
+        .with_checkpoints(checkpoints)
+        .with_validators(validators)
+        .with_validator_stats(validator_stats.clone())
+        .with_downtime_monitor(peer::downtime::DowntimeMonitor::new(&config.alerts))
+        .with_transfer_monitor(peer::transfers::TransferMonitor::new(&config.transfers))
+        .with_anomaly_detector(peer::anomalies::AnomalyDetector::new(&config.anomalies))
+        .with_watchlist(watchlist.clone())
+        .with_deployments(deployments)
+        .with_hooks(hooks)
+        .with_relay(config.propagation.relay)
+        .with_upload_throttle(bandwidth.upload.clone())
+        .with_duplicate_tracker(peer_duplicates.clone())
+        .with_peer_heads(peer_heads.clone())
+        .with_head(state_manager.head_tracker())
+        .with_sync(state_manager.clone());
+        if config.propagation.announce_fetched {
+            let known_blocks = peer::announce::KnownBlocks::default();
+            sync_actor = sync_actor.with_announcements(known_blocks.clone());
+            block_importer = block_importer.with_known_blocks(known_blocks);
+        }
+
+        let handshake = peer::handshake::BscHandshake::new(fork_id_policy.clone())
+            .with_status_validator(peer::status::StatusValidator::new(&chain_spec))
+            .with_instance(instance::current())
+            .with_timeout(Duration::from_secs(config.session.handshake_timeout_secs));
+        let mut hello =
+            HelloMessageWithProtocols::builder(pk2id(&secret_key.public_key(SECP256K1)));
+        if let Some(client_version) = &config.capabilities.client_version {
+            hello = hello.client_version(client_version);
+        }
+        // behind a proxy, only the dialer opens outbound sessions, relayed through it, while reth
+        // keeps the discovered nodes without dialing them
+        let proxy = config.proxy.socks5.clone().map(peer::proxy::Socks5Forwarder::new);
+        let mut peers_config =
+            config.peers.peers_config()?.with_ban_list(ban_list.lock().unwrap().to_reth_ban_list());
+        if proxy.is_some() {
+            peers_config = peers_config.with_max_outbound(0);
+            if !config.dialer.enabled {
+                warn!("proxy configured with the dialer disabled, no peers will be dialed");
+            }
+            if !config.peers.trusted_nodes.is_empty() {
+                warn!("trusted nodes are dialed directly, bypassing the proxy");
+            }
+        }
+        let mut net_builder = NetworkConfig::builder(secret_key)
+            .hello_message(hello.build())
+            .boot_nodes(boot_nodes.clone())
+            .set_head(head)
+            .with_pow()
+            .listener_addr(config.p2p.listener_addr())
+            .discovery_addr(config.p2p.discovery_addr())
+            .external_ip_resolver(config.p2p.nat_resolver())
+            .peer_config(peers_config)
+            .sessions_config(config.sessions_config())
+            .eth_rlpx_handshake(Arc::new(handshake))
+            .block_import(Box::new(block_importer));
+        // discovery runs over UDP, which the proxy doesn't relay, so it would reveal the address
+        // of the node
+        if proxy.is_some() {
+            net_builder = net_builder.disable_discovery();
+        }
+        let mut net_cfg = net_builder.build(NoopProvider::eth(chain_spec.clone()));
+        if proxy.is_none() {
+            net_cfg = net_cfg.set_discovery_v4(
+                config
+                    .discovery
+                    .discv4_builder()
+                    .add_boot_nodes(boot_nodes.clone())
+                    .enable_eip868(config.fork_id.filter_discovery)
+                    .external_ip_resolver(Some(config.p2p.nat_resolver()))
+                    .build(),
+            );
+        }
+        // bound before any task is spawned, so a taken port fails the start cleanly
+        let rpc_server = if config.rpc.enabled {
+            let server = rpc::bind_server(config.rpc.addr)
+                .await
+                .map_err(|source| Error::Rpc { addr: config.rpc.addr, source })?;
+            Some(server)
+        } else {
+            None
+        };
+        let mut net_manager = NetworkManager::<EthNetworkPrimitives>::new(net_cfg).await?;
+
+        if config.capabilities.trust {
+            net_manager
+                .add_rlpx_sub_protocol(peer::trust::TrustProtocol::new(event_sender.clone()));
+        }
+        if config.capabilities.snap {
+            net_manager.add_rlpx_sub_protocol(peer::snap::SnapProtocol);
+        }
+        for protocol in &config.capabilities.extra {
+            net_manager
+                .add_rlpx_sub_protocol(peer::passthrough::PassthroughProtocol::new(protocol));
+        }
+        let net_handle = net_manager.handle().clone();
+        let mut transaction_filter = None;
+        let mut transactions_task = None;
+        if config.transactions.enabled {
+            let (transactions_tx, transactions_rx) = mpsc::unbounded_channel();
+            net_manager.set_transactions(transactions_tx);
+            let gossip = peer::transactions::TransactionGossip::new(
+                net_handle.clone(),
+                event_sender.clone(),
+                &config.transactions,
+            );
+            transaction_filter = Some(gossip.filter_handle());
+            transactions_task = Some(gossip.spawn(transactions_rx));
+        }
+        let (supervisor, task_failures) = Supervisor::new(&config.supervisor);
+        let network_events = net_handle.event_listener();
+        #[cfg(feature = "postgres")]
+        if let Some(postgres) = &postgres {
+            postgres.track_sessions(net_handle.event_listener());
+        }
+        #[cfg(feature = "webhook")]
+        if let Some(webhook) = &webhook {
+            webhook.watch_peers(net_handle.clone());
+        }
+
+        supervisor.critical("network", instance::spawn(net_manager));
+        supervisor.critical("sync", sync_actor.spawn(net_handle.clone()));
+        if let Some(task) = transactions_task {
+            supervisor.critical("transactions", task);
+        }
+        let discovery_filter = config
+            .fork_id
+            .filter_discovery
+            .then(|| fork_id_policy.fork_filter(chain_spec.fork_filter(head)));
+        if let Some(fork_filter) = &discovery_filter {
+            let (network, fork_filter) = (net_handle.clone(), fork_filter.clone());
+            supervisor.restartable("discovery_filter", move || {
+                peer::forkid::spawn_discovery_filter(network.clone(), fork_filter.clone())
+            });
+        }
+        if config.dialer.enabled {
+            let mut dialer = peer::dialer::Dialer::new(
+                &config.dialer,
+                net_handle.clone(),
+                config.peers.max_outbound,
+            );
+            if let Some(fork_filter) = discovery_filter {
+                dialer = dialer.with_fork_filter(fork_filter);
+            }
+            if let Some(proxy) = proxy.clone() {
+                dialer = dialer.with_proxy(proxy);
+            }
+            if config.recovery.enabled {
+                dialer = dialer.with_recovery();
+            }
+            let mut nodes = boot_nodes.clone();
+            nodes.extend(config.peers.trusted_records());
+            supervisor
+                .critical("dialer", dialer.with_peers_file(config.peers_file_path()).spawn(&nodes));
+        }
+        if config.recovery.enabled {
+            let (recovery, network, events) =
+                (config.recovery.clone(), net_handle.clone(), event_sender.clone());
+            supervisor.restartable("peer_recovery", move || {
+                peer::recovery::spawn(&recovery, network.clone(), events.clone())
+            });
+        }
+        // refreshed nodes are added to the peer set, which reth dials directly
+        if !config.bootnodes.sources.is_empty()
+            && config.bootnodes.refresh_secs > 0
+            && proxy.is_none()
+        {
+            let (network, bootnodes, known) =
+                (net_handle.clone(), config.bootnodes.clone(), boot_nodes.clone());
+            supervisor.restartable("bootnode_refresh", move || {
+                peer::bootnodes::spawn_refresh(network.clone(), bootnodes.clone(), known.clone())
+            });
+        }
+        let peer_latency = Arc::new(Mutex::new(peer::latency::LatencyTracker::default()));
+        let (network, tracker, session) =
+            (net_handle.clone(), peer_latency.clone(), config.session.clone());
+        supervisor.restartable("latency_probe", move || {
+            peer::latency::spawn_probe(network.clone(), tracker.clone(), &session)
+        });
+        let disconnects = Arc::new(Mutex::new(peer::disconnects::DisconnectStats::default()));
+        let stats = disconnects.clone();
+        supervisor.restartable("disconnect_report", move || {
+            peer::disconnects::spawn_report(stats.clone())
+        });
+        let transaction_sender =
+            peer::transactions::TransactionSender::new(net_handle.clone(), chain_spec.chain.id());
+
+        info!("BSC P2P network started, listening and requesting blocks...");
+
+        let (reloader, reload_requests) = ConfigReloader::new();
+        #[cfg(unix)]
+        if config_source.is_some() {
+            reload::spawn_sighup(reloader.clone());
+        }
+
+        if let Some(server) = rpc_server {
+            let admin = rpc::admin::AdminRpc::new(
+                net_handle.clone(),
+                ban_list.clone(),
+                state_manager.clone(),
+            )
+            .with_geo(peer_geo.clone())
+            .with_latency(peer_latency.clone())
+            .with_duplicates(peer_duplicates.clone())
+            .with_peer_heads(peer_heads.clone())
+            .with_disconnects(disconnects.clone())
+            .with_validator_stats(validator_stats.clone())
+            .with_watchlist(watchlist.clone())
+            .with_reloader(reloader.clone());
+            let mut methods = admin.into_rpc();
+            methods
+                .merge(rpc::parlia::ParliaRpc::new(state_manager.clone()).into_rpc())
+                .expect("rpc method names are unique");
+            if config.rpc.send_transactions {
+                methods
+                    .merge(rpc::bsc::BscRpc::new(transaction_sender.clone()).into_rpc())
+                    .expect("rpc method names are unique");
+            }
+            let server = server.start(methods);
+            info!(addr = %config.rpc.addr, "RPC server started");
+            // the server stops once its handle is dropped with the aborted task
+            supervisor.critical("rpc", instance::spawn(server.stopped()));
+        }
+
+        let state_for_timer = state_manager.clone();
+        let geo_for_timer = peer_geo.clone();
+        let max_asn_share = config.geoip.max_asn_share;
+        supervisor.restartable("timer", move || {
+            let state_for_timer = state_for_timer.clone();
+            let geo_for_timer = geo_for_timer.clone();
+            instance::spawn(async move {
+                let mut interval = interval(Duration::from_secs(10));
+                loop {
+                    interval.tick().await;
+
+                    state_for_timer.tick();
+
+                    let distribution = geo_for_timer.lock().unwrap().update_metrics();
+                    if let Some((asn, share)) = distribution.dominant_asn() {
+                        if share > max_asn_share && distribution.total > 1 {
+                            warn!(
+                                asn,
+                                share,
+                                peers = distribution.total,
+                                "peers concentrated in one network"
+                            );
+                        }
+                    }
+                }
+            })
+        });
+
+        let events_for_flush = event_sender.clone();
+        supervisor.restartable("event_flush", move || {
+            let events_for_flush = events_for_flush.clone();
+            instance::spawn(async move {
+                let mut interval = interval(peer::events::COALESCE_FLUSH_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    events_for_flush.flush();
+                }
+            })
+        });
+
+        let reporter = status::StatusReporter::new(
+            net_handle.clone(),
+            state_manager.clone(),
+            event_sender.clone(),
+            Duration::from_secs(config.status.interval_secs),
+        );
+        let status = reporter.subscribe();
+        if config.status.interval_secs > 0 {
+            supervisor.restartable("status", move || instance::spawn(reporter.clone().run()));
+        }
+
+        let requests = peer::requests::RequestClient::new(net_handle.clone(), peer_heads.clone());
+        let node = BscPeer {
+            client_filter: peer::filter::ClientFilter::new(&config.client_filter),
+            config,
+            net_handle: net_handle.clone(),
+            supervisor,
+            state_manager: state_manager.clone(),
+            ban_list,
+            peer_geo,
+            peer_latency,
+            peer_duplicates,
+            peer_heads,
+            disconnects,
+            client_versions: HashMap::new(),
+            session_versions: event_sender.session_versions(),
+            proxy,
+            events: event_sender.clone(),
+            sinks,
+            event_sinks,
+            transaction_filter,
+            config_source,
+            log_filter,
+        };
+        let (shutdown, shutdown_requests) = mpsc::channel(1);
+        let task = instance::spawn(node.run(
+            network_events,
+            event_receiver,
+            reload_requests,
+            task_failures,
+            shutdown_requests,
+        ));
+
+        Ok(BscPeerHandle {
+            network: net_handle,
+            state: state_manager,
+            transactions: transaction_sender,
+            events: event_sender,
+            requests,
+            parlia,
+            status,
+            reloader,
+            shutdown,
+            task: Some(task),
+        })
+    }
+}
+
+/// Handle to a running node.
+#[derive(Debug)]
+pub struct BscPeerHandle {
+    network: NetworkHandle<EthNetworkPrimitives>,
+    state: BlockStateManager,
+    transactions: peer::transactions::TransactionSender,
+    events: peer::events::EventSender,
+    requests: peer::requests::RequestClient,
+    parlia: parlia::Parlia,
+    status: watch::Receiver<status::StatusReport>,
+    reloader: ConfigReloader,
+    /// Asks the event loop to stop.
+    shutdown: mpsc::Sender<()>,
+    /// Task of the event loop, taken once it completed.
+    task: Option<JoinHandle<()>>,
+}
+
+impl BscPeerHandle {
+    /// Returns the handle of the underlying network.
+    pub fn network(&self) -> &NetworkHandle<EthNetworkPrimitives> {
+        &self.network
+    }
+
+    /// Returns the handle of the block sync state.
+    pub fn state(&self) -> &BlockStateManager {
+        &self.state
+    }
+
+    /// Returns a receiver of the canonical head, notified whenever it advances or reorgs.
+    pub fn subscribe_head(&self) -> watch::Receiver<Option<ChainHead>> {
+        self.state.subscribe_head()
+    }
+
+    /// Subscribes to the block events sent from now on, with its own queue of `capacity` events
+    /// and overflow policy, e.g. for analytics or a TUI.
+    pub fn subscribe_events(
+        &self,
+        name: &str,
+        capacity: usize,
+        policy: peer::events::OverflowPolicy,
+    ) -> mpsc::Receiver<EventEnvelope> {
+        self.events.subscribe(name, capacity, policy)
+    }
+
+    /// Subscribes to the pending transactions received from now on, on a queue of `capacity`
+    /// events of its own, dropping the further ones while it's full.
+    pub fn subscribe_transactions(
+        &self,
+        name: &str,
+        capacity: usize,
+    ) -> mpsc::Receiver<EventEnvelope> {
+        self.events.subscribe_transactions(name, capacity)
+    }
+
+    /// Fetches the canonical headers of a range from the connected peers, oldest first.
+    pub async fn get_headers(
+        &self,
+        range: RangeInclusive<u64>,
+    ) -> Result<Vec<Header>, peer::requests::RequestError> {
+        self.requests.get_headers(range).await
+    }
+
+    /// Fetches the bodies of the blocks with the given hashes from the connected peers, in order.
+    pub async fn get_bodies(
+        &self,
+        hashes: Vec<B256>,
+    ) -> Result<Vec<BlockBody>, peer::requests::RequestError> {
+        self.requests.get_bodies(hashes).await
+    }
+
+    /// Returns the Parlia helpers of the chain, sharing the seal cache of the block importer, e.g.
+    /// to recover the proposers of headers from [`Self::get_headers`].
+    pub fn parlia(&self) -> &parlia::Parlia {
+        &self.parlia
+    }
+
+    /// Returns a receiver of the periodic status report, see [`crate::status`].
+    pub fn subscribe_status(&self) -> watch::Receiver<status::StatusReport> {
+        self.status.clone()
+    }
+
+    /// Returns the sender broadcasting transactions to the connected peers.
+    pub fn transactions(&self) -> &peer::transactions::TransactionSender {
+        &self.transactions
+    }
+
+    /// Returns the handle reloading the config of the node.
+    pub fn config_reloader(&self) -> &ConfigReloader {
+        &self.reloader
+    }
+
+    /// Waits until the node stops, i.e. its network or block event stream ended.
+    pub async fn wait(mut self) {
+        self.stopped().await
+    }
+
+    /// Waits until the node stops, like [`Self::wait`], keeping the handle to shut it down.
+    pub async fn stopped(&mut self) {
+        let Some(task) = &mut self.task else { return };
+        let result = task.await;
+        self.task = None;
+        if let Err(e) = result {
+            warn!("node task failed: {}", e);
+        }
+    }
+
+    /// Disconnects the peers and stops the node, waiting until its sinks wrote out what they
+    /// queued.
+    pub async fn shutdown(mut self) {
+        if self.network.shutdown().await.is_err() {
+            debug!("network already stopped");
+        }
+        let _ = self.shutdown.try_send(());
+        self.stopped().await;
+    }
+}
+
+/// Resolves the chain spec, head and boot nodes of the configured chain.
+pub(crate) fn resolve_chain(
+    chain: &ChainConfig,
+) -> Result<(ChainSpec, Head, Vec<NodeRecord>), Error> {
+    let hardforks = chain.hardforks.as_deref().map(chain_config::schedule::load).transpose()?;
+
+    match &chain.genesis {
+        Some(genesis) => {
+            let hardforks = hardforks.unwrap_or_else(|| chain.hardfork_profile.hardforks());
+            let chain_spec = chain_config::custom::chain_spec_from_genesis(
+                genesis,
+                hardforks,
+                chain.genesis_hash,
+            )?;
+            let head = chain_config::custom::genesis_head(&chain_spec);
+            // custom chains are reached through the configured trusted nodes only
+            Ok((chain_spec, head, Vec::new()))
+        }
+        None => Ok((
+            chain.network.chain_spec(hardforks),
+            chain.network.head(),
+            chain.network.boot_nodes(),
+        )),
+    }
+}
+
+/// Fetches the head from the configured RPC endpoint, falling back to the resolved one, also if
+/// the endpoint follows another chain.
+pub(crate) async fn resolve_head(chain: &ChainConfig, chain_spec: &ChainSpec, head: Head) -> Head {
+    let Some(url) = &chain.head_rpc_url else { return head };
+    match chain_config::remote::fetch_head(url, chain_spec, head).await {
+        Ok(head) => {
+            info!(number = head.number, hash = %head.hash, "fetched head from rpc");
+            head
+        }
+        Err(e) => {
+            warn!(%url, "failed to fetch head, using the built-in one: {}", e);
+            head
+        }
+    }
+}
+
+/// Event loop of a running node, admitting peers and dispatching block events.
+struct BscPeer {
+    config: Config,
+    net_handle: NetworkHandle<EthNetworkPrimitives>,
+    /// Watches the tasks of the node, aborted once it stops.
+    supervisor: Supervisor,
+    state_manager: BlockStateManager,
+    ban_list: Arc<Mutex<peer::banlist::BanList>>,
+    peer_geo: Arc<Mutex<peer::geo::PeerGeoTracker>>,
+    peer_latency: Arc<Mutex<peer::latency::LatencyTracker>>,
+    peer_duplicates: Arc<Mutex<peer::duplicates::DuplicateTracker>>,
+    peer_heads: Arc<Mutex<peer::peer_heads::PeerHeads>>,
+    disconnects: Arc<Mutex<peer::disconnects::DisconnectStats>>,
+    client_filter: peer::filter::ClientFilter,
+    client_versions: HashMap<PeerId, Arc<str>>,
+    /// Eth versions of the sessions, stamped on the events of their peers.
+    session_versions: peer::events::SessionVersions,
+    /// Relays of the dials through a proxy, mapping their sessions to the real peer addresses.
+    proxy: Option<peer::proxy::Socks5Forwarder>,
+    events: peer::events::EventSender,
+    /// Tasks of the sinks added to the builder, parquet, postgres and the webhook, stopped with
+    /// the node.
+    sinks: Vec<SinkTask>,
+    /// Tasks of the sinks of the config consuming the event stream, replaced on reload.
+    event_sinks: Vec<SinkTask>,
+    transaction_filter: Option<peer::transactions::TransactionFilterHandle>,
+    config_source: Option<ConfigSource>,
+    log_filter: Option<LogFilterReloader>,
+}
+
+impl BscPeer {
+    async fn run(
+        mut self,
+        mut network_events: impl tokio_stream::Stream<Item = NetworkEvent> + Unpin,
+        mut event_receiver: mpsc::Receiver<EventEnvelope>,
+        mut reload_requests: mpsc::UnboundedReceiver<reload::ReloadRequest>,
+        mut task_failures: mpsc::UnboundedReceiver<TaskFailure>,
+        mut shutdown_requests: mpsc::Receiver<()>,
+    ) {
+        loop {
+            tokio::select! {
+                network_event = network_events.next() => {
+                    match network_event {
+                        Some(event) => self.on_network_event(event),
+                        None => {
+                            warn!("network event stream ended");
+                            break;
+                        }
+                    }
+                }
+
+                block_event = event_receiver.recv() => {
+                    match block_event {
+                        Some(event) => self.on_block_event(event),
+                        None => {
+                            warn!("block event stream ended");
+                            break;
+                        }
+                    }
+                }
+
+                Some(response) = reload_requests.recv() => {
+                    let _ = response.send(self.reload().await);
+                }
+
+                Some(failure) = task_failures.recv() => {
+                    error!(task = failure.task, "stopping node: {}", failure.error);
+                    break;
+                }
+
+                Some(()) = shutdown_requests.recv() => break,
+            }
+        }
+        // nothing of a stopped node keeps running, whatever stopped it
+        if self.net_handle.shutdown().await.is_err() {
+            debug!("network already stopped");
+        }
+        self.supervisor.abort_all();
+        // the sinks write out what they queued before the node is reported stopped
+        let sinks = self.sinks.drain(..).chain(self.event_sinks.drain(..));
+        futures::future::join_all(sinks.map(SinkTask::stop)).await;
+    }
+
+    fn on_network_event(&mut self, event: NetworkEvent) {
+        match event {
+            NetworkEvent::ActivePeerSession { info, .. } => {
+                let SessionInfo { status, client_version, peer_id, remote_addr, version, .. } =
+                    info;
+                peer::handshake::record_handshake(&client_version, version);
+                let remote_addr = match &self.proxy {
+                    Some(proxy) => proxy.resolve(&peer_id, remote_addr),
+                    None => remote_addr,
+                };
+
+                if self.ban_list.lock().unwrap().is_peer_banned(peer_id, remote_addr.ip()) {
+                    info!(%peer_id, %remote_addr, "disconnecting banned peer");
+                    self.net_handle
+                        .disconnect_peer_with_reason(peer_id, DisconnectReason::UselessPeer);
+                    return;
+                }
+
+                if !self.client_filter.is_allowed(&client_version) {
+                    info!(%peer_id, ?client_version, "disconnecting filtered client");
+                    self.net_handle
+                        .disconnect_peer_with_reason(peer_id, DisconnectReason::UselessPeer);
+                    return;
+                }
+
+                self.client_versions.insert(peer_id, client_version.clone());
+                self.session_versions.insert(peer_id, version);
+                let location =
+                    self.peer_geo.lock().unwrap().add_peer(peer_id, remote_addr.ip()).clone();
+
+                info!(
+                    peers = %self.net_handle.num_connected_peers(),
+                    %peer_id,
+                    chain = %status.chain,
+                    best_block = %status.blockhash,
+                    ?status.total_difficulty,
+                    ?client_version,
+                    country = ?location.country,
+                    asn = ?location.asn,
+                    "new node connected"
+                );
+
+                self.peer_heads.lock().unwrap().on_status(
+                    peer_id,
+                    status.blockhash,
+                    status.latest_block,
+                    status.total_difficulty,
+                );
+                if let Some(number) = status.latest_block {
+                    self.state_manager.peer_head(peer_id, number);
+                }
+                self.state_manager.add_peer(peer_id);
+                self.peer_latency.lock().unwrap().add_peer(peer_id);
+            }
+            NetworkEvent::Peer(PeerEvent::SessionClosed { peer_id, reason }) => {
+                self.state_manager.remove_peer(peer_id);
+                self.peer_geo.lock().unwrap().remove_peer(&peer_id);
+                self.peer_latency.lock().unwrap().remove_peer(&peer_id);
+                self.peer_duplicates.lock().unwrap().remove_peer(&peer_id);
+                self.peer_heads.lock().unwrap().remove_peer(&peer_id);
+                self.session_versions.remove(&peer_id);
+                if let Some(client_version) = self.client_versions.remove(&peer_id) {
+                    peer::handshake::record_disconnect(&client_version, reason);
+                    self.disconnects.lock().unwrap().record(&client_version, reason);
+                }
+
+                info!(
+                    peers = %self.net_handle.num_connected_peers(),
+                    %peer_id,
+                    ?reason,
+                    "node connection closed"
+                );
+            }
+            _ => {}
+        }
+    }
+
+    fn on_block_event(&mut self, event: EventEnvelope) {
+        match &event.event {
+            BlockEvent::NewBlock { peer_id, block, turn_status, finality, .. } => {
+                info!(
+                    %peer_id,
+                    block_number = block.number,
+                    block_hash = %block.hash,
+                    miner = %block.miner,
+                    validator = ?block.validator.as_ref().map(Validator::label),
+                    transaction_count = block.transaction_count,
+                    gas_used = block.gas_used,
+                    ?turn_status,
+                    finalized = ?finality.finalized.map(|head| head.number),
+                    current_height = %self.state_manager.get_current_height(),
+                    "process new block event"
+                );
+
+                self.state_manager.block_received(block.number);
+            }
+            BlockEvent::NewBlockHashes { peer_id, block_numbers } => {
+                info!(
+                    %peer_id,
+                    block_count = block_numbers.len(),
+                    current_height = %self.state_manager.get_current_height(),
+                    "process block hashes event"
+                );
+
+                self.state_manager.block_hashes(block_numbers.clone());
+            }
+            BlockEvent::InvalidBlock { peer_id, block_number, reason, error } => {
+                warn!(%peer_id, block_number = block_number, reason, error, "invalid block");
+            }
+            BlockEvent::Receipts { peer_id, block_number, block_hash, receipts } => {
+                info!(
+                    %peer_id,
+                    block_number = block_number,
+                    block_hash = %block_hash,
+                    receipt_count = receipts.len(),
+                    "process receipts event"
+                );
+            }
+            BlockEvent::PendingTransactions { peer_id, transactions } => {
+                debug!(%peer_id, count = transactions.len(), "process pending transactions event");
+            }
+            BlockEvent::TrustMessage { peer_id, message } => {
+                info!(%peer_id, ?message, "process trust message event");
+            }
+            BlockEvent::SyncGap { height, highest_seen, gap } => {
+                warn!(height, highest_seen, gap, "block sync falling behind");
+            }
+            BlockEvent::SyncStalled { height, idle_secs } => {
+                warn!(height, idle_secs, "no new block seen");
+            }
+            BlockEvent::NoPeers { idle_secs } => {
+                warn!(idle_secs, "no peer connected");
+            }
+            BlockEvent::TokenTransfers { peer_id, block_number, transfers, .. } => {
+                debug!(%peer_id, block_number, count = transfers.len(), "token transfers");
+            }
+            BlockEvent::TransferAlert { rules, block_number, tx_hash, from, to, value, .. } => {
+                info!(?rules, block_number, %tx_hash, %from, ?to, %value, "transfer alert");
+            }
+            BlockEvent::ValidatorDown { validator, block_number, since_block, missed_blocks } => {
+                warn!(
+                    validator = %validator.label(),
+                    address = %validator.address,
+                    block_number,
+                    since_block,
+                    missed_blocks,
+                    "validator missing its turns"
+                );
+            }
+            BlockEvent::Reorg { reorg } => {
+                info!(
+                    common_ancestor = ?reorg.common_ancestor.map(|block| block.number),
+                    dropped = reorg.dropped.len(),
+                    added = reorg.added.len(),
+                    "chain reorg"
+                );
+            }
+            BlockEvent::Anomaly { block_number, block_hash, anomaly } => {
+                warn!(block_number, %block_hash, ?anomaly, "block anomaly");
+            }
+            BlockEvent::ContractDeployed { block_number, deployment, .. } => {
+                info!(
+                    contract = %deployment.contract,
+                    deployer = %deployment.deployer,
+                    block_number,
+                    tx_hash = %deployment.tx_hash,
+                    "contract deployed"
+                );
+            }
+            BlockEvent::WatchlistHit { hit } => {
+                info!(
+                    address = %hit.address,
+                    label = ?hit.label,
+                    role = ?hit.role,
+                    block_number = hit.block_number,
+                    tx_hash = %hit.tx_hash,
+                    "watched address hit"
+                );
+            }
+        }
+    }
+
+    /// Rereads the config and applies its reloadable settings, see [`reload`].
+    async fn reload(&mut self) -> Result<ReloadReport, ReloadError> {
+        let result = self.apply_reload().await;
+        match &result {
+            Ok(report) => {
+                counter!("bscpeer_config_reloads_total", "outcome" => "ok").increment(1);
+                info!(applied = ?report.applied, "reloaded config");
+                if !report.restart_required.is_empty() {
+                    warn!(settings = ?report.restart_required, "config changes require a restart");
+                }
+            }
+            Err(e) => {
+                counter!("bscpeer_config_reloads_total", "outcome" => "error").increment(1);
+                warn!("failed to reload config: {}", e);
+            }
+        }
+        result
+    }
+
+    async fn apply_reload(&mut self) -> Result<ReloadReport, ReloadError> {
+        let source = self.config_source.as_ref().ok_or(ReloadError::NoSource)?;
+        let (config, report) = reload::plan(&self.config, &source()?)?;
+        if config.log != self.config.log {
+            if let Some(log_filter) = &self.log_filter {
+                log_filter(&config.log.filter).map_err(ReloadError::LogFilter)?;
+            }
+            self.config.log = config.log.clone();
+        }
+        if config.sinks != self.config.sinks {
+            let sinks = crate::sink::event_sinks(&config.sinks).await?;
+            // the previous sinks write out what they queued before the new ones start, so two
+            // writers never append to the same file at once
+            futures::future::join_all(self.event_sinks.drain(..).map(SinkTask::stop)).await;
+            self.event_sinks = sinks
+                .into_iter()
+                .map(|sink| crate::sink::spawn(&self.events, sink, config.event_buffer))
+                .collect();
+            debug!(sinks = self.event_sinks.len(), "restarted event sinks");
+        }
+        self.client_filter = peer::filter::ClientFilter::new(&config.client_filter);
+        if let Some(transaction_filter) = &self.transaction_filter {
+            transaction_filter.set(&config.transactions.filter);
+        }
+        self.config = config;
+        Ok(report)
+    }
+}
@@ -0,0 +1,207 @@
+//! Layout of the Parlia `extraData` header field.
+//!
+//! ```text
+//! | vanity (32) | validators (epoch blocks only) | vote attestation (post-Luban) | seal (65) |
+//! ```
+//!
+//! Before Luban the validator section is a list of 20 byte addresses. From Luban on it starts
+//! with a one byte count followed by `count` entries of address plus 48 byte BLS vote address,
+//! and from Bohr on it is followed by a one byte turn length.
+use alloy_primitives::{Address, FixedBytes};
+use serde::{Deserialize, Serialize};
+
+/// Fixed number of leading bytes reserved for signer vanity.
+pub const EXTRA_VANITY_LEN: usize = 32;
+/// Fixed number of trailing bytes reserved for the signer seal.
+pub const EXTRA_SEAL_LEN: usize = 65;
+/// Size of the validator count prefix, post-Luban.
+pub const VALIDATOR_NUMBER_SIZE: usize = 1;
+/// Size of the turn length suffix, post-Bohr.
+pub const TURN_LENGTH_SIZE: usize = 1;
+/// Length of a validator consensus address.
+pub const ADDRESS_LEN: usize = 20;
+/// Length of a BLS public key used for fast finality votes.
+pub const BLS_PUBLIC_KEY_LEN: usize = 48;
+/// Length of a validator entry before Luban.
+pub const VALIDATOR_BYTES_LEN_BEFORE_LUBAN: usize = ADDRESS_LEN;
+/// Length of a validator entry from Luban on.
+pub const VALIDATOR_BYTES_LEN: usize = ADDRESS_LEN + BLS_PUBLIC_KEY_LEN;
+
+/// A BLS public key identifying a validator's votes.
+pub type VoteAddress = FixedBytes<BLS_PUBLIC_KEY_LEN>;
+
+/// Errors that can occur while parsing `extraData`.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ExtraDataError {
+    /// The field is too short to contain vanity and seal.
+    #[error("extra data too short: {0} bytes")]
+    TooShort(usize),
+    /// The validator section has an invalid length.
+    #[error("invalid validator bytes in extra data")]
+    InvalidValidatorBytes,
+}
+
+/// Which parts of the layout are present, depending on the block and active forks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtraDataLayout {
+    /// The block is an epoch boundary carrying the validator set.
+    pub is_epoch: bool,
+    /// Luban is active.
+    pub is_luban: bool,
+    /// Bohr is active.
+    pub is_bohr: bool,
+}
+
+/// Validator set announced in an epoch boundary header.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidatorSet {
+    /// Consensus addresses of the validators, in the order they appear in the header.
+    pub validators: Vec<Address>,
+    /// BLS vote addresses, index-aligned with `validators`. Empty before Luban.
+    pub vote_addresses: Vec<VoteAddress>,
+    /// Number of consecutive blocks each validator produces, post-Bohr.
+    pub turn_length: Option<u8>,
+}
+
+fn body(extra_data: &[u8]) -> Result<&[u8], ExtraDataError> {
+    if extra_data.len() < EXTRA_VANITY_LEN + EXTRA_SEAL_LEN {
+        return Err(ExtraDataError::TooShort(extra_data.len()));
+    }
+    Ok(&extra_data[EXTRA_VANITY_LEN..extra_data.len() - EXTRA_SEAL_LEN])
+}
+
+/// Returns the seal, i.e. the proposer's signature over the header.
+pub fn seal(extra_data: &[u8]) -> Result<&[u8], ExtraDataError> {
+    body(extra_data)?;
+    Ok(&extra_data[extra_data.len() - EXTRA_SEAL_LEN..])
+}
+
+/// Parses the validator set of an epoch boundary header.
+///
+/// Returns `Ok(None)` for non-epoch blocks.
+pub fn parse_validators(
+    extra_data: &[u8],
+    layout: ExtraDataLayout,
+) -> Result<Option<ValidatorSet>, ExtraDataError> {
+    if !layout.is_epoch {
+        return Ok(None);
+    }
+    let body = body(extra_data)?;
+
+    if !layout.is_luban {
+        if body.is_empty() || body.len() % VALIDATOR_BYTES_LEN_BEFORE_LUBAN != 0 {
+            return Err(ExtraDataError::InvalidValidatorBytes);
+        }
+        let validators =
+            body.chunks_exact(VALIDATOR_BYTES_LEN_BEFORE_LUBAN).map(Address::from_slice).collect();
+        return Ok(Some(ValidatorSet { validators, ..Default::default() }));
+    }
+
+    let count = *body.first().ok_or(ExtraDataError::InvalidValidatorBytes)? as usize;
+    let end = VALIDATOR_NUMBER_SIZE + count * VALIDATOR_BYTES_LEN;
+    let min_len = if layout.is_bohr { end + TURN_LENGTH_SIZE } else { end };
+    if count == 0 || body.len() < min_len {
+        return Err(ExtraDataError::InvalidValidatorBytes);
+    }
+
+    let mut set = ValidatorSet::default();
+    for entry in body[VALIDATOR_NUMBER_SIZE..end].chunks_exact(VALIDATOR_BYTES_LEN) {
+        let (address, vote_address) = entry.split_at(ADDRESS_LEN);
+        set.validators.push(Address::from_slice(address));
+        set.vote_addresses.push(VoteAddress::from_slice(vote_address));
+    }
+    if layout.is_bohr {
+        set.turn_length = Some(body[end]);
+    }
+    Ok(Some(set))
+}
+
+/// Returns the RLP encoded vote attestation, if the header carries one.
+pub fn vote_attestation_bytes(
+    extra_data: &[u8],
+    layout: ExtraDataLayout,
+) -> Result<Option<&[u8]>, ExtraDataError> {
+    if !layout.is_luban {
+        return Ok(None);
+    }
+    let body = body(extra_data)?;
+    let start = if layout.is_epoch {
+        let count = *body.first().ok_or(ExtraDataError::InvalidValidatorBytes)? as usize;
+        let start = VALIDATOR_NUMBER_SIZE + count * VALIDATOR_BYTES_LEN;
+        if layout.is_bohr { start + TURN_LENGTH_SIZE } else { start }
+    } else {
+        0
+    };
+    Ok((start < body.len()).then(|| &body[start..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LUBAN_EPOCH: ExtraDataLayout =
+        ExtraDataLayout { is_epoch: true, is_luban: true, is_bohr: true };
+
+    fn luban_extra(validators: &[(Address, VoteAddress)], attestation: &[u8]) -> Vec<u8> {
+        let mut extra = vec![0u8; EXTRA_VANITY_LEN];
+        extra.push(validators.len() as u8);
+        for (address, vote_address) in validators {
+            extra.extend_from_slice(address.as_slice());
+            extra.extend_from_slice(vote_address.as_slice());
+        }
+        extra.push(4);
+        extra.extend_from_slice(attestation);
+        extra.extend_from_slice(&[0u8; EXTRA_SEAL_LEN]);
+        extra
+    }
+
+    #[test]
+    fn test_parse_validators_before_luban() {
+        let mut extra = vec![0u8; EXTRA_VANITY_LEN];
+        extra.extend_from_slice(Address::repeat_byte(1).as_slice());
+        extra.extend_from_slice(Address::repeat_byte(2).as_slice());
+        extra.extend_from_slice(&[0u8; EXTRA_SEAL_LEN]);
+
+        let layout = ExtraDataLayout { is_epoch: true, is_luban: false, is_bohr: false };
+        let set = parse_validators(&extra, layout).unwrap().unwrap();
+        assert_eq!(set.validators, vec![Address::repeat_byte(1), Address::repeat_byte(2)]);
+        assert!(set.vote_addresses.is_empty());
+        assert_eq!(set.turn_length, None);
+    }
+
+    #[test]
+    fn test_parse_validators_after_bohr() {
+        let validators = [
+            (Address::repeat_byte(1), VoteAddress::repeat_byte(0xa)),
+            (Address::repeat_byte(2), VoteAddress::repeat_byte(0xb)),
+        ];
+        let extra = luban_extra(&validators, &[0xc0]);
+
+        let set = parse_validators(&extra, LUBAN_EPOCH).unwrap().unwrap();
+        assert_eq!(set.validators, vec![Address::repeat_byte(1), Address::repeat_byte(2)]);
+        assert_eq!(set.vote_addresses[1], VoteAddress::repeat_byte(0xb));
+        assert_eq!(set.turn_length, Some(4));
+        assert_eq!(vote_attestation_bytes(&extra, LUBAN_EPOCH).unwrap(), Some(&[0xc0][..]));
+    }
+
+    #[test]
+    fn test_non_epoch_block() {
+        let mut extra = vec![0u8; EXTRA_VANITY_LEN];
+        extra.extend_from_slice(&[0xc0]);
+        extra.extend_from_slice(&[0u8; EXTRA_SEAL_LEN]);
+
+        let layout = ExtraDataLayout { is_epoch: false, ..LUBAN_EPOCH };
+        assert_eq!(parse_validators(&extra, layout).unwrap(), None);
+        assert_eq!(vote_attestation_bytes(&extra, layout).unwrap(), Some(&[0xc0][..]));
+    }
+
+    #[test]
+    fn test_invalid_extra_data() {
+        assert_eq!(parse_validators(&[0u8; 10], LUBAN_EPOCH), Err(ExtraDataError::TooShort(10)));
+        let extra = [0u8; EXTRA_VANITY_LEN + EXTRA_SEAL_LEN + 1];
+        assert_eq!(
+            parse_validators(&extra, LUBAN_EPOCH),
+            Err(ExtraDataError::InvalidValidatorBytes)
+        );
+    }
+}
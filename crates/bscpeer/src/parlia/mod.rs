@@ -0,0 +1,109 @@
+//! Parlia consensus helpers.
+pub mod extra_data;
+pub mod seal;
+pub mod snapshot;
+pub mod validation;
+pub mod vote;
+
+use crate::chain_config::hardfork::BscHardfork;
+use alloy_consensus::Header;
+use extra_data::{ExtraDataError, ExtraDataLayout, ValidatorSet};
+use reth_chainspec::ChainSpec;
+use seal::SealCache;
+use std::sync::Arc;
+
+/// Epoch length before Lorentz.
+pub const DEFAULT_EPOCH_LENGTH: u64 = 200;
+/// Epoch length from Lorentz on.
+pub const LORENTZ_EPOCH_LENGTH: u64 = 500;
+/// Epoch length from Maxwell on.
+pub const MAXWELL_EPOCH_LENGTH: u64 = 1000;
+
+/// Block interval before Lorentz.
+pub const DEFAULT_BLOCK_INTERVAL_MS: u64 = 3000;
+/// Block interval from Lorentz on.
+pub const LORENTZ_BLOCK_INTERVAL_MS: u64 = 1500;
+/// Block interval from Maxwell on.
+pub const MAXWELL_BLOCK_INTERVAL_MS: u64 = 750;
+
+/// Fork-aware access to Parlia header fields. Clones share the cache of recovered seals.
+#[derive(Debug, Clone)]
+pub struct Parlia {
+    chain_spec: Arc<ChainSpec>,
+    seals: Arc<SealCache>,
+}
+
+impl Parlia {
+    pub fn new(chain_spec: Arc<ChainSpec>) -> Self {
+        Self { chain_spec, seals: Arc::default() }
+    }
+
+    /// Returns the id of the chain, which the header seals commit to.
+    pub fn chain_id(&self) -> u64 {
+        self.chain_spec.chain.id()
+    }
+
+    /// Returns `true` if Luban is active at the given block.
+    pub fn is_luban(&self, number: u64) -> bool {
+        self.chain_spec.hardforks.fork(BscHardfork::Luban).active_at_block(number)
+    }
+
+    /// Returns `true` if Bohr is active at the given timestamp.
+    pub fn is_bohr(&self, timestamp: u64) -> bool {
+        self.chain_spec.hardforks.fork(BscHardfork::Bohr).active_at_timestamp(timestamp)
+    }
+
+    /// Returns `true` if Lorentz is active at the given timestamp.
+    pub fn is_lorentz(&self, timestamp: u64) -> bool {
+        self.chain_spec.hardforks.fork(BscHardfork::Lorentz).active_at_timestamp(timestamp)
+    }
+
+    /// Returns `true` if Maxwell is active at the given timestamp.
+    pub fn is_maxwell(&self, timestamp: u64) -> bool {
+        self.chain_spec.hardforks.fork(BscHardfork::Maxwell).active_at_timestamp(timestamp)
+    }
+
+    /// Returns the epoch length in effect at the given timestamp.
+    pub fn epoch_length(&self, timestamp: u64) -> u64 {
+        if self.is_maxwell(timestamp) {
+            MAXWELL_EPOCH_LENGTH
+        } else if self.is_lorentz(timestamp) {
+            LORENTZ_EPOCH_LENGTH
+        } else {
+            DEFAULT_EPOCH_LENGTH
+        }
+    }
+
+    /// Returns the minimum spacing between blocks in effect at the given timestamp.
+    pub fn block_interval_ms(&self, timestamp: u64) -> u64 {
+        if self.is_maxwell(timestamp) {
+            MAXWELL_BLOCK_INTERVAL_MS
+        } else if self.is_lorentz(timestamp) {
+            LORENTZ_BLOCK_INTERVAL_MS
+        } else {
+            DEFAULT_BLOCK_INTERVAL_MS
+        }
+    }
+
+    /// Returns `true` if the header is an epoch boundary carrying the validator set.
+    pub fn is_epoch_block(&self, header: &Header) -> bool {
+        header.number % self.epoch_length(header.timestamp) == 0
+    }
+
+    /// Returns the `extraData` layout of the header.
+    pub fn layout(&self, header: &Header) -> ExtraDataLayout {
+        ExtraDataLayout {
+            is_epoch: self.is_epoch_block(header),
+            is_luban: self.is_luban(header.number),
+            is_bohr: self.is_bohr(header.timestamp),
+        }
+    }
+
+    /// Parses the validator set from an epoch boundary header, `None` for other headers.
+    pub fn parse_validators(
+        &self,
+        header: &Header,
+    ) -> Result<Option<ValidatorSet>, ExtraDataError> {
+        extra_data::parse_validators(&header.extra_data, self.layout(header))
+    }
+}
@@ -0,0 +1,244 @@
+//! Recovery of the proposer from the header seal.
+//!
+//! The seal is the proposer's secp256k1 signature over the header, with the seal itself cut from
+//! `extraData` and the chain id prepended to prevent replays across BSC networks.
+//!
+//! Recovery is an ECDSA operation per header, so its results are kept in an LRU cache keyed by
+//! the header hash, and ranges of headers, e.g. for backfill, are recovered in parallel.
+use crate::parlia::{
+    Parlia,
+    extra_data::{self, EXTRA_SEAL_LEN, ExtraDataError},
+};
+use alloy_consensus::Header;
+use alloy_primitives::{Address, B256, keccak256};
+use alloy_rlp::Encodable;
+use metrics::counter;
+use rayon::prelude::*;
+use schnellru::{ByLength, LruMap};
+use secp256k1::{
+    Message, PublicKey, SECP256K1,
+    ecdsa::{RecoverableSignature, RecoveryId},
+};
+use std::{fmt, sync::Mutex};
+
+/// Recovered seals kept in the cache.
+pub const SEAL_CACHE_SIZE: u32 = 4096;
+
+/// Errors of a seal the proposer can't be recovered from.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SealError {
+    #[error(transparent)]
+    ExtraData(#[from] ExtraDataError),
+    #[error("invalid seal signature: {0}")]
+    Signature(#[from] secp256k1::Error),
+}
+
+/// Proposers recovered from the seals of recent headers, by header hash.
+pub struct SealCache(Mutex<LruMap<B256, Result<Address, SealError>>>);
+
+impl SealCache {
+    pub fn new(size: u32) -> Self {
+        Self(Mutex::new(LruMap::new(ByLength::new(size))))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, hash: &B256) -> Option<Result<Address, SealError>> {
+        let result = self.0.lock().unwrap().get(hash).cloned();
+        let outcome = if result.is_some() { "hit" } else { "miss" };
+        counter!("bscpeer_seal_cache_lookups_total", "outcome" => outcome).increment(1);
+        result
+    }
+}
+
+impl Default for SealCache {
+    fn default() -> Self {
+        Self::new(SEAL_CACHE_SIZE)
+    }
+}
+
+impl fmt::Debug for SealCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SealCache").field("len", &self.len()).finish()
+    }
+}
+
+/// Returns the hash the proposer signed.
+///
+/// From Cancun on, the header fields added since London are covered as well, which BSC signals
+/// with a zero parent beacon block root.
+pub fn seal_hash(header: &Header, chain_id: u64) -> B256 {
+    let extra = header.extra_data.slice(..header.extra_data.len().saturating_sub(EXTRA_SEAL_LEN));
+    let mut fields: Vec<&dyn Encodable> = vec![
+        &chain_id,
+        &header.parent_hash,
+        &header.ommers_hash,
+        &header.beneficiary,
+        &header.state_root,
+        &header.transactions_root,
+        &header.receipts_root,
+        &header.logs_bloom,
+        &header.difficulty,
+        &header.number,
+        &header.gas_limit,
+        &header.gas_used,
+        &header.timestamp,
+        &extra,
+        &header.mix_hash,
+        &header.nonce,
+    ];
+    let base_fee = header.base_fee_per_gas.unwrap_or_default();
+    let withdrawals_root = header.withdrawals_root.unwrap_or_default();
+    let blob_gas_used = header.blob_gas_used.unwrap_or_default();
+    let excess_blob_gas = header.excess_blob_gas.unwrap_or_default();
+    if header.parent_beacon_block_root == Some(B256::ZERO) {
+        fields.extend([
+            &base_fee as &dyn Encodable,
+            &withdrawals_root,
+            &blob_gas_used,
+            &excess_blob_gas,
+            &B256::ZERO,
+        ]);
+        if let Some(requests_hash) = &header.requests_hash {
+            fields.push(requests_hash);
+        }
+    }
+
+    let payload_length = fields.iter().map(|field| field.length()).sum();
+    let mut out = Vec::with_capacity(payload_length + 3);
+    alloy_rlp::Header { list: true, payload_length }.encode(&mut out);
+    for field in fields {
+        field.encode(&mut out);
+    }
+    keccak256(out)
+}
+
+/// Returns the address of a secp256k1 public key.
+pub fn public_key_address(public_key: &PublicKey) -> Address {
+    let hash = keccak256(&public_key.serialize_uncompressed()[1..]);
+    Address::from_slice(&hash[12..])
+}
+
+impl Parlia {
+    /// Recovers the validator that sealed the header.
+    pub fn recover_proposer(&self, header: &Header) -> Result<Address, SealError> {
+        let seal = extra_data::seal(&header.extra_data)?;
+        let recovery_id = RecoveryId::try_from(seal[64] as i32)?;
+        let signature = RecoverableSignature::from_compact(&seal[..64], recovery_id)?;
+        let message = Message::from_digest(seal_hash(header, self.chain_id()).0);
+        let public_key = SECP256K1.recover_ecdsa(&message, &signature)?;
+        Ok(public_key_address(&public_key))
+    }
+
+    /// Recovers the validator that sealed the header with the given hash, served from the cache
+    /// if the header was recovered before. The hash must be the header's own, it's trusted as the
+    /// cache key.
+    pub(crate) fn proposer(&self, header: &Header, hash: B256) -> Result<Address, SealError> {
+        if let Some(result) = self.seals.get(&hash) {
+            return result;
+        }
+        let result = self.recover_proposer(header);
+        self.seals.0.lock().unwrap().insert(hash, result.clone());
+        result
+    }
+
+    /// Recovers the validators that sealed a range of headers, in order. Headers missing from the
+    /// cache are recovered in parallel on the rayon pool, blocking the calling thread.
+    pub fn recover_proposers(&self, headers: &[Header]) -> Vec<Result<Address, SealError>> {
+        let hashes: Vec<_> = headers.par_iter().map(Header::hash_slow).collect();
+        let cached: Vec<_> = hashes.iter().map(|hash| self.seals.get(hash)).collect();
+        let results: Vec<_> = headers
+            .par_iter()
+            .zip(cached)
+            .map(|(header, cached)| cached.unwrap_or_else(|| self.recover_proposer(header)))
+            .collect();
+
+        let mut seals = self.seals.0.lock().unwrap();
+        for (hash, result) in hashes.into_iter().zip(&results) {
+            seals.insert(hash, result.clone());
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain_config::bsc::bsc_mainnet;
+    use crate::parlia::extra_data::EXTRA_VANITY_LEN;
+    use crate::testing::seal_header as seal;
+    use secp256k1::SecretKey;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_recover_proposer() {
+        let parlia = Parlia::new(Arc::new(bsc_mainnet()));
+        let secret_key = SecretKey::from_slice(&[7; 32]).unwrap();
+        let signer = public_key_address(&secret_key.public_key(SECP256K1));
+        let mut header = Header {
+            number: 100,
+            extra_data: vec![0u8; EXTRA_VANITY_LEN + EXTRA_SEAL_LEN].into(),
+            ..Default::default()
+        };
+        seal(&mut header, &secret_key, 56);
+        assert_eq!(parlia.recover_proposer(&header), Ok(signer));
+
+        // the Cancun fields are signed once the parent beacon block root is set
+        let mut cancun = Header { parent_beacon_block_root: Some(B256::ZERO), ..header.clone() };
+        assert_ne!(seal_hash(&cancun, 56), seal_hash(&header, 56));
+        seal(&mut cancun, &secret_key, 56);
+        assert_eq!(parlia.recover_proposer(&cancun), Ok(signer));
+
+        // a seal of another chain recovers another address
+        seal(&mut header, &secret_key, 97);
+        assert_ne!(parlia.recover_proposer(&header), Ok(signer));
+
+        let short = Header { extra_data: vec![0u8; 10].into(), ..Default::default() };
+        assert_eq!(
+            parlia.recover_proposer(&short),
+            Err(SealError::ExtraData(ExtraDataError::TooShort(10)))
+        );
+    }
+
+    #[test]
+    fn test_seal_cache() {
+        let parlia = Parlia::new(Arc::new(bsc_mainnet()));
+        let secret_key = SecretKey::from_slice(&[7; 32]).unwrap();
+        let signer = public_key_address(&secret_key.public_key(SECP256K1));
+        let headers: Vec<_> = (1..=3)
+            .map(|number| {
+                let mut header = Header {
+                    number,
+                    extra_data: vec![0u8; EXTRA_VANITY_LEN + EXTRA_SEAL_LEN].into(),
+                    ..Default::default()
+                };
+                seal(&mut header, &secret_key, 56);
+                header
+            })
+            .collect();
+
+        let first = &headers[0];
+        assert_eq!(parlia.proposer(first, first.hash_slow()), Ok(signer));
+        assert_eq!(parlia.seals.len(), 1);
+
+        // the batch recovers the rest and keeps the order
+        assert_eq!(parlia.recover_proposers(&headers), vec![Ok(signer); 3]);
+        assert_eq!(parlia.seals.len(), 3);
+
+        // recovered headers are served from the cache
+        let last = &headers[2];
+        assert_eq!(parlia.proposer(last, last.hash_slow()), Ok(signer));
+        assert_eq!(parlia.seals.len(), 3);
+
+        // failed recoveries are cached as well
+        let short = Header { extra_data: vec![0u8; 10].into(), ..Default::default() };
+        assert!(parlia.proposer(&short, short.hash_slow()).is_err());
+        assert_eq!(parlia.seals.len(), 4);
+    }
+}
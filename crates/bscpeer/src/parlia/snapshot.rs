@@ -0,0 +1,575 @@
+//! Parlia epoch snapshots: the active validator set, proposer rotation and recent signers.
+use crate::parlia::{
+    Parlia,
+    extra_data::{ValidatorSet, VoteAddress},
+    vote::{VoteAttestation, VoteData, VoteError},
+};
+use alloy_consensus::Header;
+use alloy_primitives::{Address, B256, U256};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tracing::{debug, info, warn};
+
+/// Default number of consecutive blocks a validator produces, before Bohr.
+pub const DEFAULT_TURN_LENGTH: u8 = 1;
+
+/// Difficulty of a block proposed by the in-turn validator.
+pub const DIFF_INTURN: U256 = U256::from_limbs([2, 0, 0, 0]);
+/// Difficulty of a block proposed by any other validator.
+pub const DIFF_NOTURN: U256 = U256::from_limbs([1, 0, 0, 0]);
+
+/// Errors that can occur while loading or persisting snapshots.
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    /// Reading or writing the snapshot file failed.
+    #[error("snapshot io error: {0}")]
+    Io(#[from] std::io::Error),
+    /// The snapshot file is malformed.
+    #[error("invalid snapshot file: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Whether a block was proposed by the validator whose turn it was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TurnStatus {
+    /// Proposed by the expected validator.
+    InTurn,
+    /// Proposed by another validator because the in-turn one was late or offline.
+    OutOfTurn,
+}
+
+impl TurnStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InTurn => "in_turn",
+            Self::OutOfTurn => "out_of_turn",
+        }
+    }
+}
+
+/// Errors of a header whose proposer doesn't match the snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ProposerError {
+    /// The proposer is not part of the active validator set.
+    #[error("block {number} proposed by unauthorized validator {proposer}")]
+    Unauthorized { number: u64, proposer: Address },
+    /// The proposer already produced its turn within the recent window.
+    #[error("block {number} proposed by recently signed validator {proposer}")]
+    SignedRecently { number: u64, proposer: Address },
+    /// The difficulty doesn't match the proposer's turn.
+    #[error("block {number} has difficulty {difficulty}, expected {expected}")]
+    WrongDifficulty { number: u64, difficulty: U256, expected: U256 },
+}
+
+impl ProposerError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Unauthorized { .. } => "unauthorized",
+            Self::SignedRecently { .. } => "signed_recently",
+            Self::WrongDifficulty { .. } => "wrong_difficulty",
+        }
+    }
+}
+
+/// Errors of a header that failed the consensus checks against the snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ConsensusError {
+    #[error(transparent)]
+    Proposer(#[from] ProposerError),
+    #[error(transparent)]
+    Vote(#[from] VoteError),
+}
+
+impl ConsensusError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Proposer(err) => err.as_str(),
+            Self::Vote(_) => "invalid_attestation",
+        }
+    }
+}
+
+/// Result of the consensus checks of an applied header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeaderStatus {
+    /// Whether the header was proposed in turn, `None` if it couldn't be checked.
+    pub turn_status: Option<TurnStatus>,
+    /// Validator that was in turn for the header, `None` without a snapshot.
+    pub inturn: Option<Address>,
+    /// Verified vote attestation of the header, justifying its parent.
+    pub attestation: Option<VoteData>,
+}
+
+/// Consensus state after applying the header at `number`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    /// Number of the last applied header.
+    pub number: u64,
+    /// Hash of the last applied header.
+    pub hash: B256,
+    /// Epoch length in effect.
+    pub epoch_length: u64,
+    /// Number of consecutive blocks each validator produces.
+    pub turn_length: u8,
+    /// Active validators, sorted ascending as used for the proposer rotation.
+    pub validators: Vec<Address>,
+    /// BLS vote addresses of the active validators, post-Luban.
+    pub vote_addresses: BTreeMap<Address, VoteAddress>,
+    /// Recent proposers by block number.
+    pub recents: BTreeMap<u64, Address>,
+    /// Validator set announced at an epoch block that has not taken effect yet.
+    pub pending: Option<(u64, ValidatorSet)>,
+    /// Latest verified vote attestation.
+    #[serde(default)]
+    pub attestation: Option<VoteData>,
+}
+
+impl Snapshot {
+    /// Creates a snapshot from the validator set announced at an epoch block.
+    pub fn new(number: u64, hash: B256, epoch_length: u64, set: ValidatorSet) -> Self {
+        let mut snapshot = Self {
+            number,
+            hash,
+            epoch_length,
+            turn_length: DEFAULT_TURN_LENGTH,
+            validators: Vec::new(),
+            vote_addresses: BTreeMap::new(),
+            recents: BTreeMap::new(),
+            pending: None,
+            attestation: None,
+        };
+        snapshot.set_validators(set);
+        snapshot
+    }
+
+    fn set_validators(&mut self, set: ValidatorSet) {
+        self.vote_addresses =
+            set.validators.iter().copied().zip(set.vote_addresses.iter().copied()).collect();
+        self.validators = set.validators;
+        self.validators.sort();
+        self.turn_length = set.turn_length.unwrap_or(DEFAULT_TURN_LENGTH).max(1);
+    }
+
+    /// Number of blocks after an epoch block until its validator set takes effect, which is also
+    /// the window of the recently-signed check.
+    pub fn miner_history_check_len(&self) -> u64 {
+        (self.validators.len() / 2 + 1) as u64 * self.turn_length as u64 - 1
+    }
+
+    /// Returns the validator expected to propose the block `number` in turn.
+    pub fn inturn_validator(&self, number: u64) -> Option<Address> {
+        if self.validators.is_empty() {
+            return None;
+        }
+        let offset = (number / self.turn_length as u64) % self.validators.len() as u64;
+        Some(self.validators[offset as usize])
+    }
+
+    /// Returns `true` if the validator is part of the active set.
+    pub fn is_validator(&self, validator: &Address) -> bool {
+        self.validators.binary_search(validator).is_ok()
+    }
+
+    /// Returns `true` if the validator already produced its full turn within the recent window
+    /// before block `number` and must not propose it.
+    ///
+    /// Only proposers still inside the window are counted, i.e. those [`Snapshot::apply`] keeps
+    /// after applying block `number`.
+    pub fn signed_recently(&self, validator: &Address, number: u64) -> bool {
+        let limit = self.miner_history_check_len() + 1;
+        let count = self
+            .recents
+            .iter()
+            .filter(|(n, v)| **n < number && **n + limit > number && *v == validator)
+            .count();
+        count >= self.turn_length as usize
+    }
+
+    /// Checks the proposer and difficulty of a header following the snapshot.
+    ///
+    /// The proposer is the validator recovered from the seal, see
+    /// [`validate_seal`](crate::parlia::validation::validate_seal). The recent signers are only checked on the header directly following
+    /// the snapshot; after a gap the history is reset by [`Snapshot::apply`] anyway.
+    pub fn check_proposer(
+        &self,
+        header: &Header,
+        proposer: Address,
+    ) -> Result<TurnStatus, ProposerError> {
+        let number = header.number;
+        if !self.is_validator(&proposer) {
+            return Err(ProposerError::Unauthorized { number, proposer });
+        }
+        if number == self.number + 1 && self.signed_recently(&proposer, number) {
+            return Err(ProposerError::SignedRecently { number, proposer });
+        }
+
+        let (status, expected) = if self.inturn_validator(number) == Some(proposer) {
+            (TurnStatus::InTurn, DIFF_INTURN)
+        } else {
+            (TurnStatus::OutOfTurn, DIFF_NOTURN)
+        };
+        if header.difficulty != expected {
+            return Err(ProposerError::WrongDifficulty {
+                number,
+                difficulty: header.difficulty,
+                expected,
+            });
+        }
+        Ok(status)
+    }
+
+    /// Returns the vote addresses of the active validators, in the order of the sorted set.
+    pub fn ordered_vote_addresses(&self) -> Vec<Option<VoteAddress>> {
+        self.validators
+            .iter()
+            .map(|validator| self.vote_addresses.get(validator).copied())
+            .collect()
+    }
+
+    /// Decodes and verifies the vote attestation of the header directly following the snapshot.
+    ///
+    /// The attestation must justify the snapshot's block, build on the latest justified block if
+    /// known, and be signed by at least two thirds of the active validators. The signature check
+    /// is skipped if it already passed against the same `verified_votes`, e.g. on a worker.
+    pub fn check_attestation(
+        &self,
+        header: &Header,
+        parlia: &Parlia,
+        verified_votes: Option<&[Option<VoteAddress>]>,
+    ) -> Result<Option<VoteData>, VoteError> {
+        let Some(attestation) =
+            VoteAttestation::from_extra_data(&header.extra_data, parlia.layout(header))?
+        else {
+            return Ok(None);
+        };
+        let data = attestation.data;
+        if data.target_number != self.number || data.target_hash != self.hash {
+            return Err(VoteError::TargetMismatch {
+                number: data.target_number,
+                hash: data.target_hash,
+            });
+        }
+        if let Some(justified) = &self.attestation {
+            if data.source_number != justified.target_number
+                || data.source_hash != justified.target_hash
+            {
+                return Err(VoteError::SourceMismatch {
+                    number: data.source_number,
+                    hash: data.source_hash,
+                });
+            }
+        }
+
+        let vote_addresses = self.ordered_vote_addresses();
+        if verified_votes != Some(vote_addresses.as_slice()) {
+            attestation.verify(&vote_addresses)?;
+        }
+        Ok(Some(data))
+    }
+
+    /// Applies the next header sealed by `proposer`, advancing the proposer history and switching validator sets at
+    /// the end of the transition window.
+    ///
+    /// Headers at or below the snapshot height are ignored. If headers were skipped the recent
+    /// signer history can't be trusted and is reset.
+    pub fn apply(
+        &mut self,
+        header: &Header,
+        hash: B256,
+        proposer: Address,
+        parlia: &Parlia,
+    ) -> bool {
+        let number = header.number;
+        if number <= self.number {
+            return false;
+        }
+        if number > self.number + 1 {
+            debug!(from = self.number, to = number, "gap in snapshot headers, reset recents");
+            self.recents.clear();
+            self.attestation = None;
+        }
+
+        self.recents.insert(number, proposer);
+        let limit = self.miner_history_check_len() + 1;
+        self.recents.retain(|n, _| n + limit > number);
+
+        match parlia.parse_validators(header) {
+            Ok(Some(set)) => self.pending = Some((number, set)),
+            Ok(None) => {}
+            Err(err) => warn!(number, %err, "invalid validator set in epoch header"),
+        }
+        if let Some((epoch_block, _)) = &self.pending {
+            if number >= epoch_block + self.miner_history_check_len() {
+                let (epoch_block, set) = self.pending.take().expect("pending set exists");
+                info!(epoch_block, validators = set.validators.len(), "switch validator set");
+                self.set_validators(set);
+            }
+        }
+
+        self.epoch_length = parlia.epoch_length(header.timestamp);
+        self.number = number;
+        self.hash = hash;
+        true
+    }
+}
+
+/// Holds the current snapshot and persists it to disk whenever the validator set changes.
+///
+/// The file is written on the blocking thread pool, off the import path, to a temporary file
+/// renamed over the previous one, so a crash never leaves a partly written snapshot behind.
+#[derive(Debug, Default)]
+pub struct SnapshotStore {
+    path: Option<PathBuf>,
+    current: Option<Snapshot>,
+    pending: Arc<Mutex<PendingWrite>>,
+}
+
+/// Serialized snapshot waiting to be written, and whether a writer is running.
+#[derive(Debug, Default)]
+struct PendingWrite {
+    snapshot: Option<Vec<u8>>,
+    writing: bool,
+}
+
+impl SnapshotStore {
+    /// Loads the persisted snapshot, if any.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, SnapshotError> {
+        let path = path.into();
+        let current = if path.exists() {
+            Some(serde_json::from_slice(&std::fs::read(&path)?)?)
+        } else {
+            None
+        };
+        Ok(Self { path: Some(path), current, pending: Arc::default() })
+    }
+
+    /// Returns the current snapshot.
+    pub fn current(&self) -> Option<&Snapshot> {
+        self.current.as_ref()
+    }
+
+    /// Validates the proposer recovered from the seal of a newly received header and applies it.
+    ///
+    /// Until the first epoch block is seen there is no snapshot; the validator set announced by
+    /// that block is used right away. A snapshot that fell behind by more than an epoch may have
+    /// missed a validator set change and is dropped.
+    ///
+    /// Headers failing the proposer or vote attestation checks are not applied. Attestations can
+    /// only be verified on headers directly following the snapshot, see
+    /// [`Snapshot::check_attestation`] for `verified_votes`.
+    pub fn apply(
+        &mut self,
+        header: &Header,
+        hash: B256,
+        proposer: Address,
+        parlia: &Parlia,
+        verified_votes: Option<&[Option<VoteAddress>]>,
+    ) -> Result<HeaderStatus, ConsensusError> {
+        if let Some(snapshot) = &self.current {
+            if header.number > snapshot.number + snapshot.epoch_length {
+                warn!(
+                    snapshot = snapshot.number,
+                    number = header.number,
+                    "snapshot is stale, waiting for the next epoch block"
+                );
+                self.current = None;
+            }
+        }
+
+        let mut status = HeaderStatus::default();
+        let changed = match &mut self.current {
+            Some(snapshot) => {
+                if header.number > snapshot.number {
+                    status.turn_status = Some(snapshot.check_proposer(header, proposer)?);
+                    status.inturn = snapshot.inturn_validator(header.number);
+                }
+                if header.number == snapshot.number + 1 && header.parent_hash == snapshot.hash {
+                    status.attestation =
+                        snapshot.check_attestation(header, parlia, verified_votes)?;
+                }
+                // an epoch block was announced or its validator set took effect
+                let pending_before = snapshot.pending.as_ref().map(|(number, _)| *number);
+                let changed = snapshot.apply(header, hash, proposer, parlia)
+                    && snapshot.pending.as_ref().map(|(number, _)| *number) != pending_before;
+                if status.attestation.is_some() {
+                    snapshot.attestation = status.attestation;
+                }
+                changed
+            }
+            None => match parlia.parse_validators(header) {
+                Ok(Some(set)) => {
+                    let epoch_length = parlia.epoch_length(header.timestamp);
+                    info!(
+                        number = header.number,
+                        validators = set.validators.len(),
+                        "create snapshot"
+                    );
+                    self.current = Some(Snapshot::new(header.number, hash, epoch_length, set));
+                    true
+                }
+                _ => false,
+            },
+        };
+
+        if changed {
+            if let Err(err) = self.save() {
+                warn!(%err, "failed to persist snapshot");
+            }
+        }
+        Ok(status)
+    }
+
+    /// Schedules writing the current snapshot. A write already running picks it up once done,
+    /// skipping the snapshots it replaced.
+    fn save(&self) -> Result<(), SnapshotError> {
+        let (Some(path), Some(snapshot)) = (&self.path, &self.current) else { return Ok(()) };
+        let bytes = serde_json::to_vec_pretty(snapshot)?;
+        {
+            let mut pending = self.pending.lock().unwrap();
+            pending.snapshot = Some(bytes);
+            if pending.writing {
+                return Ok(());
+            }
+            pending.writing = true;
+        }
+        let (path, pending) = (path.clone(), self.pending.clone());
+        let write = move || {
+            loop {
+                let bytes = {
+                    let mut pending = pending.lock().unwrap();
+                    let Some(bytes) = pending.snapshot.take() else {
+                        pending.writing = false;
+                        return;
+                    };
+                    bytes
+                };
+                if let Err(err) = write_file(&path, &bytes) {
+                    warn!(%err, path = %path.display(), "failed to persist snapshot");
+                }
+            }
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(write)),
+            Err(_) => write(),
+        }
+        Ok(())
+    }
+}
+
+/// Writes the file through a temporary file next to it, renamed over it once complete.
+fn write_file(path: &Path, bytes: &[u8]) -> Result<(), SnapshotError> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, bytes)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(validators: &[u8], turn_length: u8) -> Snapshot {
+        let set = ValidatorSet {
+            validators: validators.iter().map(|b| Address::repeat_byte(*b)).collect(),
+            vote_addresses: Vec::new(),
+            turn_length: Some(turn_length),
+        };
+        Snapshot::new(0, B256::ZERO, 1000, set)
+    }
+
+    #[test]
+    fn test_inturn_validator() {
+        let snapshot = snapshot(&[3, 1, 2], 4);
+        // validators are sorted, each produces four consecutive blocks
+        assert_eq!(snapshot.inturn_validator(0), Some(Address::repeat_byte(1)));
+        assert_eq!(snapshot.inturn_validator(3), Some(Address::repeat_byte(1)));
+        assert_eq!(snapshot.inturn_validator(4), Some(Address::repeat_byte(2)));
+        assert_eq!(snapshot.inturn_validator(12), Some(Address::repeat_byte(1)));
+    }
+
+    #[test]
+    fn test_signed_recently() {
+        let mut snapshot = snapshot(&[1, 2, 3], 1);
+        assert_eq!(snapshot.miner_history_check_len(), 1);
+        snapshot.recents.insert(1, Address::repeat_byte(1));
+        assert!(snapshot.signed_recently(&Address::repeat_byte(1), 2));
+        assert!(!snapshot.signed_recently(&Address::repeat_byte(2), 2));
+        // the proposer of block 1 drops out of the window at block 3
+        assert!(!snapshot.signed_recently(&Address::repeat_byte(1), 3));
+    }
+
+    #[test]
+    fn test_check_proposer_recents() {
+        let mut snapshot = snapshot(&[1, 2, 3], 1);
+        snapshot.number = 2;
+        snapshot.recents.insert(1, Address::repeat_byte(1));
+        snapshot.recents.insert(2, Address::repeat_byte(2));
+        let header = |number: u64, proposer: u8, difficulty: U256| Header {
+            number,
+            beneficiary: Address::repeat_byte(proposer),
+            difficulty,
+            ..Default::default()
+        };
+        let check = |header: Header| snapshot.check_proposer(&header, header.beneficiary);
+
+        // validator 1 signed block 1, which is outside the window of block 3
+        assert_eq!(check(header(3, 1, DIFF_INTURN)), Ok(TurnStatus::InTurn));
+        // validator 2 signed block 2, still inside the window
+        assert_eq!(
+            check(header(3, 2, DIFF_NOTURN)),
+            Err(ProposerError::SignedRecently { number: 3, proposer: Address::repeat_byte(2) })
+        );
+        // after a gap the recents are stale and not checked
+        assert_eq!(check(header(5, 2, DIFF_NOTURN)), Ok(TurnStatus::OutOfTurn));
+    }
+
+    #[test]
+    fn test_check_proposer() {
+        let snapshot = snapshot(&[1, 2, 3], 1);
+        let header = |proposer: u8, difficulty: U256| Header {
+            number: 1,
+            beneficiary: Address::repeat_byte(proposer),
+            difficulty,
+            ..Default::default()
+        };
+        let check = |header: Header| snapshot.check_proposer(&header, header.beneficiary);
+
+        assert_eq!(check(header(2, DIFF_INTURN)), Ok(TurnStatus::InTurn));
+        assert_eq!(check(header(3, DIFF_NOTURN)), Ok(TurnStatus::OutOfTurn));
+        assert!(matches!(
+            check(header(3, DIFF_INTURN)),
+            Err(ProposerError::WrongDifficulty { .. })
+        ));
+        assert!(matches!(check(header(4, DIFF_INTURN)), Err(ProposerError::Unauthorized { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_save() {
+        let dir = std::env::temp_dir().join(format!("bscpeer-snapshot-{}", std::process::id()));
+        let path = dir.join("snapshot.json");
+        let mut store = SnapshotStore::load(&path).unwrap();
+        assert!(store.current().is_none());
+        for number in 1..=3 {
+            store.current = Some(Snapshot { number, ..snapshot(&[1, 2], 1) });
+            store.save().unwrap();
+        }
+        // the writes run in the background, the last one wins
+        while store.pending.lock().unwrap().writing {
+            tokio::task::yield_now().await;
+        }
+        let loaded = SnapshotStore::load(&path).unwrap();
+        assert_eq!(loaded.current().map(|snapshot| snapshot.number), Some(3));
+        assert!(!dir.join("snapshot.json.tmp").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
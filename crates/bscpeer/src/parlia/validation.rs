@@ -0,0 +1,347 @@
+//! Lightweight structural checks of Parlia headers, done before any consensus state is touched.
+use crate::parlia::{
+    Parlia,
+    extra_data::{self, ExtraDataError},
+    seal::SealError,
+    snapshot::{DIFF_INTURN, DIFF_NOTURN},
+};
+use alloy_consensus::{
+    EMPTY_OMMER_ROOT_HASH, Header, ReceiptWithBloom,
+    proofs::{calculate_receipt_root, calculate_transaction_root},
+};
+use alloy_eips::eip2718::Encodable2718;
+use alloy_primitives::{Address, B256, B64, U256};
+use reth_ethereum_primitives::Receipt;
+
+/// Bound divisor of the gas limit change between consecutive blocks.
+pub const GAS_LIMIT_BOUND_DIVISOR: u64 = 256;
+/// Minimum gas limit of a block.
+pub const MIN_GAS_LIMIT: u64 = 5000;
+/// Maximum gas limit of a block.
+pub const MAX_GAS_LIMIT: u64 = 0x7fffffffffffffff;
+
+/// Errors of a header violating the BSC header invariants.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum HeaderError {
+    #[error(transparent)]
+    ExtraData(#[from] ExtraDataError),
+    #[error("non-empty ommers hash {0}")]
+    NonEmptyOmmers(B256),
+    #[error("non-zero nonce {0}")]
+    NonZeroNonce(B64),
+    #[error("non-zero mix digest {0} before Lorentz")]
+    NonZeroMixDigest(B256),
+    #[error("invalid difficulty {0}")]
+    InvalidDifficulty(U256),
+    #[error("gas used {gas_used} exceeds gas limit {gas_limit}")]
+    GasUsedExceedsLimit { gas_used: u64, gas_limit: u64 },
+    #[error("gas limit {0} out of bounds")]
+    GasLimitOutOfBounds(u64),
+    #[error("gas limit {gas_limit} changed too much from parent gas limit {parent}")]
+    GasLimitChange { gas_limit: u64, parent: u64 },
+    #[error("block number {number} does not follow parent {parent}")]
+    InvalidNumber { number: u64, parent: u64 },
+    #[error("timestamp {timestamp} is before parent timestamp {parent}")]
+    TimestampBeforeParent { timestamp: u64, parent: u64 },
+    #[error("block interval {interval_ms}ms is shorter than {min_ms}ms")]
+    IntervalTooShort { interval_ms: u64, min_ms: u64 },
+    #[error("transactions root mismatch: header {expected}, body {got}")]
+    TransactionsRootMismatch { expected: B256, got: B256 },
+    #[error("receipts root mismatch: header {expected}, receipts {got}")]
+    ReceiptsRootMismatch { expected: B256, got: B256 },
+    #[error("cumulative gas used {got} does not match header gas used {expected}")]
+    GasUsedMismatch { expected: u64, got: u64 },
+    #[error("block {number} is {got}, contradicting checkpoint {expected}")]
+    CheckpointMismatch { number: u64, expected: B256, got: B256 },
+    #[error(transparent)]
+    Seal(#[from] SealError),
+    #[error("block sealed by {signer}, not its coinbase {coinbase}")]
+    SealMismatch { signer: Address, coinbase: Address },
+}
+
+impl HeaderError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ExtraData(_) => "invalid_extra_data",
+            Self::NonEmptyOmmers(_) => "non_empty_ommers",
+            Self::NonZeroNonce(_) => "non_zero_nonce",
+            Self::NonZeroMixDigest(_) => "non_zero_mix_digest",
+            Self::InvalidDifficulty(_) => "invalid_difficulty",
+            Self::GasUsedExceedsLimit { .. } => "gas_used_exceeds_limit",
+            Self::GasLimitOutOfBounds(_) => "gas_limit_out_of_bounds",
+            Self::GasLimitChange { .. } => "gas_limit_change",
+            Self::InvalidNumber { .. } => "invalid_number",
+            Self::TimestampBeforeParent { .. } => "timestamp_before_parent",
+            Self::IntervalTooShort { .. } => "interval_too_short",
+            Self::TransactionsRootMismatch { .. } => "transactions_root_mismatch",
+            Self::ReceiptsRootMismatch { .. } => "receipts_root_mismatch",
+            Self::GasUsedMismatch { .. } => "gas_used_mismatch",
+            Self::CheckpointMismatch { .. } => "checkpoint_mismatch",
+            Self::Seal(_) => "invalid_seal",
+            Self::SealMismatch { .. } => "seal_mismatch",
+        }
+    }
+}
+
+impl Parlia {
+    /// Checks the fields of a header that don't depend on any other block.
+    ///
+    /// Parlia blocks never have ommers, leave the nonce zero and carry a difficulty of either
+    /// [`DIFF_INTURN`] or [`DIFF_NOTURN`]. Before Lorentz the mix digest is zero as well; from
+    /// Lorentz on it carries the millisecond part of the timestamp.
+    pub fn validate_header(&self, header: &Header) -> Result<(), HeaderError> {
+        extra_data::seal(&header.extra_data)?;
+        if header.ommers_hash != EMPTY_OMMER_ROOT_HASH {
+            return Err(HeaderError::NonEmptyOmmers(header.ommers_hash));
+        }
+        if header.nonce != B64::ZERO {
+            return Err(HeaderError::NonZeroNonce(header.nonce));
+        }
+        if !self.is_lorentz(header.timestamp) && header.mix_hash != B256::ZERO {
+            return Err(HeaderError::NonZeroMixDigest(header.mix_hash));
+        }
+        if header.number > 0
+            && header.difficulty != DIFF_INTURN
+            && header.difficulty != DIFF_NOTURN
+        {
+            return Err(HeaderError::InvalidDifficulty(header.difficulty));
+        }
+        if !(MIN_GAS_LIMIT..=MAX_GAS_LIMIT).contains(&header.gas_limit) {
+            return Err(HeaderError::GasLimitOutOfBounds(header.gas_limit));
+        }
+        if header.gas_used > header.gas_limit {
+            return Err(HeaderError::GasUsedExceedsLimit {
+                gas_used: header.gas_used,
+                gas_limit: header.gas_limit,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Returns the header timestamp in milliseconds.
+///
+/// From Lorentz on, the mix digest carries the millisecond part of the timestamp.
+pub fn millis_timestamp(header: &Header) -> u64 {
+    let millis = U256::from_be_bytes(header.mix_hash.0).saturating_to::<u64>();
+    header.timestamp * 1000 + millis
+}
+
+impl Parlia {
+    /// Checks that a header correctly links to its parent.
+    ///
+    /// The gas limit may change by less than `1/GAS_LIMIT_BOUND_DIVISOR` of the parent's per
+    /// block, and blocks must be at least the block interval of the active fork apart.
+    pub fn validate_against_parent(
+        &self,
+        header: &Header,
+        parent: &Header,
+    ) -> Result<(), HeaderError> {
+        if header.number != parent.number + 1 {
+            return Err(HeaderError::InvalidNumber {
+                number: header.number,
+                parent: parent.number,
+            });
+        }
+        if header.gas_limit.abs_diff(parent.gas_limit) >= parent.gas_limit / GAS_LIMIT_BOUND_DIVISOR
+        {
+            return Err(HeaderError::GasLimitChange {
+                gas_limit: header.gas_limit,
+                parent: parent.gas_limit,
+            });
+        }
+        if header.timestamp < parent.timestamp {
+            return Err(HeaderError::TimestampBeforeParent {
+                timestamp: header.timestamp,
+                parent: parent.timestamp,
+            });
+        }
+        let interval_ms = millis_timestamp(header).saturating_sub(millis_timestamp(parent));
+        let min_ms = self.block_interval_ms(header.timestamp);
+        if interval_ms < min_ms {
+            return Err(HeaderError::IntervalTooShort { interval_ms, min_ms });
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the transactions of a body hash to the header's `transactionsRoot`.
+pub fn validate_transactions_root<T: Encodable2718>(
+    header: &Header,
+    transactions: &[T],
+) -> Result<(), HeaderError> {
+    let got = calculate_transaction_root(transactions);
+    if got != header.transactions_root {
+        let expected = header.transactions_root;
+        return Err(HeaderError::TransactionsRootMismatch { expected, got });
+    }
+    Ok(())
+}
+
+/// Checks the proposer recovered from the header seal, which Parlia requires to be the coinbase,
+/// and returns it.
+pub fn validate_seal(
+    header: &Header,
+    proposer: &Result<Address, SealError>,
+) -> Result<Address, HeaderError> {
+    let signer = proposer.clone()?;
+    if signer != header.beneficiary {
+        return Err(HeaderError::SealMismatch { signer, coinbase: header.beneficiary });
+    }
+    Ok(signer)
+}
+
+/// Checks that the receipts of a block hash to the header's `receiptsRoot` and add up to its gas
+/// used.
+pub fn validate_receipts(
+    header: &Header,
+    receipts: &[ReceiptWithBloom<Receipt>],
+) -> Result<(), HeaderError> {
+    let got = calculate_receipt_root(receipts);
+    if got != header.receipts_root {
+        let expected = header.receipts_root;
+        return Err(HeaderError::ReceiptsRootMismatch { expected, got });
+    }
+    let gas_used = receipts.last().map_or(0, |r| r.receipt.cumulative_gas_used);
+    if gas_used != header.gas_used {
+        return Err(HeaderError::GasUsedMismatch { expected: header.gas_used, got: gas_used });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain_config::bsc::bsc_mainnet;
+    use crate::parlia::extra_data::{EXTRA_SEAL_LEN, EXTRA_VANITY_LEN};
+    use std::sync::Arc;
+
+    fn header() -> Header {
+        Header {
+            number: 1,
+            ommers_hash: EMPTY_OMMER_ROOT_HASH,
+            difficulty: DIFF_INTURN,
+            gas_limit: 140_000_000,
+            extra_data: vec![0u8; EXTRA_VANITY_LEN + EXTRA_SEAL_LEN].into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_validate_header() {
+        let parlia = Parlia::new(Arc::new(bsc_mainnet()));
+        assert_eq!(parlia.validate_header(&header()), Ok(()));
+
+        let uncles = Header { ommers_hash: B256::ZERO, ..header() };
+        assert!(matches!(parlia.validate_header(&uncles), Err(HeaderError::NonEmptyOmmers(_))));
+
+        let nonce = Header { nonce: B64::repeat_byte(1), ..header() };
+        assert!(matches!(parlia.validate_header(&nonce), Err(HeaderError::NonZeroNonce(_))));
+
+        let mix_hash = Header { mix_hash: B256::repeat_byte(1), ..header() };
+        assert!(matches!(
+            parlia.validate_header(&mix_hash),
+            Err(HeaderError::NonZeroMixDigest(_))
+        ));
+
+        let difficulty = Header { difficulty: U256::from(3), ..header() };
+        assert!(matches!(
+            parlia.validate_header(&difficulty),
+            Err(HeaderError::InvalidDifficulty(_))
+        ));
+
+        let gas_limit = Header { gas_limit: MIN_GAS_LIMIT - 1, ..header() };
+        assert!(matches!(
+            parlia.validate_header(&gas_limit),
+            Err(HeaderError::GasLimitOutOfBounds(_))
+        ));
+
+        let gas_used = Header { gas_used: 140_000_001, ..header() };
+        assert!(matches!(
+            parlia.validate_header(&gas_used),
+            Err(HeaderError::GasUsedExceedsLimit { .. })
+        ));
+    }
+
+    #[test]
+    fn test_validate_against_parent() {
+        let parlia = Parlia::new(Arc::new(bsc_mainnet()));
+        let parent = Header { number: 1, timestamp: 10, ..header() };
+        let child = Header { number: 2, timestamp: 13, ..header() };
+        assert_eq!(parlia.validate_against_parent(&child, &parent), Ok(()));
+        let limit = parent.gas_limit / GAS_LIMIT_BOUND_DIVISOR;
+        let raised = Header { gas_limit: parent.gas_limit + limit - 1, ..child.clone() };
+        assert_eq!(parlia.validate_against_parent(&raised, &parent), Ok(()));
+        let lowered = Header { gas_limit: parent.gas_limit - limit, ..child.clone() };
+        assert!(matches!(
+            parlia.validate_against_parent(&lowered, &parent),
+            Err(HeaderError::GasLimitChange { .. })
+        ));
+        assert!(matches!(
+            parlia.validate_against_parent(&Header { number: 3, ..child.clone() }, &parent),
+            Err(HeaderError::InvalidNumber { .. })
+        ));
+        assert!(matches!(
+            parlia.validate_against_parent(&Header { timestamp: 9, ..child.clone() }, &parent),
+            Err(HeaderError::TimestampBeforeParent { .. })
+        ));
+        assert_eq!(
+            parlia.validate_against_parent(&Header { timestamp: 12, ..child }, &parent),
+            Err(HeaderError::IntervalTooShort { interval_ms: 2000, min_ms: 3000 })
+        );
+    }
+
+    #[test]
+    fn test_validate_seal() {
+        let header = Header { beneficiary: Address::repeat_byte(1), ..header() };
+        assert_eq!(validate_seal(&header, &Ok(Address::repeat_byte(1))), Ok(header.beneficiary));
+        assert_eq!(
+            validate_seal(&header, &Ok(Address::repeat_byte(2))),
+            Err(HeaderError::SealMismatch {
+                signer: Address::repeat_byte(2),
+                coinbase: Address::repeat_byte(1)
+            })
+        );
+        let invalid = Err(SealError::ExtraData(ExtraDataError::TooShort(0)));
+        assert!(matches!(validate_seal(&header, &invalid), Err(HeaderError::Seal(_))));
+    }
+
+    #[test]
+    fn test_millis_timestamp() {
+        let mut mix_hash = B256::ZERO;
+        mix_hash.0[31] = 250;
+        let header = Header { timestamp: 10, mix_hash, ..header() };
+        assert_eq!(millis_timestamp(&header), 10_250);
+    }
+
+    #[test]
+    fn test_validate_transactions_root() {
+        let transactions: Vec<reth_ethereum_primitives::TransactionSigned> = Vec::new();
+        let empty = Header { transactions_root: alloy_consensus::EMPTY_ROOT_HASH, ..header() };
+        assert_eq!(validate_transactions_root(&empty, &transactions), Ok(()));
+        assert!(matches!(
+            validate_transactions_root(&header(), &transactions),
+            Err(HeaderError::TransactionsRootMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_validate_receipts() {
+        let receipt = Receipt { success: true, cumulative_gas_used: 21_000, ..Default::default() };
+        let receipts = vec![ReceiptWithBloom::from(receipt)];
+        let header = Header {
+            receipts_root: calculate_receipt_root(&receipts),
+            gas_used: 21_000,
+            ..header()
+        };
+        assert_eq!(validate_receipts(&header, &receipts), Ok(()));
+        assert!(matches!(
+            validate_receipts(&Header { gas_used: 42_000, ..header.clone() }, &receipts),
+            Err(HeaderError::GasUsedMismatch { .. })
+        ));
+        assert!(matches!(
+            validate_receipts(&header, &[]),
+            Err(HeaderError::ReceiptsRootMismatch { .. })
+        ));
+    }
+}
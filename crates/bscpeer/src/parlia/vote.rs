@@ -0,0 +1,181 @@
+//! Fast finality vote attestations carried in Parlia `extraData`.
+//!
+//! From Luban on, every header may carry an aggregated BLS signature of the validators that voted
+//! for its parent. A valid attestation justifies its target block and finalizes its source block.
+use crate::parlia::extra_data::{self, ExtraDataError, ExtraDataLayout, VoteAddress};
+use alloy_primitives::{B256, Bytes, FixedBytes, keccak256};
+use alloy_rlp::{Decodable, RlpDecodable, RlpEncodable};
+use blst::{
+    BLST_ERROR,
+    min_pk::{PublicKey, Signature},
+};
+use serde::{Deserialize, Serialize};
+
+/// Length of a compressed BLS signature.
+pub const BLS_SIGNATURE_LEN: usize = 96;
+
+/// Domain separation tag used by validators to sign votes.
+pub const BLS_DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+pub type BlsSignature = FixedBytes<BLS_SIGNATURE_LEN>;
+
+/// Errors of a malformed or invalid vote attestation.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum VoteError {
+    #[error(transparent)]
+    ExtraData(#[from] ExtraDataError),
+    #[error("invalid vote attestation encoding: {0}")]
+    Rlp(#[from] alloy_rlp::Error),
+    #[error("vote target {number} ({hash}) is not the parent block")]
+    TargetMismatch { number: u64, hash: B256 },
+    #[error("vote source {number} ({hash}) is not the justified block")]
+    SourceMismatch { number: u64, hash: B256 },
+    #[error("vote from unknown validator index {0}")]
+    UnknownVoter(usize),
+    #[error("not enough votes: {votes}, required {required}")]
+    InsufficientVotes { votes: usize, required: usize },
+    #[error("invalid BLS vote address")]
+    InvalidVoteAddress,
+    #[error("invalid aggregated vote signature")]
+    InvalidSignature,
+}
+
+/// The pair of blocks a vote is cast for.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, RlpEncodable, RlpDecodable, Serialize, Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct VoteData {
+    /// Latest justified block the vote builds on.
+    pub source_number: u64,
+    pub source_hash: B256,
+    /// Block the vote justifies.
+    pub target_number: u64,
+    pub target_hash: B256,
+}
+
+impl VoteData {
+    /// Returns the message signed by the voters.
+    pub fn hash(&self) -> B256 {
+        keccak256(alloy_rlp::encode(self))
+    }
+}
+
+/// Aggregated votes embedded in a header.
+#[derive(Debug, Clone, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct VoteAttestation {
+    /// Bit set of the voters, indexed by their position in the sorted validator set.
+    pub vote_address_set: u64,
+    /// Aggregated signature of all voters.
+    pub agg_signature: BlsSignature,
+    pub data: VoteData,
+    pub extra: Bytes,
+}
+
+impl VoteAttestation {
+    /// Decodes the attestation from a header's `extraData`, `None` if the header carries none.
+    pub fn from_extra_data(
+        extra_data: &[u8],
+        layout: ExtraDataLayout,
+    ) -> Result<Option<Self>, VoteError> {
+        match extra_data::vote_attestation_bytes(extra_data, layout)? {
+            Some(mut bytes) => Ok(Some(Self::decode(&mut bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the indices of the validators that voted.
+    pub fn voters(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..u64::BITS as usize).filter(|i| self.vote_address_set >> i & 1 == 1)
+    }
+
+    /// Verifies the aggregated signature against the vote addresses of the active validators,
+    /// given in the order of the sorted validator set.
+    ///
+    /// At least two thirds of the validators must have voted.
+    pub fn verify(&self, vote_addresses: &[Option<VoteAddress>]) -> Result<(), VoteError> {
+        let voters: Vec<usize> = self.voters().collect();
+        let required = (vote_addresses.len() * 2).div_ceil(3);
+        if voters.len() < required {
+            return Err(VoteError::InsufficientVotes { votes: voters.len(), required });
+        }
+
+        let mut public_keys = Vec::with_capacity(voters.len());
+        for index in voters {
+            let vote_address = vote_addresses
+                .get(index)
+                .ok_or(VoteError::UnknownVoter(index))?
+                .ok_or(VoteError::InvalidVoteAddress)?;
+            let public_key = PublicKey::from_bytes(vote_address.as_slice())
+                .map_err(|_| VoteError::InvalidVoteAddress)?;
+            public_keys.push(public_key);
+        }
+
+        let signature = Signature::from_bytes(self.agg_signature.as_slice())
+            .map_err(|_| VoteError::InvalidSignature)?;
+        let public_keys: Vec<&PublicKey> = public_keys.iter().collect();
+        match signature.fast_aggregate_verify(
+            true,
+            self.data.hash().as_slice(),
+            BLS_DST,
+            &public_keys,
+        ) {
+            BLST_ERROR::BLST_SUCCESS => Ok(()),
+            _ => Err(VoteError::InvalidSignature),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blst::min_pk::{AggregateSignature, SecretKey};
+
+    fn secret_key(seed: u8) -> SecretKey {
+        SecretKey::key_gen(&[seed; 32], &[]).unwrap()
+    }
+
+    fn attestation(signers: &[u8], vote_address_set: u64, data: VoteData) -> VoteAttestation {
+        let signatures: Vec<Signature> = signers
+            .iter()
+            .map(|seed| secret_key(*seed).sign(data.hash().as_slice(), BLS_DST, &[]))
+            .collect();
+        let signatures: Vec<&Signature> = signatures.iter().collect();
+        let agg_signature = AggregateSignature::aggregate(&signatures, true).unwrap();
+        VoteAttestation {
+            vote_address_set,
+            agg_signature: BlsSignature::from(agg_signature.to_signature().to_bytes()),
+            data,
+            extra: Bytes::new(),
+        }
+    }
+
+    #[test]
+    fn test_verify_attestation() {
+        let vote_addresses: Vec<Option<VoteAddress>> = (1..=3)
+            .map(|seed| Some(VoteAddress::from(secret_key(seed).sk_to_pk().to_bytes())))
+            .collect();
+        let data = VoteData {
+            source_number: 9,
+            source_hash: B256::repeat_byte(9),
+            target_number: 10,
+            target_hash: B256::repeat_byte(10),
+        };
+
+        let valid = attestation(&[1, 3], 0b101, data);
+        assert_eq!(valid.voters().collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(valid.verify(&vote_addresses), Ok(()));
+
+        let encoded = alloy_rlp::encode(&valid);
+        assert_eq!(VoteAttestation::decode(&mut encoded.as_slice()).unwrap(), valid);
+
+        let wrong_voters = attestation(&[1, 3], 0b011, data);
+        assert_eq!(wrong_voters.verify(&vote_addresses), Err(VoteError::InvalidSignature));
+
+        let too_few = attestation(&[1], 0b001, data);
+        assert_eq!(
+            too_few.verify(&vote_addresses),
+            Err(VoteError::InsufficientVotes { votes: 1, required: 2 })
+        );
+    }
+}
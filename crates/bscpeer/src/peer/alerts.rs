@@ -0,0 +1,99 @@
+//! Alerts on the block sync falling behind the network or stalling.
+//!
+//! A node that silently lags still emits events, just late or not at all, so consumers can't
+//! tell a quiet chain from a broken feed. [`SyncMonitor`] compares the tracked height with the
+//! highest announced block and times the last new block, raising an event when either exceeds
+//! its threshold. Each alert is raised once per episode; the gauges carry the current values.
+use crate::{config::AlertsConfig, peer::blockstate::BlockEvent};
+use metrics::{counter, gauge};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Checks the sync progress against the alert thresholds.
+#[derive(Debug)]
+pub struct SyncMonitor {
+    max_gap: u64,
+    stall_after: Option<Duration>,
+    /// Highest block seen at the last check.
+    highest_seen: u64,
+    /// When the highest seen block last advanced.
+    last_progress: Instant,
+    lagging: bool,
+    stalled: bool,
+}
+
+impl SyncMonitor {
+    pub fn new(config: &AlertsConfig, now: Instant) -> Self {
+        Self {
+            max_gap: config.max_gap,
+            stall_after: (config.stall_secs > 0).then(|| Duration::from_secs(config.stall_secs)),
+            highest_seen: 0,
+            last_progress: now,
+            lagging: false,
+            stalled: false,
+        }
+    }
+
+    /// Updates the progress, returning the alerts entered since the last check.
+    pub fn check(&mut self, height: u64, highest_seen: u64, now: Instant) -> Vec<BlockEvent> {
+        let mut alerts = Vec::new();
+
+        let gap = highest_seen.saturating_sub(height);
+        gauge!("bscpeer_sync_gap").set(gap as f64);
+        let lagging = self.max_gap > 0 && gap > self.max_gap;
+        if lagging && !self.lagging {
+            counter!("bscpeer_sync_alerts_total", "kind" => "gap").increment(1);
+            alerts.push(BlockEvent::SyncGap { height, highest_seen, gap });
+        } else if !lagging && self.lagging {
+            info!(height, highest_seen, "block sync caught up");
+        }
+        self.lagging = lagging;
+
+        if highest_seen > self.highest_seen {
+            self.highest_seen = highest_seen;
+            self.last_progress = now;
+        }
+        let idle = now.duration_since(self.last_progress);
+        gauge!("bscpeer_sync_idle_seconds").set(idle.as_secs_f64());
+        let stalled = self.stall_after.is_some_and(|stall_after| idle > stall_after);
+        if stalled && !self.stalled {
+            counter!("bscpeer_sync_alerts_total", "kind" => "stall").increment(1);
+            alerts.push(BlockEvent::SyncStalled { height, idle_secs: idle.as_secs() });
+        } else if !stalled && self.stalled {
+            info!(height, highest_seen, "new block seen after stall");
+        }
+        self.stalled = stalled;
+
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gap_and_stall() {
+        let now = Instant::now();
+        let config = AlertsConfig { max_gap: 10, stall_secs: 30, missed_blocks: 0 };
+        let mut monitor = SyncMonitor::new(&config, now);
+        assert!(monitor.check(100, 105, now).is_empty());
+
+        // raised once while the gap persists
+        let alerts = monitor.check(100, 120, now + Duration::from_secs(10));
+        assert!(matches!(alerts[..], [BlockEvent::SyncGap { gap: 20, .. }]));
+        assert!(monitor.check(101, 120, now + Duration::from_secs(20)).is_empty());
+
+        let alerts = monitor.check(118, 120, now + Duration::from_secs(41));
+        assert!(matches!(alerts[..], [BlockEvent::SyncStalled { height: 118, idle_secs: 31 }]));
+        assert!(monitor.check(119, 120, now + Duration::from_secs(50)).is_empty());
+
+        // a new block ends the stall, a later one can raise it again
+        assert!(monitor.check(121, 121, now + Duration::from_secs(60)).is_empty());
+        assert_eq!(monitor.check(121, 121, now + Duration::from_secs(91)).len(), 1);
+
+        let config = AlertsConfig { max_gap: 0, stall_secs: 0, missed_blocks: 0 };
+        let mut disabled = SyncMonitor::new(&config, now);
+        assert!(disabled.check(0, 1000, now + Duration::from_secs(1000)).is_empty());
+    }
+}
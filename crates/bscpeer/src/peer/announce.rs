@@ -0,0 +1,92 @@
+//! Announcing blocks the node fetched itself to the peers that don't have them yet.
+//!
+//! [`KnownBlocks`] remembers the recent blocks each peer sent or announced to us, or served when
+//! we fetched them, so a block is announced to every other peer exactly once.
+use crate::peer::{transactions::RecentHashes, wire::trace_wire};
+use alloy_primitives::B256;
+use metrics::counter;
+use reth_eth_wire::{BlockHashNumber, NewBlockHashes};
+use reth_network::{EthNetworkPrimitives, NetworkHandle, message::PeerMessage};
+use reth_network_api::Peers;
+use reth_network_peers::PeerId;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tracing::{debug, warn};
+
+/// Number of recent block hashes remembered per peer.
+const KNOWN_BLOCKS_PER_PEER: usize = 512;
+
+/// Recent blocks known by each connected peer, shared between the importer and the sync tasks.
+#[derive(Debug, Clone, Default)]
+pub struct KnownBlocks {
+    peers: Arc<Mutex<HashMap<PeerId, RecentHashes>>>,
+}
+
+impl KnownBlocks {
+    /// Marks the block as known by the peer, returning `false` if it already was.
+    pub fn insert(&self, peer_id: PeerId, hash: B256) -> bool {
+        self.peers
+            .lock()
+            .unwrap()
+            .entry(peer_id)
+            .or_insert_with(|| RecentHashes::new(KNOWN_BLOCKS_PER_PEER))
+            .insert(hash)
+    }
+
+    /// Forgets the blocks known by a disconnected peer.
+    pub fn remove_peer(&self, peer_id: &PeerId) {
+        self.peers.lock().unwrap().remove(peer_id);
+    }
+
+    /// Announces the block by hash to the connected peers not known to have it, returning the
+    /// number of peers it was announced to.
+    pub async fn announce(
+        &self,
+        network: &NetworkHandle<EthNetworkPrimitives>,
+        hash: B256,
+        number: u64,
+    ) -> usize {
+        let peers = match network.get_all_peers().await {
+            Ok(peers) => peers,
+            Err(e) => {
+                warn!(block_number = number, "failed to list peers to announce block: {}", e);
+                return 0;
+            }
+        };
+        let announcement = NewBlockHashes(vec![BlockHashNumber { hash, number }]);
+        let mut announced = 0;
+        for peer in peers {
+            if self.insert(peer.remote_id, hash) {
+                trace_wire!(Some(peer.remote_id), Outbound, NewBlockHashes, &announcement);
+                network.send_eth_message(
+                    peer.remote_id,
+                    PeerMessage::NewBlockHashes(announcement.clone()),
+                );
+                announced += 1;
+            }
+        }
+        counter!("bscpeer_block_announcements_total").increment(announced as u64);
+        debug!(block_number = number, block_hash = %hash, announced, "announced block");
+        announced
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_blocks() {
+        let known = KnownBlocks::default();
+        let (peer, other) = (PeerId::repeat_byte(1), PeerId::repeat_byte(2));
+        let hash = B256::with_last_byte(1);
+        assert!(known.insert(peer, hash));
+        assert!(!known.insert(peer, hash));
+        assert!(known.insert(other, hash));
+
+        known.remove_peer(&peer);
+        assert!(known.insert(peer, hash));
+    }
+}
@@ -0,0 +1,194 @@
+//! Detection of blocks deviating from the recent chain behaviour.
+//!
+//! The interval to the parent block and the gas used are compared to rolling baselines of the
+//! previous blocks, flagging values more than the configured number of standard deviations away
+//! from the mean. Streaks of blocks without transactions are flagged once they reach the
+//! configured length, which points at censoring validators or a stuck mempool.
+use crate::{config::AnomaliesConfig, parlia::validation, peer::blockstate::BlockEvent};
+use alloy_consensus::Header;
+use alloy_primitives::B256;
+use metrics::{counter, gauge};
+use serde::Serialize;
+use std::collections::VecDeque;
+
+/// A block deviating from the baseline.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "anomaly", rename_all = "snake_case", rename_all_fields = "camelCase")]
+pub enum Anomaly {
+    /// The block came unusually early or late after its parent.
+    BlockTime {
+        interval_ms: u64,
+        mean_ms: f64,
+        /// Standard deviations from the mean.
+        sigmas: f64,
+    },
+    /// The block used unusually little or much gas.
+    GasUsed { gas_used: u64, mean: f64, sigmas: f64 },
+    /// The block is the last of a streak without transactions.
+    EmptyBlocks { streak: u64 },
+}
+
+impl Anomaly {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::BlockTime { .. } => "block_time",
+            Self::GasUsed { .. } => "gas_used",
+            Self::EmptyBlocks { .. } => "empty_blocks",
+        }
+    }
+}
+
+/// Rolling window of the latest values of a metric.
+#[derive(Debug)]
+struct Baseline {
+    values: VecDeque<f64>,
+    capacity: usize,
+}
+
+impl Baseline {
+    fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self { values: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    /// Returns the mean and the deviation of the value from it in standard deviations, `None`
+    /// until the window holds `min_samples` values or while they are all equal.
+    fn deviation(&self, value: f64, min_samples: usize) -> Option<(f64, f64)> {
+        if self.values.is_empty() || self.values.len() < min_samples {
+            return None;
+        }
+        let count = self.values.len() as f64;
+        let mean = self.values.iter().sum::<f64>() / count;
+        let variance = self.values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / count;
+        let std_dev = variance.sqrt();
+        (std_dev > 0.0).then(|| (mean, (value - mean) / std_dev))
+    }
+
+    fn push(&mut self, value: f64) {
+        if self.values.len() == self.capacity {
+            self.values.pop_front();
+        }
+        self.values.push_back(value);
+    }
+}
+
+/// Checks each imported block against the baselines of the previous ones.
+#[derive(Debug)]
+pub struct AnomalyDetector {
+    /// Deviation flagged as anomalous, disabled when zero.
+    sigma: f64,
+    min_samples: usize,
+    /// Streak of empty blocks flagged as anomalous, disabled when zero.
+    empty_blocks: u64,
+    block_time: Baseline,
+    gas_used: Baseline,
+    /// Hash and timestamp in milliseconds of the previous block.
+    parent: Option<(B256, u64)>,
+    empty_streak: u64,
+}
+
+impl AnomalyDetector {
+    pub fn new(config: &AnomaliesConfig) -> Self {
+        Self {
+            sigma: config.sigma as f64,
+            min_samples: config.min_samples,
+            empty_blocks: config.empty_blocks,
+            block_time: Baseline::new(config.window),
+            gas_used: Baseline::new(config.window),
+            parent: None,
+            empty_streak: 0,
+        }
+    }
+
+    /// Records an imported block, returning the anomalies it shows. The block time is only
+    /// tracked between consecutive imports of parent and child.
+    pub fn record(&mut self, header: &Header, hash: B256, transactions: usize) -> Vec<BlockEvent> {
+        let mut anomalies = Vec::new();
+        let timestamp_ms = validation::millis_timestamp(header);
+        let parent = self.parent.replace((hash, timestamp_ms));
+        let interval_ms = parent
+            .filter(|(parent_hash, _)| *parent_hash == header.parent_hash)
+            .map(|(_, parent_ms)| timestamp_ms.saturating_sub(parent_ms));
+        if let Some(interval_ms) = interval_ms {
+            let value = interval_ms as f64;
+            if let Some((mean_ms, sigmas)) = self.check(&self.block_time, value) {
+                anomalies.push(Anomaly::BlockTime { interval_ms, mean_ms, sigmas });
+            }
+            self.block_time.push(value);
+        }
+
+        let value = header.gas_used as f64;
+        if let Some((mean, sigmas)) = self.check(&self.gas_used, value) {
+            anomalies.push(Anomaly::GasUsed { gas_used: header.gas_used, mean, sigmas });
+        }
+        self.gas_used.push(value);
+
+        self.empty_streak = if transactions == 0 { self.empty_streak + 1 } else { 0 };
+        gauge!("bscpeer_empty_block_streak").set(self.empty_streak as f64);
+        // flagged once per streak
+        if self.empty_blocks > 0 && self.empty_streak == self.empty_blocks {
+            anomalies.push(Anomaly::EmptyBlocks { streak: self.empty_streak });
+        }
+
+        anomalies
+            .into_iter()
+            .map(|anomaly| {
+                counter!("bscpeer_block_anomalies_total", "anomaly" => anomaly.as_str())
+                    .increment(1);
+                BlockEvent::Anomaly { block_number: header.number, block_hash: hash, anomaly }
+            })
+            .collect()
+    }
+
+    /// Returns the mean and deviation of a value beyond the threshold.
+    fn check(&self, baseline: &Baseline, value: f64) -> Option<(f64, f64)> {
+        if self.sigma == 0.0 {
+            return None;
+        }
+        baseline.deviation(value, self.min_samples).filter(|(_, sigmas)| sigmas.abs() >= self.sigma)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(events: &[BlockEvent]) -> Vec<&'static str> {
+        events
+            .iter()
+            .map(|event| match event {
+                BlockEvent::Anomaly { anomaly, .. } => anomaly.as_str(),
+                _ => panic!("unexpected event {event:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_anomalies() {
+        let config = AnomaliesConfig { window: 10, min_samples: 4, sigma: 3, empty_blocks: 3 };
+        let mut detector = AnomalyDetector::new(&config);
+        let mut parent_hash = B256::ZERO;
+        let mut record = |number: u64, timestamp, gas_used, transactions| {
+            let header = Header { number, parent_hash, timestamp, gas_used, ..Default::default() };
+            let hash = B256::with_last_byte(number as u8);
+            parent_hash = hash;
+            kinds(&detector.record(&header, hash, transactions))
+        };
+
+        // alternating around 3s and 1M gas, nothing is flagged while the baselines fill
+        for number in 1..=8 {
+            let jitter = number % 2;
+            let events = record(number, number * 3 + jitter, 1_000_000 + jitter * 1000, 10);
+            assert!(events.is_empty(), "block {number}: {events:?}");
+        }
+        // a block 30s late using no gas
+        assert_eq!(record(9, 54, 0, 1), ["block_time", "gas_used"]);
+
+        // empty blocks are flagged once per streak
+        let mut flagged = Vec::new();
+        for number in 10..=14 {
+            flagged.extend(record(number, 54 + (number - 9) * 3, 0, 0));
+        }
+        assert_eq!(flagged.iter().filter(|kind| **kind == "empty_blocks").count(), 1);
+    }
+}
@@ -0,0 +1,166 @@
+//! Global bandwidth caps of the block sync and relays.
+//!
+//! reth owns the sockets, so the traffic can't be shaped on the wire. Instead each direction
+//! has a token bucket charged with the estimated size of the messages the node sends or asks
+//! for: a fetch waits while the download bucket is in deficit, and a relay is skipped while the
+//! upload one is. The bucket may go into deficit by a single large block, which the following
+//! requests then pay off.
+use crate::config::BandwidthConfig;
+use metrics::counter;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Burst a bucket allows after being idle, in seconds of its rate.
+const BURST_SECS: f64 = 1.0;
+/// Bytes of a single block hash announcement, charged to the upload cap.
+pub const ANNOUNCEMENT_SIZE: usize = 48;
+
+/// Returns the bytes the network sends relaying a block of `size` bytes to `peers` peers: the
+/// block in full to the square root of them, plus one, and its hash to the others.
+pub fn relay_size(size: usize, peers: usize) -> usize {
+    let full = ((peers as f64).sqrt() as usize + 1).min(peers);
+    full * size + (peers - full) * ANNOUNCEMENT_SIZE
+}
+
+/// Token bucket in bytes.
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64, now: Instant) -> Self {
+        let rate = rate as f64;
+        Self { rate, tokens: rate * BURST_SECS, updated: now }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate * BURST_SECS);
+        self.updated = now;
+    }
+
+    /// Returns how long until the deficit is paid off, zero if there is none.
+    fn delay(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+
+    fn consume(&mut self, bytes: usize, now: Instant) {
+        self.refill(now);
+        self.tokens -= bytes as f64;
+    }
+}
+
+/// Cap of one direction, shared by the tasks using it. Unlimited by default.
+#[derive(Debug, Clone, Default)]
+pub struct Throttle {
+    bucket: Option<Arc<Mutex<Bucket>>>,
+    direction: &'static str,
+}
+
+impl Throttle {
+    /// Creates a cap of `rate` bytes per second, unlimited if zero.
+    pub fn new(direction: &'static str, rate: u64) -> Self {
+        let bucket = (rate > 0).then(|| Arc::new(Mutex::new(Bucket::new(rate, Instant::now()))));
+        Self { bucket, direction }
+    }
+
+    /// Returns how long until the cap allows more traffic.
+    pub fn delay(&self, now: Instant) -> Duration {
+        self.bucket.as_ref().map_or(Duration::ZERO, |bucket| bucket.lock().unwrap().delay(now))
+    }
+
+    /// Waits until the cap allows more traffic.
+    pub async fn wait(&self) {
+        let delay = self.delay(Instant::now());
+        if !delay.is_zero() {
+            counter!("bscpeer_bandwidth_throttled_total", "direction" => self.direction)
+                .increment(1);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Charges the cap with transferred bytes.
+    pub fn consume(&self, bytes: usize) {
+        counter!("bscpeer_bandwidth_bytes_total", "direction" => self.direction)
+            .increment(bytes as u64);
+        if let Some(bucket) = &self.bucket {
+            bucket.lock().unwrap().consume(bytes, Instant::now());
+        }
+    }
+
+    /// Charges the cap with bytes about to be sent, unless it is over the cap. Returns whether
+    /// the bytes may be sent.
+    pub fn try_consume(&self, bytes: usize) -> bool {
+        if !self.delay(Instant::now()).is_zero() {
+            counter!("bscpeer_bandwidth_throttled_total", "direction" => self.direction)
+                .increment(1);
+            return false;
+        }
+        self.consume(bytes);
+        true
+    }
+}
+
+/// Upload and download caps.
+#[derive(Debug, Clone, Default)]
+pub struct Bandwidth {
+    pub upload: Throttle,
+    pub download: Throttle,
+}
+
+impl Bandwidth {
+    pub fn new(config: &BandwidthConfig) -> Self {
+        Self {
+            upload: Throttle::new("upload", config.upload),
+            download: Throttle::new("download", config.download),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(1000, now);
+        assert_eq!(bucket.delay(now), Duration::ZERO);
+
+        // a large message puts the bucket into deficit, paid off at the rate
+        bucket.consume(3000, now);
+        assert_eq!(bucket.delay(now), Duration::from_secs(2));
+        assert_eq!(bucket.delay(now + Duration::from_secs(1)), Duration::from_secs(1));
+        assert_eq!(bucket.delay(now + Duration::from_secs(2)), Duration::ZERO);
+
+        // idle time refills at most the burst
+        bucket.refill(now + Duration::from_secs(60));
+        assert_eq!(bucket.tokens, 1000.0);
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let throttle = Throttle::new("upload", 0);
+        throttle.consume(usize::MAX);
+        assert!(throttle.try_consume(1 << 30));
+        assert_eq!(throttle.delay(Instant::now()), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_relay_size() {
+        assert_eq!(relay_size(1000, 0), 0);
+        assert_eq!(relay_size(1000, 1), 1000);
+        // four peers get the block, the other five its hash
+        assert_eq!(relay_size(1000, 9), 4 * 1000 + 5 * ANNOUNCEMENT_SIZE);
+    }
+}
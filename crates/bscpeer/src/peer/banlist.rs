@@ -0,0 +1,207 @@
+//! Ban list of peer ids and IP addresses that is persisted across restarts.
+use reth_network_peers::PeerId;
+use serde::{Deserialize, Serialize};
+use serde_with::{DisplayFromStr, serde_as};
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Errors that can occur while loading or persisting the ban list.
+#[derive(Debug, thiserror::Error)]
+pub enum BanListError {
+    /// Reading or writing the ban list file failed.
+    #[error("ban list io error: {0}")]
+    Io(#[from] std::io::Error),
+    /// The ban list file is malformed.
+    #[error("invalid ban list file: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// A banned peer id or IP address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BanTarget {
+    /// A peer, identified by its node id.
+    Peer(PeerId),
+    /// Any peer connecting from this IP address.
+    Ip(IpAddr),
+}
+
+impl fmt::Display for BanTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Peer(peer_id) => write!(f, "{peer_id}"),
+            Self::Ip(ip) => write!(f, "{ip}"),
+        }
+    }
+}
+
+impl FromStr for BanTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            return Ok(Self::Ip(ip));
+        }
+        s.parse::<PeerId>()
+            .map(Self::Peer)
+            .map_err(|_| format!("`{s}` is neither a peer id nor an IP address"))
+    }
+}
+
+/// A single persisted ban.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BanEntry {
+    /// The banned peer id or IP address.
+    #[serde_as(as = "DisplayFromStr")]
+    pub target: BanTarget,
+    /// Unix timestamp in seconds at which the ban expires, `None` for a permanent ban.
+    pub expires_at: Option<u64>,
+}
+
+/// Ban list backed by a JSON file.
+#[derive(Debug, Default)]
+pub struct BanList {
+    path: Option<PathBuf>,
+    entries: HashMap<BanTarget, Option<u64>>,
+}
+
+impl BanList {
+    /// Loads the ban list from the given file, starting empty if it does not exist yet.
+    ///
+    /// Expired entries are dropped on load.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, BanListError> {
+        let path = path.into();
+        let mut entries = HashMap::new();
+        if path.exists() {
+            let contents = std::fs::read(&path)?;
+            let now = unix_now();
+            for entry in serde_json::from_slice::<Vec<BanEntry>>(&contents)? {
+                if entry.expires_at.is_none_or(|expires_at| expires_at > now) {
+                    entries.insert(entry.target, entry.expires_at);
+                }
+            }
+        }
+        Ok(Self { path: Some(path), entries })
+    }
+
+    /// Bans the target for the given duration, or permanently if `None`, and persists the list.
+    pub fn ban(
+        &mut self,
+        target: BanTarget,
+        duration: Option<Duration>,
+    ) -> Result<(), BanListError> {
+        let expires_at = duration.map(|d| unix_now() + d.as_secs());
+        self.entries.insert(target, expires_at);
+        self.save()
+    }
+
+    /// Lifts the ban on the target and persists the list.
+    ///
+    /// Returns `false` if the target was not banned.
+    pub fn unban(&mut self, target: &BanTarget) -> Result<bool, BanListError> {
+        if self.entries.remove(target).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Returns `true` if the target is currently banned.
+    pub fn is_banned(&self, target: &BanTarget) -> bool {
+        self.entries
+            .get(target)
+            .is_some_and(|expires_at| expires_at.is_none_or(|expires_at| expires_at > unix_now()))
+    }
+
+    /// Returns `true` if either the peer id or its IP address is currently banned.
+    pub fn is_peer_banned(&self, peer_id: PeerId, ip: IpAddr) -> bool {
+        self.is_banned(&BanTarget::Peer(peer_id)) || self.is_banned(&BanTarget::Ip(ip))
+    }
+
+    /// Returns all active bans.
+    pub fn entries(&self) -> Vec<BanEntry> {
+        let now = unix_now();
+        self.entries
+            .iter()
+            .filter(|(_, expires_at)| expires_at.is_none_or(|expires_at| expires_at > now))
+            .map(|(target, expires_at)| BanEntry { target: *target, expires_at: *expires_at })
+            .collect()
+    }
+
+    /// Converts the active bans into a reth ban list, which the network consults before dialing
+    /// and when accepting inbound connections.
+    pub fn to_reth_ban_list(&self) -> reth_net_banlist::BanList {
+        let now = unix_now();
+        let deadline = |expires_at: Option<u64>| {
+            expires_at.map(|t| Instant::now() + Duration::from_secs(t.saturating_sub(now)))
+        };
+
+        let mut banned_peers = HashMap::new();
+        let mut banned_ips = HashMap::new();
+        for entry in self.entries() {
+            match entry.target {
+                BanTarget::Peer(peer_id) => {
+                    banned_peers.insert(peer_id, deadline(entry.expires_at));
+                }
+                BanTarget::Ip(ip) => {
+                    banned_ips.insert(ip, deadline(entry.expires_at));
+                }
+            }
+        }
+        reth_net_banlist::BanList::new_with_timeout(banned_peers, banned_ips)
+    }
+
+    fn save(&self) -> Result<(), BanListError> {
+        let Some(path) = &self.path else { return Ok(()) };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_vec_pretty(&self.entries())?)?;
+        Ok(())
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ban_target() {
+        assert_eq!("10.0.0.1".parse::<BanTarget>(), Ok(BanTarget::Ip([10, 0, 0, 1].into())));
+        let peer_id = PeerId::repeat_byte(0x11);
+        assert_eq!(peer_id.to_string().parse::<BanTarget>(), Ok(BanTarget::Peer(peer_id)));
+        assert!("not-a-peer".parse::<BanTarget>().is_err());
+    }
+
+    #[test]
+    fn test_ban_list_persists() {
+        let path =
+            std::env::temp_dir().join(format!("bscpeer-banlist-{}.json", std::process::id()));
+        let peer_id = PeerId::repeat_byte(0x11);
+        let ip: IpAddr = [10, 0, 0, 1].into();
+
+        let mut ban_list = BanList::load(&path).unwrap();
+        ban_list.ban(BanTarget::Peer(peer_id), None).unwrap();
+        ban_list.ban(BanTarget::Ip(ip), Some(Duration::from_secs(3600))).unwrap();
+        assert!(ban_list.is_peer_banned(peer_id, [10, 0, 0, 2].into()));
+
+        let mut ban_list = BanList::load(&path).unwrap();
+        assert!(ban_list.is_banned(&BanTarget::Peer(peer_id)));
+        assert!(ban_list.is_banned(&BanTarget::Ip(ip)));
+
+        assert!(ban_list.unban(&BanTarget::Peer(peer_id)).unwrap());
+        assert!(!BanList::load(&path).unwrap().is_banned(&BanTarget::Peer(peer_id)));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,271 @@
+//! Download benchmark of selected peers, behind the `benchmark` subcommand.
+//!
+//! Every peer is asked for the headers of its latest blocks, walking back from its head in
+//! batches, then for their bodies one at a time. The latency of each request and the throughput
+//! of each phase tell apart peers worth adding as static peers from slow or flaky ones.
+use crate::{
+    config::Config,
+    peer::{fetch, ping},
+};
+use alloy_consensus::Header;
+use alloy_primitives::B256;
+use alloy_rlp::Encodable;
+use reth_network::{
+    EthNetworkPrimitives, NetworkEvent, NetworkEventListenerProvider, NetworkHandle,
+};
+use reth_network_api::Peers;
+use reth_network_peers::{NodeRecord, PeerId};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio_stream::StreamExt;
+
+/// What to download from every peer.
+#[derive(Debug, Clone)]
+pub struct BenchmarkSettings {
+    /// Blocks to download, counting back from the head of the peer.
+    pub blocks: u64,
+    /// Headers per request.
+    pub batch: u64,
+    /// How long to wait for the sessions.
+    pub connect_timeout: Duration,
+}
+
+/// Request statistics of a download phase.
+#[derive(Debug, Clone, Default)]
+pub struct RequestStats {
+    latencies: Vec<Duration>,
+    failures: u64,
+    items: u64,
+    bytes: u64,
+    elapsed: Duration,
+}
+
+impl RequestStats {
+    fn record(&mut self, latency: Duration, items: u64, bytes: u64) {
+        self.latencies.push(latency);
+        self.items += items;
+        self.bytes += bytes;
+    }
+
+    /// Returns the latency below which the given fraction of the requests completed.
+    pub fn percentile(&self, fraction: f64) -> Option<Duration> {
+        let mut latencies = self.latencies.clone();
+        latencies.sort_unstable();
+        let rank = (fraction * latencies.len() as f64).ceil() as usize;
+        latencies.get(rank.saturating_sub(1)).copied()
+    }
+
+    pub fn summary(&self) -> RequestSummary {
+        let secs = self.elapsed.as_secs_f64();
+        let rate = |value: u64| if secs > 0.0 { value as f64 / secs } else { 0.0 };
+        let ms = |latency: Option<Duration>| latency.map(|latency| latency.as_secs_f64() * 1000.0);
+        RequestSummary {
+            requests: self.latencies.len() as u64 + self.failures,
+            failures: self.failures,
+            items: self.items,
+            bytes: self.bytes,
+            items_per_sec: rate(self.items),
+            bytes_per_sec: rate(self.bytes),
+            p50_ms: ms(self.percentile(0.5)),
+            p90_ms: ms(self.percentile(0.9)),
+            p99_ms: ms(self.percentile(0.99)),
+            max_ms: ms(self.latencies.iter().max().copied()),
+        }
+    }
+}
+
+/// Summary of a download phase, with the latencies of the successful requests in milliseconds.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestSummary {
+    pub requests: u64,
+    pub failures: u64,
+    /// Headers or bodies received.
+    pub items: u64,
+    /// RLP size of the received items.
+    pub bytes: u64,
+    pub items_per_sec: f64,
+    pub bytes_per_sec: f64,
+    pub p50_ms: Option<f64>,
+    pub p90_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+    pub max_ms: Option<f64>,
+}
+
+/// Benchmark result of a peer.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerBenchmark {
+    pub peer_id: PeerId,
+    pub client_version: Option<Arc<str>>,
+    /// Why the benchmark of the peer stopped early.
+    pub error: Option<String>,
+    pub headers: RequestSummary,
+    pub bodies: RequestSummary,
+}
+
+/// Connects to the peers with the chain settings of the config and benchmarks them
+/// concurrently, returning their results in the given order.
+pub async fn run(
+    config: &Config,
+    peers: Vec<NodeRecord>,
+    settings: &BenchmarkSettings,
+) -> Result<Vec<PeerBenchmark>, ping::PingError> {
+    let (network, task) = ping::spawn_network(config, None).await?;
+    let mut events = network.event_listener();
+    for peer in &peers {
+        network.add_peer(peer.id, peer.tcp_addr());
+    }
+
+    // head and client of every peer with an established session
+    let mut sessions = HashMap::new();
+    let _ = tokio::time::timeout(settings.connect_timeout, async {
+        while sessions.len() < peers.len() {
+            let Some(event) = events.next().await else { break };
+            if let NetworkEvent::ActivePeerSession { info, .. } = event {
+                sessions.insert(info.peer_id, (info.status.blockhash, info.client_version));
+            }
+        }
+    })
+    .await;
+
+    let results = futures::future::join_all(peers.iter().map(|peer| {
+        let session = sessions.get(&peer.id).cloned();
+        let network = &network;
+        async move {
+            match session {
+                Some((head, client_version)) => {
+                    let mut result = benchmark_peer(network, peer.id, head, settings).await;
+                    result.client_version = Some(client_version);
+                    result
+                }
+                None => PeerBenchmark {
+                    peer_id: peer.id,
+                    client_version: None,
+                    error: Some("no session established".to_string()),
+                    headers: RequestStats::default().summary(),
+                    bodies: RequestStats::default().summary(),
+                },
+            }
+        }
+    }))
+    .await;
+    task.abort();
+    Ok(results)
+}
+
+/// Downloads the headers, then the bodies of the latest blocks of the peer.
+pub async fn benchmark_peer(
+    network: &NetworkHandle<EthNetworkPrimitives>,
+    peer_id: PeerId,
+    head: B256,
+    settings: &BenchmarkSettings,
+) -> PeerBenchmark {
+    let mut error = None;
+    let mut headers = RequestStats::default();
+    let mut downloaded: Vec<Header> = Vec::new();
+    let started = Instant::now();
+    let mut next = head;
+    while (downloaded.len() as u64) < settings.blocks {
+        let limit = settings.batch.min(settings.blocks - downloaded.len() as u64);
+        let sent = Instant::now();
+        match fetch::fetch_ancestors(network, peer_id, next, limit).await {
+            Ok(batch) => {
+                let bytes = batch.iter().map(|header| header.length() as u64).sum();
+                headers.record(sent.elapsed(), batch.len() as u64, bytes);
+                let last = batch.last().expect("ancestors are not empty");
+                next = last.parent_hash;
+                let reached_genesis = last.number == 0;
+                downloaded.extend(batch);
+                if reached_genesis {
+                    break;
+                }
+            }
+            Err(e) => {
+                headers.failures += 1;
+                error = Some(format!("header request failed: {e}"));
+                break;
+            }
+        }
+    }
+    headers.elapsed = started.elapsed();
+
+    let mut bodies = RequestStats::default();
+    let started = Instant::now();
+    for header in &downloaded {
+        let sent = Instant::now();
+        match fetch::fetch_body(network, peer_id, header.hash_slow(), header).await {
+            Ok(body) => bodies.record(sent.elapsed(), 1, body.length() as u64),
+            Err(e) => {
+                bodies.failures += 1;
+                error.get_or_insert_with(|| format!("body request failed: {e}"));
+            }
+        }
+    }
+    bodies.elapsed = started.elapsed();
+
+    PeerBenchmark {
+        peer_id,
+        client_version: None,
+        error,
+        headers: headers.summary(),
+        bodies: bodies.summary(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chain_config::{BscNetwork, custom::genesis_head},
+        testing::{MockPeer, canned_blocks, local_network},
+    };
+    use reth_chainspec::Head;
+
+    #[test]
+    fn test_percentile() {
+        let mut stats = RequestStats::default();
+        for ms in 1..=100 {
+            stats.record(Duration::from_millis(ms), 1, 10);
+        }
+        stats.elapsed = Duration::from_secs(2);
+        assert_eq!(stats.percentile(0.5), Some(Duration::from_millis(50)));
+        assert_eq!(stats.percentile(0.99), Some(Duration::from_millis(99)));
+
+        let summary = stats.summary();
+        assert_eq!(summary.requests, 100);
+        assert_eq!(summary.items_per_sec, 50.0);
+        assert_eq!(summary.bytes_per_sec, 500.0);
+        assert_eq!(summary.max_ms, Some(100.0));
+        assert_eq!(RequestStats::default().summary().p50_ms, None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_benchmark_mock_peer() {
+        let chain_spec = Arc::new(BscNetwork::Chapel.chain_spec(None));
+        let blocks = canned_blocks(&chain_spec, 8);
+        let (tip, tip_block) = blocks[7].clone();
+        let head = Head {
+            number: 8,
+            hash: tip,
+            timestamp: tip_block.header.timestamp,
+            ..Default::default()
+        };
+        let peer = MockPeer::spawn(chain_spec.clone(), head, blocks).await;
+        let network = local_network(chain_spec.clone(), genesis_head(&chain_spec)).await;
+        tokio::time::timeout(Duration::from_secs(10), peer.connect(&network)).await.unwrap();
+
+        let settings =
+            BenchmarkSettings { blocks: 6, batch: 4, connect_timeout: Duration::from_secs(10) };
+        let result = benchmark_peer(&network, peer.peer_id(), tip, &settings).await;
+        assert_eq!(result.error, None);
+        assert_eq!(result.headers.requests, 2);
+        assert_eq!(result.headers.items, 6);
+        assert_eq!(result.bodies.requests, 6);
+        assert_eq!(result.bodies.failures, 0);
+    }
+}
@@ -1,208 +1,1042 @@
+use crate::chain_config::{
+    checkpoints::Checkpoints,
+    validators::{Validator, ValidatorRegistry},
+};
+use crate::parlia::{
+    Parlia,
+    extra_data::{ValidatorSet, VoteAddress},
+    snapshot::{ConsensusError, HeaderStatus, Snapshot, SnapshotStore, TurnStatus},
+    validation,
+    vote::VoteData,
+};
+use crate::peer::{
+    announce::KnownBlocks,
+    anomalies::{Anomaly, AnomalyDetector},
+    bandwidth::{self, Throttle},
+    chain::Reorg,
+    deployments::{ContractDeployment, DeploymentDetector},
+    downtime::DowntimeMonitor,
+    duplicates::DuplicateTracker,
+    events::{EventSender, ReceiveTime},
+    fees::GasPriceStats,
+    head::{ChainHead, HeadTracker, HeadUpdate},
+    orphans::{ORPHAN_WINDOW, OrphanPool},
+    peer_heads::PeerHeads,
+    sync::{SyncActor, SyncCommand, SyncState},
+    tokens::TokenTransfer,
+    transactions::PendingTransaction,
+    transfers::TransferMonitor,
+    trust::TrustMessage,
+    validator_stats::ValidatorStats,
+    verify::{Verified, VerifyQueue},
+    watchlist::{WatchHit, Watchlist},
+    wire::trace_wire,
+};
+use alloy_consensus::Header;
+use alloy_primitives::{Address, B256, U256};
+use metrics::{counter, gauge};
+use reth_ethereum_primitives::Receipt;
 use reth_network_peers::PeerId;
-use std::collections::{HashMap, HashSet};
+use reth_primitives_traits::InMemorySize;
+use serde::{Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::sync::{Arc, Mutex};
-use std::task::{Context, Poll};
-use tracing::{info, warn};
+use std::task::{Context, Poll, Waker};
+use std::time::Instant;
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, info, instrument, warn};
 
-use reth_eth_wire::{GetBlockHeaders, HeadersDirection};
-use reth_eth_wire_types::BlockHashOrNumber;
-use reth_network::import::{BlockImport, BlockImportEvent, NewBlockEvent};
-use reth_network::{EthNetworkPrimitives, NetworkHandle};
-use reth_network_api::PeerRequest;
-use tokio::sync::{mpsc, oneshot};
+use reth_network::import::{
+    BlockImport, BlockImportError, BlockImportEvent, BlockImportOutcome, BlockValidation,
+    NewBlockEvent,
+};
+use reth_network::message::NewBlockMessage;
 
-#[derive(Debug, Clone)]
+/// Event emitted by the node, serialized with a `type` tag for the sinks.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase", rename_all_fields = "camelCase")]
 pub enum BlockEvent {
     NewBlock {
         peer_id: PeerId,
-        block_number: u64,
-        block_hash: String,
-        transaction_count: usize,
+        #[serde(flatten)]
+        block: BlockSummary,
+        /// Whether the block was proposed in turn, `None` while no snapshot is available.
+        turn_status: Option<TurnStatus>,
+        /// Verified vote attestation, justifying its target and finalizing its source block.
+        attestation: Option<VoteData>,
+        /// Justified and finalized heads after importing this block.
+        finality: FinalityHeads,
+        /// Validator set announced by an epoch boundary block.
+        validators: Option<ValidatorSet>,
     },
     NewBlockHashes {
         peer_id: PeerId,
         block_numbers: Vec<u64>,
     },
+    /// A block was dropped because it violates the header or consensus rules.
+    InvalidBlock {
+        peer_id: PeerId,
+        block_number: u64,
+        reason: &'static str,
+        error: String,
+    },
+    /// Receipts of a fetched block, validated against its header.
+    Receipts {
+        peer_id: PeerId,
+        block_number: u64,
+        block_hash: B256,
+        #[serde(serialize_with = "serialize_receipts")]
+        receipts: Vec<Receipt>,
+    },
+    /// Transactions first seen in the gossip of a peer.
+    PendingTransactions {
+        peer_id: PeerId,
+        transactions: Vec<PendingTransaction>,
+    },
+    /// Message received over the `trust` sub-protocol.
+    TrustMessage {
+        peer_id: PeerId,
+        message: TrustMessage,
+    },
+    /// The tracked height fell more than the allowed gap behind the highest announced block.
+    SyncGap {
+        height: u64,
+        highest_seen: u64,
+        gap: u64,
+    },
+    /// No new block was seen for longer than the allowed time.
+    SyncStalled {
+        height: u64,
+        idle_secs: u64,
+    },
+    /// No peer was connected for longer than the allowed time.
+    NoPeers {
+        idle_secs: u64,
+    },
+    /// Transfers of the allowlisted tokens, decoded from the receipts of a fetched block.
+    TokenTransfers {
+        peer_id: PeerId,
+        block_number: u64,
+        block_hash: B256,
+        transfers: Vec<TokenTransfer>,
+    },
+    /// A transaction of an imported block matched transfer alert rules.
+    TransferAlert {
+        /// Names of the matched rules.
+        rules: Vec<String>,
+        block_number: u64,
+        block_hash: B256,
+        tx_hash: B256,
+        from: Address,
+        to: Option<Address>,
+        value: U256,
+    },
+    /// The validator missed more consecutive in-turn blocks than allowed.
+    ValidatorDown {
+        validator: Validator,
+        /// Block the streak reached the threshold at.
+        block_number: u64,
+        /// First block of the streak.
+        since_block: u64,
+        missed_blocks: u64,
+    },
+    /// The canonical head moved to another branch. Consumers following the canonical chain
+    /// roll back the dropped blocks and apply the added ones.
+    Reorg {
+        #[serde(flatten)]
+        reorg: Reorg,
+    },
+    /// The block deviates from the baseline of the recent blocks.
+    Anomaly {
+        block_number: u64,
+        block_hash: B256,
+        #[serde(flatten)]
+        anomaly: Anomaly,
+    },
+    /// A transaction of a received block deploys a contract.
+    ContractDeployed {
+        block_number: u64,
+        block_hash: B256,
+        #[serde(flatten)]
+        deployment: ContractDeployment,
+    },
+    /// A transaction or token transfer touched a watched address.
+    WatchlistHit {
+        #[serde(flatten)]
+        hit: WatchHit,
+    },
+}
+
+/// Serializes receipts as summaries, leaving out the logs.
+fn serialize_receipts<S: Serializer>(
+    receipts: &[Receipt],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct ReceiptSummary {
+        success: bool,
+        cumulative_gas_used: u64,
+        log_count: usize,
+    }
+    serializer.collect_seq(receipts.iter().map(|receipt| ReceiptSummary {
+        success: receipt.success,
+        cumulative_gas_used: receipt.cumulative_gas_used,
+        log_count: receipt.logs.len(),
+    }))
+}
+
+impl BlockEvent {
+    /// Returns the event name used in metrics and logs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::NewBlock { .. } => "new_block",
+            Self::NewBlockHashes { .. } => "new_block_hashes",
+            Self::InvalidBlock { .. } => "invalid_block",
+            Self::Receipts { .. } => "receipts",
+            Self::PendingTransactions { .. } => "pending_transactions",
+            Self::TrustMessage { .. } => "trust_message",
+            Self::SyncGap { .. } => "sync_gap",
+            Self::SyncStalled { .. } => "sync_stalled",
+            Self::NoPeers { .. } => "no_peers",
+            Self::TokenTransfers { .. } => "token_transfers",
+            Self::TransferAlert { .. } => "transfer_alert",
+            Self::ValidatorDown { .. } => "validator_down",
+            Self::Reorg { .. } => "reorg",
+            Self::Anomaly { .. } => "anomaly",
+            Self::ContractDeployed { .. } => "contract_deployed",
+            Self::WatchlistHit { .. } => "watchlist_hit",
+        }
+    }
+
+    /// Returns the peer the data of the event was received from, `None` for events derived by
+    /// the node.
+    pub fn peer_id(&self) -> Option<PeerId> {
+        match self {
+            Self::NewBlock { peer_id, .. }
+            | Self::NewBlockHashes { peer_id, .. }
+            | Self::InvalidBlock { peer_id, .. }
+            | Self::Receipts { peer_id, .. }
+            | Self::PendingTransactions { peer_id, .. }
+            | Self::TrustMessage { peer_id, .. }
+            | Self::TokenTransfers { peer_id, .. } => Some(*peer_id),
+            Self::SyncGap { .. }
+            | Self::SyncStalled { .. }
+            | Self::NoPeers { .. }
+            | Self::TransferAlert { .. }
+            | Self::ValidatorDown { .. }
+            | Self::WatchlistHit { .. }
+            | Self::Reorg { .. }
+            | Self::Anomaly { .. }
+            | Self::ContractDeployed { .. } => None,
+        }
+    }
+
+    /// Returns the block the event is about, the highest one for announcements, the tracked
+    /// height for sync alerts and zero for pending transactions and peer alerts.
+    pub fn block_number(&self) -> u64 {
+        match self {
+            Self::NewBlock { block, .. } => block.number,
+            Self::InvalidBlock { block_number, .. }
+            | Self::Receipts { block_number, .. }
+            | Self::TokenTransfers { block_number, .. }
+            | Self::TransferAlert { block_number, .. }
+            | Self::ValidatorDown { block_number, .. }
+            | Self::Anomaly { block_number, .. }
+            | Self::ContractDeployed { block_number, .. } => *block_number,
+            Self::WatchlistHit { hit } => hit.block_number,
+            Self::Reorg { reorg } => reorg.added.last().map_or(0, |block| block.number),
+            Self::SyncGap { height, .. } | Self::SyncStalled { height, .. } => *height,
+            Self::NewBlockHashes { block_numbers, .. } => {
+                block_numbers.iter().copied().max().unwrap_or_default()
+            }
+            Self::TrustMessage { message, .. } => message.block_number(),
+            Self::PendingTransactions { .. } | Self::NoPeers { .. } => 0,
+        }
+    }
+}
+
+/// Header fields of an imported block, so consumers don't have to request them again.
+///
+/// The number and hash keep the `blockNumber` and `blockHash` names of the event they're
+/// flattened into.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockSummary {
+    #[serde(rename = "blockNumber")]
+    pub number: u64,
+    #[serde(rename = "blockHash")]
+    pub hash: B256,
+    pub parent_hash: B256,
+    /// Coinbase of the block, the validator that sealed it on BSC.
+    pub miner: Address,
+    /// Unix timestamp in seconds.
+    pub timestamp: u64,
+    pub gas_used: u64,
+    pub gas_limit: u64,
+    pub base_fee_per_gas: Option<u64>,
+    pub transaction_count: usize,
+    /// Validator that sealed the block, `None` until attributed.
+    pub validator: Option<Validator>,
+    /// Gas prices paid by the transactions, `None` for blocks without paying ones.
+    pub gas_prices: Option<GasPriceStats>,
 }
 
+impl BlockSummary {
+    pub fn new(header: &Header, hash: B256, transaction_count: usize) -> Self {
+        Self {
+            number: header.number,
+            hash,
+            parent_hash: header.parent_hash,
+            miner: header.beneficiary,
+            timestamp: header.timestamp,
+            gas_used: header.gas_used,
+            gas_limit: header.gas_limit,
+            base_fee_per_gas: header.base_fee_per_gas,
+            transaction_count,
+            validator: None,
+            gas_prices: None,
+        }
+    }
+
+    /// Attributes the block to the validator that sealed it.
+    pub fn with_validator(mut self, validator: Validator) -> Self {
+        self.validator = Some(validator);
+        self
+    }
+
+    /// Sets the gas price stats of the block's transactions.
+    pub fn with_gas_prices(mut self, gas_prices: Option<GasPriceStats>) -> Self {
+        self.gas_prices = gas_prices;
+        self
+    }
+}
+
+/// A block identified by number and hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BlockRef {
+    pub number: u64,
+    pub hash: B256,
+}
+
+/// Latest justified and finalized blocks, driven by verified vote attestations.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct FinalityHeads {
+    pub justified: Option<BlockRef>,
+    pub finalized: Option<BlockRef>,
+}
+
+/// Consensus state derived from the received headers, owned by the block importer.
+///
+/// Readers get the latest published values through a [`ConsensusReader`].
+#[derive(Debug)]
+pub struct ConsensusState {
+    /// Parlia snapshot built from the received headers.
+    snapshots: SnapshotStore,
+    finality: FinalityHeads,
+    snapshot_tx: watch::Sender<Option<Snapshot>>,
+    finality_tx: watch::Sender<FinalityHeads>,
+}
+
+impl ConsensusState {
+    pub fn new(snapshots: SnapshotStore) -> Self {
+        let snapshot_tx = watch::Sender::new(snapshots.current().cloned());
+        let finality_tx = watch::Sender::new(FinalityHeads::default());
+        Self { snapshots, finality: FinalityHeads::default(), snapshot_tx, finality_tx }
+    }
+
+    /// Returns a reader of the published state.
+    pub fn subscribe(&self) -> ConsensusReader {
+        ConsensusReader {
+            snapshot: self.snapshot_tx.subscribe(),
+            finality: self.finality_tx.subscribe(),
+        }
+    }
+
+    /// Applies a header sealed by `proposer` to the snapshot and publishes the result.
+    fn apply(
+        &mut self,
+        header: &Header,
+        hash: B256,
+        proposer: Address,
+        parlia: &Parlia,
+        verified_votes: Option<&[Option<VoteAddress>]>,
+    ) -> Result<HeaderStatus, ConsensusError> {
+        let status = self.snapshots.apply(header, hash, proposer, parlia, verified_votes)?;
+        self.snapshot_tx.send_replace(self.snapshots.current().cloned());
+        Ok(status)
+    }
+
+    /// Advances the justified and finalized heads to the target and source of a verified vote
+    /// attestation. Heads never move backwards.
+    fn update_finality(&mut self, attestation: &VoteData) -> FinalityHeads {
+        let finality = &mut self.finality;
+        let justified =
+            BlockRef { number: attestation.target_number, hash: attestation.target_hash };
+        if finality.justified.is_none_or(|head| justified.number > head.number) {
+            finality.justified = Some(justified);
+            gauge!("bscpeer_justified_block").set(justified.number as f64);
+        }
+        let finalized =
+            BlockRef { number: attestation.source_number, hash: attestation.source_hash };
+        if finality.finalized.is_none_or(|head| finalized.number > head.number) {
+            info!(
+                justified = justified.number,
+                finalized = finalized.number,
+                "update finalized block"
+            );
+            finality.finalized = Some(finalized);
+            gauge!("bscpeer_finalized_block").set(finalized.number as f64);
+        }
+        self.finality_tx.send_replace(*finality);
+        *finality
+    }
+
+    /// Returns the vote addresses to pre-verify the attestation of a header against, if the
+    /// header may follow the snapshot by the time it is applied.
+    fn vote_addresses(&self, header: &Header) -> Option<Vec<Option<VoteAddress>>> {
+        let snapshot = self.snapshots.current()?;
+        (header.number > snapshot.number).then(|| snapshot.ordered_vote_addresses())
+    }
+}
+
+/// Read access to the consensus state published by the block importer.
+#[derive(Debug, Clone)]
+pub struct ConsensusReader {
+    snapshot: watch::Receiver<Option<Snapshot>>,
+    finality: watch::Receiver<FinalityHeads>,
+}
+
+impl ConsensusReader {
+    pub fn current_snapshot(&self) -> Option<Snapshot> {
+        self.snapshot.borrow().clone()
+    }
+
+    pub fn finality(&self) -> FinalityHeads {
+        *self.finality.borrow()
+    }
+}
+
+/// Handle to the block sync state, which is owned by a [`SyncActor`] task.
+///
+/// Calls only enqueue commands, so the handle can be shared freely between the network event
+/// loop and timers without any locking.
 #[derive(Debug, Clone)]
 pub struct BlockStateManager {
-    pub current_height: Arc<Mutex<u64>>,
-    pub peerset: Arc<Mutex<Vec<PeerId>>>,
-    /// 等待的区块请求
-    pub pending_requests: Arc<Mutex<HashMap<u64, bool>>>,
-    pub received_blocks: Arc<Mutex<HashSet<u64>>>,
+    commands: mpsc::UnboundedSender<SyncCommand>,
+    height: watch::Receiver<u64>,
+    in_flight: watch::Receiver<usize>,
+    consensus: ConsensusReader,
+    head: HeadTracker,
 }
 
 impl BlockStateManager {
-    pub fn new(starting_height: u64) -> Self {
-        Self {
-            current_height: Arc::new(Mutex::new(starting_height)),
-            peerset: Arc::new(Mutex::new(Vec::new())),
-            pending_requests: Arc::new(Mutex::new(HashMap::new())),
-            received_blocks: Arc::new(Mutex::new(HashSet::new())),
+    /// Creates the handle together with the actor driving it, which must be spawned once the
+    /// network is running. Fetched blocks are validated with `parlia` against the consensus
+    /// state.
+    pub fn new(
+        starting_height: u64,
+        parlia: Parlia,
+        consensus: ConsensusReader,
+    ) -> (Self, SyncActor) {
+        let (commands, receiver) = mpsc::unbounded_channel();
+        let (height_tx, height) = watch::channel(starting_height);
+        let (in_flight_tx, in_flight) = watch::channel(0);
+        let head = HeadTracker::default();
+        let actor = SyncActor::new(
+            SyncState::new(starting_height),
+            receiver,
+            commands.clone(),
+            height_tx,
+            in_flight_tx,
+            parlia,
+            consensus.clone(),
+            head.clone(),
+        );
+        (Self { commands, height, in_flight, consensus, head }, actor)
+    }
+
+    fn send(&self, command: SyncCommand) {
+        if self.commands.send(command).is_err() {
+            warn!("block sync actor stopped");
         }
     }
 
+    /// Adds a peer and requests the next block from it.
     pub fn add_peer(&self, peer_id: PeerId) {
-        let mut peers = self.peerset.lock().unwrap();
-        if !peers.contains(&peer_id) {
-            peers.push(peer_id);
-            info!(%peer_id, "peerset add new peer");
-        }
+        self.send(SyncCommand::AddPeer(peer_id));
     }
 
-    pub fn remove_peer(&self, peer_id: &PeerId) {
-        let mut peers = self.peerset.lock().unwrap();
-        peers.retain(|p| p != peer_id);
-        info!(%peer_id, "peerset remove peer");
+    pub fn remove_peer(&self, peer_id: PeerId) {
+        self.send(SyncCommand::RemovePeer(peer_id));
     }
 
-    pub fn get_current_height(&self) -> u64 {
-        *self.current_height.lock().unwrap()
+    /// 处理收到的区块
+    pub fn block_received(&self, block_number: u64) {
+        self.send(SyncCommand::BlockReceived(block_number));
     }
 
-    pub fn update_height(&self, new_height: u64) -> bool {
-        let mut current = self.current_height.lock().unwrap();
-        if new_height > *current {
-            let old_height = *current;
-            *current = new_height;
-            info!(
-                old_height = old_height,
-                new_height = new_height,
-                "update block height"
-            );
-            true
-        } else {
-            false
+    /// Records that the peer's best block advanced, so backfill above it isn't requested from it.
+    pub fn peer_head(&self, peer_id: PeerId, number: u64) {
+        self.send(SyncCommand::PeerHead { peer_id, number });
+    }
+
+    /// Requests the announced blocks that are above the current height and not yet received.
+    pub fn block_hashes(&self, block_numbers: Vec<u64>) {
+        self.send(SyncCommand::BlockHashes(block_numbers));
+    }
+
+    /// Requests the missing parent of a block sent by the peer.
+    pub fn request_parent(&self, peer_id: PeerId, hash: B256, number: u64) {
+        self.send(SyncCommand::MissingParent { peer_id, hash, number });
+    }
+
+    /// Cleans up expired requests and requests the next block.
+    pub fn tick(&self) {
+        if let Some(finalized) = self.consensus.finality().finalized {
+            self.send(SyncCommand::Finalized(finalized.number));
         }
+        self.send(SyncCommand::Tick);
     }
 
-    pub fn add_received_block(&self, block_number: u64) {
-        let mut received = self.received_blocks.lock().unwrap();
-        received.insert(block_number);
+    /// Returns the number of the canonical head, or the sync height until the first block
+    /// arrived.
+    pub fn get_current_height(&self) -> u64 {
+        self.head.current().map_or_else(|| *self.height.borrow(), |head| head.number)
     }
 
-    pub fn is_block_received(&self, block_number: u64) -> bool {
-        let received = self.received_blocks.lock().unwrap();
-        received.contains(&block_number)
+    /// Returns the number of block requests of the sync in flight.
+    pub fn in_flight_requests(&self) -> usize {
+        *self.in_flight.borrow()
     }
 
-    pub fn request_block_by_number(
-        &self,
-        block_number: u64,
-        network_handle: &NetworkHandle<EthNetworkPrimitives>,
-    ) {
-        let peers = self.peerset.lock().unwrap();
-        if let Some(peer_id) = peers.first() {
-            {
-                let mut pending = self.pending_requests.lock().unwrap();
-                if pending.contains_key(&block_number) {
-                    return;
-                }
-                pending.insert(block_number, true);
-            }
+    pub fn current_snapshot(&self) -> Option<Snapshot> {
+        self.consensus.current_snapshot()
+    }
+
+    pub fn finality(&self) -> FinalityHeads {
+        self.consensus.finality()
+    }
+
+    /// Returns the canonical head, `None` until the first block arrived.
+    pub fn head(&self) -> Option<ChainHead> {
+        self.head.current()
+    }
+
+    /// Returns a receiver notified whenever the canonical head advances or reorgs.
+    pub fn subscribe_head(&self) -> watch::Receiver<Option<ChainHead>> {
+        self.head.subscribe()
+    }
+
+    /// Returns the tracker the block importer feeds the head to.
+    pub(crate) fn head_tracker(&self) -> HeadTracker {
+        self.head.clone()
+    }
+}
 
-            let (response_tx, _response_rx) = oneshot::channel();
+/// Number of most recent block heights whose headers are kept to check parent linkage.
+const RECENT_HEADERS: usize = 64;
 
-            let request = GetBlockHeaders {
-                start_block: BlockHashOrNumber::Number(block_number),
-                limit: 1,
-                skip: 0,
-                direction: HeadersDirection::Rising,
-            };
+/// Blocks spaced more than this many block intervals from their parent are flagged.
+const ANOMALOUS_SPACING_FACTOR: u64 = 2;
 
-            let peer_request = PeerRequest::GetBlockHeaders {
-                request,
-                response: response_tx,
-            };
+/// Hook called with every block that passed validation.
+pub type BlockImportHook = Arc<dyn Fn(PeerId, &reth_eth_wire::NewBlock) + Send + Sync>;
 
-            network_handle.send_request(*peer_id, peer_request);
-            info!(block_number = block_number, %peer_id, "request block");
-        } else {
-            warn!("no available peer to request block {}", block_number);
+pub struct SmartBlockImporter {
+    event_sender: EventSender,
+    parlia: Parlia,
+    consensus: ConsensusState,
+    checkpoints: Arc<Checkpoints>,
+    /// Names of the validators blocks are attributed to.
+    validators: Arc<ValidatorRegistry>,
+    /// Block production of each validator.
+    validator_stats: Arc<Mutex<ValidatorStats>>,
+    /// Missed-turn streaks of the validators, alerting on downtime.
+    downtime: Option<DowntimeMonitor>,
+    /// Rules the block transactions are checked against.
+    transfers: Option<TransferMonitor>,
+    /// Baselines of the recent blocks, flagging deviating ones.
+    anomalies: Option<AnomalyDetector>,
+    /// Addresses the block transactions are checked against.
+    watchlist: Arc<Mutex<Watchlist>>,
+    /// Contract deployments among the block transactions, shared with the sync.
+    deployments: DeploymentDetector,
+    hooks: Vec<BlockImportHook>,
+    /// Recently received headers by number and hash.
+    recent_headers: BTreeMap<u64, HashMap<B256, Header>>,
+    /// Whether valid blocks are re-broadcast to the rest of the peerset.
+    relay: bool,
+    /// Upload cap, relays are skipped while over it.
+    upload: Throttle,
+    /// Blocks sent or announced by each peer, which fetched blocks aren't announced back to.
+    known_blocks: Option<KnownBlocks>,
+    /// Announcements of recent blocks, whose repeats aren't processed again.
+    duplicates: Arc<Mutex<DuplicateTracker>>,
+    /// Best block of each peer, advanced by its announcements.
+    peer_heads: Arc<Mutex<PeerHeads>>,
+    /// Canonical head, advanced by the imported blocks.
+    head: HeadTracker,
+    /// Blocks being verified off the event loop, imported in the order they were received.
+    verifier: VerifyQueue<(PeerId, NewBlockMessage<reth_eth_wire::NewBlock>, ReceiveTime)>,
+    /// Verified blocks waiting for their parent.
+    orphans: OrphanPool<(PeerId, NewBlockMessage<reth_eth_wire::NewBlock>, ReceiveTime, Verified)>,
+    /// Sync handle the missing parents are requested from.
+    sync: Option<BlockStateManager>,
+    /// Import results to report to the network, which penalizes the sending peer of rejected
+    /// blocks and propagates relayed ones.
+    outcomes: VecDeque<BlockImportEvent<reth_eth_wire::NewBlock>>,
+    waker: Option<Waker>,
+}
+
+impl SmartBlockImporter {
+    pub fn new(event_sender: EventSender, parlia: Parlia, consensus: ConsensusState) -> Self {
+        Self {
+            event_sender,
+            verifier: VerifyQueue::new(parlia.clone()),
+            parlia,
+            consensus,
+            checkpoints: Arc::default(),
+            validators: Arc::default(),
+            validator_stats: Arc::default(),
+            downtime: None,
+            transfers: None,
+            anomalies: None,
+            watchlist: Arc::default(),
+            deployments: DeploymentDetector::default(),
+            hooks: Vec::new(),
+            recent_headers: BTreeMap::new(),
+            relay: false,
+            upload: Throttle::default(),
+            known_blocks: None,
+            duplicates: Arc::default(),
+            peer_heads: Arc::default(),
+            head: HeadTracker::default(),
+            orphans: OrphanPool::default(),
+            sync: None,
+            outcomes: VecDeque::new(),
+            waker: None,
         }
     }
 
-    pub fn request_next_block(&self, network_handle: &NetworkHandle<EthNetworkPrimitives>) {
-        let current_height = self.get_current_height();
-        let next_height = current_height + 1;
-        self.request_block_by_number(next_height, network_handle);
+    /// Sets the checkpoints received blocks are verified against.
+    pub fn with_checkpoints(mut self, checkpoints: Arc<Checkpoints>) -> Self {
+        self.checkpoints = checkpoints;
+        self
     }
 
-    pub fn check_and_request_missing_blocks(
-        &self,
-        received_block_number: u64,
-        network_handle: &NetworkHandle<EthNetworkPrimitives>,
-    ) {
-        let current_height = self.get_current_height();
+    /// Sets the names of the validators blocks are attributed to.
+    pub fn with_validators(mut self, validators: Arc<ValidatorRegistry>) -> Self {
+        self.validators = validators;
+        self
+    }
 
-        if received_block_number > current_height + 1 {
-            info!(
-                current_height = current_height,
-                received_block = received_block_number,
-                gap = received_block_number - current_height - 1,
-                "detect block gap, start request missing blocks"
-            );
+    /// Shares the block production of each validator, e.g. with the admin API.
+    pub fn with_validator_stats(mut self, validator_stats: Arc<Mutex<ValidatorStats>>) -> Self {
+        self.validator_stats = validator_stats;
+        self
+    }
 
-            let start = current_height + 1;
-            let end = std::cmp::min(start + 5, received_block_number);
+    /// Alerts on validators missing their turns, see [`DowntimeMonitor`].
+    pub fn with_downtime_monitor(mut self, downtime: DowntimeMonitor) -> Self {
+        self.downtime = Some(downtime);
+        self
+    }
 
-            for missing_block in start..end {
-                if !self.is_block_received(missing_block) {
-                    self.request_block_by_number(missing_block, network_handle);
-                }
-            }
+    /// Alerts on transactions matching transfer rules, see [`TransferMonitor`].
+    pub fn with_transfer_monitor(mut self, transfers: Option<TransferMonitor>) -> Self {
+        self.transfers = transfers;
+        self
+    }
+
+    /// Flags blocks deviating from the recent ones, see [`AnomalyDetector`].
+    pub fn with_anomaly_detector(mut self, anomalies: AnomalyDetector) -> Self {
+        self.anomalies = Some(anomalies);
+        self
+    }
+
+    /// Shares the watchlist the block transactions are checked against, e.g. with the admin API.
+    pub fn with_watchlist(mut self, watchlist: Arc<Mutex<Watchlist>>) -> Self {
+        self.watchlist = watchlist;
+        self
+    }
+
+    /// Shares the deployments detector with the sync, so blocks both received and fetched are
+    /// reported once.
+    pub fn with_deployments(mut self, deployments: DeploymentDetector) -> Self {
+        self.deployments = deployments;
+        self
+    }
+
+    /// Sets the hooks called with every valid block.
+    pub fn with_hooks(mut self, hooks: Vec<BlockImportHook>) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Enables re-broadcasting valid blocks to the peers that did not send them.
+    pub fn with_relay(mut self, relay: bool) -> Self {
+        self.relay = relay;
+        self
+    }
+
+    /// Caps the bandwidth of the relays.
+    pub fn with_upload_throttle(mut self, upload: Throttle) -> Self {
+        self.upload = upload;
+        self
+    }
+
+    /// Records the blocks each peer sent or announced, see [`KnownBlocks`].
+    pub fn with_known_blocks(mut self, known_blocks: KnownBlocks) -> Self {
+        self.known_blocks = Some(known_blocks);
+        self
+    }
+
+    /// Shares the tracker of duplicate announcements, e.g. with the admin API.
+    pub fn with_duplicate_tracker(mut self, duplicates: Arc<Mutex<DuplicateTracker>>) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
+
+    /// Shares the per-peer heads, e.g. with the admin API.
+    pub fn with_peer_heads(mut self, peer_heads: Arc<Mutex<PeerHeads>>) -> Self {
+        self.peer_heads = peer_heads;
+        self
+    }
+
+    /// Shares the canonical head tracker, e.g. with the sync.
+    pub fn with_head(mut self, head: HeadTracker) -> Self {
+        self.head = head;
+        self
+    }
+
+    /// Requests the missing parents of orphan blocks through the sync.
+    pub fn with_sync(mut self, sync: BlockStateManager) -> Self {
+        self.sync = Some(sync);
+        self
+    }
+
+    /// Runs the structural header checks, including the parent linkage if the parent is known.
+    fn validate_header(&self, header: &Header) -> Result<(), validation::HeaderError> {
+        self.parlia.validate_header(header)?;
+        let parent = header
+            .number
+            .checked_sub(1)
+            .and_then(|number| self.recent_headers.get(&number))
+            .and_then(|headers| headers.get(&header.parent_hash));
+        if let Some(parent) = parent {
+            self.parlia.validate_against_parent(header, parent)?;
+            self.record_spacing(header, parent);
         }
+        Ok(())
     }
 
-    /// 处理收到的区块
-    pub fn process_received_block(&self, block_number: u64) {
-        {
-            let mut pending = self.pending_requests.lock().unwrap();
-            pending.remove(&block_number);
+    /// Tracks the spacing between consecutive blocks, flagging blocks that took much longer than
+    /// the block interval, e.g. because the in-turn validator missed its slot.
+    fn record_spacing(&self, header: &Header, parent: &Header) {
+        let interval_ms = validation::millis_timestamp(header)
+            .saturating_sub(validation::millis_timestamp(parent));
+        let expected_ms = self.parlia.block_interval_ms(header.timestamp);
+        gauge!("bscpeer_block_interval_ms").set(interval_ms as f64);
+        if interval_ms > ANOMALOUS_SPACING_FACTOR * expected_ms {
+            warn!(
+                block_number = %header.number,
+                interval_ms,
+                expected_ms,
+                "anomalous block spacing"
+            );
+            counter!("bscpeer_block_spacing_anomalies_total").increment(1);
         }
+    }
+
+    fn insert_recent_header(&mut self, hash: B256, header: Header) {
+        self.recent_headers.entry(header.number).or_default().insert(hash, header);
+        while self.recent_headers.len() > RECENT_HEADERS {
+            self.recent_headers.pop_first();
+        }
+    }
 
-        self.add_received_block(block_number);
-        self.update_height(block_number);
+    /// Drops an invalid block and reports it, so the network lowers the peer's reputation.
+    fn reject<E>(&mut self, peer_id: PeerId, block_number: u64, reason: &'static str, err: E)
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        warn!(
+            peer_id = %peer_id,
+            block_number = %block_number,
+            reason,
+            "drop invalid block: {}",
+            err
+        );
+        counter!("bscpeer_invalid_blocks_total", "reason" => reason).increment(1);
+        let event =
+            BlockEvent::InvalidBlock { peer_id, block_number, reason, error: err.to_string() };
+        self.event_sender.send(event);
+        self.outcomes.push_back(BlockImportEvent::Outcome(BlockImportOutcome {
+            peer: peer_id,
+            result: Err(BlockImportError::Other(Box::new(err))),
+        }));
+        self.wake();
     }
 
-    pub fn process_block_hashes(
-        &self,
-        block_numbers: &[u64],
-        network_handle: &NetworkHandle<EthNetworkPrimitives>,
+    /// Re-broadcasts a valid block: the network sends it in full to the square root of the peers
+    /// that don't know it yet and announces its hash to the others.
+    fn relay(&mut self, peer_id: PeerId, block: NewBlockMessage<reth_eth_wire::NewBlock>) {
+        // every peer but the sender may get it
+        let peers = self.peer_heads.lock().unwrap().len().saturating_sub(1);
+        if !self.upload.try_consume(bandwidth::relay_size(block.block.block.size(), peers)) {
+            debug!(block_hash = %block.hash, "upload cap reached, skip relay");
+            return;
+        }
+        counter!("bscpeer_blocks_relayed_total").increment(1);
+        self.outcomes.push_back(BlockImportEvent::Outcome(BlockImportOutcome {
+            peer: peer_id,
+            result: Ok(BlockValidation::ValidHeader { block: block.clone() }),
+        }));
+        self.outcomes
+            .push_back(BlockImportEvent::Announcement(BlockValidation::ValidBlock { block }));
+        self.wake();
+    }
+
+    /// Hands a block to the verification workers, see [`VerifyQueue`].
+    fn verify(
+        &mut self,
+        peer_id: PeerId,
+        block_msg: NewBlockMessage<reth_eth_wire::NewBlock>,
+        received: ReceiveTime,
     ) {
-        let current_height = self.get_current_height();
+        let vote_addresses = self.consensus.vote_addresses(&block_msg.block.block.header);
+        let block = block_msg.block.clone();
+        let hash = block_msg.hash;
+        let item = (peer_id, block_msg, received);
+        if !self.verifier.submit(item, hash, block, vote_addresses) {
+            debug!(%peer_id, block_hash = %hash, "skip block verification");
+            return;
+        }
+        self.wake();
+    }
 
-        for &block_number in block_numbers {
-            if block_number > current_height && !self.is_block_received(block_number) {
-                self.request_block_by_number(block_number, network_handle);
+    /// Validates a verified block and applies it to the consensus state, or parks it in the
+    /// orphan pool if its parent is unknown. `received` is when the block arrived, stamped on
+    /// its event.
+    #[instrument(
+        name = "import_block",
+        skip_all,
+        fields(
+            peer_id = %peer_id,
+            block_number = block_msg.block.block.header.number,
+            block_hash = %block_msg.hash
+        )
+    )]
+    fn import_block(
+        &mut self,
+        peer_id: PeerId,
+        block_msg: NewBlockMessage<reth_eth_wire::NewBlock>,
+        received: ReceiveTime,
+        verified: Verified,
+    ) {
+        let block = &block_msg.block.block;
+        let block_number = block.header.number;
+        if self.is_orphan(&block.header) {
+            let parent_hash = block.header.parent_hash;
+            debug!(%peer_id, block_number, %parent_hash, "park orphan block");
+            let orphan = (peer_id, block_msg.clone(), received, verified);
+            if self.orphans.insert(block_msg.hash, parent_hash, orphan) {
+                if let Some(sync) = &self.sync {
+                    sync.request_parent(peer_id, parent_hash, block_number - 1);
+                }
             }
+            return;
         }
-    }
+        let known = self
+            .recent_headers
+            .get(&block_number)
+            .is_some_and(|headers| headers.contains_key(&block_msg.hash));
+
+        let checks = self
+            .validate_header(&block.header)
+            .and_then(|_| self.checkpoints.verify(&block.header, block_msg.hash))
+            .and_then(|_| {
+                self.checkpoints.verify_ancestry(&block.header, |hash| self.head.parent_hash(hash))
+            })
+            .and_then(|_| verified.transactions_root.clone())
+            .and_then(|_| validation::validate_seal(&block.header, &verified.proposer));
+        let proposer = match checks {
+            Ok(proposer) => proposer,
+            Err(e) => {
+                self.reject(peer_id, block_number, e.as_str(), e);
+                return;
+            }
+        };
 
-    pub fn cleanup_expired_requests(&self) {
-        let mut pending = self.pending_requests.lock().unwrap();
-        if pending.len() > 100 {
-            // 如果待处理请求太多，清理一些旧的
-            let current_height = self.get_current_height();
-            pending.retain(|&block_num, _| block_num > current_height.saturating_sub(50));
+        let verified_votes = verified.verified_votes.as_deref();
+        let result = self.consensus.apply(
+            &block.header,
+            block_msg.hash,
+            proposer,
+            &self.parlia,
+            verified_votes,
+        );
+        let status = match result {
+            Ok(status) => status,
+            Err(e) => {
+                self.reject(peer_id, block_number, e.as_str(), e);
+                return;
+            }
+        };
+        self.insert_recent_header(block_msg.hash, block.header.clone());
+        let justified = status.attestation.map(|attestation| attestation.target_number);
+        if let HeadUpdate::Reorged(reorg) =
+            self.head.update(&block.header, block_msg.hash, justified)
+        {
+            self.event_sender.send(BlockEvent::Reorg { reorg });
+        }
+        if self.relay && !known {
+            self.relay(peer_id, block_msg.clone());
+        }
+        if let Some(turn_status) = status.turn_status {
+            counter!("bscpeer_blocks_total", "turn" => turn_status.as_str()).increment(1);
+        }
+        let validator = self.validators.resolve(proposer);
+        let gas_prices =
+            GasPriceStats::new(&block.body.transactions, block.header.base_fee_per_gas);
+        if !known {
+            if let Some(gas_prices) = &gas_prices {
+                gas_prices.record_metrics();
+            }
+            self.validator_stats.lock().unwrap().record(
+                &validator,
+                block_number,
+                status.turn_status,
+                block.body.transactions.len(),
+                block.header.gas_used,
+            );
+            let inturn = status.inturn.map(|address| self.validators.resolve(address));
+            let alert = self
+                .downtime
+                .as_mut()
+                .and_then(|downtime| downtime.record(block_number, &validator, inturn.as_ref()));
+            if let Some(alert) = alert {
+                self.event_sender.send(alert);
+            }
+            if let Some(transfers) = &self.transfers {
+                let transactions = &block.body.transactions;
+                let senders = &verified.senders;
+                for alert in transfers.scan(block_number, block_msg.hash, transactions, senders) {
+                    self.event_sender.send(alert);
+                }
+            }
+            let hits = self.watchlist.lock().unwrap().check_transactions(
+                block_number,
+                block_msg.hash,
+                &block.body.transactions,
+                &verified.senders,
+            );
+            for hit in hits {
+                self.event_sender.send(hit);
+            }
+            for deployment in self.deployments.detect(
+                block_number,
+                block_msg.hash,
+                &block.body.transactions,
+                &verified.senders,
+            ) {
+                self.event_sender.send(deployment);
+            }
+            if let Some(anomalies) = &mut self.anomalies {
+                let transactions = block.body.transactions.len();
+                for anomaly in anomalies.record(&block.header, block_msg.hash, transactions) {
+                    self.event_sender.send(anomaly);
+                }
+            }
+        }
+        let finality = match &status.attestation {
+            Some(attestation) => self.consensus.update_finality(attestation),
+            None => self.consensus.finality,
+        };
+        for hook in &self.hooks {
+            hook(peer_id, &block_msg.block);
+        }
+
+        let event = BlockEvent::NewBlock {
+            peer_id,
+            block: BlockSummary::new(&block.header, block_msg.hash, block.body.transactions.len())
+                .with_validator(validator)
+                .with_gas_prices(gas_prices),
+            turn_status: status.turn_status,
+            attestation: status.attestation,
+            finality,
+            validators: self.parlia.parse_validators(&block.header).ok().flatten(),
+        };
+
+        self.event_sender.send_block(event, !known, received);
+
+        if !block.body.transactions.is_empty() {
             info!(
-                "cleanup expired block requests, current pending requests: {}",
-                pending.len()
+                block_number = %block_number,
+                "block contains transactions count: {}",
+                block.body.transactions.len()
             );
         }
     }
-}
 
-#[derive(Debug)]
-pub struct SmartBlockImporter {
-    event_sender: mpsc::UnboundedSender<BlockEvent>,
+    /// Returns whether the parent of a block close to the head is unknown. Until the first block
+    /// arrived nothing is known, so no block is an orphan.
+    fn is_orphan(&self, header: &Header) -> bool {
+        let Some(head) = self.head.current() else { return false };
+        header.number > 0
+            && header.number.abs_diff(head.number) <= ORPHAN_WINDOW
+            && !self.head.contains(&header.parent_hash)
+    }
+
+    /// Imports the orphans whose parent arrived, through the network or the sync.
+    fn connect_orphans(&mut self) {
+        loop {
+            let connected: Vec<B256> =
+                self.orphans.missing_parents().filter(|hash| self.head.contains(hash)).collect();
+            if connected.is_empty() {
+                return;
+            }
+            for parent_hash in connected {
+                let children = self.orphans.take_children(&parent_hash);
+                for (peer_id, block_msg, received, verified) in children {
+                    let block_number = block_msg.block.block.header.number;
+                    debug!(%peer_id, block_number, "import connected orphan");
+                    self.import_block(peer_id, block_msg, received, verified);
+                }
+            }
+        }
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
 }
 
-impl SmartBlockImporter {
-    pub fn new(event_sender: mpsc::UnboundedSender<BlockEvent>) -> Self {
-        Self { event_sender }
+impl std::fmt::Debug for SmartBlockImporter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SmartBlockImporter")
+            .field("parlia", &self.parlia)
+            .field("consensus", &self.consensus)
+            .field("checkpoints", &self.checkpoints)
+            .field("validators", &self.validators.len())
+            .field("validator_stats", &self.validator_stats)
+            .field("downtime", &self.downtime)
+            .field("transfers", &self.transfers)
+            .field("hooks", &self.hooks.len())
+            .field("recent_headers", &self.recent_headers.len())
+            .field("relay", &self.relay)
+            .field("upload", &self.upload)
+            .field("known_blocks", &self.known_blocks.is_some())
+            .field("duplicates", &self.duplicates)
+            .field("peer_heads", &self.peer_heads)
+            .field("head", &self.head)
+            .field("verifying", &self.verifier.len())
+            .field("orphans", &self.orphans.len())
+            .field("outcomes", &self.outcomes)
+            .finish_non_exhaustive()
     }
 }
 
@@ -212,8 +1046,10 @@ impl BlockImport<reth_eth_wire::NewBlock> for SmartBlockImporter {
         peer_id: PeerId,
         incoming_block: NewBlockEvent<reth_eth_wire::NewBlock>,
     ) {
+        let received = ReceiveTime::now();
         match incoming_block {
             NewBlockEvent::Block(block_msg) => {
+                trace_wire!(Some(peer_id), Inbound, NewBlock, &*block_msg.block);
                 let block = &block_msg.block.block;
                 let block_number = block.header.number;
 
@@ -228,34 +1064,75 @@ impl BlockImport<reth_eth_wire::NewBlock> for SmartBlockImporter {
                     transactions_count = %block.body.transactions.len(),
                     "receive new block"
                 );
-
-                let event = BlockEvent::NewBlock {
+                if let Some(known_blocks) = &self.known_blocks {
+                    known_blocks.insert(peer_id, block_msg.hash);
+                }
+                let advanced = self.peer_heads.lock().unwrap().on_announcement(
                     peer_id,
+                    block_msg.hash,
                     block_number,
-                    block_hash: block_msg.hash.to_string(),
-                    transaction_count: block.body.transactions.len(),
-                };
-
-                if let Err(e) = self.event_sender.send(event) {
-                    warn!("failed to send block event: {}", e);
+                    Some(U256::from(block_msg.block.td)),
+                );
+                if let (true, Some(sync)) = (advanced, &self.sync) {
+                    sync.peer_head(peer_id, block_number);
                 }
-
-                if !block.body.transactions.is_empty() {
-                    info!(
-                        block_number = %block_number,
-                        "block contains transactions count: {}",
-                        block.body.transactions.len()
-                    );
+                // only the first copy of a block is processed and relayed, the network tracks
+                // who knows it
+                let first = self.duplicates.lock().unwrap().record(
+                    peer_id,
+                    block_msg.hash,
+                    block_number,
+                    "block",
+                    Instant::now(),
+                );
+                let known = self
+                    .recent_headers
+                    .get(&block_number)
+                    .is_some_and(|headers| headers.contains_key(&block_msg.hash));
+                if known && !first {
+                    debug!(%peer_id, block_number, "skip duplicate block");
+                    return;
                 }
+
+                self.verify(peer_id, block_msg, received);
             }
             NewBlockEvent::Hashes(hashes) => {
+                trace_wire!(Some(peer_id), Inbound, NewBlockHashes, &hashes);
                 info!(
                     peer_id = %peer_id,
                     hashes_count = %hashes.0.len(),
                     "receive block hashes list"
                 );
 
-                let block_numbers: Vec<u64> = hashes.0.iter().map(|h| h.number).collect();
+                if let Some(known_blocks) = &self.known_blocks {
+                    for hash_data in &hashes.0 {
+                        known_blocks.insert(peer_id, hash_data.hash);
+                    }
+                }
+                let mut peer_heads = self.peer_heads.lock().unwrap();
+                let advanced = hashes
+                    .0
+                    .iter()
+                    .filter(|h| peer_heads.on_announcement(peer_id, h.hash, h.number, None))
+                    .map(|h| h.number)
+                    .max();
+                drop(peer_heads);
+                if let (Some(number), Some(sync)) = (advanced, &self.sync) {
+                    sync.peer_head(peer_id, number);
+                }
+                // hashes announced before, or whose block arrived already, aren't requested again
+                let now = Instant::now();
+                let mut duplicates = self.duplicates.lock().unwrap();
+                let block_numbers: Vec<u64> = hashes
+                    .0
+                    .iter()
+                    .filter(|h| duplicates.record(peer_id, h.hash, h.number, "hash", now))
+                    .map(|h| h.number)
+                    .collect();
+                drop(duplicates);
+                if block_numbers.is_empty() {
+                    return;
+                }
 
                 for hash_data in &hashes.0 {
                     info!(
@@ -271,14 +1148,29 @@ impl BlockImport<reth_eth_wire::NewBlock> for SmartBlockImporter {
                     block_numbers,
                 };
 
-                if let Err(e) = self.event_sender.send(event) {
-                    warn!("failed to send block hashes event: {}", e);
-                }
+                // only hashes announced first are left
+                self.event_sender.send_block(event, true, received);
             }
         }
     }
 
-    fn poll(&mut self, _cx: &mut Context<'_>) -> Poll<BlockImportEvent<reth_eth_wire::NewBlock>> {
-        Poll::Pending
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<BlockImportEvent<reth_eth_wire::NewBlock>> {
+        while let Poll::Ready(Some(((peer_id, block_msg, received), verified))) =
+            self.verifier.poll_next(cx)
+        {
+            self.import_block(peer_id, block_msg, received, verified);
+        }
+        // parents fetched by the sync arrive outside of the network, checked whenever the
+        // network polls the importer
+        if !self.orphans.is_empty() {
+            self.connect_orphans();
+        }
+        match self.outcomes.pop_front() {
+            Some(outcome) => Poll::Ready(outcome),
+            None => {
+                self.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
     }
 }
@@ -0,0 +1,143 @@
+//! Boot node lists fetched from remote sources.
+//!
+//! The built-in boot nodes go stale as operators retire machines, which can leave a fresh node
+//! isolated. The configured sources, HTTP(S) documents or DNS TXT records listing enode URLs, are
+//! fetched at startup and merged with the built-in nodes, then refreshed periodically with the
+//! new nodes added to the peer set.
+use crate::{
+    chain_config::bootnodes::parse_node,
+    config::{BootnodeSource, BootnodesConfig},
+    instance,
+};
+use hickory_resolver::TokioResolver;
+use metrics::{counter, gauge};
+use reth_network::{EthNetworkPrimitives, NetworkHandle};
+use reth_network_api::Peers;
+use reth_network_peers::{NodeRecord, PeerId};
+use std::{collections::HashSet, time::Duration};
+use tokio::{task::JoinHandle, time::interval};
+use tracing::{debug, info, warn};
+
+/// Timeout of a single HTTP request.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Errors fetching a boot node list.
+#[derive(Debug, thiserror::Error)]
+pub enum BootnodeFetchError {
+    #[error("http request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("dns lookup failed: {0}")]
+    Dns(#[from] hickory_resolver::ResolveError),
+}
+
+/// Parses the enode URLs of a list, separated by whitespace or commas. Lines starting with `#`
+/// are comments, invalid entries are skipped.
+pub fn parse_nodes(list: &str) -> Vec<NodeRecord> {
+    list.lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| match parse_node(entry) {
+            Ok(node) => Some(node),
+            Err(e) => {
+                warn!(%entry, "skipping invalid boot node: {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Appends the nodes not yet in `nodes`, returning how many were added.
+pub fn merge(nodes: &mut Vec<NodeRecord>, fetched: impl IntoIterator<Item = NodeRecord>) -> usize {
+    let mut ids: HashSet<PeerId> = nodes.iter().map(|node| node.id).collect();
+    let before = nodes.len();
+    nodes.extend(fetched.into_iter().filter(|node| ids.insert(node.id)));
+    nodes.len() - before
+}
+
+/// Fetches the nodes listed by a single source.
+pub async fn fetch(source: &BootnodeSource) -> Result<Vec<NodeRecord>, BootnodeFetchError> {
+    match source {
+        BootnodeSource::Url(url) => {
+            let client = reqwest::Client::builder().timeout(FETCH_TIMEOUT).build()?;
+            let list = client.get(url).send().await?.error_for_status()?.text().await?;
+            Ok(parse_nodes(&list))
+        }
+        BootnodeSource::Txt(domain) => {
+            let resolver = TokioResolver::builder_tokio()?.build();
+            let lookup = resolver.txt_lookup(domain.as_str()).await?;
+            // a record may be split into several strings, each holding part of a node
+            let list: Vec<String> = lookup
+                .iter()
+                .map(|txt| {
+                    txt.txt_data().iter().map(|data| String::from_utf8_lossy(data)).collect()
+                })
+                .collect();
+            Ok(parse_nodes(&list.join("\n")))
+        }
+    }
+}
+
+/// Fetches every source, logging the ones that fail.
+pub async fn fetch_all(sources: &[BootnodeSource]) -> Vec<NodeRecord> {
+    let mut nodes = Vec::new();
+    for source in sources {
+        match fetch(source).await {
+            Ok(fetched) => {
+                debug!(?source, nodes = fetched.len(), "fetched boot nodes");
+                counter!("bscpeer_bootnode_fetches_total", "outcome" => "ok").increment(1);
+                merge(&mut nodes, fetched);
+            }
+            Err(e) => {
+                warn!(?source, "failed to fetch boot nodes: {}", e);
+                counter!("bscpeer_bootnode_fetches_total", "outcome" => "error").increment(1);
+            }
+        }
+    }
+    gauge!("bscpeer_remote_bootnodes").set(nodes.len() as f64);
+    nodes
+}
+
+/// Spawns the task refetching the sources and adding the nodes not in `known` to the peer set.
+pub fn spawn_refresh(
+    network: NetworkHandle<EthNetworkPrimitives>,
+    config: BootnodesConfig,
+    mut known: Vec<NodeRecord>,
+) -> JoinHandle<()> {
+    instance::spawn(async move {
+        let mut interval = interval(Duration::from_secs(config.refresh_secs));
+        // the sources were fetched at startup
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let fetched = fetch_all(&config.sources).await;
+            let start = known.len();
+            let added = merge(&mut known, fetched);
+            for node in &known[start..] {
+                network.add_peer(node.id, node.tcp_addr());
+            }
+            if added > 0 {
+                info!(added, "added refreshed boot nodes");
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NODE_A: &str = "enode://433c8bfdf53a3e2268ccb1b829e47f629793291cbddf0c76ae626da802f90532251fc558e2e0d10d6725e759088439bf1cd4714716b03a259a35d4b2e4acfa7f@52.69.102.73:30311";
+    const NODE_B: &str = "enode://571bee8fb902a625942f10a770ccf727ae2ba1bab2a2b64e121594a99c9437317f6166a395670a00b7d93647eacafe598b6bbcef15b40b6d1a10243865a3e80f@35.73.84.120:30311";
+
+    #[test]
+    fn test_parse_and_merge() {
+        let list = format!("# bsc boot nodes\n{NODE_A}, {NODE_B}\n\nenode://invalid\n");
+        let fetched = parse_nodes(&list);
+        assert_eq!(fetched.len(), 2);
+
+        let mut nodes = vec![NODE_A.parse().unwrap()];
+        assert_eq!(merge(&mut nodes, fetched), 1);
+        assert_eq!(nodes[1], NODE_B.parse().unwrap());
+    }
+}
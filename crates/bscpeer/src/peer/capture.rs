@@ -0,0 +1,204 @@
+//! Captures of the traced eth messages in a replayable file.
+//!
+//! The file follows the e2store layout: a sequence of records, each an 8 byte header (2 byte
+//! type, 4 byte little endian length, 2 reserved zero bytes) followed by the data, starting with
+//! an empty version record. Each traced message is a frame record holding:
+//!
+//! | bytes | field                                           |
+//! |-------|-------------------------------------------------|
+//! | 8     | capture time, milliseconds since the epoch, LE  |
+//! | 64    | peer id, zero during the handshake              |
+//! | 1     | direction, 0 inbound, 1 outbound                |
+//! | 1     | eth message id                                  |
+//! | rest  | RLP payload                                     |
+//!
+//! Readers skip records of unknown types, so the format can grow without breaking old captures.
+use crate::peer::wire::Direction;
+use alloy_primitives::Bytes;
+use reth_network_peers::PeerId;
+use std::io::{self, BufWriter, Read, Write};
+
+/// Type of the version record.
+const VERSION: [u8; 2] = *b"e2";
+/// Type of a frame record.
+const FRAME: [u8; 2] = *b"wf";
+const HEADER_LEN: usize = 8;
+/// Frame bytes before the payload.
+const FRAME_PREFIX_LEN: usize = 8 + 64 + 1 + 1;
+
+/// Errors reading a capture.
+#[derive(Debug, thiserror::Error)]
+pub enum CaptureError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("capture does not start with a version record")]
+    MissingVersion,
+    #[error("frame record of {0} bytes is too short")]
+    ShortFrame(usize),
+    #[error("unknown direction {0}")]
+    Direction(u8),
+}
+
+/// A captured message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub timestamp_ms: u64,
+    pub peer_id: Option<PeerId>,
+    pub direction: Direction,
+    pub message_id: u8,
+    pub payload: Bytes,
+}
+
+impl Frame {
+    /// Decodes the payload as the given message.
+    pub fn decode<T: alloy_rlp::Decodable>(&self) -> alloy_rlp::Result<T> {
+        T::decode(&mut self.payload.as_ref())
+    }
+
+    fn parse(data: &[u8]) -> Result<Self, CaptureError> {
+        if data.len() < FRAME_PREFIX_LEN {
+            return Err(CaptureError::ShortFrame(data.len()));
+        }
+        let timestamp_ms = u64::from_le_bytes(data[..8].try_into().unwrap());
+        let peer_id = PeerId::from_slice(&data[8..72]);
+        let direction = match data[72] {
+            0 => Direction::Inbound,
+            1 => Direction::Outbound,
+            other => return Err(CaptureError::Direction(other)),
+        };
+        Ok(Self {
+            timestamp_ms,
+            peer_id: (!peer_id.is_zero()).then_some(peer_id),
+            direction,
+            message_id: data[73],
+            payload: Bytes::copy_from_slice(&data[FRAME_PREFIX_LEN..]),
+        })
+    }
+}
+
+/// Appends frames to a capture.
+#[derive(Debug)]
+pub struct CaptureWriter<W: Write> {
+    out: BufWriter<W>,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Starts a capture, writing the version record.
+    pub fn new(out: W) -> io::Result<Self> {
+        let mut writer = Self { out: BufWriter::new(out) };
+        writer.record(VERSION, &[])?;
+        writer.out.flush()?;
+        Ok(writer)
+    }
+
+    /// Appends a frame, flushed right away so a capture survives a crash.
+    pub fn write(
+        &mut self,
+        timestamp_ms: u64,
+        peer_id: Option<PeerId>,
+        direction: Direction,
+        message_id: u8,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let mut data = Vec::with_capacity(FRAME_PREFIX_LEN + payload.len());
+        data.extend_from_slice(&timestamp_ms.to_le_bytes());
+        data.extend_from_slice(peer_id.unwrap_or_default().as_slice());
+        data.push(match direction {
+            Direction::Inbound => 0,
+            Direction::Outbound => 1,
+        });
+        data.push(message_id);
+        data.extend_from_slice(payload);
+        self.record(FRAME, &data)?;
+        self.out.flush()
+    }
+
+    fn record(&mut self, kind: [u8; 2], data: &[u8]) -> io::Result<()> {
+        let len = u32::try_from(data.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "record too large"))?;
+        self.out.write_all(&kind)?;
+        self.out.write_all(&len.to_le_bytes())?;
+        self.out.write_all(&[0, 0])?;
+        self.out.write_all(data)
+    }
+}
+
+/// Reads the frames of a capture in order.
+#[derive(Debug)]
+pub struct CaptureReader<R: Read> {
+    input: R,
+    started: bool,
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(input: R) -> Self {
+        Self { input, started: false }
+    }
+
+    /// Reads the next record, `None` at the end of the capture.
+    fn record(&mut self) -> Result<Option<([u8; 2], Vec<u8>)>, CaptureError> {
+        let mut header = [0u8; HEADER_LEN];
+        match self.input.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let len = u32::from_le_bytes(header[2..6].try_into().unwrap()) as usize;
+        let mut data = vec![0u8; len];
+        self.input.read_exact(&mut data)?;
+        Ok(Some(([header[0], header[1]], data)))
+    }
+
+    fn next_frame(&mut self) -> Result<Option<Frame>, CaptureError> {
+        if !self.started {
+            match self.record()? {
+                Some((VERSION, _)) => self.started = true,
+                _ => return Err(CaptureError::MissingVersion),
+            }
+        }
+        while let Some((kind, data)) = self.record()? {
+            if kind == FRAME {
+                return Frame::parse(&data).map(Some);
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<Frame, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reth_eth_wire::{BlockHashNumber, NewBlockHashes};
+
+    #[test]
+    fn test_capture_roundtrip() {
+        let hashes = NewBlockHashes(vec![BlockHashNumber { hash: Default::default(), number: 7 }]);
+        let peer_id = PeerId::repeat_byte(1);
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        writer
+            .write(1_000, Some(peer_id), Direction::Inbound, 0x01, &alloy_rlp::encode(&hashes))
+            .unwrap();
+        writer.write(1_001, None, Direction::Outbound, 0x0b, &[0xc1, 0x80]).unwrap();
+        let mut file = writer.out.into_inner().unwrap();
+        // unknown records are skipped
+        file.extend_from_slice(&[b'x', b'x', 1, 0, 0, 0, 0, 0, 0xff]);
+
+        let frames: Vec<_> = CaptureReader::new(file.as_slice()).collect::<Result<_, _>>().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].peer_id, Some(peer_id));
+        assert_eq!(frames[0].decode::<NewBlockHashes>().unwrap(), hashes);
+        assert_eq!((frames[1].peer_id, frames[1].direction), (None, Direction::Outbound));
+        assert_eq!(frames[1].payload.as_ref(), &[0xc1, 0x80]);
+
+        let err = CaptureReader::new(&file[8..]).next().unwrap().unwrap_err();
+        assert!(matches!(err, CaptureError::MissingVersion));
+    }
+}
@@ -0,0 +1,338 @@
+//! In-memory chain of recent headers with Parlia fork choice.
+//!
+//! Headers are stored by hash and linked through their parents, so competing blocks at the same
+//! height and reorgs are told apart instead of only tracking the highest number. The best chain
+//! is the one with the highest justified block, then the highest total difficulty: in-turn
+//! blocks weigh twice as much as out-of-turn ones, so the chain with more in-turn blocks wins.
+//!
+//! The node doesn't know the total difficulty of the chain it joins, so a header whose parent
+//! isn't stored is weighed as if every block before it were out of turn, or as extending the
+//! head if it's above it. Once the missing parent arrives, the descendants are relinked to it.
+use crate::{parlia::snapshot::DIFF_NOTURN, peer::blockstate::BlockRef};
+use alloy_consensus::Header;
+use alloy_primitives::{B256, U256};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Canonical blocks kept below the head.
+pub const RETAIN_BLOCKS: u64 = 256;
+/// Blocks of side chains kept below the head, older forks can't become canonical anymore.
+const RETAIN_FORKS: u64 = 32;
+
+/// A stored header and the weight of the chain ending in it.
+#[derive(Debug)]
+struct Entry {
+    header: Header,
+    /// Total difficulty, estimated where ancestors are missing.
+    td: U256,
+    /// Block justified by the header's own vote attestation.
+    attested: Option<u64>,
+    /// Highest block justified on the chain ending in this header.
+    justified: u64,
+}
+
+impl Entry {
+    /// Fork choice weight: highest justified block, then total difficulty. The head only moves
+    /// to a strictly heavier chain.
+    fn weight(&self) -> (u64, U256) {
+        (self.justified, self.td)
+    }
+}
+
+/// Result of adding a header to the chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainUpdate {
+    /// The header was already stored.
+    Known,
+    /// The header, or one of its stored descendants, extends the head.
+    Extended,
+    /// The head moved to another branch, abandoning `depth` canonical blocks.
+    Reorg { depth: u64 },
+    /// The header joined the canonical chain below the head, filling a gap.
+    Canonical,
+    /// The header was stored on a side chain.
+    Fork,
+}
+
+/// Blocks leaving and joining the canonical chain when the head moved to another branch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Reorg {
+    /// Last block shared by both branches, `None` if they only join below the stored blocks.
+    pub common_ancestor: Option<BlockRef>,
+    /// Blocks of the abandoned branch, oldest first.
+    pub dropped: Vec<BlockRef>,
+    /// Blocks of the new canonical branch up to the head, oldest first.
+    pub added: Vec<BlockRef>,
+}
+
+/// Recent headers by hash and the canonical chain chosen among them.
+#[derive(Debug, Default)]
+pub struct ChainTracker {
+    headers: HashMap<B256, Entry>,
+    /// Hashes of the stored headers by number.
+    by_number: BTreeMap<u64, Vec<B256>>,
+    /// Canonical hash at each number up to the head.
+    canonical: BTreeMap<u64, B256>,
+    head: Option<B256>,
+}
+
+impl ChainTracker {
+    /// Returns the head hash and header.
+    pub fn head(&self) -> Option<(B256, &Header)> {
+        let hash = self.head?;
+        Some((hash, &self.headers[&hash].header))
+    }
+
+    /// Returns the canonical hash at a height, if still stored.
+    pub fn canonical_hash(&self, number: u64) -> Option<B256> {
+        self.canonical.get(&number).copied()
+    }
+
+    pub fn header(&self, hash: &B256) -> Option<&Header> {
+        self.headers.get(hash).map(|entry| &entry.header)
+    }
+
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    /// Stores a valid header, with the block justified by its vote attestation if any, and moves
+    /// the head to the best chain.
+    pub fn insert(&mut self, header: Header, hash: B256, attested: Option<u64>) -> ChainUpdate {
+        if self.headers.contains_key(&hash) {
+            return ChainUpdate::Known;
+        }
+        let (td, justified) = match self.headers.get(&header.parent_hash) {
+            Some(parent) => {
+                (parent.td + header.difficulty, parent.justified.max(attested.unwrap_or_default()))
+            }
+            None => (self.estimate_td(&header), attested.unwrap_or_default()),
+        };
+        self.by_number.entry(header.number).or_default().push(hash);
+        self.headers.insert(hash, Entry { header, td, attested, justified });
+
+        // the header may be the missing parent of stored ones
+        let mut changed = vec![hash];
+        changed.extend(self.relink(hash));
+        let best = changed
+            .into_iter()
+            .max_by_key(|hash| self.headers[hash].weight())
+            .expect("the inserted header");
+        let update = match self.head {
+            None => {
+                self.set_head(best);
+                ChainUpdate::Extended
+            }
+            // relinking may have made the head itself heavier
+            Some(head)
+                if best == head || self.headers[&best].weight() > self.headers[&head].weight() =>
+            {
+                let previous = self.headers[&head].header.number;
+                match self.set_head(best) {
+                    0 if self.headers[&best].header.number > previous => ChainUpdate::Extended,
+                    0 => ChainUpdate::Canonical,
+                    depth => ChainUpdate::Reorg { depth },
+                }
+            }
+            Some(_) => return ChainUpdate::Fork,
+        };
+        self.prune();
+        update
+    }
+
+    /// Returns the blocks between the canonical chain and a former head, which must have been
+    /// abandoned by the latest insert.
+    pub fn reorg(&self, old_head: B256) -> Reorg {
+        let mut dropped = Vec::new();
+        let mut common_ancestor = None;
+        let mut cursor = old_head;
+        while let Some(entry) = self.headers.get(&cursor) {
+            let number = entry.header.number;
+            if self.canonical.get(&number) == Some(&cursor) {
+                common_ancestor = Some(BlockRef { number, hash: cursor });
+                break;
+            }
+            dropped.push(BlockRef { number, hash: cursor });
+            cursor = entry.header.parent_hash;
+        }
+        dropped.reverse();
+        // the new branch starts above the ancestor, or where the walk left the stored blocks
+        let start = match common_ancestor {
+            Some(ancestor) => ancestor.number + 1,
+            None => dropped.first().map_or(u64::MAX, |block| block.number),
+        };
+        let added = self
+            .canonical
+            .range(start..)
+            .map(|(number, hash)| BlockRef { number: *number, hash: *hash })
+            .collect();
+        Reorg { common_ancestor, dropped, added }
+    }
+
+    /// Estimates the total difficulty of a header whose parent isn't stored: above the head as
+    /// if it extended it, otherwise as if all its ancestors were out of turn.
+    fn estimate_td(&self, header: &Header) -> U256 {
+        match self.head.map(|hash| &self.headers[&hash]) {
+            Some(head) if header.number > head.header.number => {
+                let gap = header.number - head.header.number - 1;
+                head.td + U256::from(gap) * DIFF_NOTURN + header.difficulty
+            }
+            _ => U256::from(header.number.saturating_sub(1)) * DIFF_NOTURN + header.difficulty,
+        }
+    }
+
+    /// Recomputes the weights of the stored descendants of a header, returning their hashes.
+    fn relink(&mut self, hash: B256) -> Vec<B256> {
+        let mut relinked = Vec::new();
+        let mut parents = vec![hash];
+        while let Some(parent_hash) = parents.pop() {
+            let parent = &self.headers[&parent_hash];
+            let (td, justified) = (parent.td, parent.justified);
+            let children: Vec<B256> = self
+                .by_number
+                .get(&(parent.header.number + 1))
+                .into_iter()
+                .flatten()
+                .filter(|child| self.headers[*child].header.parent_hash == parent_hash)
+                .copied()
+                .collect();
+            for child_hash in children {
+                let child = self.headers.get_mut(&child_hash).expect("stored child");
+                child.td = td + child.header.difficulty;
+                child.justified = justified.max(child.attested.unwrap_or_default());
+                relinked.push(child_hash);
+                parents.push(child_hash);
+            }
+        }
+        relinked
+    }
+
+    /// Moves the head and updates the canonical chain, returning how many canonical blocks
+    /// were abandoned.
+    fn set_head(&mut self, hash: B256) -> u64 {
+        let number = self.headers[&hash].header.number;
+        let mut abandoned = self.canonical.split_off(&(number + 1)).len() as u64;
+        let mut cursor = hash;
+        while let Some(entry) = self.headers.get(&cursor) {
+            let number = entry.header.number;
+            match self.canonical.insert(number, cursor) {
+                // unless the parent just arrived, the rest of the chain is canonical already
+                Some(previous)
+                    if previous == cursor
+                        && self.canonical.get(&number.wrapping_sub(1))
+                            == Some(&entry.header.parent_hash) =>
+                {
+                    break;
+                }
+                Some(previous) if previous != cursor => abandoned += 1,
+                _ => {}
+            }
+            cursor = entry.header.parent_hash;
+        }
+        self.head = Some(hash);
+        abandoned
+    }
+
+    /// Drops canonical blocks beyond [`RETAIN_BLOCKS`] and side chains beyond [`RETAIN_FORKS`]
+    /// below the head.
+    fn prune(&mut self) {
+        let Some((_, head)) = self.head() else { return };
+        let head_number = head.number;
+        let keep_canonical = head_number.saturating_sub(RETAIN_BLOCKS);
+        let keep_forks = head_number.saturating_sub(RETAIN_FORKS);
+
+        let stale: Vec<(u64, B256)> = self
+            .by_number
+            .range(..keep_forks)
+            .flat_map(|(number, hashes)| hashes.iter().map(|hash| (*number, *hash)))
+            .filter(|(number, hash)| {
+                *number < keep_canonical || self.canonical.get(number) != Some(hash)
+            })
+            .collect();
+        let removed: HashSet<B256> = stale.iter().map(|(_, hash)| *hash).collect();
+        for (number, hash) in stale {
+            self.headers.remove(&hash);
+            if let Some(hashes) = self.by_number.get_mut(&number) {
+                hashes.retain(|stored| !removed.contains(stored));
+                if hashes.is_empty() {
+                    self.by_number.remove(&number);
+                }
+            }
+        }
+        self.canonical = self.canonical.split_off(&keep_canonical);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parlia::snapshot::DIFF_INTURN;
+
+    fn header(number: u64, parent: u8, difficulty: U256) -> Header {
+        Header { number, parent_hash: hash(parent), difficulty, ..Default::default() }
+    }
+
+    fn hash(byte: u8) -> B256 {
+        B256::with_last_byte(byte)
+    }
+
+    #[test]
+    fn test_fork_choice() {
+        let mut chain = ChainTracker::default();
+        assert_eq!(chain.insert(header(10, 0, DIFF_INTURN), hash(1), None), ChainUpdate::Extended);
+        assert_eq!(chain.insert(header(11, 1, DIFF_NOTURN), hash(2), None), ChainUpdate::Extended);
+        assert_eq!(chain.insert(header(11, 1, DIFF_NOTURN), hash(2), None), ChainUpdate::Known);
+
+        // an in-turn sibling outweighs the out-of-turn head
+        assert_eq!(
+            chain.insert(header(11, 1, DIFF_INTURN), hash(3), None),
+            ChainUpdate::Reorg { depth: 1 }
+        );
+        assert_eq!(chain.insert(header(12, 2, DIFF_NOTURN), hash(4), None), ChainUpdate::Fork);
+        assert_eq!(chain.canonical_hash(11), Some(hash(3)));
+
+        // a justified block wins over a heavier chain
+        assert_eq!(chain.insert(header(12, 3, DIFF_INTURN), hash(5), None), ChainUpdate::Extended);
+        assert_eq!(
+            chain.insert(header(13, 4, DIFF_NOTURN), hash(6), Some(11)),
+            ChainUpdate::Reorg { depth: 2 }
+        );
+        assert_eq!(chain.head().map(|(hash, header)| (hash, header.number)), Some((hash(6), 13)));
+        assert_eq!(chain.canonical_hash(11), Some(hash(2)));
+        let block = |number, byte| BlockRef { number, hash: hash(byte) };
+        assert_eq!(
+            chain.reorg(hash(5)),
+            Reorg {
+                common_ancestor: Some(block(10, 1)),
+                dropped: vec![block(11, 3), block(12, 5)],
+                added: vec![block(11, 2), block(12, 4), block(13, 6)],
+            }
+        );
+    }
+
+    #[test]
+    fn test_relink_and_prune() {
+        let mut chain = ChainTracker::default();
+        chain.insert(header(10, 0, DIFF_NOTURN), hash(1), None);
+        // children of a missing parent extend the head once it arrives
+        assert_eq!(chain.insert(header(12, 2, DIFF_INTURN), hash(3), None), ChainUpdate::Extended);
+        assert_eq!(chain.insert(header(11, 9, DIFF_INTURN), hash(4), None), ChainUpdate::Fork);
+        assert_eq!(chain.insert(header(11, 1, DIFF_INTURN), hash(2), None), ChainUpdate::Canonical);
+        assert_eq!(chain.canonical_hash(11), Some(hash(2)));
+
+        let mut parent = 3;
+        for number in 13..13 + RETAIN_FORKS {
+            chain.insert(header(number, parent, DIFF_INTURN), hash(number as u8), None);
+            parent = number as u8;
+        }
+        // the side chain at 11 was dropped, the canonical block kept
+        assert!(chain.header(&hash(4)).is_none());
+        assert!(chain.header(&hash(2)).is_some());
+        assert_eq!(chain.len(), RETAIN_FORKS as usize + 3);
+    }
+}
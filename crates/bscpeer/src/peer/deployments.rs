@@ -0,0 +1,143 @@
+//! Contract deployments among the transactions of received blocks.
+//!
+//! A transaction without recipient deploys a contract at the address derived from its sender and
+//! nonce. Contracts created by other contracts don't show in the transactions, and whether the
+//! deployment succeeded is only told by the receipt, so the events flag deployment attempts.
+//! A block can be both received and fetched, so its deployments are reported only the first time
+//! it is scanned.
+use crate::peer::{blockstate::BlockEvent, transactions::RecentHashes};
+use alloy_consensus::Transaction;
+use alloy_primitives::{Address, B256};
+use metrics::counter;
+use reth_ethereum_primitives::TransactionSigned;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+
+/// Blocks remembered as scanned, to report their deployments once.
+const SCANNED_BLOCKS: usize = 1024;
+
+/// A contract deployed by a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContractDeployment {
+    /// Address the contract is created at.
+    pub contract: Address,
+    pub deployer: Address,
+    pub nonce: u64,
+    pub tx_hash: B256,
+    /// Size of the init code in bytes.
+    pub init_code_size: usize,
+}
+
+/// Detects the contract deployments of blocks. Clones share the blocks scanned so far.
+#[derive(Debug, Clone)]
+pub struct DeploymentDetector {
+    scanned: Arc<Mutex<RecentHashes>>,
+}
+
+impl Default for DeploymentDetector {
+    fn default() -> Self {
+        Self { scanned: Arc::new(Mutex::new(RecentHashes::new(SCANNED_BLOCKS))) }
+    }
+}
+
+impl DeploymentDetector {
+    /// Returns an event for every contract deployed by the transactions of a block, given their
+    /// recovered senders. A block already scanned returns no events.
+    pub fn detect(
+        &self,
+        block_number: u64,
+        block_hash: B256,
+        transactions: &[TransactionSigned],
+        senders: &[Option<Address>],
+    ) -> Vec<BlockEvent> {
+        if !self.scanned.lock().unwrap().insert(block_hash) {
+            return Vec::new();
+        }
+        detect(block_number, block_hash, transactions, senders)
+    }
+}
+
+/// Returns an event for every contract deployed by the transactions of a block, given their
+/// recovered senders.
+fn detect(
+    block_number: u64,
+    block_hash: B256,
+    transactions: &[TransactionSigned],
+    senders: &[Option<Address>],
+) -> Vec<BlockEvent> {
+    let mut events = Vec::new();
+    for (tx, sender) in transactions.iter().zip(senders).filter(|(tx, _)| tx.is_create()) {
+        let Some(deployer) = *sender else {
+            debug!(block_number, tx_hash = %tx.tx_hash(), "deployment without sender");
+            continue;
+        };
+        counter!("bscpeer_contract_deployments_total").increment(1);
+        let deployment = ContractDeployment {
+            contract: deployer.create(tx.nonce()),
+            deployer,
+            nonce: tx.nonce(),
+            tx_hash: *tx.tx_hash(),
+            init_code_size: tx.input().len(),
+        };
+        events.push(BlockEvent::ContractDeployed { block_number, block_hash, deployment });
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parlia::seal::public_key_address, peer::verify::recover_senders};
+    use alloy_consensus::{SignableTransaction, Signed, TxLegacy};
+    use alloy_primitives::{Signature, TxKind, U256};
+    use secp256k1::{Message, SECP256K1, SecretKey};
+
+    fn sign(secret_key: &SecretKey, tx: TxLegacy) -> TransactionSigned {
+        let message = Message::from_digest(tx.signature_hash().0);
+        let (recovery_id, signature) =
+            SECP256K1.sign_ecdsa_recoverable(&message, secret_key).serialize_compact();
+        let signature = Signature::new(
+            U256::from_be_slice(&signature[..32]),
+            U256::from_be_slice(&signature[32..]),
+            i32::from(recovery_id) != 0,
+        );
+        TransactionSigned::Legacy(Signed::new_unhashed(tx, signature))
+    }
+
+    #[test]
+    fn test_detect_deployments() {
+        let key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let deployer = public_key_address(&key.public_key(SECP256K1));
+        let create = TxLegacy {
+            nonce: 7,
+            to: TxKind::Create,
+            input: vec![0x60, 0x80].into(),
+            ..Default::default()
+        };
+        let call = TxLegacy { to: TxKind::Call(Address::repeat_byte(1)), ..Default::default() };
+        let transactions = [sign(&key, call), sign(&key, create)];
+
+        let senders = recover_senders(&transactions);
+        let detector = DeploymentDetector::default();
+        let events = detector.detect(1, B256::ZERO, &transactions, &senders);
+        let [BlockEvent::ContractDeployed { deployment, .. }] = &events[..] else {
+            panic!("expected one deployment, got {events:?}")
+        };
+        assert_eq!(
+            *deployment,
+            ContractDeployment {
+                contract: deployer.create(7),
+                deployer,
+                nonce: 7,
+                tx_hash: *transactions[1].tx_hash(),
+                init_code_size: 2,
+            }
+        );
+
+        // a block received and fetched is reported once, also by a clone
+        assert!(detector.clone().detect(1, B256::ZERO, &transactions, &senders).is_empty());
+        assert_eq!(detector.detect(2, B256::with_last_byte(2), &transactions, &senders).len(), 1);
+    }
+}
@@ -0,0 +1,575 @@
+//! Dialing of known peers with exponential backoff.
+//!
+//! Discovery only dials a node when it (re)discovers it, so good peers lost after a disconnect
+//! may take long to come back. [`DialScheduler`] remembers the nodes seen through discovery and
+//! the boot nodes, and redials the disconnected ones: peers that were connected recently first,
+//! failed dials after an exponentially growing, jittered delay, and never more than a few at once.
+//!
+//! The peers that were connected are written to a peers file, periodically and when the dialer
+//! stops, and dialed first after a restart. Once no peer is connected, the scheduler enters
+//! recovery: every known node is redialed without backoff, twice as many at once, until a session
+//! is established again. Failures during recovery aren't counted, as the host is likely offline,
+//! and the boot and trusted nodes are never forgotten, so there is someone to dial once it is back.
+use crate::{
+    chain_config::bootnodes::parse_node, config::DialerConfig, instance,
+    peer::proxy::Socks5Forwarder,
+};
+use futures::StreamExt;
+use metrics::{counter, gauge};
+use reth_ethereum_forks::{ForkFilter, ForkId};
+use reth_network::{
+    EthNetworkPrimitives, NetworkEvent, NetworkEventListenerProvider, NetworkHandle, PeersInfo,
+};
+use reth_network_api::{
+    Peers,
+    events::{DiscoveredEvent, DiscoveryEvent, PeerEvent},
+};
+use reth_network_peers::{NodeRecord, PeerId};
+use secp256k1::rand::{self, Rng};
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tokio::{task::JoinHandle, time::interval};
+use tracing::{debug, warn};
+
+/// Dials without an established session after this long count as failed.
+const DIAL_TIMEOUT: Duration = Duration::from_secs(15);
+/// Never connected nodes are forgotten after this many failed dials, unless boot or trusted nodes.
+const MAX_FAILURES: u32 = 8;
+/// Maximum number of remembered nodes.
+const MAX_CANDIDATES: usize = 2048;
+/// Maximum share of the backoff added as random jitter.
+const MAX_JITTER: f64 = 0.5;
+/// Interval between two writes of the peers file.
+const PERSIST_INTERVAL: Duration = Duration::from_secs(300);
+/// Maximum number of peers written to the peers file.
+const MAX_PERSISTED: usize = 256;
+
+/// A node the scheduler may dial.
+#[derive(Debug, Clone)]
+struct Candidate {
+    addr: SocketAddr,
+    /// Whether it's a boot or trusted node, which is never forgotten.
+    seed: bool,
+    connected: bool,
+    last_connected: Option<Instant>,
+    /// Consecutive failed dials.
+    failures: u32,
+    next_attempt: Instant,
+}
+
+/// State of the dial scheduler, driven with explicit timestamps so it can be tested.
+#[derive(Debug)]
+pub struct DialScheduler {
+    candidates: HashMap<PeerId, Candidate>,
+    /// Dials in flight with their start.
+    dialing: HashMap<PeerId, Instant>,
+    max_concurrent: usize,
+    backoff: Backoff,
+    /// Whether no peer is connected, see [`Self::recover`].
+    recovering: bool,
+}
+
+/// Exponential backoff between the dials of a node.
+#[derive(Debug, Clone, Copy)]
+struct Backoff {
+    min: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    /// Returns the delay after the given number of consecutive failures, before jitter.
+    fn delay(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.min.saturating_mul(factor).min(self.max)
+    }
+}
+
+impl DialScheduler {
+    pub fn new(config: &DialerConfig) -> Self {
+        Self {
+            candidates: HashMap::new(),
+            dialing: HashMap::new(),
+            max_concurrent: config.max_concurrent,
+            backoff: Backoff {
+                min: Duration::from_secs(config.min_backoff_secs),
+                max: Duration::from_secs(config.max_backoff_secs),
+            },
+            recovering: false,
+        }
+    }
+
+    /// Remembers a node, updating its address if already known.
+    pub fn add_candidate(&mut self, peer_id: PeerId, addr: SocketAddr, now: Instant) {
+        if let Some(candidate) = self.candidates.get_mut(&peer_id) {
+            candidate.addr = addr;
+            return;
+        }
+        if self.candidates.len() >= MAX_CANDIDATES {
+            return;
+        }
+        let candidate = Candidate {
+            addr,
+            seed: false,
+            connected: false,
+            last_connected: None,
+            failures: 0,
+            next_attempt: now,
+        };
+        self.candidates.insert(peer_id, candidate);
+    }
+
+    /// Remembers a boot or trusted node, which is kept however often dialing it fails.
+    pub fn add_seed(&mut self, peer_id: PeerId, addr: SocketAddr, now: Instant) {
+        self.add_candidate(peer_id, addr, now);
+        if let Some(candidate) = self.candidates.get_mut(&peer_id) {
+            candidate.seed = true;
+        }
+    }
+
+    /// Remembers a node connected in an earlier run, dialed before the never connected ones.
+    pub fn add_known(&mut self, peer_id: PeerId, addr: SocketAddr, now: Instant) {
+        self.add_candidate(peer_id, addr, now);
+        if let Some(candidate) = self.candidates.get_mut(&peer_id) {
+            candidate.last_connected.get_or_insert(now);
+        }
+    }
+
+    pub fn on_connected(&mut self, peer_id: PeerId, now: Instant) {
+        self.dialing.remove(&peer_id);
+        if let Some(candidate) = self.candidates.get_mut(&peer_id) {
+            candidate.connected = true;
+            candidate.last_connected = Some(now);
+            candidate.failures = 0;
+        }
+    }
+
+    /// Forgets a node, e.g. because it turned out to be on another network.
+    pub fn remove_candidate(&mut self, peer_id: &PeerId) {
+        self.candidates.remove(peer_id);
+        self.dialing.remove(peer_id);
+    }
+
+    /// Schedules a redial of a known peer after the minimum backoff.
+    pub fn on_disconnected(&mut self, peer_id: PeerId, now: Instant) {
+        if let Some(candidate) = self.candidates.get_mut(&peer_id) {
+            candidate.connected = false;
+            candidate.next_attempt = now + self.backoff.min;
+        }
+    }
+
+    /// Enters recovery once no peer is connected: every known node becomes due right away, failed
+    /// dials are retried after the minimum backoff and twice as many nodes are dialed at once.
+    pub fn recover(&mut self, now: Instant) {
+        if self.recovering {
+            return;
+        }
+        self.recovering = true;
+        for candidate in self.candidates.values_mut() {
+            candidate.next_attempt = candidate.next_attempt.min(now);
+        }
+    }
+
+    /// Leaves recovery once a peer is connected again.
+    pub fn end_recovery(&mut self) {
+        self.recovering = false;
+    }
+
+    /// Returns the nodes that were connected, most recently connected first.
+    pub fn known_peers(&self) -> Vec<NodeRecord> {
+        let mut known: Vec<_> = self
+            .candidates
+            .iter()
+            .filter_map(|(peer_id, c)| Some((c.last_connected?, NodeRecord::new(c.addr, *peer_id))))
+            .collect();
+        known.sort_by_key(|(last_connected, _)| std::cmp::Reverse(*last_connected));
+        known.into_iter().take(MAX_PERSISTED).map(|(_, node)| node).collect()
+    }
+
+    /// Expires timed out dials and returns the nodes to dial now, most promising first.
+    ///
+    /// `jitter` returns a random number in `[0, 1)`, scaling the share of up to [`MAX_JITTER`]
+    /// added to each backoff.
+    pub fn due(
+        &mut self,
+        now: Instant,
+        mut jitter: impl FnMut() -> f64,
+    ) -> Vec<(PeerId, SocketAddr)> {
+        let expired: Vec<_> = self
+            .dialing
+            .iter()
+            .filter(|(_, started)| now.duration_since(**started) >= DIAL_TIMEOUT)
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+        for peer_id in expired {
+            self.dialing.remove(&peer_id);
+            counter!("bscpeer_dials_failed_total").increment(1);
+            let Some(candidate) = self.candidates.get_mut(&peer_id) else { continue };
+            if candidate.connected {
+                continue;
+            }
+            // while offline, every dial fails whatever the node
+            let delay = if self.recovering {
+                self.backoff.min
+            } else {
+                candidate.failures += 1;
+                let forget = !candidate.seed && candidate.last_connected.is_none();
+                if forget && candidate.failures >= MAX_FAILURES {
+                    self.candidates.remove(&peer_id);
+                    continue;
+                }
+                self.backoff.delay(candidate.failures)
+            };
+            candidate.next_attempt = now + delay.mul_f64(1.0 + MAX_JITTER * jitter());
+        }
+
+        let max_concurrent =
+            if self.recovering { self.max_concurrent * 2 } else { self.max_concurrent };
+        let slots = max_concurrent.saturating_sub(self.dialing.len());
+        let mut ready: Vec<_> = self
+            .candidates
+            .iter()
+            .filter(|(peer_id, candidate)| {
+                !candidate.connected
+                    && candidate.next_attempt <= now
+                    && !self.dialing.contains_key(peer_id)
+            })
+            .collect();
+        // recently good peers first, then those failing the least
+        ready.sort_by_key(|(_, candidate)| {
+            (std::cmp::Reverse(candidate.last_connected), candidate.failures)
+        });
+        let due: Vec<_> =
+            ready.into_iter().take(slots).map(|(peer_id, c)| (*peer_id, c.addr)).collect();
+        for (peer_id, _) in &due {
+            self.dialing.insert(*peer_id, now);
+        }
+        gauge!("bscpeer_dial_candidates").set(self.candidates.len() as f64);
+        due
+    }
+}
+
+/// Task running a [`DialScheduler`] against the network.
+#[derive(Debug)]
+pub struct Dialer {
+    scheduler: DialScheduler,
+    network: NetworkHandle<EthNetworkPrimitives>,
+    /// Connected peers above which nothing is dialed.
+    max_peers: usize,
+    /// Filter the ENR fork ids of discovered nodes are validated against.
+    fork_filter: Option<ForkFilter>,
+    /// Proxy the dials are relayed through.
+    proxy: Option<Socks5Forwarder>,
+    /// File the connected peers are persisted to.
+    peers_file: Option<PathBuf>,
+    /// Whether to redial aggressively once no peer is connected.
+    recovery: bool,
+}
+
+impl Dialer {
+    pub fn new(
+        config: &DialerConfig,
+        network: NetworkHandle<EthNetworkPrimitives>,
+        max_peers: usize,
+    ) -> Self {
+        Self {
+            scheduler: DialScheduler::new(config),
+            network,
+            max_peers,
+            fork_filter: None,
+            proxy: None,
+            peers_file: None,
+            recovery: false,
+        }
+    }
+
+    /// Skips discovered nodes whose ENR advertises a fork id rejected by the filter.
+    pub fn with_fork_filter(mut self, fork_filter: ForkFilter) -> Self {
+        self.fork_filter = Some(fork_filter);
+        self
+    }
+
+    /// Dials through a SOCKS5 proxy.
+    pub fn with_proxy(mut self, proxy: Socks5Forwarder) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Loads the peers connected in an earlier run from the file and persists the connected
+    /// peers to it.
+    pub fn with_peers_file(mut self, path: PathBuf) -> Self {
+        self.peers_file = Some(path);
+        self
+    }
+
+    /// Redials every known node without backoff while no peer is connected.
+    pub fn with_recovery(mut self) -> Self {
+        self.recovery = true;
+        self
+    }
+
+    /// Spawns the dialer, seeded with the given nodes and learning new ones through discovery.
+    pub fn spawn(mut self, nodes: &[NodeRecord]) -> JoinHandle<()> {
+        let now = Instant::now();
+        if let Some(path) = &self.peers_file {
+            match load_peers(path) {
+                Ok(known) => {
+                    debug!(count = known.len(), "loaded persisted peers");
+                    for node in known {
+                        self.scheduler.add_known(node.id, node.tcp_addr(), now);
+                    }
+                }
+                Err(e) => warn!(path = %path.display(), "failed to load persisted peers: {}", e),
+            }
+        }
+        for node in nodes {
+            self.scheduler.add_seed(node.id, node.tcp_addr(), now);
+        }
+        instance::spawn(self.run())
+    }
+
+    async fn run(mut self) {
+        let mut sessions = self.network.event_listener();
+        let mut discovery = self.network.discovery_listener();
+        let mut tick = interval(Duration::from_secs(1));
+        let mut persist = interval(PERSIST_INTERVAL);
+        loop {
+            tokio::select! {
+                Some(event) = sessions.next() => match event {
+                    NetworkEvent::ActivePeerSession { info, .. } => {
+                        self.scheduler.on_connected(info.peer_id, Instant::now());
+                    }
+                    NetworkEvent::Peer(PeerEvent::SessionClosed { peer_id, .. }) => {
+                        self.scheduler.on_disconnected(peer_id, Instant::now());
+                    }
+                    _ => {}
+                },
+                Some(event) = discovery.next() => match event {
+                    DiscoveryEvent::NewNode(DiscoveredEvent::EventQueued {
+                        peer_id,
+                        addr,
+                        fork_id,
+                    }) => {
+                        if fork_id.is_none_or(|fork_id| self.accepts(fork_id)) {
+                            self.scheduler.add_candidate(peer_id, addr.tcp(), Instant::now());
+                        }
+                    }
+                    DiscoveryEvent::EnrForkId(peer_id, fork_id) => {
+                        if !self.accepts(fork_id) {
+                            self.scheduler.remove_candidate(&peer_id);
+                            if let Some(proxy) = &self.proxy {
+                                proxy.remove(&peer_id);
+                            }
+                        }
+                    }
+                },
+                _ = tick.tick() => self.dial(),
+                _ = persist.tick() => self.persist(),
+            }
+        }
+    }
+
+    fn persist(&self) {
+        let Some(path) = &self.peers_file else { return };
+        let known = self.scheduler.known_peers();
+        // a restart before the first session would wipe the file
+        if known.is_empty() {
+            return;
+        }
+        if let Err(e) = save_peers(path, &known) {
+            warn!(path = %path.display(), "failed to persist peers: {}", e);
+        }
+    }
+
+    fn accepts(&self, fork_id: ForkId) -> bool {
+        self.fork_filter.as_ref().is_none_or(|filter| filter.validate(fork_id).is_ok())
+    }
+
+    fn dial(&mut self) {
+        let connected = self.network.num_connected_peers();
+        if self.recovery {
+            match connected {
+                0 => self.scheduler.recover(Instant::now()),
+                _ => self.scheduler.end_recovery(),
+            }
+        }
+        if connected >= self.max_peers {
+            return;
+        }
+        let mut rng = rand::thread_rng();
+        for (peer_id, addr) in self.scheduler.due(Instant::now(), || rng.r#gen::<f64>()) {
+            debug!(%peer_id, %addr, "dialing known peer");
+            counter!("bscpeer_dials_total").increment(1);
+            let addr = match &self.proxy {
+                Some(proxy) => match proxy.forward(peer_id, addr) {
+                    Ok(local) => local,
+                    Err(e) => {
+                        warn!(%peer_id, "failed to bind proxy listener: {}", e);
+                        continue;
+                    }
+                },
+                None => addr,
+            };
+            self.network.connect_peer(peer_id, addr);
+        }
+    }
+}
+
+impl Drop for Dialer {
+    /// Persists the peers once more when the dialer stops with its node.
+    fn drop(&mut self) {
+        self.persist();
+    }
+}
+
+/// Reads a peers file, a JSON list of enode URLs, skipping invalid entries. A missing file holds
+/// no peers.
+fn load_peers(path: &Path) -> io::Result<Vec<NodeRecord>> {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let urls: Vec<String> = serde_json::from_slice(&contents)?;
+    Ok(urls.iter().filter_map(|url| parse_node(url).ok()).collect())
+}
+
+fn save_peers(path: &Path, peers: &[NodeRecord]) -> io::Result<()> {
+    let urls: Vec<_> = peers.iter().map(ToString::to_string).collect();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_vec_pretty(&urls)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler() -> DialScheduler {
+        DialScheduler::new(&DialerConfig {
+            enabled: true,
+            max_concurrent: 2,
+            min_backoff_secs: 5,
+            max_backoff_secs: 60,
+        })
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_backoff() {
+        let backoff = scheduler().backoff;
+        assert_eq!(backoff.delay(1), Duration::from_secs(5));
+        assert_eq!(backoff.delay(2), Duration::from_secs(10));
+        assert_eq!(backoff.delay(4), Duration::from_secs(40));
+        assert_eq!(backoff.delay(5), Duration::from_secs(60));
+        assert_eq!(backoff.delay(40), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_dial_schedule() {
+        let mut scheduler = scheduler();
+        let start = Instant::now();
+        let (good, a, b) = (PeerId::repeat_byte(1), PeerId::repeat_byte(2), PeerId::repeat_byte(3));
+        scheduler.add_candidate(a, addr(1), start);
+        scheduler.add_candidate(b, addr(2), start);
+        scheduler.add_candidate(good, addr(3), start);
+        scheduler.on_connected(good, start);
+        scheduler.on_disconnected(good, start);
+
+        // concurrent dials are capped, the recently good peer waits for its backoff
+        let due = scheduler.due(start, || 0.0);
+        assert_eq!(due.len(), 2);
+        assert!(due.iter().all(|(peer_id, _)| *peer_id != good));
+
+        // the recently good peer goes first once due
+        let later = start + Duration::from_secs(5);
+        scheduler.on_connected(a, later);
+        let due = scheduler.due(later, || 0.0);
+        assert_eq!(due, vec![(good, addr(3))]);
+
+        // a timed out dial is retried after the backoff
+        let timeout = start + DIAL_TIMEOUT;
+        assert!(scheduler.due(timeout, || 0.0).is_empty());
+        assert_eq!(scheduler.candidates[&b].failures, 1);
+        assert_eq!(scheduler.candidates[&b].next_attempt, timeout + Duration::from_secs(5));
+        let retry = timeout + Duration::from_secs(5);
+        assert_eq!(scheduler.due(retry, || 0.0), vec![(b, addr(2))]);
+    }
+
+    #[test]
+    fn test_recovery() {
+        let mut scheduler = scheduler();
+        let start = Instant::now();
+        let peer_id = PeerId::repeat_byte(1);
+        scheduler.add_candidate(peer_id, addr(1), start);
+
+        // two failed dials back off for 10s
+        assert_eq!(scheduler.due(start, || 0.0).len(), 1);
+        let retry = start + DIAL_TIMEOUT + Duration::from_secs(5);
+        assert!(scheduler.due(start + DIAL_TIMEOUT, || 0.0).is_empty());
+        assert_eq!(scheduler.due(retry, || 0.0).len(), 1);
+        let timeout = retry + DIAL_TIMEOUT;
+        assert!(scheduler.due(timeout, || 0.0).is_empty());
+        assert_eq!(scheduler.candidates[&peer_id].next_attempt, timeout + Duration::from_secs(10));
+
+        // recovery redials right away, and after the minimum backoff once failed, without
+        // counting the failure
+        scheduler.recover(timeout);
+        assert_eq!(scheduler.due(timeout, || 0.0), vec![(peer_id, addr(1))]);
+        let timeout = timeout + DIAL_TIMEOUT;
+        assert!(scheduler.due(timeout, || 0.0).is_empty());
+        assert_eq!(scheduler.candidates[&peer_id].next_attempt, timeout + Duration::from_secs(5));
+        assert_eq!(scheduler.candidates[&peer_id].failures, 2);
+
+        // with twice as many dials at once
+        for port in 2..=5 {
+            scheduler.add_candidate(PeerId::repeat_byte(port as u8), addr(port), timeout);
+        }
+        assert_eq!(scheduler.due(timeout + Duration::from_secs(5), || 0.0).len(), 4);
+    }
+
+    #[test]
+    fn test_seeds_kept() {
+        let mut scheduler = scheduler();
+        let mut now = Instant::now();
+        let (seed, other) = (PeerId::repeat_byte(1), PeerId::repeat_byte(2));
+        scheduler.add_seed(seed, addr(1), now);
+        scheduler.add_candidate(other, addr(2), now);
+        for _ in 0..MAX_FAILURES {
+            scheduler.due(now, || 0.0);
+            now += DIAL_TIMEOUT;
+            scheduler.due(now, || 0.0);
+            now += scheduler.backoff.max;
+        }
+        assert!(scheduler.candidates.contains_key(&seed));
+        assert!(!scheduler.candidates.contains_key(&other));
+    }
+
+    #[test]
+    fn test_peers_file() {
+        let mut scheduler = scheduler();
+        let start = Instant::now();
+        let (a, b, c) = (PeerId::repeat_byte(1), PeerId::repeat_byte(2), PeerId::repeat_byte(3));
+        scheduler.add_candidate(a, addr(1), start);
+        scheduler.add_candidate(b, addr(2), start);
+        scheduler.add_known(c, addr(3), start);
+        scheduler.on_connected(a, start + Duration::from_secs(1));
+        let known = scheduler.known_peers();
+        assert_eq!(known, vec![NodeRecord::new(addr(1), a), NodeRecord::new(addr(3), c)]);
+
+        let dir = std::env::temp_dir().join(format!("bscpeer-peers-{}", std::process::id()));
+        let path = dir.join("peers.json");
+        assert!(load_peers(&path).unwrap().is_empty());
+        save_peers(&path, &known).unwrap();
+        assert_eq!(load_peers(&path).unwrap(), known);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
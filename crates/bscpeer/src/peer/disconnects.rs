@@ -0,0 +1,206 @@
+//! Why sessions end.
+//!
+//! A disconnect reason alone doesn't tell who is at fault: `TooManyPeers` means the remote is
+//! full, while breaches and useless-peer disconnects point at one side misbehaving. Reasons are
+//! grouped into [`DisconnectCategory`]s and counted per client, and [`spawn_report`] logs the top
+//! reasons and offending clients periodically, so a rise of our own faults stands out from the
+//! usual churn.
+use crate::{instance, peer::handshake::client_name};
+use metrics::counter;
+use reth_eth_wire_types::DisconnectReason;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{task::JoinHandle, time::interval};
+use tracing::info;
+
+/// Interval between two reports.
+const REPORT_INTERVAL: Duration = Duration::from_secs(300);
+/// Entries in each top list of a report.
+const REPORT_TOP: usize = 5;
+
+/// Disconnect reasons grouped by what they say about the session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectCategory {
+    /// The remote has no slot left or already has a session with us.
+    Capacity,
+    /// Protocol or usefulness violations, by either side.
+    Misbehaviour,
+    /// Connection failures and timeouts, or no reason given.
+    Network,
+    /// Orderly shutdowns and self-connections.
+    Requested,
+}
+
+impl DisconnectCategory {
+    pub const fn of(reason: Option<DisconnectReason>) -> Self {
+        match reason {
+            Some(DisconnectReason::TooManyPeers | DisconnectReason::AlreadyConnected) => {
+                Self::Capacity
+            }
+            Some(
+                DisconnectReason::ProtocolBreach
+                | DisconnectReason::UselessPeer
+                | DisconnectReason::IncompatibleP2PProtocolVersion
+                | DisconnectReason::NullNodeIdentity
+                | DisconnectReason::UnexpectedHandshakeIdentity
+                | DisconnectReason::SubprotocolSpecific,
+            ) => Self::Misbehaviour,
+            Some(
+                DisconnectReason::DisconnectRequested
+                | DisconnectReason::ClientQuitting
+                | DisconnectReason::ConnectedToSelf,
+            ) => Self::Requested,
+            Some(DisconnectReason::TcpSubsystemError | DisconnectReason::PingTimeout) | None => {
+                Self::Network
+            }
+        }
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Capacity => "capacity",
+            Self::Misbehaviour => "misbehaviour",
+            Self::Network => "network",
+            Self::Requested => "requested",
+        }
+    }
+}
+
+/// Disconnects of one client version.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientDisconnects {
+    pub client: String,
+    pub total: u64,
+    pub misbehaviour: u64,
+    pub reasons: BTreeMap<String, u64>,
+}
+
+/// Snapshot of the disconnect counters.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DisconnectReport {
+    pub total: u64,
+    pub categories: BTreeMap<DisconnectCategory, u64>,
+    /// Most frequent reasons, most frequent first.
+    pub top_reasons: Vec<(String, u64)>,
+    /// Clients with the most misbehaviour disconnects, most first.
+    pub top_offenders: Vec<ClientDisconnects>,
+}
+
+/// Disconnect counters since startup.
+#[derive(Debug, Default)]
+pub struct DisconnectStats {
+    total: u64,
+    categories: BTreeMap<DisconnectCategory, u64>,
+    reasons: HashMap<String, u64>,
+    clients: HashMap<String, ClientDisconnects>,
+}
+
+impl DisconnectStats {
+    /// Records a closed session of the client.
+    pub fn record(&mut self, client_version: &str, reason: Option<DisconnectReason>) {
+        let category = DisconnectCategory::of(reason);
+        let reason = reason.map_or_else(|| "none".to_string(), |r| r.to_string());
+        counter!("bscpeer_session_disconnect_categories_total", "category" => category.as_str())
+            .increment(1);
+
+        self.total += 1;
+        *self.categories.entry(category).or_default() += 1;
+        *self.reasons.entry(reason.clone()).or_default() += 1;
+        let client = client_name(client_version);
+        let stats = self.clients.entry(client.to_string()).or_insert_with(|| ClientDisconnects {
+            client: client.to_string(),
+            ..Default::default()
+        });
+        stats.total += 1;
+        if category == DisconnectCategory::Misbehaviour {
+            stats.misbehaviour += 1;
+        }
+        *stats.reasons.entry(reason).or_default() += 1;
+    }
+
+    /// Returns the counters, with the top `limit` reasons and offending clients.
+    pub fn report(&self, limit: usize) -> DisconnectReport {
+        let mut top_reasons: Vec<_> =
+            self.reasons.iter().map(|(reason, count)| (reason.clone(), *count)).collect();
+        top_reasons.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top_reasons.truncate(limit);
+
+        let mut top_offenders: Vec<_> =
+            self.clients.values().filter(|client| client.misbehaviour > 0).cloned().collect();
+        top_offenders.sort_by(|a, b| {
+            b.misbehaviour.cmp(&a.misbehaviour).then_with(|| a.client.cmp(&b.client))
+        });
+        top_offenders.truncate(limit);
+
+        DisconnectReport {
+            total: self.total,
+            categories: self.categories.clone(),
+            top_reasons,
+            top_offenders,
+        }
+    }
+}
+
+/// Spawns the task logging a disconnect report periodically.
+pub fn spawn_report(stats: Arc<Mutex<DisconnectStats>>) -> JoinHandle<()> {
+    instance::spawn(async move {
+        let mut interval = interval(REPORT_INTERVAL);
+        // nothing to report at startup
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let report = stats.lock().unwrap().report(REPORT_TOP);
+            if report.total == 0 {
+                continue;
+            }
+            let offenders: Vec<_> = report
+                .top_offenders
+                .iter()
+                .map(|client| (client.client.as_str(), client.misbehaviour))
+                .collect();
+            info!(
+                total = report.total,
+                categories = ?report.categories,
+                top_reasons = ?report.top_reasons,
+                top_offenders = ?offenders,
+                "disconnect report"
+            );
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disconnect_report() {
+        let mut stats = DisconnectStats::default();
+        stats.record("Geth/v1.5.7-abc/linux", Some(DisconnectReason::TooManyPeers));
+        stats.record("Geth/v1.5.7-def/linux", Some(DisconnectReason::TooManyPeers));
+        stats.record("Geth/v1.4.0/linux", Some(DisconnectReason::UselessPeer));
+        stats.record("erigon/v3", Some(DisconnectReason::ProtocolBreach));
+        stats.record("erigon/v3", Some(DisconnectReason::ProtocolBreach));
+        stats.record("erigon/v3", None);
+
+        let report = stats.report(2);
+        assert_eq!(report.total, 6);
+        assert_eq!(report.categories[&DisconnectCategory::Capacity], 2);
+        assert_eq!(report.categories[&DisconnectCategory::Misbehaviour], 3);
+        assert_eq!(report.categories[&DisconnectCategory::Network], 1);
+        assert_eq!(report.top_reasons.len(), 2);
+        assert_eq!(report.top_reasons[0].1, 2);
+
+        let offenders: Vec<_> =
+            report.top_offenders.iter().map(|c| (c.client.as_str(), c.misbehaviour)).collect();
+        assert_eq!(offenders, vec![("erigon/v3", 2), ("Geth/v1.4.0", 1)]);
+        assert_eq!(report.top_offenders[0].total, 3);
+    }
+}
@@ -0,0 +1,125 @@
+//! Alerts on validators missing their turns.
+//!
+//! Each block has a validator in turn according to the snapshot's proposer schedule. When
+//! another validator produces it instead, the in-turn one missed its slot. [`DowntimeMonitor`]
+//! counts the consecutive slots each validator missed and raises an alert once the streak
+//! reaches the threshold. The streak ends with the next block the validator produces.
+use crate::{
+    chain_config::validators::Validator, config::AlertsConfig, peer::blockstate::BlockEvent,
+};
+use alloy_primitives::Address;
+use metrics::{counter, gauge};
+use std::collections::HashMap;
+use tracing::info;
+
+/// Consecutive slots a validator missed.
+#[derive(Debug)]
+struct Streak {
+    /// First missed block.
+    since_block: u64,
+    missed: u64,
+    alerted: bool,
+}
+
+/// Tracks the missed-slot streaks of the validators.
+#[derive(Debug)]
+pub struct DowntimeMonitor {
+    /// Streak length raising an alert, disabled when zero.
+    threshold: u64,
+    streaks: HashMap<Address, Streak>,
+}
+
+impl DowntimeMonitor {
+    pub fn new(config: &AlertsConfig) -> Self {
+        Self { threshold: config.missed_blocks, streaks: HashMap::new() }
+    }
+
+    /// Records the producer of a block and the validator that was in turn, returning the alert
+    /// entered by the in-turn validator, if any.
+    pub fn record(
+        &mut self,
+        number: u64,
+        producer: &Validator,
+        inturn: Option<&Validator>,
+    ) -> Option<BlockEvent> {
+        if let Some(streak) = self.streaks.remove(&producer.address) {
+            gauge!("bscpeer_validator_missed_streak", "validator" => producer.label()).set(0.0);
+            if streak.alerted {
+                info!(
+                    validator = %producer.label(),
+                    address = %producer.address,
+                    number,
+                    missed_blocks = streak.missed,
+                    "validator producing blocks again"
+                );
+            }
+        }
+        let inturn = inturn.filter(|inturn| inturn.address != producer.address)?;
+
+        let label = inturn.label();
+        counter!("bscpeer_validator_missed_blocks_total", "validator" => label.clone())
+            .increment(1);
+        let streak = self.streaks.entry(inturn.address).or_insert(Streak {
+            since_block: number,
+            missed: 0,
+            alerted: false,
+        });
+        streak.missed += 1;
+        gauge!("bscpeer_validator_missed_streak", "validator" => label.clone())
+            .set(streak.missed as f64);
+        if self.threshold == 0 || streak.alerted || streak.missed < self.threshold {
+            return None;
+        }
+        streak.alerted = true;
+        counter!("bscpeer_validator_downtime_alerts_total", "validator" => label).increment(1);
+        Some(BlockEvent::ValidatorDown {
+            validator: inturn.clone(),
+            block_number: number,
+            since_block: streak.since_block,
+            missed_blocks: streak.missed,
+        })
+    }
+
+    /// Returns the current missed-slot streak of the validator.
+    pub fn streak(&self, address: &Address) -> u64 {
+        self.streaks.get(address).map_or(0, |streak| streak.missed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missed_turns() {
+        let config = AlertsConfig { missed_blocks: 3, ..Default::default() };
+        let mut monitor = DowntimeMonitor::new(&config);
+        let validator = |byte| Validator { address: Address::repeat_byte(byte), name: None };
+        let (a, b) = (validator(1), validator(2));
+
+        // in turn, or without a snapshot, nothing is missed
+        assert!(monitor.record(1, &a, Some(&a)).is_none());
+        assert!(monitor.record(2, &a, None).is_none());
+
+        // alerted once when the streak reaches the threshold
+        assert!(monitor.record(3, &b, Some(&a)).is_none());
+        assert!(monitor.record(4, &b, Some(&a)).is_none());
+        let alert = monitor.record(5, &b, Some(&a));
+        assert!(matches!(
+            alert,
+            Some(BlockEvent::ValidatorDown {
+                block_number: 5,
+                since_block: 3,
+                missed_blocks: 3,
+                ..
+            })
+        ));
+        assert!(monitor.record(6, &b, Some(&a)).is_none());
+        assert_eq!(monitor.streak(&a.address), 4);
+
+        // producing a block ends the streak
+        assert!(monitor.record(7, &a, Some(&b)).is_none());
+        assert_eq!(monitor.streak(&a.address), 0);
+        assert_eq!(monitor.streak(&b.address), 1);
+    }
+}
@@ -0,0 +1,183 @@
+//! Duplicate block announcements.
+//!
+//! Every block reaches the node many times: in full from a few peers and as a hash from most
+//! others. [`DuplicateTracker`] remembers who announced each hash within a sliding window, so the
+//! importer only processes the first copy, and keeps per-peer duplication ratios: a peer that
+//! mostly repeats blocks others already delivered is a poor propagation source.
+//!
+//! A block whose fetch failed is forgotten, so the next announcement of it is processed again.
+use alloy_primitives::B256;
+use metrics::counter;
+use reth_network_peers::PeerId;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// How long announcements of a hash are remembered.
+const WINDOW: Duration = Duration::from_secs(120);
+/// Maximum number of remembered hashes.
+const MAX_HASHES: usize = 4096;
+
+/// Announcers of a hash.
+#[derive(Debug)]
+struct Announcements {
+    number: u64,
+    /// Time of the first announcement, matching the entry of the hash in the expiry order.
+    first: Instant,
+    first_peer: PeerId,
+    peers: HashSet<PeerId>,
+    count: u64,
+}
+
+/// Announcement counters of a peer.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerDuplication {
+    pub peer_id: PeerId,
+    pub announcements: u64,
+    /// Announcements of hashes already announced by another peer or itself.
+    pub duplicates: u64,
+    /// Announcements of hashes no other peer announced before.
+    pub first_seen: u64,
+    /// Share of duplicates in the announcements.
+    pub ratio: f64,
+}
+
+/// Announcements of recent block hashes and duplication counters per peer.
+#[derive(Debug, Default)]
+pub struct DuplicateTracker {
+    hashes: HashMap<B256, Announcements>,
+    /// Hashes by first announcement, oldest first, to expire them.
+    order: VecDeque<(Instant, B256)>,
+    peers: HashMap<PeerId, PeerDuplication>,
+}
+
+impl DuplicateTracker {
+    /// Records an announcement, full block or hash, returning `true` if the hash is new within
+    /// the window and should be processed.
+    pub fn record(
+        &mut self,
+        peer_id: PeerId,
+        hash: B256,
+        number: u64,
+        kind: &'static str,
+        now: Instant,
+    ) -> bool {
+        self.expire(now);
+        let stats = self
+            .peers
+            .entry(peer_id)
+            .or_insert_with(|| PeerDuplication { peer_id, ..Default::default() });
+        stats.announcements += 1;
+
+        let first = match self.hashes.get_mut(&hash) {
+            Some(announcements) => {
+                announcements.count += 1;
+                announcements.peers.insert(peer_id);
+                stats.duplicates += 1;
+                counter!("bscpeer_duplicate_announcements_total", "kind" => kind).increment(1);
+                false
+            }
+            None => {
+                let announcements = Announcements {
+                    number,
+                    first: now,
+                    first_peer: peer_id,
+                    peers: HashSet::from([peer_id]),
+                    count: 1,
+                };
+                self.hashes.insert(hash, announcements);
+                self.order.push_back((now, hash));
+                stats.first_seen += 1;
+                true
+            }
+        };
+        stats.ratio = stats.duplicates as f64 / stats.announcements as f64;
+        first
+    }
+
+    /// Returns how often the hash was announced within the window, and by whom first.
+    pub fn announcements(&self, hash: &B256) -> Option<(PeerId, usize, u64)> {
+        self.hashes.get(hash).map(|announcements| {
+            (announcements.first_peer, announcements.peers.len(), announcements.count)
+        })
+    }
+
+    /// Forgets the announcements of the blocks at a height whose fetch failed, so the next
+    /// announcement is processed again.
+    pub fn forget(&mut self, number: u64) {
+        self.hashes.retain(|_, announcements| announcements.number != number);
+    }
+
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.peers.remove(peer_id);
+    }
+
+    /// Returns the counters of every peer, most duplicating first.
+    pub fn peers(&self) -> Vec<PeerDuplication> {
+        let mut peers: Vec<_> = self.peers.values().cloned().collect();
+        peers.sort_by(|a, b| b.ratio.total_cmp(&a.ratio));
+        peers
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((first, hash)) = self.order.front().copied() {
+            if now.duration_since(first) < WINDOW && self.order.len() < MAX_HASHES {
+                break;
+            }
+            self.order.pop_front();
+            // unless forgotten and announced again since
+            if self.hashes.get(&hash).is_some_and(|announcements| announcements.first == first) {
+                self.hashes.remove(&hash);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicates() {
+        let mut tracker = DuplicateTracker::default();
+        let (fast, slow) = (PeerId::repeat_byte(1), PeerId::repeat_byte(2));
+        let (a, b) = (B256::with_last_byte(1), B256::with_last_byte(2));
+        let now = Instant::now();
+
+        assert!(tracker.record(fast, a, 1, "block", now));
+        assert!(!tracker.record(slow, a, 1, "hash", now));
+        assert!(!tracker.record(fast, a, 1, "hash", now));
+        assert!(tracker.record(fast, b, 2, "block", now));
+        assert!(!tracker.record(slow, b, 2, "block", now));
+        assert_eq!(tracker.announcements(&a), Some((fast, 2, 3)));
+
+        let peers = tracker.peers();
+        assert_eq!(peers[0].peer_id, slow);
+        assert_eq!(peers[0].ratio, 1.0);
+        assert_eq!((peers[1].first_seen, peers[1].duplicates), (2, 1));
+
+        // announcements are forgotten after the window
+        assert!(tracker.record(slow, a, 1, "hash", now + WINDOW));
+        assert_eq!(tracker.announcements(&b), None);
+    }
+
+    #[test]
+    fn test_forget() {
+        let mut tracker = DuplicateTracker::default();
+        let (peer, hash) = (PeerId::repeat_byte(1), B256::with_last_byte(1));
+        let now = Instant::now();
+        assert!(tracker.record(peer, hash, 1, "hash", now));
+        assert!(!tracker.record(peer, hash, 1, "hash", now));
+
+        // the fetch failed, the next announcement is processed
+        tracker.forget(1);
+        let later = now + WINDOW / 2;
+        assert!(tracker.record(peer, hash, 1, "hash", later));
+        // and not expired with the first one
+        tracker.expire(now + WINDOW);
+        assert!(tracker.announcements(&hash).is_some());
+    }
+}
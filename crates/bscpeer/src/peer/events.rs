@@ -0,0 +1,471 @@
+//! Fan-out delivery of block events to independent subscribers.
+//!
+//! Every subscriber, e.g. the node's event loop, an analytics task or a TUI, has its own bounded
+//! queue and [`OverflowPolicy`], so a slow subscriber never holds back the others. The queue depth
+//! and the dropped events are reported per subscriber.
+//!
+//! Pending transactions outnumber the block events by far, so they are delivered only to the
+//! subscribers of [`EventSender::subscribe_transactions`], on queues of their own, and never take
+//! the room of the block events.
+//!
+//! Every event is delivered in an [`EventEnvelope`] stamped with its [`Provenance`]: the peer the
+//! data came from, when it was received and over which eth version, for propagation studies and
+//! deduplication in the sinks.
+use crate::peer::blockstate::BlockEvent;
+use metrics::{counter, gauge};
+use reth_eth_wire_types::EthVersion;
+use reth_network_peers::PeerId;
+use serde::Serialize;
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    ops::Deref,
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{debug, warn};
+
+/// Default capacity of the queue of a block event subscriber.
+pub const DEFAULT_EVENT_BUFFER: usize = 1024;
+/// Coalesced block numbers kept at most per peer, the lowest are dropped first.
+pub const MAX_COALESCED_NUMBERS: usize = 1024;
+/// Interval at which coalesced announcements are delivered if no other event comes along.
+pub const COALESCE_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Origin of the monotonic receive times.
+static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// What happens to the events of a subscriber whose queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Block hash announcements are coalesced into a pending announcement per peer, delivered
+    /// once there is room again. Any other event is dropped.
+    #[default]
+    CoalesceHashes,
+    /// Every event is dropped.
+    Drop,
+}
+
+/// When data was received, on the wall clock and on the monotonic clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReceiveTime {
+    /// Milliseconds since the Unix epoch.
+    pub at_ms: u64,
+    /// Microseconds since the node started.
+    pub monotonic_us: u64,
+}
+
+impl ReceiveTime {
+    pub fn now() -> Self {
+        Self {
+            at_ms: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()
+                as u64,
+            monotonic_us: STARTED.elapsed().as_micros() as u64,
+        }
+    }
+}
+
+/// Where and when the data of an event was received.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Provenance {
+    /// Peer the data came from, `None` for events derived by the node, e.g. alerts.
+    pub peer_id: Option<PeerId>,
+    /// Wall clock receive time in milliseconds since the Unix epoch.
+    pub received_at_ms: u64,
+    /// Receive time in microseconds since the node started, unaffected by clock adjustments.
+    pub received_monotonic_us: u64,
+    /// Eth protocol version negotiated with the peer.
+    pub eth_version: Option<u8>,
+    /// Whether the event is about a block the node hadn't seen before, `None` if unknown.
+    pub first_seen: Option<bool>,
+}
+
+impl Provenance {
+    fn new(
+        peer_id: Option<PeerId>,
+        eth_version: Option<EthVersion>,
+        received: ReceiveTime,
+    ) -> Self {
+        Self {
+            peer_id,
+            received_at_ms: received.at_ms,
+            received_monotonic_us: received.monotonic_us,
+            eth_version: eth_version.map(u8::from),
+            first_seen: None,
+        }
+    }
+}
+
+/// Event delivered to the sinks, serialized as the event with a `provenance` field.
+#[derive(Debug, Clone, Serialize)]
+pub struct EventEnvelope {
+    #[serde(flatten)]
+    pub event: BlockEvent,
+    pub provenance: Provenance,
+}
+
+impl Deref for EventEnvelope {
+    type Target = BlockEvent;
+
+    fn deref(&self) -> &BlockEvent {
+        &self.event
+    }
+}
+
+/// Negotiated eth versions of the active sessions, shared with the node tracking them.
+#[derive(Debug, Clone, Default)]
+pub struct SessionVersions(Arc<Mutex<HashMap<PeerId, EthVersion>>>);
+
+impl SessionVersions {
+    pub fn insert(&self, peer_id: PeerId, version: EthVersion) {
+        self.0.lock().unwrap().insert(peer_id, version);
+    }
+
+    pub fn remove(&self, peer_id: &PeerId) {
+        self.0.lock().unwrap().remove(peer_id);
+    }
+
+    fn get(&self, peer_id: &PeerId) -> Option<EthVersion> {
+        self.0.lock().unwrap().get(peer_id).copied()
+    }
+}
+
+/// Queue of a single subscriber.
+#[derive(Debug)]
+struct Subscriber {
+    name: String,
+    sender: mpsc::Sender<EventEnvelope>,
+    policy: OverflowPolicy,
+    /// Whether the subscriber receives the pending transactions, and only them.
+    transactions: bool,
+    /// Hash announcements that didn't fit into the queue by announcing peer, in the order the
+    /// peers first overflowed, with the provenance of the peer's first coalesced announcement.
+    coalesced: VecDeque<(PeerId, BTreeSet<u64>, Provenance)>,
+}
+
+impl Subscriber {
+    /// Queues an event, returning `false` once the receiver is dropped.
+    fn deliver(&mut self, envelope: EventEnvelope) -> bool {
+        self.flush_coalesced();
+        match self.sender.try_send(envelope) {
+            Ok(()) => {}
+            Err(TrySendError::Full(EventEnvelope {
+                event: BlockEvent::NewBlockHashes { peer_id, block_numbers },
+                provenance,
+            })) if self.policy == OverflowPolicy::CoalesceHashes => {
+                let numbers = match self.coalesced.iter().position(|(peer, ..)| *peer == peer_id) {
+                    Some(index) => &mut self.coalesced[index].1,
+                    None => {
+                        self.coalesced.push_back((peer_id, BTreeSet::new(), provenance));
+                        &mut self.coalesced.back_mut().expect("just pushed").1
+                    }
+                };
+                numbers.extend(block_numbers);
+                let evicted = numbers.len().saturating_sub(MAX_COALESCED_NUMBERS);
+                for _ in 0..evicted {
+                    numbers.pop_first();
+                }
+                if evicted > 0 {
+                    let subscriber = self.name.clone();
+                    counter!("bscpeer_block_hashes_coalesce_evicted_total", "subscriber" => subscriber)
+                        .increment(evicted as u64);
+                }
+                counter!("bscpeer_block_hashes_coalesced_total", "subscriber" => self.name.clone())
+                    .increment(1);
+            }
+            Err(TrySendError::Full(envelope)) => {
+                let subscriber = self.name.clone();
+                counter!(
+                    "bscpeer_block_events_dropped_total",
+                    "subscriber" => subscriber,
+                    "kind" => envelope.kind()
+                )
+                .increment(1);
+            }
+            Err(TrySendError::Closed(_)) => {
+                debug!(subscriber = self.name, "block event subscriber closed");
+                gauge!("bscpeer_block_events_queue_depth", "subscriber" => self.name.clone())
+                    .set(0.0);
+                return false;
+            }
+        }
+        gauge!("bscpeer_block_events_queue_depth", "subscriber" => self.name.clone())
+            .set((self.sender.max_capacity() - self.sender.capacity()) as f64);
+        true
+    }
+
+    /// Delivers the coalesced hash announcements, one event per peer, while there is room in
+    /// the queue.
+    fn flush_coalesced(&mut self) {
+        while let Some((peer_id, numbers, provenance)) = self.coalesced.pop_front() {
+            let block_numbers = numbers.into_iter().collect();
+            let event = BlockEvent::NewBlockHashes { peer_id, block_numbers };
+            if let Err(TrySendError::Full(EventEnvelope {
+                event: BlockEvent::NewBlockHashes { peer_id, block_numbers },
+                provenance,
+            })) = self.sender.try_send(EventEnvelope { event, provenance })
+            {
+                let numbers = block_numbers.into_iter().collect();
+                self.coalesced.push_front((peer_id, numbers, provenance));
+                return;
+            }
+        }
+    }
+}
+
+/// Sending side of the block event bus, never blocking the caller. Clones share the subscribers.
+#[derive(Debug, Clone, Default)]
+pub struct EventSender {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+    versions: SessionVersions,
+    /// Events sent so far.
+    sent: Arc<AtomicU64>,
+}
+
+impl EventSender {
+    /// Returns the eth versions stamped on the events, to be updated as sessions come and go.
+    pub fn session_versions(&self) -> SessionVersions {
+        self.versions.clone()
+    }
+
+    /// Returns the number of events sent so far.
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    /// Subscribes to the events sent from now on, queueing up to `capacity` of them. The stream
+    /// ends once every sender is dropped, and the subscription is removed with the receiver.
+    pub fn subscribe(
+        &self,
+        name: &str,
+        capacity: usize,
+        policy: OverflowPolicy,
+    ) -> mpsc::Receiver<EventEnvelope> {
+        self.add_subscriber(name, capacity, policy, false)
+    }
+
+    /// Subscribes to the pending transactions received from now on, queueing up to `capacity`
+    /// events and dropping the further ones while the queue is full.
+    pub fn subscribe_transactions(
+        &self,
+        name: &str,
+        capacity: usize,
+    ) -> mpsc::Receiver<EventEnvelope> {
+        self.add_subscriber(name, capacity, OverflowPolicy::Drop, true)
+    }
+
+    fn add_subscriber(
+        &self,
+        name: &str,
+        capacity: usize,
+        policy: OverflowPolicy,
+        transactions: bool,
+    ) -> mpsc::Receiver<EventEnvelope> {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        let subscriber = Subscriber {
+            name: name.to_string(),
+            sender,
+            policy,
+            transactions,
+            coalesced: VecDeque::new(),
+        };
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.push(subscriber);
+        gauge!("bscpeer_block_event_subscribers").set(subscribers.len() as f64);
+        receiver
+    }
+
+    /// Delivers the coalesced announcements of every subscriber with room in its queue, to be
+    /// called every [`COALESCE_FLUSH_INTERVAL`] so they don't wait for the next event.
+    pub fn flush(&self) {
+        for subscriber in self.subscribers.lock().unwrap().iter_mut() {
+            subscriber.flush_coalesced();
+        }
+    }
+
+    /// Sends an event to every subscriber, applying its overflow policy if its queue is full.
+    pub fn send(&self, event: BlockEvent) {
+        self.send_with_provenance(event, None, ReceiveTime::now());
+    }
+
+    /// Sends an event about a block received at the given time, telling whether the node had seen
+    /// it before.
+    pub fn send_block(&self, event: BlockEvent, first_seen: bool, received: ReceiveTime) {
+        self.send_with_provenance(event, Some(first_seen), received);
+    }
+
+    fn send_with_provenance(
+        &self,
+        event: BlockEvent,
+        first_seen: Option<bool>,
+        received: ReceiveTime,
+    ) {
+        let peer_id = event.peer_id();
+        let eth_version = peer_id.and_then(|peer_id| self.versions.get(&peer_id));
+        let provenance =
+            Provenance { first_seen, ..Provenance::new(peer_id, eth_version, received) };
+        let transactions = matches!(event, BlockEvent::PendingTransactions { .. });
+        let envelope = EventEnvelope { event, provenance };
+        self.sent.fetch_add(1, Ordering::Relaxed);
+
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let count = subscribers.len();
+        subscribers.retain_mut(|subscriber| {
+            subscriber.transactions != transactions || subscriber.deliver(envelope.clone())
+        });
+        if subscribers.len() != count {
+            gauge!("bscpeer_block_event_subscribers").set(subscribers.len() as f64);
+        }
+        if subscribers.is_empty() {
+            warn!(kind = envelope.kind(), "no block event subscribers");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashes(block_numbers: Vec<u64>) -> BlockEvent {
+        BlockEvent::NewBlockHashes { peer_id: PeerId::repeat_byte(1), block_numbers }
+    }
+
+    fn channel(capacity: usize) -> (EventSender, mpsc::Receiver<EventEnvelope>) {
+        let sender = EventSender::default();
+        let receiver = sender.subscribe("test", capacity, OverflowPolicy::CoalesceHashes);
+        (sender, receiver)
+    }
+
+    #[test]
+    fn test_coalesce_hash_announcements() {
+        let (sender, mut receiver) = channel(1);
+        sender.send(hashes(vec![1]));
+        sender.send(hashes(vec![2]));
+        sender.send(hashes(vec![3, 2]));
+
+        let Ok(EventEnvelope { event: BlockEvent::NewBlockHashes { block_numbers, .. }, .. }) =
+            receiver.try_recv()
+        else {
+            panic!("expected hashes event")
+        };
+        assert_eq!(block_numbers, vec![1]);
+
+        // the next send flushes the coalesced announcements first
+        sender.send(hashes(vec![4]));
+        let Ok(EventEnvelope { event: BlockEvent::NewBlockHashes { block_numbers, .. }, .. }) =
+            receiver.try_recv()
+        else {
+            panic!("expected hashes event")
+        };
+        assert_eq!(block_numbers, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_transaction_subscribers() {
+        let (sender, mut blocks) = channel(1);
+        let mut transactions = sender.subscribe_transactions("transactions", 1);
+        let pending =
+            || BlockEvent::PendingTransactions { peer_id: PeerId::ZERO, transactions: Vec::new() };
+        sender.send(pending());
+        sender.send(pending());
+        sender.send(hashes(vec![1]));
+
+        // the transactions neither fill the block queue nor get the block events
+        assert!(matches!(blocks.try_recv().unwrap().event, BlockEvent::NewBlockHashes { .. }));
+        assert!(blocks.try_recv().is_err());
+        assert_eq!(transactions.try_recv().unwrap().kind(), "pending_transactions");
+        assert!(transactions.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_coalesce_cap_and_flush() {
+        let (sender, mut receiver) = channel(1);
+        sender.send(hashes(vec![0]));
+        sender.send(hashes((1..=MAX_COALESCED_NUMBERS as u64 + 10).collect()));
+        assert!(receiver.try_recv().is_ok());
+
+        // delivered without another event, the lowest numbers evicted
+        sender.flush();
+        let Ok(EventEnvelope { event: BlockEvent::NewBlockHashes { block_numbers, .. }, .. }) =
+            receiver.try_recv()
+        else {
+            panic!("expected hashes event")
+        };
+        assert_eq!(block_numbers.len(), MAX_COALESCED_NUMBERS);
+        assert_eq!(block_numbers[0], 11);
+    }
+
+    #[test]
+    fn test_coalesce_by_peer() {
+        let (sender, mut receiver) = channel(1);
+        sender.send(hashes(vec![1]));
+        let other = PeerId::repeat_byte(2);
+        sender.send(BlockEvent::NewBlockHashes { peer_id: other, block_numbers: vec![2] });
+        sender.send(hashes(vec![3]));
+        assert!(receiver.try_recv().is_ok());
+
+        // each peer's announcements are delivered under its own provenance
+        sender.send(hashes(vec![4]));
+        let envelope = receiver.try_recv().unwrap();
+        assert_eq!(envelope.provenance.peer_id, Some(other));
+        assert!(matches!(
+            envelope.event,
+            BlockEvent::NewBlockHashes { peer_id, ref block_numbers }
+                if peer_id == other && *block_numbers == [2]
+        ));
+        sender.send(hashes(vec![5]));
+        let envelope = receiver.try_recv().unwrap();
+        assert_eq!(envelope.provenance.peer_id, Some(PeerId::repeat_byte(1)));
+        assert!(matches!(
+            envelope.event,
+            BlockEvent::NewBlockHashes { ref block_numbers, .. } if *block_numbers == [3, 4]
+        ));
+    }
+
+    #[test]
+    fn test_fan_out() {
+        let (sender, mut fast) = channel(4);
+        let mut slow = sender.subscribe("slow", 1, OverflowPolicy::Drop);
+        let closed = sender.subscribe("closed", 4, OverflowPolicy::Drop);
+        drop(closed);
+        for number in 1..=3 {
+            sender.send(hashes(vec![number]));
+        }
+        assert_eq!(sender.subscribers.lock().unwrap().len(), 2);
+
+        // every subscriber is served up to its own capacity
+        for _ in 1..=3 {
+            assert!(fast.try_recv().is_ok());
+        }
+        assert!(slow.try_recv().is_ok());
+        assert!(slow.try_recv().is_err());
+
+        // nothing was coalesced for the dropping subscriber
+        sender.send(BlockEvent::NoPeers { idle_secs: 10 });
+        assert!(matches!(slow.try_recv().unwrap().event, BlockEvent::NoPeers { .. }));
+        assert_eq!(sender.sent(), 4);
+    }
+
+    #[test]
+    fn test_provenance() {
+        let (sender, mut receiver) = channel(4);
+        let peer_id = PeerId::repeat_byte(1);
+        sender.session_versions().insert(peer_id, EthVersion::Eth68);
+        let received = ReceiveTime::now();
+        sender.send_block(hashes(vec![1]), true, received);
+        sender.send(BlockEvent::NoPeers { idle_secs: 10 });
+
+        let provenance = receiver.try_recv().unwrap().provenance;
+        assert_eq!(provenance.peer_id, Some(peer_id));
+        assert_eq!((provenance.eth_version, provenance.first_seen), (Some(68), Some(true)));
+        assert!(provenance.received_at_ms > 0);
+        assert_eq!(provenance.received_monotonic_us, received.monotonic_us);
+
+        let provenance = receiver.try_recv().unwrap().provenance;
+        assert_eq!((provenance.peer_id, provenance.eth_version), (None, None));
+    }
+}
@@ -0,0 +1,102 @@
+//! Gas price distribution of the transactions in a block, for fee estimation.
+//!
+//! The percentiles are taken over the transactions rather than weighted by gas, since the gas
+//! used by each transaction is only known from the receipts. Transactions paying nothing, e.g.
+//! the BSC system transactions, are left out as no sender competes at that price.
+use alloy_consensus::Transaction;
+use metrics::gauge;
+use serde::Serialize;
+
+/// Effective gas price percentiles and priority fees of a block's transactions, in wei.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GasPriceStats {
+    /// Transactions the stats are computed over.
+    pub transactions: usize,
+    pub min: u128,
+    pub p10: u128,
+    pub p50: u128,
+    pub p90: u128,
+    pub max: u128,
+    /// Median of the effective gas price above the base fee.
+    pub priority_fee_p50: u128,
+    pub priority_fee_avg: u128,
+}
+
+impl GasPriceStats {
+    /// Computes the stats, `None` if no transaction pays for its gas.
+    pub fn new<'a, T: Transaction + 'a>(
+        transactions: impl IntoIterator<Item = &'a T>,
+        base_fee: Option<u64>,
+    ) -> Option<Self> {
+        let mut prices: Vec<u128> = transactions
+            .into_iter()
+            .map(|tx| tx.effective_gas_price(base_fee))
+            .filter(|price| *price > 0)
+            .collect();
+        if prices.is_empty() {
+            return None;
+        }
+        prices.sort_unstable();
+        let base_fee = base_fee.unwrap_or_default() as u128;
+        // sorted as the prices are
+        let tips: Vec<u128> = prices.iter().map(|price| price.saturating_sub(base_fee)).collect();
+        Some(Self {
+            transactions: prices.len(),
+            min: prices[0],
+            p10: percentile(&prices, 0.1),
+            p50: percentile(&prices, 0.5),
+            p90: percentile(&prices, 0.9),
+            max: prices[prices.len() - 1],
+            priority_fee_p50: percentile(&tips, 0.5),
+            priority_fee_avg: tips.iter().sum::<u128>() / tips.len() as u128,
+        })
+    }
+
+    /// Publishes the stats of the latest block.
+    pub fn record_metrics(&self) {
+        for (quantile, price) in [("0.1", self.p10), ("0.5", self.p50), ("0.9", self.p90)] {
+            gauge!("bscpeer_block_gas_price_wei", "quantile" => quantile).set(price as f64);
+        }
+        gauge!("bscpeer_block_priority_fee_wei", "stat" => "p50").set(self.priority_fee_p50 as f64);
+        gauge!("bscpeer_block_priority_fee_wei", "stat" => "avg").set(self.priority_fee_avg as f64);
+    }
+}
+
+/// Returns the value below which the given fraction of the sorted values lies.
+fn percentile(sorted: &[u128], fraction: f64) -> u128 {
+    let rank = (fraction * sorted.len() as f64).ceil() as usize;
+    sorted[rank.saturating_sub(1).min(sorted.len() - 1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_consensus::{TxEip1559, TxLegacy, TypedTransaction};
+
+    #[test]
+    fn test_gas_price_stats() {
+        let legacy =
+            |gas_price| TypedTransaction::Legacy(TxLegacy { gas_price, ..Default::default() });
+        let mut transactions: Vec<_> = (1..=10).map(|gwei| legacy(gwei * 1_000_000_000)).collect();
+        // system transactions don't count
+        transactions.push(legacy(0));
+        // capped by the max fee
+        transactions.push(TypedTransaction::Eip1559(TxEip1559 {
+            max_fee_per_gas: 3_000_000_000,
+            max_priority_fee_per_gas: 5_000_000_000,
+            ..Default::default()
+        }));
+
+        let stats = GasPriceStats::new(&transactions, Some(1_000_000_000)).unwrap();
+        assert_eq!(stats.transactions, 11);
+        assert_eq!((stats.min, stats.max), (1_000_000_000, 10_000_000_000));
+        assert_eq!(
+            (stats.p10, stats.p50, stats.p90),
+            (2_000_000_000, 5_000_000_000, 9_000_000_000)
+        );
+        assert_eq!(stats.priority_fee_p50, 4_000_000_000);
+
+        assert_eq!(GasPriceStats::new(&transactions[10..11], None), None);
+    }
+}
@@ -0,0 +1,343 @@
+//! Fetching blocks from a single peer, validating the responses against the requested headers.
+use crate::{
+    parlia::{
+        snapshot::ProposerError,
+        validation::{self, HeaderError},
+    },
+    peer::wire::trace_wire,
+};
+use alloy_consensus::{EMPTY_ROOT_HASH, Header, PooledTransaction};
+use alloy_primitives::B256;
+use reth_eth_wire::{
+    GetBlockBodies, GetBlockHeaders, GetPooledTransactions, GetReceipts, HeadersDirection,
+};
+use reth_eth_wire_types::BlockHashOrNumber;
+use reth_ethereum_primitives::{BlockBody, Receipt};
+use reth_network::{EthNetworkPrimitives, NetworkHandle};
+use reth_network_api::PeerRequest;
+use reth_network_peers::PeerId;
+use std::ops::RangeInclusive;
+use tokio::sync::oneshot;
+use tracing::instrument;
+
+/// Headers requested at once at most, the most peers serve per request.
+pub const MAX_HEADERS: u64 = 1024;
+
+/// Errors that can occur while fetching a block.
+#[derive(Debug, thiserror::Error)]
+pub enum FetchError {
+    /// The request failed or timed out.
+    #[error("request failed: {0}")]
+    Request(String),
+    /// The session was closed before a response arrived.
+    #[error("response channel closed")]
+    ChannelClosed,
+    /// The peer doesn't have the header.
+    #[error("peer returned no header for block {0}")]
+    MissingHeader(u64),
+    /// The peer doesn't have the header with the requested hash.
+    #[error("peer returned no header for hash {0}")]
+    MissingHash(B256),
+    /// The peer returned a header other than the requested one.
+    #[error("peer returned block {got} instead of {expected}")]
+    Unexpected { expected: u64, got: u64 },
+    /// The peer returned headers that don't form a chain.
+    #[error("peer returned a header not linked to the next one at block {0}")]
+    Unlinked(u64),
+    /// The peer returned headers not linked to those fetched before, e.g. after a reorg.
+    #[error("peer returned headers on another branch after block {0}")]
+    OtherBranch(u64),
+    /// The peer doesn't have the body.
+    #[error("peer returned no body for block {0}")]
+    MissingBody(B256),
+    /// The peer doesn't have the receipts.
+    #[error("peer returned no receipts for block {0}")]
+    MissingReceipts(B256),
+    /// The peer returned data that doesn't match the header.
+    #[error(transparent)]
+    Invalid(#[from] HeaderError),
+    /// The header wasn't proposed by an authorized validator, or with the wrong difficulty.
+    #[error(transparent)]
+    Proposer(#[from] ProposerError),
+}
+
+impl FetchError {
+    /// Returns `true` if the peer served data inconsistent with the header and should be
+    /// penalized.
+    pub fn is_bad_data(&self) -> bool {
+        matches!(
+            self,
+            Self::Invalid(_) | Self::Proposer(_) | Self::Unlinked(_) | Self::Unexpected { .. }
+        )
+    }
+}
+
+/// Sends a header request to the peer and awaits the response.
+async fn request_headers(
+    network: &NetworkHandle<EthNetworkPrimitives>,
+    peer_id: PeerId,
+    request: GetBlockHeaders,
+) -> Result<Vec<Header>, FetchError> {
+    let (response, rx) = oneshot::channel();
+    trace_wire!(Some(peer_id), Outbound, GetBlockHeaders, &request);
+    network.send_request(peer_id, PeerRequest::GetBlockHeaders { request, response });
+    let headers = rx
+        .await
+        .map_err(|_| FetchError::ChannelClosed)?
+        .map_err(|e| FetchError::Request(e.to_string()))?;
+    trace_wire!(Some(peer_id), Inbound, BlockHeaders, &headers);
+    Ok(headers.0)
+}
+
+/// Sends a body request to the peer and awaits the response.
+async fn request_bodies(
+    network: &NetworkHandle<EthNetworkPrimitives>,
+    peer_id: PeerId,
+    hashes: Vec<B256>,
+) -> Result<Vec<BlockBody>, FetchError> {
+    let (response, rx) = oneshot::channel();
+    let request = GetBlockBodies(hashes);
+    trace_wire!(Some(peer_id), Outbound, GetBlockBodies, &request);
+    network.send_request(peer_id, PeerRequest::GetBlockBodies { request, response });
+    let bodies = rx
+        .await
+        .map_err(|_| FetchError::ChannelClosed)?
+        .map_err(|e| FetchError::Request(e.to_string()))?;
+    trace_wire!(Some(peer_id), Inbound, BlockBodies, &bodies);
+    Ok(bodies.0)
+}
+
+/// Sends the peer an empty body request, which it answers without any lookup, and awaits the
+/// empty response.
+pub async fn ping(
+    network: &NetworkHandle<EthNetworkPrimitives>,
+    peer_id: PeerId,
+) -> Result<(), FetchError> {
+    request_bodies(network, peer_id, Vec::new()).await.map(drop)
+}
+
+/// Fetches the canonical header at `number` as seen by the peer.
+#[instrument(
+    name = "get_block_header",
+    skip_all,
+    fields(peer_id = %peer_id, block_number = number)
+)]
+pub async fn fetch_header(
+    network: &NetworkHandle<EthNetworkPrimitives>,
+    peer_id: PeerId,
+    number: u64,
+) -> Result<Header, FetchError> {
+    let request = GetBlockHeaders {
+        start_block: BlockHashOrNumber::Number(number),
+        limit: 1,
+        skip: 0,
+        direction: HeadersDirection::Rising,
+    };
+    request_headers(network, peer_id, request)
+        .await?
+        .into_iter()
+        .find(|header| header.number == number)
+        .ok_or(FetchError::MissingHeader(number))
+}
+
+/// Fetches the canonical headers of a range as seen by the peer, oldest first, checking that
+/// each is the parent of the next one. The peer may serve only the start of the range, at most
+/// [`MAX_HEADERS`] headers are requested.
+#[instrument(
+    name = "get_block_headers",
+    skip_all,
+    fields(peer_id = %peer_id, first = range.start(), last = range.end())
+)]
+pub async fn fetch_headers(
+    network: &NetworkHandle<EthNetworkPrimitives>,
+    peer_id: PeerId,
+    range: RangeInclusive<u64>,
+) -> Result<Vec<Header>, FetchError> {
+    if range.is_empty() {
+        return Ok(Vec::new());
+    }
+    let (first, limit) = (*range.start(), header_limit(&range));
+    let request = GetBlockHeaders {
+        start_block: BlockHashOrNumber::Number(first),
+        limit,
+        skip: 0,
+        direction: HeadersDirection::Rising,
+    };
+    let mut headers = request_headers(network, peer_id, request).await?;
+    headers.truncate(limit as usize);
+    if headers.is_empty() {
+        return Err(FetchError::MissingHeader(first));
+    }
+    check_range(first, &headers)?;
+    Ok(headers)
+}
+
+/// Fetches up to `limit` headers walking back from the block with the given hash, newest first,
+/// checking that each is the parent of the previous one.
+#[instrument(
+    name = "get_ancestors",
+    skip_all,
+    fields(peer_id = %peer_id, block_hash = %hash, limit = limit)
+)]
+pub async fn fetch_ancestors(
+    network: &NetworkHandle<EthNetworkPrimitives>,
+    peer_id: PeerId,
+    hash: B256,
+    limit: u64,
+) -> Result<Vec<Header>, FetchError> {
+    let request = GetBlockHeaders {
+        start_block: BlockHashOrNumber::Hash(hash),
+        limit,
+        skip: 0,
+        direction: HeadersDirection::Falling,
+    };
+    let headers = request_headers(network, peer_id, request).await?;
+    if headers.is_empty() {
+        return Err(FetchError::MissingHash(hash));
+    }
+    let mut expected = hash;
+    for header in &headers {
+        if header.hash_slow() != expected {
+            return Err(FetchError::Unlinked(header.number));
+        }
+        expected = header.parent_hash;
+    }
+    Ok(headers)
+}
+
+/// Fetches the body of the block with the given hash and checks it against the header.
+#[instrument(
+    name = "get_block_body",
+    skip_all,
+    fields(peer_id = %peer_id, block_number = header.number, block_hash = %hash)
+)]
+pub async fn fetch_body(
+    network: &NetworkHandle<EthNetworkPrimitives>,
+    peer_id: PeerId,
+    hash: B256,
+    header: &Header,
+) -> Result<BlockBody, FetchError> {
+    let bodies = request_bodies(network, peer_id, vec![hash]).await?;
+    let body = bodies.into_iter().next().ok_or(FetchError::MissingBody(hash))?;
+    validation::validate_transactions_root(header, &body.transactions)?;
+    Ok(body)
+}
+
+/// Fetches the bodies of the blocks with the given hashes, in order. The peer may serve only the
+/// first ones. Without the headers the bodies can't be checked, see [`fetch_body`].
+#[instrument(name = "get_block_bodies", skip_all, fields(peer_id = %peer_id, count = hashes.len()))]
+pub async fn fetch_bodies(
+    network: &NetworkHandle<EthNetworkPrimitives>,
+    peer_id: PeerId,
+    hashes: Vec<B256>,
+) -> Result<Vec<BlockBody>, FetchError> {
+    let first = hashes.first().copied();
+    let count = hashes.len();
+    let mut bodies = request_bodies(network, peer_id, hashes).await?;
+    bodies.truncate(count);
+    match first {
+        Some(hash) if bodies.is_empty() => Err(FetchError::MissingBody(hash)),
+        _ => Ok(bodies),
+    }
+}
+
+/// Fetches the receipts of the block with the given hash and checks them against the header.
+#[instrument(
+    name = "get_receipts",
+    skip_all,
+    fields(peer_id = %peer_id, block_number = header.number, block_hash = %hash)
+)]
+pub async fn fetch_receipts(
+    network: &NetworkHandle<EthNetworkPrimitives>,
+    peer_id: PeerId,
+    hash: B256,
+    header: &Header,
+) -> Result<Vec<Receipt>, FetchError> {
+    let (response, rx) = oneshot::channel();
+    let request = GetReceipts(vec![hash]);
+    trace_wire!(Some(peer_id), Outbound, GetReceipts, &request);
+    network.send_request(peer_id, PeerRequest::GetReceipts { request, response });
+    let receipts = rx
+        .await
+        .map_err(|_| FetchError::ChannelClosed)?
+        .map_err(|e| FetchError::Request(e.to_string()))?;
+    trace_wire!(Some(peer_id), Inbound, Receipts, &receipts);
+    let receipts = match receipts.0.into_iter().next() {
+        Some(receipts) => receipts,
+        // an empty response is only valid for blocks without transactions
+        None if header.receipts_root == EMPTY_ROOT_HASH => Vec::new(),
+        None => return Err(FetchError::MissingReceipts(hash)),
+    };
+    validation::validate_receipts(header, &receipts)?;
+    Ok(receipts.into_iter().map(|receipt| receipt.receipt).collect())
+}
+
+/// Returns the number of headers to request for a non-empty range.
+fn header_limit(range: &RangeInclusive<u64>) -> u64 {
+    (range.end() - range.start()).min(MAX_HEADERS - 1) + 1
+}
+
+/// Checks that the headers are consecutive from `first`, each being the parent of the next one.
+fn check_range(first: u64, headers: &[Header]) -> Result<(), FetchError> {
+    let mut parent_hash = None;
+    for (expected, header) in (first..).zip(headers) {
+        if header.number != expected {
+            return Err(FetchError::Unexpected { expected, got: header.number });
+        }
+        if parent_hash.is_some_and(|parent_hash| parent_hash != header.parent_hash) {
+            return Err(FetchError::Unlinked(header.number));
+        }
+        parent_hash = Some(header.hash_slow());
+    }
+    Ok(())
+}
+
+/// Fetches announced transactions from the pool of the peer. Transactions the peer no longer
+/// has are missing from the result.
+#[instrument(
+    name = "get_pooled_transactions",
+    skip_all,
+    fields(peer_id = %peer_id, count = hashes.len())
+)]
+pub async fn fetch_pooled_transactions(
+    network: &NetworkHandle<EthNetworkPrimitives>,
+    peer_id: PeerId,
+    hashes: Vec<B256>,
+) -> Result<Vec<PooledTransaction>, FetchError> {
+    let (response, rx) = oneshot::channel();
+    let request = GetPooledTransactions(hashes);
+    trace_wire!(Some(peer_id), Outbound, GetPooledTransactions, &request);
+    network.send_request(peer_id, PeerRequest::GetPooledTransactions { request, response });
+    let transactions = rx
+        .await
+        .map_err(|_| FetchError::ChannelClosed)?
+        .map_err(|e| FetchError::Request(e.to_string()))?;
+    trace_wire!(Some(peer_id), Inbound, PooledTransactions, &transactions);
+    Ok(transactions.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_range() {
+        let first = Header { number: 10, ..Default::default() };
+        let second = Header { number: 11, parent_hash: first.hash_slow(), ..Default::default() };
+        assert!(check_range(10, &[first.clone(), second]).is_ok());
+        assert!(matches!(
+            check_range(9, &[first.clone()]),
+            Err(FetchError::Unexpected { expected: 9, got: 10 })
+        ));
+
+        let unlinked = Header { number: 11, ..Default::default() };
+        assert!(matches!(check_range(10, &[first, unlinked]), Err(FetchError::Unlinked(11))));
+    }
+
+    #[test]
+    fn test_header_limit() {
+        assert_eq!(header_limit(&(10..=10)), 1);
+        assert_eq!(header_limit(&(10..=19)), 10);
+        assert_eq!(header_limit(&(0..=u64::MAX)), MAX_HEADERS);
+    }
+}
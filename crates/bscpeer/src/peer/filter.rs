@@ -0,0 +1,58 @@
+//! Policy hook deciding which remote clients we keep sessions with.
+use crate::config::ClientFilterConfig;
+
+/// Matches client version strings against configured allow/deny patterns.
+#[derive(Debug, Clone, Default)]
+pub struct ClientFilter {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl ClientFilter {
+    pub fn new(config: &ClientFilterConfig) -> Self {
+        let lowercase = |patterns: &[String]| patterns.iter().map(|p| p.to_lowercase()).collect();
+        Self { allow: lowercase(&config.allow), deny: lowercase(&config.deny) }
+    }
+
+    /// Returns `true` if a peer reporting the given client version should be kept.
+    ///
+    /// The denylist takes precedence over the allowlist.
+    pub fn is_allowed(&self, client_version: &str) -> bool {
+        let client_version = client_version.to_lowercase();
+        if self.deny.iter().any(|p| client_version.contains(p.as_str())) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|p| client_version.contains(p.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(allow: &[&str], deny: &[&str]) -> ClientFilter {
+        ClientFilter::new(&ClientFilterConfig {
+            allow: allow.iter().map(|s| s.to_string()).collect(),
+            deny: deny.iter().map(|s| s.to_string()).collect(),
+        })
+    }
+
+    #[test]
+    fn test_empty_filter_allows_all() {
+        assert!(filter(&[], &[]).is_allowed("Geth/v1.5.7/linux-amd64/go1.23"));
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let filter = filter(&["geth"], &[]);
+        assert!(filter.is_allowed("Geth/v1.5.7/linux-amd64/go1.23"));
+        assert!(!filter.is_allowed("erigon/v2.60.0/linux-amd64/go1.22"));
+    }
+
+    #[test]
+    fn test_denylist_takes_precedence() {
+        let filter = filter(&["geth"], &["v1.4"]);
+        assert!(filter.is_allowed("Geth/v1.5.7/linux-amd64/go1.23"));
+        assert!(!filter.is_allowed("Geth/v1.4.16/linux-amd64/go1.21"));
+    }
+}
@@ -0,0 +1,171 @@
+//! Fork id validation policy applied during the BSC handshake and to discovered nodes.
+use crate::{config::ForkIdConfig, instance};
+use futures::StreamExt;
+use metrics::counter;
+use reth_chainspec::{ChainSpec, ForkCondition, Head};
+use reth_ethereum_forks::{ForkFilter, ForkHash, ForkId, ValidationError};
+use reth_network::{EthNetworkPrimitives, NetworkHandle};
+use reth_network_api::{
+    PeerKind, Peers,
+    events::{DiscoveredEvent, DiscoveryEvent},
+};
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+/// Decides how fork id mismatches are handled and explains them.
+#[derive(Debug, Clone, Default)]
+pub struct ForkIdPolicy {
+    /// Names of the forks activated at our head, in order, with the fork hash each one produces.
+    schedule: Vec<(&'static str, ForkHash)>,
+    /// Head used for validation in lenient mode.
+    lenient_head: Option<Head>,
+}
+
+impl ForkIdPolicy {
+    pub fn new(chain_spec: &ChainSpec, head: Head, config: &ForkIdConfig) -> Self {
+        let mut schedule = vec![("Genesis", chain_spec.fork_id(&Head::default()).hash)];
+        for (fork, condition) in chain_spec.hardforks.forks_iter() {
+            if !condition.active_at_head(&head) {
+                continue;
+            }
+            let fork_head = match condition {
+                ForkCondition::Block(number) => Head { number, ..Default::default() },
+                ForkCondition::Timestamp(timestamp) => {
+                    Head { number: u64::MAX, timestamp, ..Default::default() }
+                }
+                _ => continue,
+            };
+            let hash = chain_spec.fork_id(&fork_head).hash;
+            // forks sharing an activation point don't change the hash
+            if schedule.last().is_some_and(|(_, last)| *last == hash) {
+                continue;
+            }
+            schedule.push((fork.name(), hash));
+        }
+
+        let lenient_head = config.lenient.then(|| Head {
+            number: head.number.saturating_sub(config.grace_blocks),
+            timestamp: head.timestamp.saturating_sub(config.grace_secs),
+            ..head
+        });
+
+        Self { schedule, lenient_head }
+    }
+
+    /// Returns the filter remote fork ids are validated against.
+    ///
+    /// In lenient mode the filter's head is moved back by the configured grace period, so peers
+    /// that have not yet activated a recently scheduled fork are still accepted.
+    pub fn fork_filter(&self, fork_filter: ForkFilter) -> ForkFilter {
+        match self.lenient_head {
+            Some(head) => {
+                let mut fork_filter = fork_filter;
+                fork_filter.set_head(head);
+                fork_filter
+            }
+            None => fork_filter,
+        }
+    }
+
+    /// Returns the first fork we activated that the remote has not, if the remote is on our
+    /// chain but behind.
+    pub fn missing_fork(&self, remote: &ForkId) -> Option<&'static str> {
+        let pos = self.schedule.iter().position(|(_, hash)| *hash == remote.hash)?;
+        self.schedule.get(pos + 1).map(|(name, _)| *name)
+    }
+
+    /// Logs and counts a rejected remote fork id.
+    pub fn record_mismatch(&self, err: &ValidationError) {
+        match err {
+            ValidationError::RemoteStale { local, remote } => {
+                let missing_fork = self.missing_fork(remote).unwrap_or("unknown");
+                debug!(?local, ?remote, missing_fork, "remote fork id is stale");
+                counter!(
+                    "bscpeer_fork_id_mismatches_total",
+                    "kind" => "remote_stale",
+                    "missing_fork" => missing_fork
+                )
+                .increment(1);
+            }
+            ValidationError::LocalIncompatibleOrStale { local, remote } => {
+                // a known hash means the remote is on our chain but expects a different next fork
+                let kind = if self.schedule.iter().any(|(_, hash)| *hash == remote.hash) {
+                    "next_mismatch"
+                } else {
+                    "incompatible"
+                };
+                debug!(?local, ?remote, kind, "remote fork id is incompatible");
+                counter!("bscpeer_fork_id_mismatches_total", "kind" => kind).increment(1);
+            }
+        }
+    }
+}
+
+/// Spawns the task removing discovered nodes whose ENR advertises a fork id rejected by the
+/// filter from the peer set, so they aren't dialed.
+///
+/// Nodes without an `eth` ENR entry are kept, the handshake validates their fork id.
+pub fn spawn_discovery_filter(
+    network: NetworkHandle<EthNetworkPrimitives>,
+    fork_filter: ForkFilter,
+) -> JoinHandle<()> {
+    instance::spawn(async move {
+        let mut discovery = network.discovery_listener();
+        while let Some(event) = discovery.next().await {
+            let (peer_id, fork_id) = match event {
+                DiscoveryEvent::EnrForkId(peer_id, fork_id)
+                | DiscoveryEvent::NewNode(DiscoveredEvent::EventQueued {
+                    peer_id,
+                    fork_id: Some(fork_id),
+                    ..
+                }) => (peer_id, fork_id),
+                _ => continue,
+            };
+            match fork_filter.validate(fork_id) {
+                Ok(()) => {
+                    counter!("bscpeer_discovered_fork_ids_total", "outcome" => "accepted")
+                        .increment(1);
+                }
+                Err(err) => {
+                    debug!(%peer_id, ?fork_id, "skipping node on another network: {}", err);
+                    counter!("bscpeer_discovered_fork_ids_total", "outcome" => "filtered")
+                        .increment(1);
+                    network.remove_peer(peer_id, PeerKind::Basic);
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain_config::bsc::{bsc_mainnet, head};
+
+    #[test]
+    fn test_missing_fork() {
+        let chain_spec = bsc_mainnet();
+        let policy = ForkIdPolicy::new(&chain_spec, head(), &ForkIdConfig::default());
+
+        let before_maxwell = Head { timestamp: head().timestamp - 1, ..head() };
+        let remote = chain_spec.fork_id(&before_maxwell);
+        assert_eq!(policy.missing_fork(&remote), Some("Maxwell"));
+        assert_eq!(policy.missing_fork(&chain_spec.fork_id(&head())), None);
+    }
+
+    #[test]
+    fn test_lenient_filter_accepts_recent_fork_laggards() {
+        let chain_spec = bsc_mainnet();
+        let before_maxwell = Head { timestamp: head().timestamp - 1, ..head() };
+        let remote = ForkId { next: 0, ..chain_spec.fork_id(&before_maxwell) };
+
+        let strict = ForkIdPolicy::new(&chain_spec, head(), &ForkIdConfig::default());
+        assert!(strict.fork_filter(chain_spec.fork_filter(head())).validate(remote).is_err());
+
+        let config = ForkIdConfig { lenient: true, ..Default::default() };
+        let lenient = ForkIdPolicy::new(&chain_spec, head(), &config);
+        let filter = lenient.fork_filter(chain_spec.fork_filter(head()));
+        assert!(filter.validate(remote).is_ok());
+        assert!(filter.validate(chain_spec.fork_id(&head())).is_ok());
+    }
+}
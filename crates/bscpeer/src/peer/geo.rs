@@ -0,0 +1,188 @@
+//! Optional GeoIP/ASN lookup of peer addresses and peerset distribution stats.
+use crate::config::GeoIpConfig;
+use maxminddb::{MaxMindDBError, Reader, geoip2};
+use metrics::gauge;
+use reth_network_peers::PeerId;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::IpAddr,
+};
+
+/// Location of a peer as resolved from its IP address.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PeerLocation {
+    /// ISO 3166-1 country code.
+    pub country: Option<String>,
+    /// Autonomous system number.
+    pub asn: Option<u32>,
+    /// Organization owning the autonomous system.
+    pub as_org: Option<String>,
+}
+
+/// Resolves IP addresses using MaxMind country and ASN databases.
+///
+/// Either database may be absent, in which case the respective fields stay empty.
+#[derive(Debug, Default)]
+pub struct GeoIpResolver {
+    country_db: Option<Reader<Vec<u8>>>,
+    asn_db: Option<Reader<Vec<u8>>>,
+}
+
+impl GeoIpResolver {
+    /// Opens the databases configured in `config`.
+    pub fn open(config: &GeoIpConfig) -> Result<Self, MaxMindDBError> {
+        let country_db = config.country_db.as_ref().map(Reader::open_readfile).transpose()?;
+        let asn_db = config.asn_db.as_ref().map(Reader::open_readfile).transpose()?;
+        Ok(Self { country_db, asn_db })
+    }
+
+    /// Returns `true` if no database is configured.
+    pub fn is_empty(&self) -> bool {
+        self.country_db.is_none() && self.asn_db.is_none()
+    }
+
+    /// Looks up the location of the given address.
+    pub fn lookup(&self, ip: IpAddr) -> PeerLocation {
+        let mut location = PeerLocation::default();
+        if let Some(db) = &self.country_db {
+            if let Ok(country) = db.lookup::<geoip2::Country<'_>>(ip) {
+                location.country =
+                    country.country.and_then(|c| c.iso_code).map(ToString::to_string);
+            }
+        }
+        if let Some(db) = &self.asn_db {
+            if let Ok(asn) = db.lookup::<geoip2::Asn<'_>>(ip) {
+                location.asn = asn.autonomous_system_number;
+                location.as_org = asn.autonomous_system_organization.map(ToString::to_string);
+            }
+        }
+        location
+    }
+}
+
+/// Distribution of connected peers across countries and autonomous systems.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct GeoDistribution {
+    /// Number of peers per country code, `"unknown"` if unresolved.
+    pub by_country: BTreeMap<String, usize>,
+    /// Number of peers per autonomous system, `"unknown"` if unresolved.
+    pub by_asn: BTreeMap<String, usize>,
+    /// Total number of tracked peers.
+    pub total: usize,
+}
+
+impl GeoDistribution {
+    /// Returns the autonomous system hosting the most peers and its share in percent.
+    pub fn dominant_asn(&self) -> Option<(&str, usize)> {
+        self.by_asn
+            .iter()
+            .filter(|(asn, _)| asn.as_str() != "unknown")
+            .max_by_key(|(_, count)| **count)
+            .map(|(asn, count)| (asn.as_str(), count * 100 / self.total.max(1)))
+    }
+}
+
+/// Tracks the location of every connected peer.
+#[derive(Debug, Default)]
+pub struct PeerGeoTracker {
+    resolver: GeoIpResolver,
+    locations: HashMap<PeerId, PeerLocation>,
+    /// Countries with a gauge set, reset once their last peer is gone.
+    reported: BTreeSet<String>,
+}
+
+impl PeerGeoTracker {
+    pub fn new(resolver: GeoIpResolver) -> Self {
+        Self { resolver, locations: HashMap::new(), reported: BTreeSet::new() }
+    }
+
+    /// Resolves and records the location of a newly connected peer.
+    pub fn add_peer(&mut self, peer_id: PeerId, ip: IpAddr) -> &PeerLocation {
+        let location = self.resolver.lookup(ip);
+        self.locations.entry(peer_id).insert_entry(location).into_mut()
+    }
+
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.locations.remove(peer_id);
+    }
+
+    /// Returns the recorded location of a peer.
+    pub fn location(&self, peer_id: &PeerId) -> Option<&PeerLocation> {
+        self.locations.get(peer_id)
+    }
+
+    /// Computes the current distribution.
+    pub fn distribution(&self) -> GeoDistribution {
+        let mut distribution =
+            GeoDistribution { total: self.locations.len(), ..Default::default() };
+        for location in self.locations.values() {
+            let country = location.country.clone().unwrap_or_else(|| "unknown".to_string());
+            *distribution.by_country.entry(country).or_default() += 1;
+            let asn = match (&location.asn, &location.as_org) {
+                (Some(asn), Some(org)) => format!("AS{asn} {org}"),
+                (Some(asn), None) => format!("AS{asn}"),
+                _ => "unknown".to_string(),
+            };
+            *distribution.by_asn.entry(asn).or_default() += 1;
+        }
+        distribution
+    }
+
+    /// Computes the current distribution and updates the per-country gauges, resetting those of
+    /// the countries without peers left.
+    pub fn update_metrics(&mut self) -> GeoDistribution {
+        let distribution = self.distribution();
+        for country in &self.reported {
+            if !distribution.by_country.contains_key(country) {
+                gauge!("bscpeer_peers_by_country", "country" => country.clone()).set(0.0);
+            }
+        }
+        for (country, count) in &distribution.by_country {
+            gauge!("bscpeer_peers_by_country", "country" => country.clone()).set(*count as f64);
+        }
+        self.reported = distribution.by_country.keys().cloned().collect();
+        distribution
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dominant_asn() {
+        let distribution = GeoDistribution {
+            by_country: BTreeMap::new(),
+            by_asn: BTreeMap::from([
+                ("AS16509 AMAZON-02".to_string(), 3),
+                ("AS15169 GOOGLE".to_string(), 1),
+            ]),
+            total: 4,
+        };
+        assert_eq!(distribution.dominant_asn(), Some(("AS16509 AMAZON-02", 75)));
+    }
+
+    #[test]
+    fn test_unresolved_peers() {
+        let mut tracker = PeerGeoTracker::default();
+        tracker.add_peer(PeerId::repeat_byte(1), [10, 0, 0, 1].into());
+        let distribution = tracker.distribution();
+        assert_eq!(distribution.by_country.get("unknown"), Some(&1));
+        assert_eq!(distribution.dominant_asn(), None);
+    }
+
+    #[test]
+    fn test_update_metrics() {
+        let mut tracker = PeerGeoTracker::default();
+        let peer_id = PeerId::repeat_byte(1);
+        tracker.add_peer(peer_id, [10, 0, 0, 1].into());
+        assert_eq!(tracker.update_metrics().total, 1);
+        assert!(tracker.reported.contains("unknown"));
+
+        // the gauge of a country without peers is reset once
+        tracker.remove_peer(&peer_id);
+        assert_eq!(tracker.update_metrics().total, 0);
+        assert!(tracker.reported.is_empty());
+    }
+}
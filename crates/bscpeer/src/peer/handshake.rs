@@ -1,65 +1,309 @@
-use crate::peer::upgrade_status::{UpgradeStatus, UpgradeStatusExtension};
+use crate::{
+    instance,
+    peer::{
+        forkid::ForkIdPolicy,
+        status::{StatusError, StatusValidator},
+        upgrade_status::{UpgradeStatus, UpgradeStatusExtension},
+        wire::trace_wire,
+    },
+};
+use alloy_primitives::U256;
 use alloy_rlp::Decodable;
 use futures::SinkExt;
+use metrics::counter;
 use reth_eth_wire::{
-    errors::{EthHandshakeError, EthStreamError},
+    errors::{EthHandshakeError, EthStreamError, P2PStreamError},
     handshake::{EthRlpxHandshake, EthereumEthHandshake, UnauthEth},
     UnifiedStatus,
 };
 use reth_eth_wire_types::{DisconnectReason, EthVersion};
-use reth_ethereum_forks::ForkFilter;
-use std::{future::Future, pin::Pin};
-use tokio::time::{timeout, Duration};
+use reth_ethereum_forks::{ForkFilter, ValidationError};
+use reth_primitives_traits::GotExpected;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::mpsc,
+    time::{timeout, Duration},
+};
 use tokio_stream::StreamExt;
-use tracing::debug;
+use tracing::{debug, field, info_span, Instrument, Span};
+
+/// Errors that can occur while performing the BSC handshake.
+#[derive(Debug, thiserror::Error)]
+pub enum HandshakeError {
+    /// The eth `Status` exchange failed.
+    #[error("status exchange failed: {0}")]
+    Status(#[source] EthStreamError),
+    /// The remote fork id was rejected.
+    #[error("fork id mismatch: {0}")]
+    ForkMismatch(#[source] ValidationError),
+    /// The remote status failed the checks of the [`StatusValidator`].
+    #[error("invalid status: {0}")]
+    InvalidStatus(#[source] StatusError),
+    /// The remote total difficulty is implausible for the head.
+    #[error("implausible total difficulty {got}, at most {maximum}")]
+    TotalDifficulty { got: U256, maximum: U256 },
+    /// The handshake did not complete in time.
+    #[error("handshake timed out")]
+    Timeout,
+    /// The peer closed the stream without sending its upgrade status.
+    #[error("peer did not respond with an upgrade status")]
+    NoUpgradeStatus,
+    /// The peer's upgrade status message could not be decoded.
+    #[error("failed to decode upgrade status: {0}")]
+    InvalidUpgradeStatus(#[source] alloy_rlp::Error),
+    /// Reading from or writing to the stream failed.
+    #[error("stream error: {0}")]
+    Stream(#[source] EthStreamError),
+}
+
+impl HandshakeError {
+    /// Returns a short, stable label for this error, used for metrics.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Status(_) => "status",
+            Self::ForkMismatch(_) => "fork_mismatch",
+            Self::InvalidStatus(_) => "invalid_status",
+            Self::TotalDifficulty { .. } => "total_difficulty",
+            Self::Timeout => "timeout",
+            Self::NoUpgradeStatus => "no_upgrade_status",
+            Self::InvalidUpgradeStatus(_) => "decode",
+            Self::Stream(_) => "stream",
+        }
+    }
+}
+
+impl From<HandshakeError> for EthStreamError {
+    fn from(err: HandshakeError) -> Self {
+        match err {
+            HandshakeError::Status(err) | HandshakeError::Stream(err) => err,
+            HandshakeError::ForkMismatch(err) => {
+                EthStreamError::EthHandshakeError(EthHandshakeError::InvalidFork(err))
+            }
+            HandshakeError::InvalidStatus(StatusError::Genesis { got, expected }) => {
+                EthStreamError::EthHandshakeError(EthHandshakeError::MismatchedGenesis(
+                    GotExpected { got, expected }.into(),
+                ))
+            }
+            HandshakeError::InvalidStatus(StatusError::Chain { got, expected }) => {
+                EthStreamError::EthHandshakeError(EthHandshakeError::MismatchedChain(GotExpected {
+                    got,
+                    expected,
+                }))
+            }
+            // reth has no error carrying the difficulty, the peer was disconnected for it
+            HandshakeError::TotalDifficulty { .. }
+            | HandshakeError::InvalidStatus(StatusError::TotalDifficulty { .. }) => {
+                EthStreamError::P2PStreamError(P2PStreamError::Disconnected(
+                    DisconnectReason::ProtocolBreach,
+                ))
+            }
+            HandshakeError::Timeout => EthStreamError::StreamTimeout,
+            HandshakeError::NoUpgradeStatus => {
+                EthStreamError::EthHandshakeError(EthHandshakeError::NoResponse)
+            }
+            HandshakeError::InvalidUpgradeStatus(_) => {
+                EthStreamError::EthHandshakeError(EthHandshakeError::NonStatusMessageInHandshake)
+            }
+        }
+    }
+}
+
+/// Records a failed handshake attempt, labelled by the eth version negotiated in the hellos.
+///
+/// reth hands the handshake neither the hello of the peer nor its id, so failures are labelled
+/// with an unknown client. Successes are recorded with [`record_handshake`] once the session is
+/// established.
+fn record_failure(err: &HandshakeError, version: EthVersion) {
+    counter!(
+        "bscpeer_handshakes_total",
+        "outcome" => err.as_str(),
+        "client" => "unknown",
+        "eth_version" => (version as u8).to_string()
+    )
+    .increment(1);
+}
+
+/// Records a successful handshake, labelled by the remote client and eth version.
+pub fn record_handshake(client_version: &str, version: EthVersion) {
+    counter!(
+        "bscpeer_handshakes_total",
+        "outcome" => "success",
+        "client" => client_name(client_version).to_string(),
+        "eth_version" => (version as u8).to_string()
+    )
+    .increment(1);
+}
+
+/// Records a closed session, labelled by the remote client and disconnect reason.
+pub fn record_disconnect(client_version: &str, reason: Option<DisconnectReason>) {
+    let reason = reason.map_or_else(|| "none".to_string(), |r| r.to_string());
+    counter!(
+        "bscpeer_session_disconnects_total",
+        "client" => client_name(client_version).to_string(),
+        "reason" => reason
+    )
+    .increment(1);
+}
+
+/// Strips platform and build details from a client version string, e.g.
+/// `Geth/v1.5.7-abcdef/linux-amd64/go1.23` becomes `Geth/v1.5.7`.
+pub fn client_name(client_version: &str) -> &str {
+    let mut parts = client_version.splitn(3, '/');
+    let name = parts.next().unwrap_or_default();
+    match parts.next() {
+        Some(version) => {
+            let version = version.split('-').next().unwrap_or(version);
+            &client_version[..name.len() + 1 + version.len()]
+        }
+        None => name,
+    }
+}
+
+/// Outcome of a handshake, sent to the channel set with [`BscHandshake::with_reports`].
+#[derive(Debug, Clone, Default)]
+pub struct HandshakeReport {
+    /// Status of the peer, if it was exchanged.
+    pub status: Option<UnifiedStatus>,
+    /// Upgrade status of the peer, exchanged from eth/67 on.
+    pub upgrade_status: Option<UpgradeStatus>,
+    /// Why the handshake failed.
+    pub error: Option<String>,
+}
 
 #[derive(Debug, Default)]
 /// The Binance Smart Chain (BSC) P2P handshake.
 #[non_exhaustive]
-pub struct BscHandshake;
+pub struct BscHandshake {
+    fork_id_policy: ForkIdPolicy,
+    /// Checks of the remote status on top of reth's.
+    status_validator: Option<StatusValidator>,
+    /// Instance the handshakes are recorded for, as they run on the session tasks.
+    instance: Option<Arc<str>>,
+    /// Receiver of the outcome of every handshake.
+    reports: Option<mpsc::UnboundedSender<HandshakeReport>>,
+    /// Timeout replacing the one reth passes in.
+    timeout: Option<Duration>,
+}
 
 impl BscHandshake {
-    /// Negotiate the upgrade status message.
+    pub fn new(fork_id_policy: ForkIdPolicy) -> Self {
+        Self {
+            fork_id_policy,
+            status_validator: None,
+            instance: None,
+            reports: None,
+            timeout: None,
+        }
+    }
+
+    /// Sets the time the status and upgrade status exchange may take, instead of reth's fixed
+    /// timeout.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Rejects peers whose status fails the validator's checks.
+    pub fn with_status_validator(mut self, status_validator: StatusValidator) -> Self {
+        self.status_validator = Some(status_validator);
+        self
+    }
+
+    /// Sets the instance the handshakes belong to.
+    pub fn with_instance(mut self, instance: Option<Arc<str>>) -> Self {
+        self.instance = instance;
+        self
+    }
+
+    /// Sends the outcome of every handshake to the given channel.
+    pub fn with_reports(mut self, reports: mpsc::UnboundedSender<HandshakeReport>) -> Self {
+        self.reports = Some(reports);
+        self
+    }
+
+    /// Maps a failed status exchange into a [`HandshakeError`], recording fork id mismatches.
+    fn status_error(&self, err: EthStreamError) -> HandshakeError {
+        match err {
+            EthStreamError::EthHandshakeError(EthHandshakeError::InvalidFork(err)) => {
+                self.fork_id_policy.record_mismatch(&err);
+                HandshakeError::ForkMismatch(err)
+            }
+            err => HandshakeError::Status(err),
+        }
+    }
+
+    /// Validates the negotiated status, disconnecting the peer if it is rejected.
+    async fn validate_status(
+        &self,
+        unauth: &mut dyn UnauthEth,
+        status: &UnifiedStatus,
+    ) -> Result<(), HandshakeError> {
+        let Some(validator) = &self.status_validator else { return Ok(()) };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        if let Err(err) = validator.validate(status, now) {
+            debug!(?status, "rejecting peer status: {}", err);
+            unauth
+                .disconnect(err.disconnect_reason())
+                .await
+                .map_err(|e| HandshakeError::Stream(e.into()))?;
+            return Err(match err {
+                StatusError::TotalDifficulty { got, maximum } => {
+                    HandshakeError::TotalDifficulty { got, maximum }
+                }
+                err => HandshakeError::InvalidStatus(err),
+            });
+        }
+        Ok(())
+    }
+
+    /// Negotiate the upgrade status message, returning the peer's from eth/67 on.
     pub async fn upgrade_status(
         unauth: &mut dyn UnauthEth,
         negotiated_status: UnifiedStatus,
-    ) -> Result<UnifiedStatus, EthStreamError> {
+    ) -> Result<Option<UpgradeStatus>, HandshakeError> {
         if negotiated_status.version > EthVersion::Eth66 {
             // Send upgrade status message allowing peer to broadcast transactions
             let upgrade_msg = UpgradeStatus {
                 extension: UpgradeStatusExtension { disable_peer_tx_broadcast: false },
             };
-            unauth.start_send_unpin(upgrade_msg.into_rlpx())?;
+            let upgrade_msg = upgrade_msg.into_rlpx();
+            trace_wire!(None, Outbound, UpgradeStatus, bytes = &upgrade_msg);
+            unauth.start_send_unpin(upgrade_msg).map_err(|e| HandshakeError::Stream(e.into()))?;
 
             // Receive peer's upgrade status response
             let their_msg = match unauth.next().await {
                 Some(Ok(msg)) => msg,
-                Some(Err(e)) => return Err(EthStreamError::from(e)),
+                Some(Err(e)) => return Err(HandshakeError::Stream(e.into())),
                 None => {
-                    unauth.disconnect(DisconnectReason::DisconnectRequested).await?;
-                    return Err(EthStreamError::EthHandshakeError(EthHandshakeError::NoResponse));
+                    unauth
+                        .disconnect(DisconnectReason::DisconnectRequested)
+                        .await
+                        .map_err(|e| HandshakeError::Stream(e.into()))?;
+                    return Err(HandshakeError::NoUpgradeStatus);
                 }
             };
 
+            trace_wire!(None, Inbound, UpgradeStatus, bytes = &their_msg);
             // Decode their response
-            match UpgradeStatus::decode(&mut their_msg.as_ref()).map_err(|e| {
-                debug!("Decode error in BSC handshake: msg={their_msg:x}");
-                EthStreamError::InvalidMessage(e.into())
-            }) {
-                Ok(_) => {
-                    // Successful handshake
-                    return Ok(negotiated_status);
+            return match UpgradeStatus::decode(&mut their_msg.as_ref()) {
+                Ok(upgrade_status) => Ok(Some(upgrade_status)),
+                Err(e) => {
+                    debug!("Decode error in BSC handshake: msg={their_msg:x}");
+                    unauth
+                        .disconnect(DisconnectReason::ProtocolBreach)
+                        .await
+                        .map_err(|e| HandshakeError::Stream(e.into()))?;
+                    Err(HandshakeError::InvalidUpgradeStatus(e))
                 }
-                Err(_) => {
-                    unauth.disconnect(DisconnectReason::ProtocolBreach).await?;
-                    return Err(EthStreamError::EthHandshakeError(
-                        EthHandshakeError::NonStatusMessageInHandshake,
-                    ));
-                }
-            }
+            };
         }
 
-        Ok(negotiated_status)
+        Ok(None)
     }
 }
 
@@ -71,13 +315,70 @@ impl EthRlpxHandshake for BscHandshake {
         fork_filter: ForkFilter,
         timeout_limit: Duration,
     ) -> Pin<Box<dyn Future<Output = Result<UnifiedStatus, EthStreamError>> + 'a + Send>> {
-        Box::pin(async move {
+        let timeout_limit = self.timeout.unwrap_or(timeout_limit);
+        let handshake = async move {
+            let fork_filter = self.fork_id_policy.fork_filter(fork_filter);
+            // reth negotiated the eth version in the hellos before handing the stream over
+            let version = status.version;
+            let mut report = HandshakeReport::default();
             let fut = async {
-                let negotiated_status =
-                    EthereumEthHandshake(unauth).eth_handshake(status, fork_filter).await?;
-                Self::upgrade_status(unauth, negotiated_status).await
+                let negotiated_status = EthereumEthHandshake(unauth)
+                    .eth_handshake(status, fork_filter)
+                    .await
+                    .map_err(|err| self.status_error(err))?;
+                report.status = Some(negotiated_status);
+                let span = Span::current();
+                span.record("remote_chain", field::display(negotiated_status.chain));
+                span.record("remote_head", field::display(negotiated_status.blockhash));
+                self.validate_status(unauth, &negotiated_status).await?;
+                report.upgrade_status = Self::upgrade_status(unauth, negotiated_status).await?;
+                Ok(negotiated_status)
             };
-            timeout(timeout_limit, fut).await.map_err(|_| EthStreamError::StreamTimeout)?
-        })
+            let result = timeout(timeout_limit, fut).await.unwrap_or(Err(HandshakeError::Timeout));
+            if let Err(e) = &result {
+                record_failure(e, version);
+                Span::current().record("error", field::display(e));
+            }
+            if let Some(reports) = &self.reports {
+                report.error = result.as_ref().err().map(ToString::to_string);
+                let _ = reports.send(report);
+            }
+            result.map_err(Into::into)
+        };
+        // the peer id isn't known yet, the session log line links the remote head to it
+        let span = info_span!(
+            "handshake",
+            remote_chain = field::Empty,
+            remote_head = field::Empty,
+            error = field::Empty
+        );
+        Box::pin(instance::scope(self.instance.clone(), handshake.instrument(span)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_name() {
+        assert_eq!(client_name("Geth/v1.5.7-abcdef/linux-amd64/go1.23"), "Geth/v1.5.7");
+        assert_eq!(client_name("Geth/v1.5.7"), "Geth/v1.5.7");
+        assert_eq!(client_name("reth"), "reth");
+    }
+
+    #[test]
+    fn test_total_difficulty_error() {
+        let (got, maximum) = (U256::from(1000), U256::from(900));
+        let err = HandshakeError::TotalDifficulty { got, maximum };
+        assert_eq!(err.to_string(), "implausible total difficulty 1000, at most 900");
+        assert_eq!(err.as_str(), "total_difficulty");
+        // reported to reth as the disconnect the peer got
+        assert!(matches!(
+            EthStreamError::from(err),
+            EthStreamError::P2PStreamError(P2PStreamError::Disconnected(
+                DisconnectReason::ProtocolBreach
+            ))
+        ));
     }
 }
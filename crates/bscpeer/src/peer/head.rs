@@ -0,0 +1,162 @@
+//! Canonical head of the chain followed by the node.
+//!
+//! Blocks arrive in full from the announcing peers and through the sync fetches, in no
+//! particular order. [`HeadTracker`] is fed every valid block from both paths, links them in a
+//! [`ChainTracker`] choosing the canonical chain and publishes its head over a `watch` channel,
+//! so consumers only following the tip don't have to filter the block event stream.
+use crate::{
+    parlia::{snapshot::DIFF_INTURN, validation},
+    peer::chain::{ChainTracker, ChainUpdate, Reorg},
+};
+use alloy_consensus::Header;
+use alloy_primitives::B256;
+use metrics::{counter, gauge, histogram};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tracing::info;
+
+/// Head block of the canonical chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainHead {
+    pub number: u64,
+    pub hash: B256,
+    /// Timestamp in seconds.
+    pub timestamp: u64,
+    /// Timestamp in milliseconds, sub-second from Lorentz on.
+    pub timestamp_ms: u64,
+    /// Whether the block was proposed in turn, which wins over an out-of-turn sibling.
+    pub in_turn: bool,
+}
+
+impl ChainHead {
+    pub fn new(header: &Header, hash: B256) -> Self {
+        Self {
+            number: header.number,
+            hash,
+            timestamp: header.timestamp,
+            timestamp_ms: validation::millis_timestamp(header),
+            in_turn: header.difficulty == DIFF_INTURN,
+        }
+    }
+}
+
+/// Move of the canonical head by an added block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeadUpdate {
+    Unchanged,
+    /// The block, or one of its stored descendants, extends the head.
+    Advanced,
+    /// The head moved to another branch.
+    Reorged(Reorg),
+}
+
+impl HeadUpdate {
+    pub fn changed(&self) -> bool {
+        !matches!(self, Self::Unchanged)
+    }
+}
+
+/// Publisher of the canonical head, shared by the block importer and the sync.
+#[derive(Debug, Clone, Default)]
+pub struct HeadTracker {
+    chain: Arc<Mutex<ChainTracker>>,
+    head: Arc<watch::Sender<Option<ChainHead>>>,
+}
+
+impl HeadTracker {
+    pub fn current(&self) -> Option<ChainHead> {
+        *self.head.borrow()
+    }
+
+    /// Returns a receiver notified whenever the head advances or reorgs.
+    pub fn subscribe(&self) -> watch::Receiver<Option<ChainHead>> {
+        self.head.subscribe()
+    }
+
+    /// Returns whether the block is stored, canonical or not.
+    pub fn contains(&self, hash: &B256) -> bool {
+        self.chain.lock().unwrap().header(hash).is_some()
+    }
+
+    /// Returns the parent hash of a stored block.
+    pub fn parent_hash(&self, hash: &B256) -> Option<B256> {
+        self.chain.lock().unwrap().header(hash).map(|header| header.parent_hash)
+    }
+
+    /// Returns the canonical hash at a recent height.
+    pub fn canonical_hash(&self, number: u64) -> Option<B256> {
+        self.chain.lock().unwrap().canonical_hash(number)
+    }
+
+    /// Adds a valid block, with the block justified by its vote attestation if any, and
+    /// publishes the head if the block moved it.
+    pub fn update(&self, header: &Header, hash: B256, attested: Option<u64>) -> HeadUpdate {
+        let mut chain = self.chain.lock().unwrap();
+        let old_head = chain.head().map(|(hash, _)| hash);
+        let update = chain.insert(header.clone(), hash, attested);
+        let (hash, header) = match update {
+            ChainUpdate::Extended | ChainUpdate::Reorg { .. } => {
+                chain.head().expect("head of a non-empty chain")
+            }
+            ChainUpdate::Known | ChainUpdate::Canonical | ChainUpdate::Fork => {
+                return HeadUpdate::Unchanged;
+            }
+        };
+        let new = ChainHead::new(header, hash);
+        let reorg = match (update, old_head) {
+            (ChainUpdate::Reorg { .. }, Some(old_head)) => Some(chain.reorg(old_head)),
+            _ => None,
+        };
+        drop(chain);
+
+        if let ChainUpdate::Reorg { depth } = update {
+            counter!("bscpeer_head_reorgs_total").increment(1);
+            histogram!("bscpeer_head_reorg_depth").record(depth as f64);
+            info!(number = new.number, hash = %new.hash, depth, "head reorged");
+        }
+        gauge!("bscpeer_head_block").set(new.number as f64);
+        self.head.send_replace(Some(new));
+        reorg.map_or(HeadUpdate::Advanced, HeadUpdate::Reorged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parlia::snapshot::DIFF_NOTURN;
+    use alloy_primitives::U256;
+
+    fn header(number: u64, parent_hash: B256, difficulty: U256) -> Header {
+        Header { number, parent_hash, difficulty, ..Default::default() }
+    }
+
+    #[test]
+    fn test_head_updates() {
+        let tracker = HeadTracker::default();
+        let mut head = tracker.subscribe();
+        let hash = B256::with_last_byte;
+
+        assert!(tracker.update(&header(10, B256::ZERO, DIFF_NOTURN), hash(1), None).changed());
+        assert!(head.has_changed().unwrap());
+        assert_eq!(head.borrow_and_update().map(|head| head.hash), Some(hash(1)));
+
+        // older blocks and out-of-turn siblings don't move the head
+        assert!(!tracker.update(&header(9, B256::ZERO, DIFF_INTURN), hash(2), None).changed());
+        assert!(!tracker.update(&header(10, B256::ZERO, DIFF_NOTURN), hash(3), None).changed());
+        assert!(!head.has_changed().unwrap());
+
+        // an in-turn sibling replaces an out-of-turn head
+        let update = tracker.update(&header(10, B256::ZERO, DIFF_INTURN), hash(4), None);
+        let HeadUpdate::Reorged(reorg) = update else { panic!("expected a reorg, got {update:?}") };
+        assert_eq!(reorg.dropped.iter().map(|block| block.hash).collect::<Vec<_>>(), [hash(1)]);
+        assert_eq!(reorg.added.iter().map(|block| block.hash).collect::<Vec<_>>(), [hash(4)]);
+        assert_eq!(
+            tracker.update(&header(11, hash(4), DIFF_NOTURN), hash(5), None),
+            HeadUpdate::Advanced
+        );
+        assert_eq!(tracker.current().map(|head| (head.number, head.hash)), Some((11, hash(5))));
+        assert_eq!(tracker.canonical_hash(10), Some(hash(4)));
+    }
+}
@@ -0,0 +1,195 @@
+//! Round-trip latency of each session.
+//!
+//! reth answers RLPx pings internally without exposing their round-trip time, so [`spawn_probe`]
+//! periodically times the cheapest eth request instead: an empty body request, which peers
+//! answer with an empty response without touching their database. Slow links show up as a high
+//! latency, slow peers as slow block delivery despite a low one.
+//!
+//! The probes double as keep-alive requests: with an idle timeout set, sessions that stopped
+//! answering them are disconnected.
+use crate::{config::SessionConfig, instance, peer::fetch};
+use metrics::{counter, histogram};
+use reth_eth_wire_types::DisconnectReason;
+use reth_network::{EthNetworkPrimitives, NetworkHandle};
+use reth_network_api::Peers;
+use reth_network_peers::PeerId;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{task::JoinHandle, time::interval};
+use tracing::debug;
+
+/// Weight of the latest sample in the smoothed latency.
+const SMOOTHING: f64 = 0.2;
+
+/// Latency of a session.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerLatency {
+    pub peer_id: PeerId,
+    /// Latest round-trip time, in milliseconds.
+    pub last_ms: f64,
+    /// Exponentially smoothed round-trip time, in milliseconds.
+    pub smoothed_ms: f64,
+    pub samples: u64,
+}
+
+/// Latency of the connected peers.
+#[derive(Debug, Default)]
+pub struct LatencyTracker {
+    peers: HashMap<PeerId, PeerLatency>,
+    /// When each peer last answered a probe.
+    answered: HashMap<PeerId, Instant>,
+    /// Peers with an open session, a probe answered after the session closed is ignored.
+    sessions: HashSet<PeerId>,
+}
+
+impl LatencyTracker {
+    pub fn add_peer(&mut self, peer_id: PeerId) {
+        self.sessions.insert(peer_id);
+    }
+
+    /// Records a round-trip time measured for the peer, if its session is still open.
+    pub fn record(&mut self, peer_id: PeerId, rtt: Duration) {
+        if !self.sessions.contains(&peer_id) {
+            return;
+        }
+        histogram!("bscpeer_peer_rtt_seconds").record(rtt.as_secs_f64());
+        let rtt_ms = rtt.as_micros() as f64 / 1000.0;
+        let latency = self.peers.entry(peer_id).or_insert(PeerLatency {
+            peer_id,
+            last_ms: rtt_ms,
+            smoothed_ms: rtt_ms,
+            samples: 0,
+        });
+        latency.last_ms = rtt_ms;
+        latency.smoothed_ms += SMOOTHING * (rtt_ms - latency.smoothed_ms);
+        latency.samples += 1;
+        self.answered.insert(peer_id, Instant::now());
+    }
+
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.peers.remove(peer_id);
+        self.answered.remove(peer_id);
+        self.sessions.remove(peer_id);
+    }
+
+    /// Returns whether the peer answered no probe for longer than the timeout, counting from the
+    /// start of its session if it never did.
+    pub fn is_idle(
+        &self,
+        peer_id: &PeerId,
+        established: Instant,
+        timeout: Duration,
+        now: Instant,
+    ) -> bool {
+        let last = self.answered.get(peer_id).copied().unwrap_or(established).max(established);
+        now.saturating_duration_since(last) > timeout
+    }
+
+    /// Returns the latency of the peer, if it was measured yet.
+    pub fn get(&self, peer_id: &PeerId) -> Option<&PeerLatency> {
+        self.peers.get(peer_id)
+    }
+
+    /// Returns the latency of every measured peer, fastest first.
+    pub fn latencies(&self) -> Vec<PeerLatency> {
+        let mut latencies: Vec<_> = self.peers.values().cloned().collect();
+        latencies.sort_by(|a, b| a.smoothed_ms.total_cmp(&b.smoothed_ms));
+        latencies
+    }
+}
+
+/// Spawns the task probing every session at the keep-alive interval, recording the results in
+/// the tracker and disconnecting idle sessions.
+pub fn spawn_probe(
+    network: NetworkHandle<EthNetworkPrimitives>,
+    tracker: Arc<Mutex<LatencyTracker>>,
+    config: &SessionConfig,
+) -> JoinHandle<()> {
+    let keepalive = Duration::from_secs(config.keepalive_secs.max(1));
+    let idle_timeout =
+        (config.idle_timeout_secs > 0).then(|| Duration::from_secs(config.idle_timeout_secs));
+    instance::spawn(async move {
+        let mut interval = interval(keepalive);
+        loop {
+            interval.tick().await;
+            let Ok(peers) = network.get_all_peers().await else { continue };
+            for peer in peers {
+                let network = network.clone();
+                let tracker = tracker.clone();
+                instance::spawn(async move {
+                    let start = Instant::now();
+                    if let Err(e) = fetch::ping(&network, peer.remote_id).await {
+                        debug!(peer_id = %peer.remote_id, "latency probe failed: {}", e);
+                        let idle = idle_timeout.is_some_and(|timeout| {
+                            let tracker = tracker.lock().unwrap();
+                            let established = peer.session_established;
+                            tracker.is_idle(&peer.remote_id, established, timeout, Instant::now())
+                        });
+                        if idle {
+                            debug!(peer_id = %peer.remote_id, "disconnecting idle session");
+                            counter!("bscpeer_idle_disconnects_total").increment(1);
+                            network.disconnect_peer_with_reason(
+                                peer.remote_id,
+                                DisconnectReason::PingTimeout,
+                            );
+                        }
+                        return;
+                    }
+                    tracker.lock().unwrap().record(peer.remote_id, start.elapsed());
+                });
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_tracker() {
+        let mut tracker = LatencyTracker::default();
+        let (fast, slow) = (PeerId::repeat_byte(1), PeerId::repeat_byte(2));
+        tracker.add_peer(fast);
+        tracker.add_peer(slow);
+        tracker.record(slow, Duration::from_millis(200));
+        tracker.record(fast, Duration::from_millis(10));
+        tracker.record(fast, Duration::from_millis(60));
+
+        let latency = tracker.get(&fast).unwrap();
+        assert_eq!(latency.samples, 2);
+        assert_eq!(latency.last_ms, 60.0);
+        assert!((latency.smoothed_ms - 20.0).abs() < 1e-9);
+
+        let order: Vec<_> = tracker.latencies().iter().map(|latency| latency.peer_id).collect();
+        assert_eq!(order, vec![fast, slow]);
+
+        tracker.remove_peer(&fast);
+        assert!(tracker.get(&fast).is_none());
+        // a probe answered after the session closed
+        tracker.record(fast, Duration::from_millis(10));
+        assert!(tracker.get(&fast).is_none());
+    }
+
+    #[test]
+    fn test_idle() {
+        let mut tracker = LatencyTracker::default();
+        let peer_id = PeerId::repeat_byte(1);
+        let timeout = Duration::from_secs(60);
+        let established = Instant::now();
+        assert!(!tracker.is_idle(&peer_id, established, timeout, established + timeout));
+        assert!(tracker.is_idle(&peer_id, established, timeout, established + timeout * 2));
+
+        // an answer restarts the timeout
+        tracker.add_peer(peer_id);
+        tracker.record(peer_id, Duration::from_millis(10));
+        let answered = tracker.answered[&peer_id];
+        assert!(!tracker.is_idle(&peer_id, established, timeout, answered + timeout));
+        assert!(tracker.is_idle(&peer_id, established, timeout, answered + timeout * 2));
+    }
+}
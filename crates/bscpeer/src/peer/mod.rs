@@ -1,3 +1,45 @@
+pub mod alerts;
+pub mod announce;
+pub mod anomalies;
+pub mod bandwidth;
+pub mod benchmark;
+pub mod banlist;
 pub mod blockstate;
+pub mod bootnodes;
+pub mod capture;
+pub mod chain;
+pub mod deployments;
+pub mod dialer;
+pub mod disconnects;
+pub mod downtime;
+pub mod duplicates;
+pub mod events;
+pub mod fees;
+pub mod fetch;
+pub mod filter;
+pub mod forkid;
+pub mod geo;
 pub mod handshake;
+pub mod head;
+pub mod latency;
+pub mod nodekey;
+pub mod orphans;
+pub mod passthrough;
+pub mod peer_heads;
+pub mod ping;
+pub mod pipeline;
+pub mod proxy;
+pub mod recovery;
+pub mod requests;
+pub mod snap;
+pub mod status;
+pub mod sync;
+pub mod tokens;
+pub mod transactions;
+pub mod transfers;
+pub mod trust;
 pub mod upgrade_status;
+pub mod validator_stats;
+pub mod verify;
+pub mod watchlist;
+pub mod wire;
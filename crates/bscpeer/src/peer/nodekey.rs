@@ -0,0 +1,129 @@
+//! The node key, persisted so the node keeps its identity across restarts.
+//!
+//! The key is stored hex encoded, like the `nodekey` file of geth, and generated on first use.
+//! The file is readable by its owner only and written through a temporary file, so a crash never
+//! leaves a truncated key behind.
+use alloy_primitives::hex;
+use enr::Enr;
+use reth_network_peers::{NodeRecord, pk2id};
+use secp256k1::{SECP256K1, SecretKey, rand};
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    net::IpAddr,
+    path::Path,
+};
+
+/// Errors that can occur while loading or persisting the node key.
+#[derive(Debug, thiserror::Error)]
+pub enum NodeKeyError {
+    /// Reading or writing the key file failed.
+    #[error("node key io error: {0}")]
+    Io(#[from] std::io::Error),
+    /// The key file doesn't hold a hex encoded secp256k1 key.
+    #[error("invalid node key file: {0}")]
+    Invalid(String),
+}
+
+/// Loads the node key from the given file, generating and persisting a new one if it does not
+/// exist yet.
+pub fn load_or_generate(path: &Path) -> Result<SecretKey, NodeKeyError> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => {
+            let bytes =
+                hex::decode(contents.trim()).map_err(|e| NodeKeyError::Invalid(e.to_string()))?;
+            SecretKey::from_slice(&bytes).map_err(|e| NodeKeyError::Invalid(e.to_string()))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let secret_key = SecretKey::new(&mut rand::thread_rng());
+            write_key(path, &secret_key)?;
+            Ok(secret_key)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Writes the key to a temporary file readable by the owner only, then renames it into place.
+fn write_key(path: &Path, secret_key: &SecretKey) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    // a leftover from an earlier attempt may have other permissions
+    match std::fs::remove_file(&tmp) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp)?;
+    file.write_all(hex::encode(secret_key.secret_bytes()).as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)
+}
+
+/// Returns the node record of the key, as advertised at the given address and ports.
+pub fn node_record(secret_key: &SecretKey, ip: IpAddr, tcp_port: u16, udp_port: u16) -> NodeRecord {
+    let id = pk2id(&secret_key.public_key(SECP256K1));
+    NodeRecord { address: ip, tcp_port, udp_port, id }
+}
+
+/// Returns the signed ENR of the key, advertising the given address and ports.
+pub fn enr(
+    secret_key: &SecretKey,
+    ip: IpAddr,
+    tcp_port: u16,
+    udp_port: u16,
+) -> Result<Enr<SecretKey>, enr::Error> {
+    let mut builder = Enr::<SecretKey>::builder();
+    builder.ip(ip);
+    match ip {
+        IpAddr::V4(_) => builder.tcp4(tcp_port).udp4(udp_port),
+        IpAddr::V6(_) => builder.tcp6(tcp_port).udp6(udp_port),
+    };
+    builder.build(secret_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_load_or_generate() {
+        let dir = std::env::temp_dir().join(format!("bscpeer-nodekey-{}", std::process::id()));
+        let path = dir.join("nodekey");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let generated = load_or_generate(&path).unwrap();
+        assert_eq!(load_or_generate(&path).unwrap(), generated);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::write(&path, "not hex").unwrap();
+        assert!(matches!(load_or_generate(&path), Err(NodeKeyError::Invalid(_))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_enr_matches_node_record() {
+        let secret_key = SecretKey::from_slice(&[0x42; 32]).unwrap();
+        let ip = IpAddr::from(Ipv4Addr::new(203, 0, 113, 7));
+        let record = node_record(&secret_key, ip, 30311, 30312);
+        assert_eq!(record.address, ip);
+        assert_eq!(record.udp_port, 30312);
+
+        let enr = enr(&secret_key, ip, 30311, 30312).unwrap();
+        assert_eq!(enr.ip4(), Some(Ipv4Addr::new(203, 0, 113, 7)));
+        assert_eq!(enr.tcp4(), Some(30311));
+        assert_eq!(enr.udp4(), Some(30312));
+        assert!(enr.to_base64().starts_with("enr:"));
+    }
+}
@@ -0,0 +1,126 @@
+//! Blocks waiting for their parent.
+//!
+//! A block whose parent isn't known can't be checked against it, and may belong to a branch the
+//! node hasn't seen. The importer parks such blocks in an [`OrphanPool`], requests the missing
+//! parent from the peer that sent the block and imports the orphans once the parent is known.
+use alloy_primitives::B256;
+use metrics::{counter, gauge};
+use std::collections::{HashMap, VecDeque};
+
+/// Only blocks within this distance of the head wait for their parent. Further ahead the sync
+/// backfills the gap, further behind they are stale forks.
+pub const ORPHAN_WINDOW: u64 = 16;
+/// Maximum number of parked blocks, the oldest are dropped first.
+const MAX_ORPHANS: usize = 128;
+
+/// A parked block.
+#[derive(Debug)]
+struct Orphan<T> {
+    parent_hash: B256,
+    block: T,
+}
+
+/// Bounded pool of blocks by hash, indexed by their missing parent.
+#[derive(Debug)]
+pub struct OrphanPool<T> {
+    blocks: HashMap<B256, Orphan<T>>,
+    /// Hashes of the orphans waiting for each parent.
+    children: HashMap<B256, Vec<B256>>,
+    /// Hashes in insertion order, to drop the oldest.
+    order: VecDeque<B256>,
+    capacity: usize,
+}
+
+impl<T> Default for OrphanPool<T> {
+    fn default() -> Self {
+        Self::new(MAX_ORPHANS)
+    }
+}
+
+impl<T> OrphanPool<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self { blocks: HashMap::new(), children: HashMap::new(), order: VecDeque::new(), capacity }
+    }
+
+    /// Parks a block. Returns `true` if no other orphan was waiting for the same parent, i.e.
+    /// the parent still has to be requested.
+    pub fn insert(&mut self, hash: B256, parent_hash: B256, block: T) -> bool {
+        if self.blocks.contains_key(&hash) {
+            return false;
+        }
+        while self.blocks.len() >= self.capacity {
+            let Some(oldest) = self.order.pop_front() else { break };
+            if self.remove(&oldest).is_some() {
+                counter!("bscpeer_orphan_blocks_dropped_total").increment(1);
+            }
+        }
+        self.blocks.insert(hash, Orphan { parent_hash, block });
+        self.order.push_back(hash);
+        let siblings = self.children.entry(parent_hash).or_default();
+        siblings.push(hash);
+        gauge!("bscpeer_orphan_blocks").set(self.blocks.len() as f64);
+        siblings.len() == 1
+    }
+
+    /// Returns the parents the orphans are waiting for.
+    pub fn missing_parents(&self) -> impl Iterator<Item = B256> + '_ {
+        self.children.keys().copied()
+    }
+
+    /// Removes and returns the orphans whose parent arrived.
+    pub fn take_children(&mut self, parent_hash: &B256) -> Vec<T> {
+        let hashes = self.children.remove(parent_hash).unwrap_or_default();
+        let children: Vec<T> = hashes
+            .into_iter()
+            .filter_map(|hash| self.blocks.remove(&hash))
+            .map(|orphan| orphan.block)
+            .collect();
+        self.order.retain(|hash| self.blocks.contains_key(hash));
+        gauge!("bscpeer_orphan_blocks").set(self.blocks.len() as f64);
+        children
+    }
+
+    fn remove(&mut self, hash: &B256) -> Option<T> {
+        let orphan = self.blocks.remove(hash)?;
+        if let Some(siblings) = self.children.get_mut(&orphan.parent_hash) {
+            siblings.retain(|sibling| sibling != hash);
+            if siblings.is_empty() {
+                self.children.remove(&orphan.parent_hash);
+            }
+        }
+        Some(orphan.block)
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_orphan_pool() {
+        let hash = B256::with_last_byte;
+        let mut pool = OrphanPool::new(3);
+        assert!(pool.insert(hash(2), hash(1), "a"));
+        assert!(!pool.insert(hash(3), hash(1), "b"));
+        assert!(!pool.insert(hash(3), hash(1), "b"));
+        assert!(pool.insert(hash(5), hash(4), "c"));
+        assert_eq!(pool.len(), 3);
+
+        // the oldest orphan makes room
+        assert!(pool.insert(hash(7), hash(6), "d"));
+        assert_eq!(pool.take_children(&hash(1)), vec!["b"]);
+        assert!(pool.take_children(&hash(1)).is_empty());
+        let mut parents: Vec<_> = pool.missing_parents().collect();
+        parents.sort();
+        assert_eq!(parents, vec![hash(4), hash(6)]);
+        assert_eq!(pool.len(), 2);
+    }
+}
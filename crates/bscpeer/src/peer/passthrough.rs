@@ -0,0 +1,84 @@
+//! RLPx sub-protocols configured by name, version and message count.
+//!
+//! They are only advertised: received messages are logged and counted, and nothing is sent, so
+//! experimental BSC sub-protocols can be negotiated without a dedicated handler.
+use crate::config::ExtraProtocolConfig;
+use bytes::BytesMut;
+use futures::{Stream, StreamExt, future};
+use metrics::counter;
+use reth_eth_wire::{
+    Capability, capability::SharedCapabilities, multiplex::ProtocolConnection, protocol::Protocol,
+};
+use reth_network::protocol::{ConnectionHandler, OnNotSupported, ProtocolHandler};
+use reth_network_api::Direction;
+use reth_network_peers::PeerId;
+use std::{net::SocketAddr, pin::Pin};
+use tracing::debug;
+
+/// Handler of a configured protocol, added to the network as an RLPx sub-protocol.
+#[derive(Debug, Clone)]
+pub struct PassthroughProtocol {
+    name: String,
+    protocol: Protocol,
+}
+
+impl PassthroughProtocol {
+    pub fn new(config: &ExtraProtocolConfig) -> Self {
+        let capability = Capability::new(config.name.clone(), config.version as usize);
+        Self { name: config.name.clone(), protocol: Protocol::new(capability, config.messages) }
+    }
+}
+
+impl ProtocolHandler for PassthroughProtocol {
+    type ConnectionHandler = Self;
+
+    fn on_incoming(&self, _socket_addr: SocketAddr) -> Option<Self::ConnectionHandler> {
+        Some(self.clone())
+    }
+
+    fn on_outgoing(
+        &self,
+        _socket_addr: SocketAddr,
+        _peer_id: PeerId,
+    ) -> Option<Self::ConnectionHandler> {
+        Some(self.clone())
+    }
+}
+
+impl ConnectionHandler for PassthroughProtocol {
+    type Connection = Pin<Box<dyn Stream<Item = BytesMut> + Send>>;
+
+    fn protocol(&self) -> Protocol {
+        self.protocol.clone()
+    }
+
+    fn on_unsupported_by_peer(
+        self,
+        _supported: &SharedCapabilities,
+        _direction: Direction,
+        _peer_id: PeerId,
+    ) -> OnNotSupported {
+        OnNotSupported::KeepAlive
+    }
+
+    fn into_connection(
+        self,
+        _direction: Direction,
+        peer_id: PeerId,
+        conn: ProtocolConnection,
+    ) -> Self::Connection {
+        debug!(%peer_id, protocol = %self.name, "negotiated extra protocol");
+        Box::pin(conn.filter_map(move |buf| {
+            debug!(
+                %peer_id,
+                protocol = %self.name,
+                id = ?buf.first(),
+                len = buf.len(),
+                "received extra protocol message"
+            );
+            counter!("bscpeer_extra_protocol_messages_total", "protocol" => self.name.clone())
+                .increment(1);
+            future::ready(None::<BytesMut>)
+        }))
+    }
+}
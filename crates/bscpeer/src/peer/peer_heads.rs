@@ -0,0 +1,134 @@
+//! Best block each peer is believed to have.
+//!
+//! The status exchanged at the handshake carries the peer's best hash and total difficulty, and
+//! eth/69 peers its best number too. Afterwards every block or hash a peer announces is one it
+//! has, so the head is advanced from the announcements. Requests for a range can then go to the
+//! peers that actually have it.
+use alloy_primitives::{B256, U256};
+use reth_network_peers::PeerId;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// How a peer's head was learned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeadSource {
+    Status,
+    Block,
+    Hash,
+}
+
+/// Best block of a peer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerHead {
+    pub peer_id: PeerId,
+    pub hash: B256,
+    /// Unknown until the peer announces a block, unless its status carries it.
+    pub number: Option<u64>,
+    /// Total difficulty from the status or the latest announced block.
+    pub total_difficulty: Option<U256>,
+    pub source: HeadSource,
+}
+
+/// Heads of the connected peers.
+#[derive(Debug, Default)]
+pub struct PeerHeads {
+    peers: HashMap<PeerId, PeerHead>,
+}
+
+impl PeerHeads {
+    /// Records the head from the peer's status at the handshake.
+    pub fn on_status(
+        &mut self,
+        peer_id: PeerId,
+        hash: B256,
+        number: Option<u64>,
+        total_difficulty: Option<U256>,
+    ) {
+        let head = PeerHead { peer_id, hash, number, total_difficulty, source: HeadSource::Status };
+        self.peers.insert(peer_id, head);
+    }
+
+    /// Advances the head to a block the peer announced, in full with its total difficulty or by
+    /// hash. Returns whether the head moved.
+    pub fn on_announcement(
+        &mut self,
+        peer_id: PeerId,
+        hash: B256,
+        number: u64,
+        total_difficulty: Option<U256>,
+    ) -> bool {
+        let source = if total_difficulty.is_some() { HeadSource::Block } else { HeadSource::Hash };
+        let head = self.peers.entry(peer_id).or_insert(PeerHead {
+            peer_id,
+            hash,
+            number: None,
+            total_difficulty: None,
+            source,
+        });
+        if head.number.is_some_and(|best| best >= number) {
+            return false;
+        }
+        head.hash = hash;
+        head.number = Some(number);
+        head.total_difficulty = total_difficulty.or(head.total_difficulty);
+        head.source = source;
+        true
+    }
+
+    /// Returns the best block number of the peer, if known.
+    pub fn best_number(&self, peer_id: &PeerId) -> Option<u64> {
+        self.peers.get(peer_id)?.number
+    }
+
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.peers.remove(peer_id);
+    }
+
+    /// Returns the number of connected peers.
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Returns the heads of all peers, highest first.
+    pub fn heads(&self) -> Vec<PeerHead> {
+        let mut heads: Vec<_> = self.peers.values().cloned().collect();
+        heads.sort_by(|a, b| b.number.cmp(&a.number));
+        heads
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peer_heads() {
+        let mut heads = PeerHeads::default();
+        let (a, b) = (PeerId::repeat_byte(1), PeerId::repeat_byte(2));
+        let hash = B256::with_last_byte;
+
+        heads.on_status(a, hash(1), None, Some(U256::from(100)));
+        assert_eq!(heads.best_number(&a), None);
+        assert!(heads.on_announcement(a, hash(2), 10, None));
+        assert!(!heads.on_announcement(a, hash(3), 9, Some(U256::from(120))));
+        assert!(heads.on_announcement(a, hash(4), 11, Some(U256::from(122))));
+
+        let head = &heads.heads()[0];
+        assert_eq!((head.hash, head.number), (hash(4), Some(11)));
+        assert_eq!(
+            (head.total_difficulty, head.source),
+            (Some(U256::from(122)), HeadSource::Block)
+        );
+
+        heads.on_status(b, hash(5), Some(20), None);
+        assert_eq!(heads.heads()[0].peer_id, b);
+        heads.remove_peer(&b);
+        assert_eq!(heads.heads().len(), 1);
+    }
+}
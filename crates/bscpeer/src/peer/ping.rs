@@ -0,0 +1,152 @@
+//! Handshake check of a single peer, behind the `ping` subcommand.
+//!
+//! Dials the peer from a throwaway network without discovery, runs the RLPx and BSC handshake as
+//! the node would and reports what the peer sent, or why the handshake failed.
+use crate::{
+    config::Config,
+    error::Error,
+    instance, node,
+    peer::{
+        forkid::ForkIdPolicy,
+        handshake::{BscHandshake, HandshakeReport},
+        status::StatusValidator,
+    },
+};
+use reth_eth_wire::HelloMessageWithProtocols;
+use reth_network::{
+    EthNetworkPrimitives, NetworkConfig, NetworkEvent, NetworkEventListenerProvider, NetworkHandle,
+    NetworkManager,
+};
+use reth_network_api::{Peers, events::SessionInfo};
+use reth_network_peers::{NodeRecord, pk2id};
+use reth_provider::noop::NoopProvider;
+use secp256k1::{SECP256K1, SecretKey, rand};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{sync::mpsc, task::JoinHandle, time::Instant};
+use tokio_stream::StreamExt;
+
+/// Errors pinging a peer.
+#[derive(Debug, thiserror::Error)]
+pub enum PingError {
+    /// The chain could not be resolved or the local network could not be started.
+    #[error(transparent)]
+    Start(#[from] Error),
+    /// The peer didn't get to the eth handshake in time, the connection or the RLPx hello failed.
+    #[error("no eth handshake within {0:?}, the connection or the RLPx hello failed")]
+    Timeout(Duration),
+}
+
+/// What was learned about the peer.
+#[derive(Debug, Default)]
+pub struct PingReport {
+    /// Outcome of the eth and BSC handshake.
+    pub handshake: HandshakeReport,
+    /// The established session, if the handshake succeeded.
+    pub session: Option<SessionInfo>,
+}
+
+/// Dials the peer with the chain settings of the config and reports the handshake outcome.
+pub async fn ping(
+    config: &Config,
+    peer: NodeRecord,
+    timeout: Duration,
+) -> Result<PingReport, PingError> {
+    let (reports_tx, mut reports) = mpsc::unbounded_channel();
+    let (network, task) = spawn_network(config, Some(reports_tx)).await?;
+
+    let mut events = network.event_listener();
+    network.add_peer(peer.id, peer.tcp_addr());
+    let deadline = Instant::now() + timeout;
+    let mut report = PingReport::default();
+    let mut handshake_done = false;
+    loop {
+        tokio::select! {
+            Some(handshake) = reports.recv() => {
+                handshake_done = true;
+                let failed = handshake.error.is_some();
+                report.handshake = handshake;
+                if failed {
+                    break;
+                }
+            }
+            Some(event) = events.next() => match event {
+                NetworkEvent::ActivePeerSession { info, .. } if info.peer_id == peer.id => {
+                    report.session = Some(info);
+                    network.disconnect_peer(peer.id);
+                    break;
+                }
+                _ => {}
+            },
+            _ = tokio::time::sleep_until(deadline) => break,
+        }
+    }
+    task.abort();
+
+    if !handshake_done {
+        return Err(PingError::Timeout(timeout));
+    }
+    Ok(report)
+}
+
+/// Starts a network with the chain settings of the config, without discovery, reporting the
+/// handshakes to `reports` if set.
+///
+/// The network uses a random key, so peers don't mistake it for the running node.
+pub(crate) async fn spawn_network(
+    config: &Config,
+    reports: Option<mpsc::UnboundedSender<HandshakeReport>>,
+) -> Result<(NetworkHandle<EthNetworkPrimitives>, JoinHandle<()>), Error> {
+    let (chain_spec, head, _) = node::resolve_chain(&config.chain)?;
+    let head = node::resolve_head(&config.chain, &chain_spec, head).await;
+    let fork_id_policy = ForkIdPolicy::new(&chain_spec, head, &config.fork_id);
+    let mut handshake = BscHandshake::new(fork_id_policy)
+        .with_status_validator(StatusValidator::new(&chain_spec))
+        .with_timeout(Duration::from_secs(config.session.handshake_timeout_secs));
+    if let Some(reports) = reports {
+        handshake = handshake.with_reports(reports);
+    }
+
+    let secret_key = SecretKey::new(&mut rand::thread_rng());
+    let mut hello = HelloMessageWithProtocols::builder(pk2id(&secret_key.public_key(SECP256K1)));
+    if let Some(client_version) = &config.capabilities.client_version {
+        hello = hello.client_version(client_version);
+    }
+    let net_cfg = NetworkConfig::builder(secret_key)
+        .hello_message(hello.build())
+        .listener_addr(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))
+        .disable_discovery()
+        .sessions_config(config.sessions_config())
+        .set_head(head)
+        .with_pow()
+        .eth_rlpx_handshake(Arc::new(handshake))
+        .build(NoopProvider::eth(Arc::new(chain_spec)));
+    let manager = NetworkManager::<EthNetworkPrimitives>::new(net_cfg).await?;
+    let network = manager.handle().clone();
+    Ok((network, instance::spawn(manager)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chain_config::BscNetwork, testing::MockPeer};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ping_mock_peer() {
+        let mut config = Config::default();
+        config.chain.network = BscNetwork::Chapel;
+        let (chain_spec, head, _) = node::resolve_chain(&config.chain).unwrap();
+        let genesis = chain_spec.genesis_hash();
+        let peer = MockPeer::spawn(Arc::new(chain_spec), head, Vec::new()).await;
+        let record = NodeRecord::new(peer.network().local_addr(), peer.peer_id());
+
+        let report = ping(&config, record, Duration::from_secs(10)).await.unwrap();
+        assert_eq!(report.handshake.error, None);
+        assert_eq!(report.handshake.status.unwrap().genesis, genesis);
+        assert!(report.handshake.upgrade_status.is_some());
+        assert_eq!(report.session.unwrap().peer_id, peer.peer_id());
+    }
+}
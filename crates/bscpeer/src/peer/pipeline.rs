@@ -0,0 +1,320 @@
+//! Download pipeline of the block sync: per-peer work queues and the fetch stages.
+//!
+//! Backfill blocks are spread over per-peer queues, each peer working through its own. A peer
+//! whose queue runs dry steals the back half of the longest queue, so a slow or overloaded peer
+//! doesn't hold up the range assigned to it. Every fetched block then goes through the
+//! [`Stage`]s in order, each timed separately.
+//!
+//! How many requests a peer gets at once is sized by its [`RequestWindow`]: it grows while the
+//! peer answers quickly and halves on failures and slow answers, so fast peers are kept busy
+//! without tripping the request limits of the slow ones.
+//!
+//! Blocks only go to peers whose best block covers them: a peer that synced recently doesn't
+//! have the range yet, and a request for it just fails after the timeout.
+use metrics::{counter, histogram};
+use reth_network_peers::PeerId;
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    time::{Duration, Instant},
+};
+
+/// Requests in flight a new peer is allowed.
+pub const INITIAL_WINDOW: usize = 4;
+/// Bounds of the request window.
+const MIN_WINDOW: f64 = 1.0;
+const MAX_WINDOW: f64 = 16.0;
+/// Responses slower than this shrink the window like failures.
+const SLOW_RESPONSE: Duration = Duration::from_secs(2);
+
+/// Stages a fetched block goes through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Fetching the header.
+    Header,
+    /// Fetching the body and checking it against the header.
+    Body,
+    /// Checking the block against the checkpoints.
+    Verify,
+    /// Reporting the block and fetching its receipts for the consumers.
+    Emit,
+}
+
+impl Stage {
+    /// Returns the stage name used in metrics.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Header => "header",
+            Self::Body => "body",
+            Self::Verify => "verify",
+            Self::Emit => "emit",
+        }
+    }
+
+    /// Runs the stage, recording its duration and whether it failed.
+    pub async fn run<T, E>(self, stage: impl Future<Output = Result<T, E>>) -> Result<T, E> {
+        let start = Instant::now();
+        let result = stage.await;
+        histogram!("bscpeer_pipeline_stage_seconds", "stage" => self.name())
+            .record(start.elapsed().as_secs_f64());
+        if result.is_err() {
+            counter!("bscpeer_pipeline_stage_failures_total", "stage" => self.name()).increment(1);
+        }
+        result
+    }
+}
+
+/// Intervals between the steps of a requested block, from the announcement of its hash to the
+/// event reporting it, so dashboards can tell where latency is introduced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interval {
+    /// From the first announcement of the block number to the request.
+    AnnounceToRequest,
+    /// From the header request to the body response.
+    RequestToResponse,
+    /// From the body response to the emitted event.
+    ResponseToEmit,
+}
+
+impl Interval {
+    /// Returns the interval name used in metrics.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::AnnounceToRequest => "announce_to_request",
+            Self::RequestToResponse => "request_to_response",
+            Self::ResponseToEmit => "response_to_emit",
+        }
+    }
+
+    /// Records the duration of the interval.
+    pub fn record(self, elapsed: Duration) {
+        histogram!("bscpeer_block_pipeline_latency_seconds", "interval" => self.name())
+            .record(elapsed.as_secs_f64());
+    }
+}
+
+/// Number of requests a peer may have in flight, adjusted additive-increase,
+/// multiplicative-decrease.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RequestWindow {
+    size: f64,
+}
+
+impl Default for RequestWindow {
+    fn default() -> Self {
+        Self { size: INITIAL_WINDOW as f64 }
+    }
+}
+
+impl RequestWindow {
+    pub fn limit(&self) -> usize {
+        self.size as usize
+    }
+
+    /// Grows the window by one per window of fast responses, or halves it on a slow one.
+    pub fn on_response(&mut self, elapsed: Duration) {
+        histogram!("bscpeer_pipeline_response_seconds").record(elapsed.as_secs_f64());
+        if elapsed > SLOW_RESPONSE {
+            self.on_failure();
+        } else {
+            self.size = (self.size + 1.0 / self.size).min(MAX_WINDOW);
+        }
+    }
+
+    /// Halves the window.
+    pub fn on_failure(&mut self) {
+        self.size = (self.size / 2.0).max(MIN_WINDOW);
+    }
+}
+
+/// Blocks assigned to each peer but not requested yet.
+#[derive(Debug, Default)]
+pub struct WorkQueues {
+    queues: HashMap<PeerId, VecDeque<u64>>,
+    /// Best block number of the peers that announced one. Peers without are assumed to have
+    /// every block.
+    heads: HashMap<PeerId, u64>,
+}
+
+impl WorkQueues {
+    pub fn add_peer(&mut self, peer_id: PeerId) {
+        self.queues.entry(peer_id).or_default();
+    }
+
+    /// Removes a peer, returning the blocks it hadn't requested yet.
+    pub fn remove_peer(&mut self, peer_id: &PeerId) -> VecDeque<u64> {
+        self.heads.remove(peer_id);
+        self.queues.remove(peer_id).unwrap_or_default()
+    }
+
+    /// Sets the best block number of a peer, returning whether it moved forward.
+    pub fn set_head(&mut self, peer_id: PeerId, number: u64) -> bool {
+        let head = self.heads.entry(peer_id).or_default();
+        let advanced = number > *head;
+        *head = (*head).max(number);
+        advanced
+    }
+
+    /// Whether the peer is believed to have the block.
+    pub fn covers(&self, peer_id: &PeerId, block_number: u64) -> bool {
+        self.heads.get(peer_id).is_none_or(|head| *head >= block_number)
+    }
+
+    /// Appends a block to the shortest queue of the peers having it. Returns `false` if no peer
+    /// has it.
+    pub fn assign(&mut self, block_number: u64) -> bool {
+        let heads = &self.heads;
+        let Some((_, queue)) = self
+            .queues
+            .iter_mut()
+            .filter(|(peer_id, _)| heads.get(peer_id).is_none_or(|head| *head >= block_number))
+            .min_by_key(|(_, queue)| queue.len())
+        else {
+            return false;
+        };
+        queue.push_back(block_number);
+        true
+    }
+
+    /// Returns the next block for the peer to request, stealing the back half of the longest
+    /// queue if its own is empty. Only blocks the peer has are stolen.
+    pub fn next(&mut self, peer_id: PeerId) -> Option<u64> {
+        if let Some(block_number) = self.queues.get_mut(&peer_id)?.pop_front() {
+            return Some(block_number);
+        }
+        let head = self.heads.get(&peer_id).copied().unwrap_or(u64::MAX);
+        let (_, victim) = self
+            .queues
+            .iter_mut()
+            .filter(|(id, queue)| **id != peer_id && queue.iter().any(|number| *number <= head))
+            .max_by_key(|(_, queue)| queue.len())?;
+        let back = victim.split_off(victim.len() / 2);
+        let (mut stolen, kept): (VecDeque<u64>, VecDeque<u64>) =
+            back.into_iter().partition(|number| *number <= head);
+        victim.extend(kept);
+        if stolen.is_empty() {
+            // the covered blocks are all in the front half
+            let front = std::mem::take(victim);
+            let (covered, kept): (VecDeque<u64>, VecDeque<u64>) =
+                front.into_iter().partition(|number| *number <= head);
+            *victim = kept;
+            stolen = covered;
+        }
+        counter!("bscpeer_pipeline_steals_total").increment(1);
+        let block_number = stolen.pop_front();
+        self.queues.insert(peer_id, stolen);
+        block_number
+    }
+
+    /// Drops a block from the queues, e.g. because it was received through an announcement.
+    pub fn remove(&mut self, block_number: u64) {
+        for queue in self.queues.values_mut() {
+            queue.retain(|queued| *queued != block_number);
+        }
+    }
+
+    /// Keeps only the blocks matching the predicate.
+    pub fn retain(&mut self, mut keep: impl FnMut(u64) -> bool) {
+        for queue in self.queues.values_mut() {
+            queue.retain(|queued| keep(*queued));
+        }
+    }
+
+    pub fn contains(&self, block_number: u64) -> bool {
+        self.queues.values().any(|queue| queue.contains(&block_number))
+    }
+
+    /// Number of queued blocks over all peers.
+    pub fn len(&self) -> usize {
+        self.queues.values().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_window() {
+        let mut window = RequestWindow::default();
+        // one more per window of fast responses
+        for _ in 0..=INITIAL_WINDOW {
+            window.on_response(Duration::from_millis(100));
+        }
+        assert_eq!(window.limit(), INITIAL_WINDOW + 1);
+
+        window.on_failure();
+        assert_eq!(window.limit(), 2);
+        window.on_response(Duration::from_secs(5));
+        window.on_failure();
+        assert_eq!(window.limit(), 1);
+
+        for _ in 0..1000 {
+            window.on_response(Duration::from_millis(100));
+        }
+        assert_eq!(window.limit(), MAX_WINDOW as usize);
+    }
+
+    #[test]
+    fn test_work_stealing() {
+        let (busy, idle) = (PeerId::repeat_byte(1), PeerId::repeat_byte(2));
+        let mut queues = WorkQueues::default();
+        assert!(!queues.assign(1));
+        queues.add_peer(busy);
+        for number in 1..=6 {
+            queues.assign(number);
+        }
+        queues.add_peer(idle);
+
+        // the idle peer takes the back half of the busy peer's range
+        assert_eq!(queues.next(idle), Some(4));
+        assert_eq!(queues.next(idle), Some(5));
+        assert_eq!(queues.next(busy), Some(1));
+
+        // new work goes to the shortest queue
+        queues.assign(7);
+        queues.remove(6);
+        assert_eq!(queues.remove_peer(&busy), VecDeque::from([2, 3]));
+        assert_eq!(queues.len(), 1);
+        assert!(queues.contains(7));
+    }
+
+    #[test]
+    fn test_peer_heads() {
+        let (full, fresh) = (PeerId::repeat_byte(1), PeerId::repeat_byte(2));
+        let mut queues = WorkQueues::default();
+        queues.add_peer(full);
+        queues.add_peer(fresh);
+        assert!(!queues.set_head(fresh, 0));
+        assert!(queues.set_head(fresh, 3));
+        assert!(!queues.set_head(fresh, 2));
+        assert!(queues.covers(&full, 100));
+        assert!(!queues.covers(&fresh, 4));
+
+        // blocks above its head never go to the fresh peer
+        for number in 4..=7 {
+            queues.assign(number);
+        }
+        assert_eq!(queues.next(fresh), None);
+        assert_eq!(queues.remove_peer(&full), VecDeque::from([4, 5, 6, 7]));
+        assert!(!queues.assign(4));
+        assert!(queues.assign(3));
+
+        // only the covered blocks are stolen
+        let mut queues = WorkQueues::default();
+        queues.add_peer(full);
+        for number in [1, 2, 4, 5] {
+            queues.assign(number);
+        }
+        queues.add_peer(fresh);
+        queues.set_head(fresh, 2);
+        assert_eq!(queues.next(fresh), Some(1));
+        assert_eq!(queues.next(fresh), Some(2));
+        assert_eq!(queues.next(fresh), None);
+        assert_eq!(queues.remove_peer(&full), VecDeque::from([4, 5]));
+    }
+}
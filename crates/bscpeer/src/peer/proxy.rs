@@ -0,0 +1,285 @@
+//! Outbound RLPx connections through a SOCKS5 proxy.
+//!
+//! reth opens its outbound TCP connections itself, so they can't be handed a proxied socket.
+//! Instead, [`Socks5Forwarder`] binds a loopback listener for every dial and relays the one
+//! connection accepted on it through the proxy to the peer's real address. The node dials the
+//! loopback address; the RLPx handshake authenticates the peer id, not the address, so the
+//! session is the same as a direct one. The listener is closed once the connection is accepted or
+//! the dial timed out, and [`Socks5Forwarder::resolve`] maps the loopback address of the session
+//! back to the peer's real one while it lasts.
+use crate::{config::Socks5Config, instance};
+use metrics::counter;
+use reth_network_peers::PeerId;
+use std::{
+    collections::HashMap,
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+    time::{sleep, timeout},
+};
+use tracing::{debug, warn};
+
+/// Time the node is given to connect to the loopback listener of a dial.
+const ACCEPT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Delay before accepting again after a failed accept, e.g. out of file descriptors.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+const VERSION: u8 = 0x05;
+const NO_AUTH: u8 = 0x00;
+const USER_PASS: u8 = 0x02;
+const USER_PASS_VERSION: u8 = 0x01;
+const CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// Errors connecting through a SOCKS5 proxy.
+#[derive(Debug, thiserror::Error)]
+pub enum Socks5Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("unexpected socks version {0:#04x}")]
+    Version(u8),
+    #[error("proxy accepts none of the offered authentication methods")]
+    NoAcceptableMethod,
+    #[error("proxy rejected the credentials")]
+    AuthFailed,
+    #[error("socks5 username and password are limited to 255 bytes")]
+    CredentialsTooLong,
+    #[error("proxy failed to connect, reply {0:#04x}")]
+    ConnectFailed(u8),
+    #[error("unknown address type {0:#04x}")]
+    UnknownAddressType(u8),
+}
+
+/// Negotiates a `CONNECT` to `target` over a stream to the proxy.
+pub async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    target: SocketAddr,
+    credentials: Option<(&str, &str)>,
+) -> Result<(), Socks5Error> {
+    if credentials.is_some_and(|(username, password)| username.len() > 255 || password.len() > 255)
+    {
+        return Err(Socks5Error::CredentialsTooLong);
+    }
+    let method = if credentials.is_some() { USER_PASS } else { NO_AUTH };
+    stream.write_all(&[VERSION, 1, method]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != VERSION {
+        return Err(Socks5Error::Version(reply[0]));
+    }
+    if reply[1] != method {
+        return Err(Socks5Error::NoAcceptableMethod);
+    }
+
+    if let Some((username, password)) = credentials {
+        let mut request = vec![USER_PASS_VERSION, username.len() as u8];
+        request.extend_from_slice(username.as_bytes());
+        request.push(password.len() as u8);
+        request.extend_from_slice(password.as_bytes());
+        stream.write_all(&request).await?;
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0 {
+            return Err(Socks5Error::AuthFailed);
+        }
+    }
+
+    let mut request = vec![VERSION, CONNECT, 0];
+    match target {
+        SocketAddr::V4(addr) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+        SocketAddr::V6(addr) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+    }
+    request.extend_from_slice(&target.port().to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header).await?;
+    if header[0] != VERSION {
+        return Err(Socks5Error::Version(header[0]));
+    }
+    if header[1] != 0 {
+        return Err(Socks5Error::ConnectFailed(header[1]));
+    }
+    // skip the bound address and port
+    let len = match header[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => stream.read_u8().await? as usize,
+        atyp => return Err(Socks5Error::UnknownAddressType(atyp)),
+    };
+    let mut bound = vec![0u8; len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+/// Opens a connection to `target` through the proxy.
+pub async fn connect(config: &Socks5Config, target: SocketAddr) -> Result<TcpStream, Socks5Error> {
+    let mut stream = TcpStream::connect((config.host.as_str(), config.port)).await?;
+    stream.set_nodelay(true)?;
+    handshake(&mut stream, target, config.credentials()).await?;
+    Ok(stream)
+}
+
+/// Dial of a peer relayed through the proxy.
+#[derive(Debug)]
+struct Relay {
+    /// Address of the loopback listener the node dials.
+    local: SocketAddr,
+    /// Real address of the peer.
+    target: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+/// Relays of the dialed peers through the proxy, one per peer while dialing or connected.
+#[derive(Debug, Clone)]
+pub struct Socks5Forwarder {
+    config: Arc<Socks5Config>,
+    relays: Arc<Mutex<HashMap<PeerId, Relay>>>,
+}
+
+impl Socks5Forwarder {
+    pub fn new(config: Socks5Config) -> Self {
+        Self { config: Arc::new(config), relays: Default::default() }
+    }
+
+    /// Returns the address of a new loopback listener relaying to `target`, replacing an earlier
+    /// relay of the peer.
+    pub fn forward(&self, peer_id: PeerId, target: SocketAddr) -> io::Result<SocketAddr> {
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        let local = listener.local_addr()?;
+        // the relay removes itself once done, so it waits for the map to be updated
+        let mut relays = self.relays.lock().unwrap();
+        let task = instance::spawn(relay(self.clone(), listener, peer_id, target));
+        if let Some(previous) = relays.insert(peer_id, Relay { local, target, task }) {
+            previous.task.abort();
+        }
+        Ok(local)
+    }
+
+    /// Returns the real address of a peer connected through a relay, or `addr` if the peer isn't
+    /// connected through the relay at `addr`.
+    pub fn resolve(&self, peer_id: &PeerId, addr: SocketAddr) -> SocketAddr {
+        match self.relays.lock().unwrap().get(peer_id) {
+            Some(relay) if relay.local == addr => relay.target,
+            _ => addr,
+        }
+    }
+
+    /// Closes the relay of a peer that won't be dialed again.
+    pub fn remove(&self, peer_id: &PeerId) {
+        if let Some(relay) = self.relays.lock().unwrap().remove(peer_id) {
+            relay.task.abort();
+        }
+    }
+
+    /// Removes the relay of the peer if it's the one at `local`, not a newer one.
+    fn finished(&self, peer_id: &PeerId, local: SocketAddr) {
+        let mut relays = self.relays.lock().unwrap();
+        if relays.get(peer_id).is_some_and(|relay| relay.local == local) {
+            relays.remove(peer_id);
+        }
+    }
+}
+
+/// Relays the first connection accepted by the listener to `target` through the proxy, closing
+/// the listener once it accepted a connection or [`ACCEPT_TIMEOUT`] passed.
+async fn relay(
+    forwarder: Socks5Forwarder,
+    listener: TcpListener,
+    peer_id: PeerId,
+    target: SocketAddr,
+) {
+    let local = listener.local_addr().ok();
+    let accepted = timeout(ACCEPT_TIMEOUT, async {
+        loop {
+            match listener.accept().await {
+                Ok((inbound, _)) => return inbound,
+                Err(e) => {
+                    warn!(%peer_id, "failed to accept proxied connection: {}", e);
+                    sleep(ACCEPT_RETRY_DELAY).await;
+                }
+            }
+        }
+    })
+    .await;
+    drop(listener);
+    if let Ok(mut inbound) = accepted {
+        match connect(&forwarder.config, target).await {
+            Ok(mut outbound) => {
+                counter!("bscpeer_proxy_connections_total", "outcome" => "ok").increment(1);
+                let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+            }
+            Err(e) => {
+                debug!(%peer_id, %target, "proxy connection failed: {}", e);
+                counter!("bscpeer_proxy_connections_total", "outcome" => "error").increment(1);
+            }
+        }
+    }
+    if let Some(local) = local {
+        forwarder.finished(&peer_id, local);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_handshake() {
+        let (mut client, mut proxy) = tokio::io::duplex(64);
+        let target: SocketAddr = "10.0.0.1:30311".parse().unwrap();
+        let server = tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            proxy.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [VERSION, 1, USER_PASS]);
+            proxy.write_all(&[VERSION, USER_PASS]).await.unwrap();
+
+            let mut auth = [0u8; 9];
+            proxy.read_exact(&mut auth).await.unwrap();
+            assert_eq!(&auth, b"\x01\x03bob\x03pwd");
+            proxy.write_all(&[USER_PASS_VERSION, 0]).await.unwrap();
+
+            let mut request = [0u8; 10];
+            proxy.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, [VERSION, CONNECT, 0, ATYP_IPV4, 10, 0, 0, 1, 0x76, 0x67]);
+            proxy.write_all(&[VERSION, 0, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]).await.unwrap();
+        });
+        handshake(&mut client, target, Some(("bob", "pwd"))).await.unwrap();
+        server.await.unwrap();
+
+        let (mut client, mut proxy) = tokio::io::duplex(64);
+        let server = tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            proxy.read_exact(&mut greeting).await.unwrap();
+            proxy.write_all(&[VERSION, NO_AUTH]).await.unwrap();
+            let mut request = [0u8; 10];
+            proxy.read_exact(&mut request).await.unwrap();
+            // connection refused
+            proxy.write_all(&[VERSION, 0x05, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]).await.unwrap();
+        });
+        let err = handshake(&mut client, target, None).await.unwrap_err();
+        assert!(matches!(err, Socks5Error::ConnectFailed(0x05)));
+        server.await.unwrap();
+
+        // a username too long for its length byte is rejected before anything is sent
+        let (mut client, _proxy) = tokio::io::duplex(64);
+        let username = "u".repeat(256);
+        let err = handshake(&mut client, target, Some((&username, "pwd"))).await.unwrap_err();
+        assert!(matches!(err, Socks5Error::CredentialsTooLong));
+    }
+}
@@ -0,0 +1,141 @@
+//! Recovery from losing every peer.
+//!
+//! Without peers the node would wait for the regular discovery lookups and the dial backoffs,
+//! which can keep it offline for minutes. While no peer is connected, [`ZeroPeerMonitor`] runs
+//! discovery self-lookups at a short interval and raises an alert event once the node stayed
+//! without peers beyond the threshold. The dialer redials the known and trusted peers without
+//! backoff meanwhile, see [`crate::peer::dialer`].
+use crate::{
+    config::RecoveryConfig,
+    instance,
+    peer::{blockstate::BlockEvent, events::EventSender},
+};
+use metrics::{counter, gauge};
+use reth_network::{EthNetworkPrimitives, NetworkHandle, PeersInfo};
+use std::time::{Duration, Instant};
+use tokio::{task::JoinHandle, time::interval};
+use tracing::{info, warn};
+
+/// What to do after a check of the peer count.
+#[derive(Debug, Default)]
+pub struct RecoveryStep {
+    /// Run a discovery lookup.
+    pub lookup: bool,
+    /// Alert entered since the last check.
+    pub alert: Option<BlockEvent>,
+}
+
+/// Tracks how long the node is without peers, driven with explicit timestamps so it can be
+/// tested.
+#[derive(Debug)]
+pub struct ZeroPeerMonitor {
+    lookup_interval: Duration,
+    alert_after: Option<Duration>,
+    /// Since when no peer is connected.
+    since: Option<Instant>,
+    last_lookup: Option<Instant>,
+    alerted: bool,
+}
+
+impl ZeroPeerMonitor {
+    pub fn new(config: &RecoveryConfig) -> Self {
+        Self {
+            lookup_interval: Duration::from_secs(config.lookup_interval_secs),
+            alert_after: (config.alert_secs > 0).then(|| Duration::from_secs(config.alert_secs)),
+            since: None,
+            last_lookup: None,
+            alerted: false,
+        }
+    }
+
+    /// Updates the state with the current peer count.
+    pub fn check(&mut self, peers: usize, now: Instant) -> RecoveryStep {
+        if peers > 0 {
+            if let Some(since) = self.since.take() {
+                let idle = now.duration_since(since);
+                info!(peers, idle_secs = idle.as_secs(), "peer connected, leaving recovery");
+            }
+            self.last_lookup = None;
+            self.alerted = false;
+            gauge!("bscpeer_zero_peer_seconds").set(0.0);
+            return RecoveryStep::default();
+        }
+
+        let since = *self.since.get_or_insert_with(|| {
+            warn!("no peer connected, entering recovery");
+            counter!("bscpeer_peer_recoveries_total").increment(1);
+            now
+        });
+        let idle = now.duration_since(since);
+        gauge!("bscpeer_zero_peer_seconds").set(idle.as_secs_f64());
+
+        let lookup =
+            self.last_lookup.is_none_or(|last| now.duration_since(last) >= self.lookup_interval);
+        if lookup {
+            self.last_lookup = Some(now);
+        }
+        let mut alert = None;
+        if !self.alerted && self.alert_after.is_some_and(|alert_after| idle > alert_after) {
+            self.alerted = true;
+            counter!("bscpeer_peer_alerts_total", "kind" => "no_peers").increment(1);
+            alert = Some(BlockEvent::NoPeers { idle_secs: idle.as_secs() });
+        }
+        RecoveryStep { lookup, alert }
+    }
+}
+
+/// Spawns the task checking the peer count every second.
+pub fn spawn(
+    config: &RecoveryConfig,
+    network: NetworkHandle<EthNetworkPrimitives>,
+    events: EventSender,
+) -> JoinHandle<()> {
+    let mut monitor = ZeroPeerMonitor::new(config);
+    instance::spawn(async move {
+        let mut tick = interval(Duration::from_secs(1));
+        loop {
+            tick.tick().await;
+            let step = monitor.check(network.num_connected_peers(), Instant::now());
+            if step.lookup {
+                // without discovery, e.g. behind a proxy, only the dialer recovers
+                if let Some(discv4) = network.discv4() {
+                    counter!("bscpeer_recovery_lookups_total").increment(1);
+                    discv4.send_lookup_self();
+                }
+            }
+            if let Some(alert) = step.alert {
+                events.send(alert);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zero_peers() {
+        let now = Instant::now();
+        let config = RecoveryConfig { enabled: true, lookup_interval_secs: 5, alert_secs: 30 };
+        let mut monitor = ZeroPeerMonitor::new(&config);
+        assert!(!monitor.check(3, now).lookup);
+
+        // lookups right away and then at the interval
+        assert!(monitor.check(0, now + Duration::from_secs(1)).lookup);
+        assert!(!monitor.check(0, now + Duration::from_secs(4)).lookup);
+        assert!(monitor.check(0, now + Duration::from_secs(6)).lookup);
+
+        // alerted once per episode
+        let step = monitor.check(0, now + Duration::from_secs(32));
+        assert!(matches!(step.alert, Some(BlockEvent::NoPeers { idle_secs: 31 })));
+        assert!(monitor.check(0, now + Duration::from_secs(40)).alert.is_none());
+
+        // a peer ends the episode, the next one starts over
+        let step = monitor.check(1, now + Duration::from_secs(41));
+        assert!(!step.lookup && step.alert.is_none());
+        let step = monitor.check(0, now + Duration::from_secs(42));
+        assert!(step.lookup && step.alert.is_none());
+        assert!(monitor.check(0, now + Duration::from_secs(73)).alert.is_some());
+    }
+}
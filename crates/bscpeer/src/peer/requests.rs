@@ -0,0 +1,185 @@
+//! Requests to the network awaited like local calls.
+//!
+//! [`RequestClient`] picks the peers to ask, preferring those whose best block covers the request
+//! and rotating between them, bounds every attempt by a timeout and retries failed requests on the
+//! next peer. Responses covering only part of a request are completed by further requests to the
+//! same peer. Peers serving headers that don't form a chain are penalized, headers continuing
+//! another branch than the ones fetched before are requested again from another peer.
+use crate::peer::{
+    fetch::{self, FetchError},
+    peer_heads::PeerHeads,
+};
+use alloy_consensus::Header;
+use alloy_primitives::B256;
+use metrics::counter;
+use reth_ethereum_primitives::BlockBody;
+use reth_network::{EthNetworkPrimitives, NetworkHandle};
+use reth_network_api::{Peers, ReputationChangeKind};
+use reth_network_peers::PeerId;
+use std::{
+    future::Future,
+    ops::RangeInclusive,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+use tokio::time::timeout;
+use tracing::debug;
+
+/// Attempts of a request before giving up, each on the next peer.
+const MAX_ATTEMPTS: usize = 3;
+
+/// Time a peer has to answer a request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Errors of a request to the network.
+#[derive(Debug, thiserror::Error)]
+pub enum RequestError {
+    /// No connected peer may have the requested data.
+    #[error("no peer to request from")]
+    NoPeers,
+    /// Every attempt failed.
+    #[error("{request} request failed after {attempts} attempts: {source}")]
+    Failed {
+        request: &'static str,
+        attempts: usize,
+        #[source]
+        source: FetchError,
+    },
+}
+
+/// Sends requests to the connected peers, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct RequestClient {
+    network: NetworkHandle<EthNetworkPrimitives>,
+    peer_heads: Arc<Mutex<PeerHeads>>,
+    /// Rotates the peer asked first across requests.
+    next_peer: Arc<AtomicUsize>,
+}
+
+impl RequestClient {
+    pub fn new(
+        network: NetworkHandle<EthNetworkPrimitives>,
+        peer_heads: Arc<Mutex<PeerHeads>>,
+    ) -> Self {
+        Self { network, peer_heads, next_peer: Arc::default() }
+    }
+
+    /// Fetches the canonical headers of a range, oldest first, each being the parent of the next.
+    pub async fn get_headers(
+        &self,
+        range: RangeInclusive<u64>,
+    ) -> Result<Vec<Header>, RequestError> {
+        let mut headers: Vec<Header> = Vec::new();
+        let mut peer = None;
+        let (mut next, last) = (*range.start(), *range.end());
+        while next <= last {
+            let parent_hash = headers.last().map(Header::hash_slow);
+            let (peer_id, batch) = self
+                .request("headers", Some(last), peer, move |peer_id| async move {
+                    let batch = fetch::fetch_headers(&self.network, peer_id, next..=last).await?;
+                    // a batch served by another peer may be on another branch
+                    match parent_hash {
+                        Some(parent_hash) if batch[0].parent_hash != parent_hash => {
+                            Err(FetchError::OtherBranch(next - 1))
+                        }
+                        _ => Ok(batch),
+                    }
+                })
+                .await?;
+            next += batch.len() as u64;
+            headers.extend(batch);
+            peer = Some(peer_id);
+        }
+        Ok(headers)
+    }
+
+    /// Fetches the bodies of the blocks with the given hashes, in order. The bodies aren't
+    /// checked against the headers, which the caller may hold.
+    pub async fn get_bodies(&self, hashes: Vec<B256>) -> Result<Vec<BlockBody>, RequestError> {
+        let mut bodies = Vec::with_capacity(hashes.len());
+        let mut peer = None;
+        while bodies.len() < hashes.len() {
+            let remaining = &hashes[bodies.len()..];
+            let (peer_id, batch) = self
+                .request("bodies", None, peer, |peer_id| {
+                    fetch::fetch_bodies(&self.network, peer_id, remaining.to_vec())
+                })
+                .await?;
+            bodies.extend(batch);
+            peer = Some(peer_id);
+        }
+        Ok(bodies)
+    }
+
+    /// Runs a request against the candidate peers until one succeeds, returning the peer that
+    /// served it.
+    async fn request<T, F, Fut>(
+        &self,
+        request: &'static str,
+        block: Option<u64>,
+        preferred: Option<PeerId>,
+        fetch: F,
+    ) -> Result<(PeerId, T), RequestError>
+    where
+        F: Fn(PeerId) -> Fut,
+        Fut: Future<Output = Result<T, FetchError>>,
+    {
+        let peers = self.candidates(block, preferred);
+        let mut last_error = None;
+        let mut attempts = 0;
+        for peer_id in peers.into_iter().cycle().take(MAX_ATTEMPTS) {
+            if attempts > 0 {
+                counter!("bscpeer_request_retries_total", "request" => request).increment(1);
+            }
+            attempts += 1;
+            let result = match timeout(REQUEST_TIMEOUT, fetch(peer_id)).await {
+                Ok(result) => result,
+                Err(_) => Err(FetchError::Request(format!("timed out after {REQUEST_TIMEOUT:?}"))),
+            };
+            match result {
+                Ok(value) => {
+                    counter!("bscpeer_requests_total", "request" => request, "outcome" => "ok")
+                        .increment(1);
+                    return Ok((peer_id, value));
+                }
+                Err(e) => {
+                    debug!(request, %peer_id, attempts, "request failed: {}", e);
+                    if e.is_bad_data() {
+                        self.network.reputation_change(peer_id, ReputationChangeKind::BadMessage);
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+        counter!("bscpeer_requests_total", "request" => request, "outcome" => "failed")
+            .increment(1);
+        match last_error {
+            Some(source) => Err(RequestError::Failed { request, attempts, source }),
+            None => Err(RequestError::NoPeers),
+        }
+    }
+
+    /// Returns the peers to ask in order: the preferred one, then those known to have the block
+    /// and finally those whose best block is unknown.
+    fn candidates(&self, block: Option<u64>, preferred: Option<PeerId>) -> Vec<PeerId> {
+        let heads = self.peer_heads.lock().unwrap().heads();
+        let (mut known, unknown): (Vec<_>, Vec<_>) =
+            heads.into_iter().partition(|head| head.number.is_some());
+        known.retain(|head| block.is_none_or(|block| head.number >= Some(block)));
+        if !known.is_empty() {
+            let offset = self.next_peer.fetch_add(1, Ordering::Relaxed) % known.len();
+            known.rotate_left(offset);
+        }
+        let mut peers: Vec<_> = known.into_iter().chain(unknown).map(|head| head.peer_id).collect();
+        if let Some(position) =
+            preferred.and_then(|preferred| peers.iter().position(|peer_id| *peer_id == preferred))
+        {
+            let peer_id = peers.remove(position);
+            peers.insert(0, peer_id);
+        }
+        peers
+    }
+}
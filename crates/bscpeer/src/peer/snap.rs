@@ -0,0 +1,158 @@
+//! The `snap/1` RLPx sub-protocol, advertised without serving any state.
+//!
+//! Some peers prefer neighbours that speak snap, so the node advertises it and answers every
+//! request with a valid empty response, which the requesting peer treats as unavailable data.
+use alloy_primitives::Bytes;
+use alloy_rlp::{Decodable, Encodable, RlpEncodable};
+use bytes::{BufMut, BytesMut};
+use futures::{Stream, StreamExt, future};
+use metrics::counter;
+use reth_eth_wire::{
+    Capability, capability::SharedCapabilities, multiplex::ProtocolConnection, protocol::Protocol,
+};
+use reth_network::protocol::{ConnectionHandler, OnNotSupported, ProtocolHandler};
+use reth_network_api::Direction;
+use reth_network_peers::PeerId;
+use std::{net::SocketAddr, pin::Pin};
+use tracing::debug;
+
+const GET_ACCOUNT_RANGE: u8 = 0x00;
+const ACCOUNT_RANGE: u8 = 0x01;
+const GET_STORAGE_RANGES: u8 = 0x02;
+const STORAGE_RANGES: u8 = 0x03;
+const GET_BYTE_CODES: u8 = 0x04;
+const BYTE_CODES: u8 = 0x05;
+const GET_TRIE_NODES: u8 = 0x06;
+const TRIE_NODES: u8 = 0x07;
+
+/// Empty `AccountRange` or `StorageRanges` response.
+#[derive(RlpEncodable)]
+struct EmptyRange {
+    request_id: u64,
+    items: Vec<Bytes>,
+    proof: Vec<Bytes>,
+}
+
+/// Empty `ByteCodes` or `TrieNodes` response.
+#[derive(RlpEncodable)]
+struct EmptyList {
+    request_id: u64,
+    items: Vec<Bytes>,
+}
+
+/// Builds the empty response to a request, prefixed with its message id, or `None` for
+/// responses and malformed messages.
+fn respond(buf: &[u8]) -> Option<BytesMut> {
+    let (&id, mut payload) = buf.split_first()?;
+    // every request is a list starting with the request id
+    let mut fields = alloy_rlp::Header::decode_bytes(&mut payload, true).ok()?;
+    let request_id = u64::decode(&mut fields).ok()?;
+
+    let (kind, response_id) = match id {
+        GET_ACCOUNT_RANGE => ("get_account_range", ACCOUNT_RANGE),
+        GET_STORAGE_RANGES => ("get_storage_ranges", STORAGE_RANGES),
+        GET_BYTE_CODES => ("get_byte_codes", BYTE_CODES),
+        GET_TRIE_NODES => ("get_trie_nodes", TRIE_NODES),
+        _ => return None,
+    };
+    counter!("bscpeer_snap_requests_total", "message" => kind).increment(1);
+
+    let mut response = BytesMut::new();
+    response.put_u8(response_id);
+    match response_id {
+        ACCOUNT_RANGE | STORAGE_RANGES => {
+            EmptyRange { request_id, items: Vec::new(), proof: Vec::new() }.encode(&mut response)
+        }
+        _ => EmptyList { request_id, items: Vec::new() }.encode(&mut response),
+    }
+    Some(response)
+}
+
+/// Handler of the snap protocol, added to the network as an RLPx sub-protocol.
+#[derive(Debug, Clone)]
+pub struct SnapProtocol;
+
+impl SnapProtocol {
+    /// The `snap/1` capability with its eight messages.
+    pub fn protocol() -> Protocol {
+        Protocol::new(Capability::new_static("snap", 1), 8)
+    }
+}
+
+impl ProtocolHandler for SnapProtocol {
+    type ConnectionHandler = Self;
+
+    fn on_incoming(&self, _socket_addr: SocketAddr) -> Option<Self::ConnectionHandler> {
+        Some(Self)
+    }
+
+    fn on_outgoing(
+        &self,
+        _socket_addr: SocketAddr,
+        _peer_id: PeerId,
+    ) -> Option<Self::ConnectionHandler> {
+        Some(Self)
+    }
+}
+
+impl ConnectionHandler for SnapProtocol {
+    type Connection = Pin<Box<dyn Stream<Item = BytesMut> + Send>>;
+
+    fn protocol(&self) -> Protocol {
+        Self::protocol()
+    }
+
+    fn on_unsupported_by_peer(
+        self,
+        _supported: &SharedCapabilities,
+        _direction: Direction,
+        _peer_id: PeerId,
+    ) -> OnNotSupported {
+        OnNotSupported::KeepAlive
+    }
+
+    fn into_connection(
+        self,
+        _direction: Direction,
+        peer_id: PeerId,
+        conn: ProtocolConnection,
+    ) -> Self::Connection {
+        Box::pin(conn.filter_map(move |buf| {
+            let response = respond(&buf);
+            if response.is_none() {
+                debug!(%peer_id, id = ?buf.first(), "ignored snap message");
+            }
+            future::ready(response)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_respond() {
+        #[derive(RlpEncodable)]
+        struct GetByteCodes {
+            request_id: u64,
+            hashes: Vec<alloy_primitives::B256>,
+            bytes: u64,
+        }
+        let mut request = vec![GET_BYTE_CODES];
+        GetByteCodes { request_id: 9, hashes: vec![Default::default()], bytes: 1024 }
+            .encode(&mut request);
+        let response = respond(&request).unwrap();
+        // id, then the list [9, []]
+        assert_eq!(&response[..], &[BYTE_CODES, 0xc2, 0x09, 0xc0]);
+
+        request[0] = GET_ACCOUNT_RANGE;
+        let response = respond(&request).unwrap();
+        assert_eq!(&response[..], &[ACCOUNT_RANGE, 0xc3, 0x09, 0xc0, 0xc0]);
+
+        // responses aren't answered
+        request[0] = BYTE_CODES;
+        assert!(respond(&request).is_none());
+        assert!(respond(&[]).is_none());
+    }
+}
@@ -0,0 +1,150 @@
+//! Checks of the remote status beyond the ones reth applies in the eth handshake.
+//!
+//! A peer on another network or a forked genesis is useless, and one claiming more total
+//! difficulty than the chain can have accumulated by now would win every fork choice based on
+//! it. Such peers are disconnected at the handshake, counted by reason.
+use crate::parlia::snapshot::DIFF_INTURN;
+use alloy_chains::Chain;
+use alloy_primitives::{B256, U256};
+use metrics::counter;
+use reth_chainspec::ChainSpec;
+use reth_eth_wire::UnifiedStatus;
+use reth_eth_wire_types::DisconnectReason;
+
+/// Shortest block interval the total difficulty bound assumes.
+const MIN_BLOCK_INTERVAL_MS: u64 = 750;
+
+/// Reasons a remote status is rejected.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum StatusError {
+    #[error("genesis mismatch: got {got}, expected {expected}")]
+    Genesis { got: B256, expected: B256 },
+    #[error("chain mismatch: got {got}, expected {expected}")]
+    Chain { got: Chain, expected: Chain },
+    #[error("implausible total difficulty {got}, at most {maximum}")]
+    TotalDifficulty { got: U256, maximum: U256 },
+}
+
+impl StatusError {
+    /// Returns a short, stable label for this error, used for metrics.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Genesis { .. } => "genesis",
+            Self::Chain { .. } => "chain",
+            Self::TotalDifficulty { .. } => "total_difficulty",
+        }
+    }
+
+    /// Returns the reason the peer is disconnected with.
+    pub const fn disconnect_reason(&self) -> DisconnectReason {
+        match self {
+            Self::Genesis { .. } | Self::Chain { .. } => DisconnectReason::UselessPeer,
+            Self::TotalDifficulty { .. } => DisconnectReason::ProtocolBreach,
+        }
+    }
+}
+
+/// Validates remote statuses against the local chain spec.
+#[derive(Debug, Clone)]
+pub struct StatusValidator {
+    chain: Chain,
+    genesis: B256,
+    genesis_difficulty: U256,
+    genesis_timestamp: u64,
+}
+
+impl StatusValidator {
+    pub fn new(chain_spec: &ChainSpec) -> Self {
+        Self {
+            chain: chain_spec.chain,
+            genesis: chain_spec.genesis_hash(),
+            genesis_difficulty: chain_spec.genesis_header().difficulty,
+            genesis_timestamp: chain_spec.genesis_header().timestamp,
+        }
+    }
+
+    /// Returns the highest total difficulty the chain can have at `now`, in seconds since the
+    /// epoch: every block since genesis in turn, at the shortest block interval.
+    pub fn max_total_difficulty(&self, now: u64) -> U256 {
+        let elapsed_ms = now.saturating_sub(self.genesis_timestamp).saturating_mul(1000);
+        let blocks = elapsed_ms / MIN_BLOCK_INTERVAL_MS;
+        self.genesis_difficulty + U256::from(blocks) * DIFF_INTURN
+    }
+
+    /// Checks the remote status, recording rejections by reason. eth/69 statuses carry no total
+    /// difficulty, which is then not checked.
+    pub fn validate(&self, status: &UnifiedStatus, now: u64) -> Result<(), StatusError> {
+        let result = self.check(status, now);
+        if let Err(err) = &result {
+            counter!("bscpeer_invalid_status_total", "reason" => err.as_str()).increment(1);
+        }
+        result
+    }
+
+    fn check(&self, status: &UnifiedStatus, now: u64) -> Result<(), StatusError> {
+        if status.genesis != self.genesis {
+            return Err(StatusError::Genesis { got: status.genesis, expected: self.genesis });
+        }
+        if status.chain != self.chain {
+            return Err(StatusError::Chain { got: status.chain, expected: self.chain });
+        }
+        if let Some(td) = status.total_difficulty {
+            let maximum = self.max_total_difficulty(now);
+            if td < self.genesis_difficulty || td > maximum {
+                return Err(StatusError::TotalDifficulty { got: td, maximum });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validator() -> StatusValidator {
+        StatusValidator {
+            chain: Chain::from_id(56),
+            genesis: B256::with_last_byte(1),
+            genesis_difficulty: U256::from(1),
+            genesis_timestamp: 1_000,
+        }
+    }
+
+    #[test]
+    fn test_validate_status() {
+        let validator = validator();
+        let status = UnifiedStatus {
+            chain: Chain::from_id(56),
+            genesis: B256::with_last_byte(1),
+            total_difficulty: Some(U256::from(100)),
+            ..Default::default()
+        };
+        assert_eq!(validator.validate(&status, 1_300), Ok(()));
+        // 400 blocks in 300 seconds at most
+        assert_eq!(validator.max_total_difficulty(1_300), U256::from(801));
+
+        // eth/69 statuses carry no total difficulty
+        assert_eq!(
+            validator.validate(&UnifiedStatus { total_difficulty: None, ..status }, 0),
+            Ok(())
+        );
+        let err = validator
+            .validate(&UnifiedStatus { total_difficulty: Some(U256::from(900)), ..status }, 1_300)
+            .unwrap_err();
+        assert_eq!(err.as_str(), "total_difficulty");
+        let err = validator
+            .validate(&UnifiedStatus { total_difficulty: Some(U256::ZERO), ..status }, 1_300)
+            .unwrap_err();
+        assert_eq!(err.disconnect_reason(), DisconnectReason::ProtocolBreach);
+
+        let err = validator
+            .validate(&UnifiedStatus { chain: Chain::from_id(97), ..status }, 1_300)
+            .unwrap_err();
+        assert_eq!(err.as_str(), "chain");
+        let err = validator
+            .validate(&UnifiedStatus { genesis: B256::ZERO, ..status }, 1_300)
+            .unwrap_err();
+        assert_eq!(err, StatusError::Genesis { got: B256::ZERO, expected: validator.genesis });
+    }
+}
@@ -0,0 +1,1171 @@
+//! Block sync logic, owned by a single task and driven by commands.
+//!
+//! [`SyncState`] is a plain state machine turning commands into fetch actions, so the sync logic
+//! can be tested with deterministic command sequences. [`SyncActor`] owns it, executes the
+//! actions against the network and feeds their results back as commands.
+use crate::{
+    chain_config::checkpoints::Checkpoints,
+    instance,
+    parlia::{Parlia, validation},
+    peer::{
+        alerts::SyncMonitor,
+        announce::KnownBlocks,
+        bandwidth::{ANNOUNCEMENT_SIZE, Bandwidth},
+        blockstate::{BlockEvent, ConsensusReader},
+        deployments::DeploymentDetector,
+        duplicates::DuplicateTracker,
+        events::EventSender,
+        fetch::{self, FetchError},
+        head::{HeadTracker, HeadUpdate},
+        pipeline::{Interval, RequestWindow, Stage, WorkQueues},
+        tokens::TokenTransferDecoder,
+        verify,
+        watchlist::Watchlist,
+    },
+};
+use alloy_consensus::Header;
+use alloy_primitives::B256;
+use metrics::{counter, gauge};
+use reth_network::{EthNetworkPrimitives, NetworkHandle};
+use reth_network_api::{Peers, ReputationChangeKind};
+use reth_network_peers::PeerId;
+use reth_primitives_traits::InMemorySize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{mpsc, watch},
+    task::AbortHandle,
+};
+use tracing::{info, instrument, warn};
+
+/// Announcements of blocks more than this many blocks below the head are dropped on cleanup.
+const ANNOUNCED_WINDOW: u64 = 50;
+/// Seconds a peer has to serve a requested block, unless configured.
+pub const DEFAULT_REQUEST_TTL_SECS: u64 = 30;
+/// Fetches in flight above which further requests are queued.
+const MAX_IN_FLIGHT: usize = 16;
+/// Blocks within this distance of the highest announced one are requested with head priority.
+const HEAD_WINDOW: u64 = 8;
+/// Blocks walked back at most from a missing parent, unless configured.
+pub const DEFAULT_ANCESTOR_DEPTH: u64 = 64;
+/// Headers requested at once when walking back from a missing parent.
+const ANCESTOR_BATCH: u64 = 32;
+/// Blocks below the finalized one, or the height until a block is finalized, whose tracking is
+/// kept on cleanup.
+const PRUNE_WINDOW: u64 = 1024;
+/// Received blocks tracked at most, the lowest are evicted first.
+const MAX_RECEIVED_BLOCKS: usize = 16 * 1024;
+/// Requests tracked at most, the lowest are evicted first.
+const MAX_TRACKED_REQUESTS: usize = 1024;
+
+/// Inputs of the sync state machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncCommand {
+    /// A session with the peer was established.
+    AddPeer(PeerId),
+    /// The session with the peer was closed.
+    RemovePeer(PeerId),
+    /// A block was received, either announced or fetched.
+    BlockReceived(u64),
+    /// Block numbers announced by a peer.
+    BlockHashes(Vec<u64>),
+    /// The best block of the peer advanced, from its status or an announcement.
+    PeerHead { peer_id: PeerId, number: u64 },
+    /// The peer served a requested block.
+    FetchCompleted { peer_id: PeerId, elapsed: Duration },
+    /// Fetching the block failed. Failures of requests that expired in the meantime are ignored.
+    FetchFailed { block_number: u64, request_id: u64 },
+    /// The peer served receipts that don't match the header.
+    ReceiptMismatch(PeerId),
+    /// The block with the given number was finalized.
+    Finalized(u64),
+    /// The peer sent a block whose parent isn't known.
+    MissingParent { peer_id: PeerId, hash: B256, number: u64 },
+    /// Fetching a missing parent and its ancestors completed or failed.
+    ParentFetched(B256),
+    /// Periodic housekeeping.
+    Tick,
+}
+
+/// Outputs of the sync state machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncAction {
+    /// Fetch the block from the peer.
+    Fetch { peer_id: PeerId, block_number: u64 },
+    /// Fetch a missing parent by hash, and its ancestors up to a known block, from the peer that
+    /// sent its child.
+    FetchParent { peer_id: PeerId, hash: B256, number: u64 },
+    /// Penalize a peer that repeatedly served invalid data.
+    Penalize { peer_id: PeerId, mismatches: u64 },
+    /// Penalize a peer that didn't serve a requested block within the TTL and abort the fetch.
+    /// The block is requested again.
+    Expired { peer_id: PeerId, block_number: u64, request_id: u64 },
+}
+
+/// Block request in flight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PendingRequest {
+    /// Identifies the request across retries of the same block.
+    id: u64,
+    peer_id: PeerId,
+    sent: Instant,
+}
+
+/// Priority of a queued block request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Blocks near the chain head, fetched first so consumers follow the head with low latency.
+    Head,
+    /// Historical catch-up, fetched when no head request is waiting.
+    Backfill,
+}
+
+/// Block sync state.
+#[derive(Debug)]
+pub struct SyncState {
+    height: u64,
+    /// Highest block number announced or received.
+    highest_seen: u64,
+    /// Number of the finalized block, if any.
+    finalized: Option<u64>,
+    /// Connected peers, in the order they connected.
+    peers: Vec<PeerId>,
+    /// 等待的区块请求, with the peer each was sent to
+    pending_requests: BTreeMap<u64, PendingRequest>,
+    /// Id of the next request.
+    next_request_id: u64,
+    /// Time a peer has to serve a request before it expires.
+    request_ttl: Duration,
+    /// Fetches in flight per peer.
+    in_flight: HashMap<PeerId, usize>,
+    /// Fetches each peer may have in flight.
+    windows: HashMap<PeerId, RequestWindow>,
+    /// Requests waiting for a free fetch slot, by priority.
+    head_queue: BTreeSet<u64>,
+    backfill_queue: BTreeSet<u64>,
+    /// Backfill requests spread over the peers.
+    work: WorkQueues,
+    max_in_flight: usize,
+    received_blocks: BTreeSet<u64>,
+    /// Number of invalid receipts served by each peer.
+    receipt_mismatches: HashMap<PeerId, u64>,
+    /// Missing parents being fetched.
+    parent_requests: HashSet<B256>,
+    /// When block numbers not requested yet were first announced.
+    announced: BTreeMap<u64, Instant>,
+}
+
+impl SyncState {
+    pub fn new(starting_height: u64) -> Self {
+        Self {
+            height: starting_height,
+            highest_seen: starting_height,
+            finalized: None,
+            peers: Vec::new(),
+            pending_requests: BTreeMap::new(),
+            next_request_id: 0,
+            request_ttl: Duration::from_secs(DEFAULT_REQUEST_TTL_SECS),
+            in_flight: HashMap::new(),
+            windows: HashMap::new(),
+            head_queue: BTreeSet::new(),
+            backfill_queue: BTreeSet::new(),
+            work: WorkQueues::default(),
+            max_in_flight: MAX_IN_FLIGHT,
+            received_blocks: BTreeSet::new(),
+            receipt_mismatches: HashMap::new(),
+            parent_requests: HashSet::new(),
+            announced: BTreeMap::new(),
+        }
+    }
+
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    pub fn highest_seen(&self) -> u64 {
+        self.highest_seen
+    }
+
+    /// Returns the number of block requests in flight.
+    pub fn in_flight(&self) -> usize {
+        self.pending_requests.len()
+    }
+
+    /// Returns the id of the request in flight for the block.
+    fn request_id(&self, block_number: u64) -> Option<u64> {
+        self.pending_requests.get(&block_number).map(|request| request.id)
+    }
+
+    /// Applies a command, returning the actions to execute.
+    pub fn handle(&mut self, command: SyncCommand) -> Vec<SyncAction> {
+        match command {
+            SyncCommand::AddPeer(peer_id) => {
+                if !self.peers.contains(&peer_id) {
+                    self.peers.push(peer_id);
+                    self.work.add_peer(peer_id);
+                    self.windows.insert(peer_id, RequestWindow::default());
+                    info!(%peer_id, "peerset add new peer");
+                }
+                self.request(self.height + 1);
+                self.dispatch()
+            }
+            SyncCommand::RemovePeer(peer_id) => {
+                self.peers.retain(|p| *p != peer_id);
+                self.receipt_mismatches.remove(&peer_id);
+                self.in_flight.remove(&peer_id);
+                self.windows.remove(&peer_id);
+                // its unrequested work goes back to the others
+                self.backfill_queue.extend(self.work.remove_peer(&peer_id));
+                info!(%peer_id, "peerset remove peer");
+                self.dispatch()
+            }
+            SyncCommand::BlockReceived(block_number) => {
+                self.finish(block_number);
+                self.head_queue.remove(&block_number);
+                self.backfill_queue.remove(&block_number);
+                self.work.remove(block_number);
+                self.announced.remove(&block_number);
+                self.received_blocks.insert(block_number);
+                self.highest_seen = self.highest_seen.max(block_number);
+                if block_number > self.height {
+                    info!(
+                        old_height = self.height,
+                        new_height = block_number,
+                        "update block height"
+                    );
+                    self.height = block_number;
+                }
+                self.dispatch()
+            }
+            SyncCommand::BlockHashes(block_numbers) => {
+                if let Some(highest) = block_numbers.iter().max() {
+                    self.highest_seen = self.highest_seen.max(*highest);
+                }
+                let now = Instant::now();
+                for number in block_numbers {
+                    if number > self.height && !self.received_blocks.contains(&number) {
+                        if !self.pending_requests.contains_key(&number) {
+                            self.announced.entry(number).or_insert(now);
+                        }
+                        self.request(number);
+                    }
+                }
+                self.dispatch()
+            }
+            SyncCommand::PeerHead { peer_id, number } => {
+                // backfill the peer couldn't serve before may be assignable now
+                if !self.work.set_head(peer_id, number) || self.backfill_queue.is_empty() {
+                    return Vec::new();
+                }
+                self.dispatch()
+            }
+            SyncCommand::FetchCompleted { peer_id, elapsed } => {
+                if let Some(window) = self.windows.get_mut(&peer_id) {
+                    window.on_response(elapsed);
+                }
+                Vec::new()
+            }
+            SyncCommand::FetchFailed { block_number, request_id } => {
+                if self.request_id(block_number) != Some(request_id) {
+                    return Vec::new();
+                }
+                if let Some(peer_id) = self.finish(block_number) {
+                    if let Some(window) = self.windows.get_mut(&peer_id) {
+                        window.on_failure();
+                    }
+                }
+                self.dispatch()
+            }
+            SyncCommand::ReceiptMismatch(peer_id) => {
+                let mismatches = self.receipt_mismatches.entry(peer_id).or_default();
+                *mismatches += 1;
+                vec![SyncAction::Penalize { peer_id, mismatches: *mismatches }]
+            }
+            SyncCommand::Finalized(number) => {
+                self.finalized = Some(number);
+                Vec::new()
+            }
+            SyncCommand::MissingParent { peer_id, hash, number } => {
+                // parents are fetched outside the request window, they are rare and block the
+                // orphans waiting for them
+                if !self.parent_requests.insert(hash) {
+                    return Vec::new();
+                }
+                info!(%peer_id, %hash, number, "request missing parent");
+                vec![SyncAction::FetchParent { peer_id, hash, number }]
+            }
+            SyncCommand::ParentFetched(hash) => {
+                self.parent_requests.remove(&hash);
+                Vec::new()
+            }
+            SyncCommand::Tick => {
+                self.prune();
+                let stale = self.height.saturating_sub(ANNOUNCED_WINDOW);
+                self.announced.retain(|&block_num, _| block_num > stale);
+                let mut actions = self.expire(Instant::now());
+                if !self.peers.is_empty() {
+                    self.request(self.height + 1);
+                    actions.extend(self.dispatch());
+                }
+                actions
+            }
+        }
+    }
+
+    /// Drops the tracking of blocks more than [`PRUNE_WINDOW`] blocks below the finalized one,
+    /// then caps what is left, evicting the lowest blocks first.
+    fn prune(&mut self) {
+        let floor = self.finalized.unwrap_or(self.height).saturating_sub(PRUNE_WINDOW);
+        let kept = self.received_blocks.split_off(&floor);
+        let pruned = std::mem::replace(&mut self.received_blocks, kept).len();
+        let mut evicted = self.received_blocks.len().saturating_sub(MAX_RECEIVED_BLOCKS);
+        for _ in 0..evicted {
+            self.received_blocks.pop_first();
+        }
+        record_evictions("received_blocks", pruned, evicted);
+
+        let expired: Vec<_> =
+            self.pending_requests.range(..floor).map(|(number, _)| *number).collect();
+        let pruned = expired.len();
+        evicted = self.pending_requests.len().saturating_sub(pruned + MAX_TRACKED_REQUESTS);
+        let evicted_requests: Vec<_> =
+            self.pending_requests.keys().skip(pruned).take(evicted).copied().collect();
+        for block_number in expired.into_iter().chain(evicted_requests) {
+            self.finish(block_number);
+        }
+        record_evictions("pending_requests", pruned, evicted);
+
+        self.announced.retain(|&block_num, _| block_num >= floor);
+        self.backfill_queue.retain(|&block_num| block_num >= floor);
+        self.work.retain(|block_num| block_num >= floor);
+        gauge!("bscpeer_sync_tracked_blocks", "set" => "received_blocks")
+            .set(self.received_blocks.len() as f64);
+        gauge!("bscpeer_sync_tracked_blocks", "set" => "pending_requests")
+            .set(self.pending_requests.len() as f64);
+    }
+
+    /// Expires the requests sent more than the TTL before `now`, see [`Self::on_expired`].
+    fn expire(&mut self, now: Instant) -> Vec<SyncAction> {
+        let expired: Vec<_> = self
+            .pending_requests
+            .iter()
+            .filter(|(_, request)| now.saturating_duration_since(request.sent) > self.request_ttl)
+            .map(|(number, _)| *number)
+            .collect();
+        if !expired.is_empty() {
+            info!(
+                expired = expired.len(),
+                pending = self.pending_requests.len() - expired.len(),
+                "expire block requests"
+            );
+        }
+        expired.into_iter().filter_map(|block_number| self.on_expired(block_number)).collect()
+    }
+
+    /// Releases the slot of an expired request, shrinks the request window of its peer and
+    /// queues the block again, returning the action penalizing the peer.
+    fn on_expired(&mut self, block_number: u64) -> Option<SyncAction> {
+        let request_id = self.request_id(block_number)?;
+        let peer_id = self.finish(block_number)?;
+        if let Some(window) = self.windows.get_mut(&peer_id) {
+            window.on_failure();
+        }
+        self.request(block_number);
+        Some(SyncAction::Expired { peer_id, block_number, request_id })
+    }
+
+    /// Returns the priority of a request: blocks close to the highest seen one are head
+    /// requests, anything further behind is backfill.
+    pub fn priority(&self, block_number: u64) -> Priority {
+        if block_number + HEAD_WINDOW >= self.highest_seen {
+            Priority::Head
+        } else {
+            Priority::Backfill
+        }
+    }
+
+    /// Queues a block request, unless already queued or in flight.
+    fn request(&mut self, block_number: u64) {
+        if self.pending_requests.contains_key(&block_number) || self.work.contains(block_number) {
+            return;
+        }
+        match self.priority(block_number) {
+            Priority::Head => self.head_queue.insert(block_number),
+            Priority::Backfill => self.backfill_queue.insert(block_number),
+        };
+    }
+
+    /// Starts fetches while slots are free: head requests first, each from the least busy
+    /// peer, then backfill, spread over the work queues of the peers having the blocks.
+    fn dispatch(&mut self) -> Vec<SyncAction> {
+        let mut actions = Vec::new();
+        if self.peers.is_empty() {
+            if let Some(block_number) = self.head_queue.first().or(self.backfill_queue.first()) {
+                warn!("no available peer to request block {}", block_number);
+            }
+        } else {
+            while let Some(&block_number) = self.head_queue.first() {
+                let Some(peer_id) = self.least_busy_peer() else { break };
+                self.head_queue.pop_first();
+                actions.push(self.start(peer_id, block_number));
+            }
+            // blocks no peer has yet stay queued, and so do the higher ones
+            while let Some(&block_number) = self.backfill_queue.first() {
+                if !self.work.assign(block_number) {
+                    break;
+                }
+                self.backfill_queue.pop_first();
+            }
+            for peer_id in self.peers.clone() {
+                while self.has_free_slot(&peer_id) {
+                    let Some(block_number) = self.work.next(peer_id) else { break };
+                    actions.push(self.start(peer_id, block_number));
+                }
+            }
+        }
+        gauge!("bscpeer_sync_queued_requests", "priority" => "head")
+            .set(self.head_queue.len() as f64);
+        gauge!("bscpeer_sync_queued_requests", "priority" => "backfill")
+            .set((self.backfill_queue.len() + self.work.len()) as f64);
+        actions
+    }
+
+    fn has_free_slot(&self, peer_id: &PeerId) -> bool {
+        let window = self.windows.get(peer_id).map_or(0, RequestWindow::limit);
+        self.pending_requests.len() < self.max_in_flight
+            && self.in_flight.get(peer_id).copied().unwrap_or_default() < window
+    }
+
+    /// Returns the connected peer with the fewest fetches in flight, if any has a free slot.
+    fn least_busy_peer(&self) -> Option<PeerId> {
+        self.peers
+            .iter()
+            .filter(|peer_id| self.has_free_slot(peer_id))
+            .min_by_key(|peer_id| self.in_flight.get(peer_id).copied().unwrap_or_default())
+            .copied()
+    }
+
+    fn start(&mut self, peer_id: PeerId, block_number: u64) -> SyncAction {
+        let id = self.next_request_id;
+        self.next_request_id += 1;
+        self.pending_requests
+            .insert(block_number, PendingRequest { id, peer_id, sent: Instant::now() });
+        *self.in_flight.entry(peer_id).or_default() += 1;
+        if let Some(announced) = self.announced.remove(&block_number) {
+            Interval::AnnounceToRequest.record(announced.elapsed());
+        }
+        info!(block_number = block_number, %peer_id, "request block");
+        SyncAction::Fetch { peer_id, block_number }
+    }
+
+    /// Releases the fetch slot of a request that completed, failed or expired, returning the
+    /// peer it was sent to.
+    fn finish(&mut self, block_number: u64) -> Option<PeerId> {
+        let PendingRequest { peer_id, .. } = self.pending_requests.remove(&block_number)?;
+        if let Some(count) = self.in_flight.get_mut(&peer_id) {
+            *count = count.saturating_sub(1);
+        }
+        Some(peer_id)
+    }
+}
+
+/// Counts the blocks dropped from a tracked set, below the finalized window or over the cap.
+fn record_evictions(set: &'static str, pruned: usize, evicted: usize) {
+    if pruned > 0 {
+        counter!("bscpeer_sync_evictions_total", "set" => set, "reason" => "finalized")
+            .increment(pruned as u64);
+    }
+    if evicted > 0 {
+        counter!("bscpeer_sync_evictions_total", "set" => set, "reason" => "cap")
+            .increment(evicted as u64);
+    }
+}
+
+/// Task owning the [`SyncState`].
+#[derive(Debug)]
+pub struct SyncActor {
+    state: SyncState,
+    commands: mpsc::UnboundedReceiver<SyncCommand>,
+    /// Sender handed to fetch tasks to report their results.
+    feedback: mpsc::UnboundedSender<SyncCommand>,
+    height: watch::Sender<u64>,
+    in_flight: watch::Sender<usize>,
+    parlia: Parlia,
+    consensus: ConsensusReader,
+    events: Option<EventSender>,
+    checkpoints: Arc<Checkpoints>,
+    known_blocks: Option<KnownBlocks>,
+    tokens: Option<TokenTransferDecoder>,
+    watchlist: Arc<Mutex<Watchlist>>,
+    deployments: DeploymentDetector,
+    duplicates: Arc<Mutex<DuplicateTracker>>,
+    monitor: Option<SyncMonitor>,
+    head: HeadTracker,
+    /// Blocks walked back at most from a missing parent.
+    ancestor_depth: u64,
+    bandwidth: Bandwidth,
+    /// Fetch tasks of the requests in flight, by request id.
+    fetches: HashMap<u64, AbortHandle>,
+}
+
+impl SyncActor {
+    pub(crate) fn new(
+        state: SyncState,
+        commands: mpsc::UnboundedReceiver<SyncCommand>,
+        feedback: mpsc::UnboundedSender<SyncCommand>,
+        height: watch::Sender<u64>,
+        in_flight: watch::Sender<usize>,
+        parlia: Parlia,
+        consensus: ConsensusReader,
+        head: HeadTracker,
+    ) -> Self {
+        Self {
+            state,
+            commands,
+            feedback,
+            height,
+            in_flight,
+            parlia,
+            consensus,
+            events: None,
+            checkpoints: Arc::default(),
+            known_blocks: None,
+            tokens: None,
+            watchlist: Arc::default(),
+            deployments: DeploymentDetector::default(),
+            duplicates: Arc::default(),
+            monitor: None,
+            head,
+            ancestor_depth: DEFAULT_ANCESTOR_DEPTH,
+            bandwidth: Bandwidth::default(),
+            fetches: HashMap::new(),
+        }
+    }
+
+    /// Sets the channel fetched data is emitted to.
+    pub fn with_events(mut self, events: EventSender) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Sets the checkpoints fetched blocks are verified against.
+    pub fn with_checkpoints(mut self, checkpoints: Arc<Checkpoints>) -> Self {
+        self.checkpoints = checkpoints;
+        self
+    }
+
+    /// Announces fetched blocks to the peers not known to have them, once their proposer passed
+    /// the checks against the validator set.
+    pub fn with_announcements(mut self, known_blocks: KnownBlocks) -> Self {
+        self.known_blocks = Some(known_blocks);
+        self
+    }
+
+    /// Decodes the token transfers from the receipts of the fetched blocks.
+    pub fn with_token_transfers(mut self, tokens: Option<TokenTransferDecoder>) -> Self {
+        self.tokens = tokens;
+        self
+    }
+
+    /// Shares the watchlist the fetched blocks are checked against, e.g. with the admin API.
+    pub fn with_watchlist(mut self, watchlist: Arc<Mutex<Watchlist>>) -> Self {
+        self.watchlist = watchlist;
+        self
+    }
+
+    /// Shares the deployments detector with the importer, so blocks both received and fetched
+    /// are reported once.
+    pub fn with_deployments(mut self, deployments: DeploymentDetector) -> Self {
+        self.deployments = deployments;
+        self
+    }
+
+    /// Shares the duplicate tracker with the importer, so blocks whose fetch failed are
+    /// requested again when announced again.
+    pub fn with_duplicate_tracker(mut self, duplicates: Arc<Mutex<DuplicateTracker>>) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
+
+    /// Raises alerts when the sync falls behind or stalls, checked on every tick.
+    pub fn with_monitor(mut self, monitor: SyncMonitor) -> Self {
+        self.monitor = Some(monitor);
+        self
+    }
+
+    /// Sets how many blocks are walked back at most from a missing parent to connect an orphan
+    /// block, zero to not fetch missing parents.
+    pub fn with_ancestor_depth(mut self, ancestor_depth: u64) -> Self {
+        self.ancestor_depth = ancestor_depth;
+        self
+    }
+
+    /// Sets the time a peer has to serve a requested block before it's penalized and the block
+    /// requested again.
+    pub fn with_request_ttl(mut self, request_ttl: Duration) -> Self {
+        self.state.request_ttl = request_ttl;
+        self
+    }
+
+    /// Caps the bandwidth of the fetches and the announcements of fetched blocks.
+    pub fn with_bandwidth(mut self, bandwidth: Bandwidth) -> Self {
+        self.bandwidth = bandwidth;
+        self
+    }
+
+    /// Spawns the actor, executing fetches over the given network.
+    pub fn spawn(
+        self,
+        network: NetworkHandle<EthNetworkPrimitives>,
+    ) -> tokio::task::JoinHandle<()> {
+        instance::spawn(self.run(network))
+    }
+
+    async fn run(mut self, network: NetworkHandle<EthNetworkPrimitives>) {
+        while let Some(command) = self.commands.recv().await {
+            if let (SyncCommand::RemovePeer(peer_id), Some(known_blocks)) =
+                (&command, &self.known_blocks)
+            {
+                known_blocks.remove_peer(peer_id);
+            }
+            let tick = command == SyncCommand::Tick;
+            if tick {
+                self.fetches.retain(|_, task| !task.is_finished());
+            }
+            for action in self.state.handle(command) {
+                self.execute(action, &network);
+            }
+            let current = self.state.height();
+            self.height.send_if_modified(|height| std::mem::replace(height, current) != current);
+            let in_flight = self.state.in_flight();
+            self.in_flight
+                .send_if_modified(|count| std::mem::replace(count, in_flight) != in_flight);
+            if let (true, Some(monitor)) = (tick, &mut self.monitor) {
+                let alerts =
+                    monitor.check(self.state.height(), self.state.highest_seen(), Instant::now());
+                if let Some(events) = &self.events {
+                    for alert in alerts {
+                        events.send(alert);
+                    }
+                }
+            }
+        }
+    }
+
+    fn execute(&mut self, action: SyncAction, network: &NetworkHandle<EthNetworkPrimitives>) {
+        let fetcher = || Fetcher {
+            network: network.clone(),
+            feedback: self.feedback.clone(),
+            parlia: self.parlia.clone(),
+            consensus: self.consensus.clone(),
+            events: self.events.clone(),
+            checkpoints: self.checkpoints.clone(),
+            known_blocks: self.known_blocks.clone(),
+            tokens: self.tokens.clone(),
+            watchlist: self.watchlist.clone(),
+            deployments: self.deployments.clone(),
+            duplicates: self.duplicates.clone(),
+            head: self.head.clone(),
+            ancestor_depth: self.ancestor_depth,
+            bandwidth: self.bandwidth.clone(),
+        };
+        match action {
+            SyncAction::Fetch { peer_id, block_number } => {
+                let Some(request_id) = self.state.request_id(block_number) else { return };
+                let fetcher = fetcher();
+                let task = instance::spawn(async move {
+                    fetcher.fetch_block(peer_id, block_number, request_id).await;
+                });
+                self.fetches.insert(request_id, task.abort_handle());
+            }
+            SyncAction::FetchParent { hash, .. } if self.ancestor_depth == 0 => {
+                let _ = self.feedback.send(SyncCommand::ParentFetched(hash));
+            }
+            SyncAction::FetchParent { peer_id, hash, number } => {
+                let fetcher = fetcher();
+                instance::spawn(async move {
+                    fetcher.fetch_ancestors(peer_id, hash, number).await;
+                });
+            }
+            SyncAction::Penalize { peer_id, mismatches } => {
+                counter!("bscpeer_receipt_mismatches_total").increment(1);
+                warn!(%peer_id, mismatches, "peer served invalid receipts");
+                network.reputation_change(peer_id, ReputationChangeKind::BadMessage);
+            }
+            SyncAction::Expired { peer_id, block_number, request_id } => {
+                counter!("bscpeer_sync_requests_expired_total").increment(1);
+                warn!(block_number, %peer_id, "block request expired");
+                if let Some(task) = self.fetches.remove(&request_id) {
+                    task.abort();
+                }
+                network.reputation_change(peer_id, ReputationChangeKind::Timeout);
+            }
+        }
+    }
+}
+
+/// Context of a fetch task.
+struct Fetcher {
+    network: NetworkHandle<EthNetworkPrimitives>,
+    feedback: mpsc::UnboundedSender<SyncCommand>,
+    parlia: Parlia,
+    consensus: ConsensusReader,
+    events: Option<EventSender>,
+    checkpoints: Arc<Checkpoints>,
+    known_blocks: Option<KnownBlocks>,
+    tokens: Option<TokenTransferDecoder>,
+    watchlist: Arc<Mutex<Watchlist>>,
+    deployments: DeploymentDetector,
+    duplicates: Arc<Mutex<DuplicateTracker>>,
+    head: HeadTracker,
+    ancestor_depth: u64,
+    bandwidth: Bandwidth,
+}
+
+impl Fetcher {
+    /// Runs a requested block through the pipeline stages: fetches its header by number, then
+    /// the rest, see [`Fetcher::import`].
+    async fn fetch_block(&self, peer_id: PeerId, block_number: u64, request_id: u64) {
+        self.bandwidth.download.wait().await;
+        let start = Instant::now();
+        let header =
+            Stage::Header.run(fetch::fetch_header(&self.network, peer_id, block_number)).await;
+        let result = match header {
+            Ok(header) => {
+                self.bandwidth.download.consume(header.size());
+                self.import(peer_id, header, Some(start)).await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            self.failed(peer_id, block_number, &e);
+            self.duplicates.lock().unwrap().forget(block_number);
+            let _ = self.feedback.send(SyncCommand::FetchFailed { block_number, request_id });
+        }
+    }
+
+    /// Walks back from a missing parent with falling header requests until a header links to a
+    /// known block, at most `ancestor_depth` blocks, then imports the ancestors oldest first so
+    /// the orphans waiting for them connect. Ancestors that don't connect within the depth are
+    /// dropped; a header failing validation stops the import and penalizes the peer.
+    async fn fetch_ancestors(&self, peer_id: PeerId, hash: B256, number: u64) {
+        let mut ancestors = Vec::new();
+        let mut next = hash;
+        let mut connected = false;
+        while !connected && (ancestors.len() as u64) < self.ancestor_depth {
+            let limit = (self.ancestor_depth - ancestors.len() as u64).min(ANCESTOR_BATCH);
+            self.bandwidth.download.wait().await;
+            let request = fetch::fetch_ancestors(&self.network, peer_id, next, limit);
+            let batch = match Stage::Header.run(request).await {
+                Ok(batch) => {
+                    self.bandwidth.download.consume(batch.iter().map(InMemorySize::size).sum());
+                    batch
+                }
+                Err(e) => {
+                    self.failed(peer_id, number, &e);
+                    break;
+                }
+            };
+            for header in batch {
+                next = header.parent_hash;
+                connected = self.head.contains(&next);
+                ancestors.push(header);
+                if connected {
+                    break;
+                }
+            }
+        }
+        let outcome = match connected {
+            true => "connected",
+            false if ancestors.len() as u64 >= self.ancestor_depth => "depth_exceeded",
+            false => "failed",
+        };
+        counter!("bscpeer_ancestor_backfills_total", "outcome" => outcome).increment(1);
+        info!(%peer_id, %hash, ancestors = ancestors.len(), outcome, "fetched ancestors");
+        if !connected {
+            let _ = self.feedback.send(SyncCommand::ParentFetched(hash));
+            return;
+        }
+
+        // the seals are recovered in parallel, the imports are then served from the seal cache
+        let (parlia, headers) = (self.parlia.clone(), ancestors.clone());
+        let _ = tokio::task::spawn_blocking(move || parlia.recover_proposers(&headers)).await;
+        for header in ancestors.into_iter().rev() {
+            let block_number = header.number;
+            if let Err(e) = self.import(peer_id, header, None).await {
+                self.failed(peer_id, block_number, &e);
+                break;
+            }
+        }
+        let _ = self.feedback.send(SyncCommand::ParentFetched(hash));
+    }
+
+    /// Fetches the body of a block whose header was fetched from the peer, validates it, see
+    /// [`Fetcher::validate`], and emits it with its receipts. `requested` is when the fetch of a
+    /// requested block started, to size the peer's request window.
+    #[instrument(
+        name = "import_fetched_block",
+        skip_all,
+        fields(peer_id = %peer_id, block_number = header.number)
+    )]
+    async fn import(
+        &self,
+        peer_id: PeerId,
+        header: Header,
+        requested: Option<Instant>,
+    ) -> Result<(), FetchError> {
+        let block_number = header.number;
+        let hash = header.hash_slow();
+        self.bandwidth.download.wait().await;
+        let body =
+            Stage::Body.run(fetch::fetch_body(&self.network, peer_id, hash, &header)).await?;
+        let responded = Instant::now();
+        if let Some(start) = requested {
+            Interval::RequestToResponse.record(responded - start);
+        }
+        self.bandwidth.download.consume(body.size());
+        let authorized = Stage::Verify.run(self.validate(&header, hash)).await?;
+        info!(
+            block_number = block_number,
+            block_hash = %hash,
+            transactions_count = body.transactions.len(),
+            %peer_id,
+            "fetched block"
+        );
+        // the attestation of a fetched block isn't verified, so it doesn't count for fork choice
+        let update = self.head.update(&header, hash, None);
+        if let Some(events) = &self.events {
+            if let HeadUpdate::Reorged(reorg) = update {
+                events.send(BlockEvent::Reorg { reorg });
+            }
+            let transactions = body.transactions.clone();
+            let senders =
+                tokio::task::spawn_blocking(move || verify::recover_senders(&transactions))
+                    .await
+                    .map_err(|e| FetchError::Request(e.to_string()))?;
+            let hits = self.watchlist.lock().unwrap().check_transactions(
+                block_number,
+                hash,
+                &body.transactions,
+                &senders,
+            );
+            for hit in hits {
+                events.send(hit);
+            }
+            for deployment in
+                self.deployments.detect(block_number, hash, &body.transactions, &senders)
+            {
+                events.send(deployment);
+            }
+        }
+        if let Some(start) = requested {
+            let _ = self
+                .feedback
+                .send(SyncCommand::FetchCompleted { peer_id, elapsed: start.elapsed() });
+        }
+        let _ = self.feedback.send(SyncCommand::BlockReceived(block_number));
+        if let Some(known_blocks) = &self.known_blocks {
+            known_blocks.insert(peer_id, hash);
+            // only blocks of an authorized proposer are vouched for to other peers
+            if authorized && self.bandwidth.upload.try_consume(0) {
+                let announced = known_blocks.announce(&self.network, hash, block_number).await;
+                self.bandwidth.upload.consume(announced * ANNOUNCEMENT_SIZE);
+            }
+        }
+
+        self.bandwidth.download.wait().await;
+        let receipts = fetch::fetch_receipts(&self.network, peer_id, hash, &header);
+        match Stage::Emit.run(receipts).await {
+            Ok(receipts) => {
+                self.bandwidth.download.consume(receipts.iter().map(InMemorySize::size).sum());
+                if let Some(events) = &self.events {
+                    let transfers = self
+                        .tokens
+                        .as_ref()
+                        .map(|tokens| tokens.decode(&receipts, &body.transactions))
+                        .unwrap_or_default();
+                    let hits = self.watchlist.lock().unwrap().check_token_transfers(
+                        block_number,
+                        hash,
+                        &transfers,
+                    );
+                    for hit in hits {
+                        events.send(hit);
+                    }
+                    if !transfers.is_empty() {
+                        events.send(BlockEvent::TokenTransfers {
+                            peer_id,
+                            block_number,
+                            block_hash: hash,
+                            transfers,
+                        });
+                    }
+                    events.send(BlockEvent::Receipts {
+                        peer_id,
+                        block_number,
+                        block_hash: hash,
+                        receipts,
+                    });
+                    Interval::ResponseToEmit.record(responded.elapsed());
+                }
+            }
+            Err(e) => {
+                warn!(block_number = block_number, %peer_id, "failed to fetch receipts: {}", e);
+                if e.is_bad_data() {
+                    let _ = self.feedback.send(SyncCommand::ReceiptMismatch(peer_id));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the Parlia checks of a fetched header before anything is derived from it: the
+    /// structural checks, the checkpoints, including those its known ancestry runs through, and
+    /// the seal, recovered on the blocking pool. Headers
+    /// following the current snapshot within its epoch are checked against its validator set as
+    /// well; older ones may predate a validator set change. Returns whether the proposer was
+    /// checked against the validator set.
+    async fn validate(&self, header: &Header, hash: B256) -> Result<bool, FetchError> {
+        self.parlia.validate_header(header)?;
+        self.checkpoints.verify(header, hash)?;
+        self.checkpoints.verify_ancestry(header, |hash| self.head.parent_hash(hash))?;
+        let (parlia, sealed) = (self.parlia.clone(), header.clone());
+        let proposer = tokio::task::spawn_blocking(move || parlia.proposer(&sealed, hash))
+            .await
+            .map_err(|e| FetchError::Request(e.to_string()))?;
+        let proposer = validation::validate_seal(header, &proposer)?;
+        if let Some(snapshot) = self.consensus.current_snapshot() {
+            if header.number > snapshot.number
+                && header.number <= snapshot.number + snapshot.epoch_length
+            {
+                snapshot.check_proposer(header, proposer)?;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Logs a failed fetch, penalizing the peer if it served invalid data.
+    fn failed(&self, peer_id: PeerId, block_number: u64, e: &FetchError) {
+        warn!(block_number = block_number, %peer_id, "failed to fetch block: {}", e);
+        if e.is_bad_data() {
+            counter!("bscpeer_invalid_bodies_total").increment(1);
+            self.network.reputation_change(peer_id, ReputationChangeKind::BadBlock);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer::pipeline::INITIAL_WINDOW;
+
+    fn fetch(peer: u8, block_number: u64) -> SyncAction {
+        SyncAction::Fetch { peer_id: PeerId::repeat_byte(peer), block_number }
+    }
+
+    fn failed(state: &SyncState, block_number: u64) -> SyncCommand {
+        let request_id = state.request_id(block_number).unwrap();
+        SyncCommand::FetchFailed { block_number, request_id }
+    }
+
+    #[test]
+    fn test_request_next_block() {
+        let mut state = SyncState::new(10);
+        assert!(state.handle(SyncCommand::Tick).is_empty());
+
+        assert_eq!(state.handle(SyncCommand::AddPeer(PeerId::repeat_byte(1))), vec![fetch(1, 11)]);
+        // the request is still pending
+        assert!(state.handle(SyncCommand::AddPeer(PeerId::repeat_byte(2))).is_empty());
+        assert!(state.handle(SyncCommand::Tick).is_empty());
+
+        assert!(state.handle(SyncCommand::BlockReceived(11)).is_empty());
+        assert_eq!(state.height(), 11);
+        assert_eq!(state.handle(SyncCommand::Tick), vec![fetch(1, 12)]);
+
+        state.handle(SyncCommand::RemovePeer(PeerId::repeat_byte(1)));
+        assert!(state.handle(failed(&state, 12)).is_empty());
+        assert_eq!(state.handle(SyncCommand::Tick), vec![fetch(2, 12)]);
+    }
+
+    #[test]
+    fn test_block_hashes() {
+        let mut state = SyncState::new(10);
+        state.handle(SyncCommand::AddPeer(PeerId::repeat_byte(1)));
+        state.handle(SyncCommand::BlockReceived(12));
+
+        let actions = state.handle(SyncCommand::BlockHashes(vec![9, 12, 13, 14]));
+        assert_eq!(actions, vec![fetch(1, 13), fetch(1, 14)]);
+        assert_eq!(state.pending_requests.keys().copied().collect::<Vec<_>>(), vec![11, 13, 14]);
+    }
+
+    #[test]
+    fn test_head_before_backfill() {
+        let mut state = SyncState::new(10);
+        state.max_in_flight = 1;
+        assert_eq!(state.handle(SyncCommand::AddPeer(PeerId::repeat_byte(1))), vec![fetch(1, 11)]);
+
+        // far behind the announced head, the next block is backfill
+        assert!(state.handle(SyncCommand::BlockHashes(vec![100])).is_empty());
+        assert!(state.announced.contains_key(&100));
+        assert_eq!(state.handle(SyncCommand::BlockReceived(11)), vec![fetch(1, 100)]);
+        assert!(state.announced.is_empty());
+        assert_eq!(state.priority(12), Priority::Backfill);
+        assert_eq!(state.priority(95), Priority::Head);
+        state.handle(SyncCommand::Tick);
+        assert!(state.work.contains(12));
+
+        // a new head block jumps the backfill queue
+        assert!(state.handle(SyncCommand::BlockHashes(vec![101])).is_empty());
+        assert_eq!(state.handle(SyncCommand::BlockReceived(100)), vec![fetch(1, 101)]);
+        assert_eq!(state.handle(failed(&state, 101)), vec![fetch(1, 12)]);
+    }
+
+    #[test]
+    fn test_spread_backfill() {
+        let mut state = SyncState::new(0);
+        state.highest_seen = 100;
+        let (slow, fast) = (PeerId::repeat_byte(1), PeerId::repeat_byte(2));
+        assert_eq!(state.handle(SyncCommand::AddPeer(slow)), vec![fetch(1, 1)]);
+        assert!(state.handle(SyncCommand::AddPeer(fast)).is_empty());
+
+        // the backfill is spread over both peers, each capped
+        for number in 2..=12 {
+            state.request(number);
+        }
+        assert_eq!(state.dispatch().len(), 2 * INITIAL_WINDOW - 1);
+        assert_eq!(state.in_flight[&slow], INITIAL_WINDOW);
+        assert_eq!(state.in_flight[&fast], INITIAL_WINDOW);
+        assert_eq!(state.work.len(), 4);
+
+        // the fast peer takes over the rest, including the slow peer's queue
+        let fast_requests: Vec<_> = state
+            .pending_requests
+            .iter()
+            .filter(|(_, request)| request.peer_id == fast)
+            .map(|(number, _)| *number)
+            .collect();
+        for number in fast_requests {
+            state.handle(SyncCommand::BlockReceived(number));
+        }
+        assert!(state.work.is_empty());
+        assert_eq!(state.in_flight[&fast], 4);
+        assert_eq!(state.in_flight[&slow], INITIAL_WINDOW);
+    }
+
+    #[test]
+    fn test_backfill_by_peer_head() {
+        let mut state = SyncState::new(0);
+        state.highest_seen = 100;
+        let (full, fresh) = (PeerId::repeat_byte(1), PeerId::repeat_byte(2));
+        assert!(state.handle(SyncCommand::PeerHead { peer_id: fresh, number: 3 }).is_empty());
+        assert_eq!(state.handle(SyncCommand::AddPeer(fresh)), vec![fetch(2, 1)]);
+
+        // the fresh peer only gets the blocks up to its head
+        for number in 2..=6 {
+            state.request(number);
+        }
+        assert_eq!(state.dispatch(), vec![fetch(2, 2), fetch(2, 3)]);
+        assert_eq!(state.backfill_queue.len(), 3);
+
+        // the rest waits for a peer having it
+        assert_eq!(state.handle(SyncCommand::AddPeer(full)).len(), 3);
+        assert!(state.backfill_queue.is_empty());
+        assert!(state.handle(SyncCommand::PeerHead { peer_id: fresh, number: 2 }).is_empty());
+    }
+
+    #[test]
+    fn test_adaptive_window() {
+        let mut state = SyncState::new(0);
+        let peer_id = PeerId::repeat_byte(1);
+        state.handle(SyncCommand::AddPeer(peer_id));
+        for number in 2..=20 {
+            state.request(number);
+        }
+        state.dispatch();
+        assert_eq!(state.in_flight[&peer_id], INITIAL_WINDOW);
+
+        // a failure halves the window, fast responses grow it again
+        assert!(state.handle(failed(&state, 1)).is_empty());
+        assert_eq!(state.windows[&peer_id].limit(), INITIAL_WINDOW / 2);
+        for _ in 0..10 {
+            state.handle(SyncCommand::FetchCompleted {
+                peer_id,
+                elapsed: Duration::from_millis(50),
+            });
+        }
+        assert!(state.windows[&peer_id].limit() > INITIAL_WINDOW / 2);
+    }
+
+    #[test]
+    fn test_receipt_mismatches() {
+        let mut state = SyncState::new(0);
+        let peer_id = PeerId::repeat_byte(1);
+        state.handle(SyncCommand::ReceiptMismatch(peer_id));
+        assert_eq!(
+            state.handle(SyncCommand::ReceiptMismatch(peer_id)),
+            vec![SyncAction::Penalize { peer_id, mismatches: 2 }]
+        );
+    }
+
+    #[test]
+    fn test_missing_parent() {
+        let mut state = SyncState::new(0);
+        let (peer_id, hash) = (PeerId::repeat_byte(1), B256::with_last_byte(1));
+        let missing = SyncCommand::MissingParent { peer_id, hash, number: 9 };
+        assert_eq!(
+            state.handle(missing.clone()),
+            vec![SyncAction::FetchParent { peer_id, hash, number: 9 }]
+        );
+        // requested once until fetched
+        assert!(state.handle(missing.clone()).is_empty());
+        state.handle(SyncCommand::ParentFetched(hash));
+        assert_eq!(state.handle(missing).len(), 1);
+    }
+
+    #[test]
+    fn test_request_ttl() {
+        let mut state = SyncState::new(10);
+        let peer_id = PeerId::repeat_byte(1);
+        assert_eq!(state.handle(SyncCommand::AddPeer(peer_id)), vec![fetch(1, 11)]);
+        let PendingRequest { id: request_id, sent, .. } = state.pending_requests[&11];
+        assert!(state.expire(sent + state.request_ttl).is_empty());
+
+        // the expired request penalizes the peer and is queued again
+        let expired = state.expire(sent + state.request_ttl + Duration::from_secs(1));
+        assert_eq!(expired, vec![SyncAction::Expired { peer_id, block_number: 11, request_id }]);
+        assert!(state.pending_requests.is_empty());
+        assert_eq!(state.windows[&peer_id].limit(), INITIAL_WINDOW / 2);
+        assert_eq!(state.dispatch(), vec![fetch(1, 11)]);
+
+        // a late failure of the expired request doesn't touch the retry
+        let stale = SyncCommand::FetchFailed { block_number: 11, request_id };
+        assert!(state.handle(stale).is_empty());
+        assert_ne!(state.request_id(11), Some(request_id));
+        assert_eq!(state.in_flight[&peer_id], 1);
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut state = SyncState::new(0);
+        state.received_blocks.extend(0..MAX_RECEIVED_BLOCKS as u64 + 10);
+        let request =
+            PendingRequest { id: 0, peer_id: PeerId::repeat_byte(1), sent: Instant::now() };
+        state.pending_requests.insert(5, request);
+        state.pending_requests.insert(2000, request);
+
+        // without finality, everything is kept up to the caps
+        state.handle(SyncCommand::Tick);
+        assert_eq!(state.received_blocks.len(), MAX_RECEIVED_BLOCKS);
+        assert_eq!(state.received_blocks.first(), Some(&10));
+        assert_eq!(state.pending_requests.len(), 2);
+
+        state.handle(SyncCommand::Finalized(PRUNE_WINDOW + 100));
+        state.handle(SyncCommand::Tick);
+        assert_eq!(state.received_blocks.first(), Some(&100));
+        assert_eq!(state.pending_requests.keys().copied().collect::<Vec<_>>(), vec![2000]);
+    }
+}
@@ -0,0 +1,128 @@
+//! ERC-20 transfers decoded from the receipts of fetched blocks.
+//!
+//! Only the `Transfer` logs of allowlisted tokens are decoded. ERC-721 transfers share the event
+//! signature but index the token id as a third topic, so they are told apart by the topic count.
+use crate::config::TokenTransfersConfig;
+use alloy_primitives::{Address, B256, U256, b256};
+use metrics::counter;
+use reth_ethereum_primitives::{Receipt, TransactionSigned};
+use serde::Serialize;
+use std::{collections::HashSet, sync::Arc};
+
+/// Topic of `Transfer(address,address,uint256)`.
+pub const TRANSFER_TOPIC: B256 =
+    b256!("0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef");
+
+/// A token transfer logged by a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenTransfer {
+    pub token: Address,
+    pub from: Address,
+    pub to: Address,
+    /// Amount in the token's base unit.
+    pub amount: U256,
+    pub tx_hash: B256,
+    /// Index of the log within the block.
+    pub log_index: u64,
+}
+
+/// Decodes the transfers of the allowlisted tokens.
+#[derive(Debug, Clone)]
+pub struct TokenTransferDecoder {
+    tokens: Arc<HashSet<Address>>,
+}
+
+impl TokenTransferDecoder {
+    /// Creates the decoder, `None` without allowlisted tokens.
+    pub fn new(config: &TokenTransfersConfig) -> Option<Self> {
+        (!config.tokens.is_empty())
+            .then(|| Self { tokens: Arc::new(config.tokens.iter().copied().collect()) })
+    }
+
+    /// Returns the transfers logged in the receipts of a block, whose transactions come in the
+    /// same order.
+    pub fn decode(
+        &self,
+        receipts: &[Receipt],
+        transactions: &[TransactionSigned],
+    ) -> Vec<TokenTransfer> {
+        let mut transfers = Vec::new();
+        let logs = receipts
+            .iter()
+            .zip(transactions)
+            .flat_map(|(receipt, tx)| receipt.logs.iter().map(move |log| (*tx.tx_hash(), log)));
+        for (log_index, (tx_hash, log)) in logs.enumerate() {
+            if !self.tokens.contains(&log.address) {
+                continue;
+            }
+            let [topic, from, to] = log.topics() else { continue };
+            if *topic != TRANSFER_TOPIC || log.data.data.len() != 32 {
+                continue;
+            }
+            counter!("bscpeer_token_transfers_total", "token" => log.address.to_string())
+                .increment(1);
+            transfers.push(TokenTransfer {
+                token: log.address,
+                from: Address::from_word(*from),
+                to: Address::from_word(*to),
+                amount: U256::from_be_slice(&log.data.data),
+                tx_hash,
+                log_index: log_index as u64,
+            });
+        }
+        transfers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_consensus::{Signed, TxLegacy};
+    use alloy_primitives::{Log, LogData, Signature};
+
+    #[test]
+    fn test_decode_transfers() {
+        let (token, other) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let (from, to) = (Address::repeat_byte(3), Address::repeat_byte(4));
+        let log = |address, topics: Vec<B256>, amount: u64| Log {
+            address,
+            data: LogData::new_unchecked(
+                topics,
+                U256::from(amount).to_be_bytes::<32>().to_vec().into(),
+            ),
+        };
+        let transfer = vec![TRANSFER_TOPIC, from.into_word(), to.into_word()];
+        let nft = vec![TRANSFER_TOPIC, from.into_word(), to.into_word(), B256::ZERO];
+        let receipts = [
+            Receipt { logs: vec![log(other, transfer.clone(), 1)], ..Default::default() },
+            Receipt {
+                logs: vec![log(token, nft, 2), log(token, transfer, 3)],
+                ..Default::default()
+            },
+        ];
+        let signature = Signature::new(U256::from(1), U256::from(1), false);
+        let transactions: Vec<_> = (0..2)
+            .map(|nonce| {
+                let tx = TxLegacy { nonce, ..Default::default() };
+                TransactionSigned::Legacy(Signed::new_unhashed(tx, signature))
+            })
+            .collect();
+
+        let config = TokenTransfersConfig { tokens: vec![token] };
+        let decoder = TokenTransferDecoder::new(&config).unwrap();
+        let transfers = decoder.decode(&receipts, &transactions);
+        assert_eq!(
+            transfers,
+            [TokenTransfer {
+                token,
+                from,
+                to,
+                amount: U256::from(3),
+                tx_hash: *transactions[1].tx_hash(),
+                log_index: 2,
+            }]
+        );
+        assert!(TokenTransferDecoder::new(&TokenTransfersConfig::default()).is_none());
+    }
+}
@@ -0,0 +1,538 @@
+//! Pending transactions gossiped by the peers, decoded for the event stream.
+//!
+//! The network manager hands its transaction messages over to [`TransactionGossip`]. Broadcast
+//! transactions are decoded as they arrive and announced hashes not seen before are requested
+//! from the announcing peer, hashes the peer didn't deliver can be requested again. Only a few
+//! requests run per peer at once, further announcements are dropped until one completes. Each
+//! transaction is emitted once, with its sender recovered on the blocking thread pool by a worker
+//! of its own, as part of a [`BlockEvent::PendingTransactions`] event, to the subscribers of
+//! [`EventSender::subscribe_transactions`].
+//!
+//! [`TransactionSender`] goes the other way, broadcasting signed transactions to the peers.
+use crate::{
+    config::{TransactionFilterConfig, TransactionsConfig},
+    instance,
+    peer::{blockstate::BlockEvent, events::EventSender, fetch, wire::trace_wire},
+};
+use alloy_consensus::{Transaction, TxEnvelope, transaction::SignerRecoverable};
+use alloy_eips::{
+    Typed2718,
+    eip2718::{Decodable2718, Eip2718Error},
+};
+use alloy_primitives::{Address, B256, Bytes, Selector, U256};
+use metrics::counter;
+use reth_eth_wire::PooledTransactions;
+use reth_ethereum_primitives::TransactionSigned;
+use reth_network::{EthNetworkPrimitives, NetworkHandle, transactions::NetworkTransactionEvent};
+use reth_network_api::Peers;
+use reth_network_peers::PeerId;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::debug;
+
+/// Pooled transaction requests in flight to a single peer at most.
+const MAX_REQUESTS_PER_PEER: usize = 2;
+/// Pooled transaction requests in flight across all peers at most.
+const MAX_REQUESTS: usize = 128;
+/// Batches of transactions queued for sender recovery before further batches are dropped.
+const DECODE_QUEUE: usize = 256;
+
+/// A pending transaction with its sender.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingTransaction {
+    pub hash: B256,
+    pub sender: Address,
+    pub nonce: u64,
+    pub tx_type: u8,
+    /// Gas price of legacy transactions, max fee per gas of the others.
+    pub gas_price: u128,
+    pub max_priority_fee_per_gas: Option<u128>,
+    pub gas_limit: u64,
+    pub to: Option<Address>,
+    pub value: U256,
+    pub input: Bytes,
+    /// The signed transaction.
+    #[serde(skip)]
+    pub envelope: TxEnvelope,
+}
+
+impl PendingTransaction {
+    /// Decodes the transaction, returning `None` if its signature is invalid.
+    pub fn decode(envelope: TxEnvelope) -> Option<Self> {
+        let sender = envelope.recover_signer().ok()?;
+        Some(Self {
+            hash: *envelope.tx_hash(),
+            sender,
+            nonce: envelope.nonce(),
+            tx_type: envelope.ty(),
+            gas_price: envelope.max_fee_per_gas(),
+            max_priority_fee_per_gas: envelope.max_priority_fee_per_gas(),
+            gas_limit: envelope.gas_limit(),
+            to: envelope.to(),
+            value: envelope.value(),
+            input: envelope.input().clone(),
+            envelope,
+        })
+    }
+}
+
+/// Consumer of the transaction messages received by the network manager.
+#[derive(Debug)]
+pub struct TransactionGossip {
+    network: NetworkHandle<EthNetworkPrimitives>,
+    events: EventSender,
+    /// Hashes already emitted or being requested.
+    seen: RecentHashes,
+    /// Requests of announced hashes in flight.
+    requests: InFlight,
+    fetch_announced: bool,
+    filter: Arc<Mutex<Arc<TransactionFilter>>>,
+}
+
+impl TransactionGossip {
+    pub fn new(
+        network: NetworkHandle<EthNetworkPrimitives>,
+        events: EventSender,
+        config: &TransactionsConfig,
+    ) -> Self {
+        Self {
+            network,
+            events,
+            seen: RecentHashes::new(config.seen_capacity),
+            requests: InFlight::default(),
+            fetch_announced: config.fetch_announced,
+            filter: Arc::new(Mutex::new(Arc::new(TransactionFilter::new(&config.filter)))),
+        }
+    }
+
+    /// Returns a handle replacing the filter once the gossip runs.
+    pub fn filter_handle(&self) -> TransactionFilterHandle {
+        TransactionFilterHandle(self.filter.clone())
+    }
+
+    /// Spawns the task handling the messages handed over by the network manager.
+    pub fn spawn(
+        self,
+        incoming: mpsc::UnboundedReceiver<NetworkTransactionEvent<EthNetworkPrimitives>>,
+    ) -> JoinHandle<()> {
+        instance::spawn(self.run(incoming))
+    }
+
+    async fn run(
+        mut self,
+        mut incoming: mpsc::UnboundedReceiver<NetworkTransactionEvent<EthNetworkPrimitives>>,
+    ) {
+        let (fetched_tx, mut fetched) = mpsc::unbounded_channel();
+        // the decoder stops once the gossip drops its queue
+        let (batches, decode_queue) = mpsc::channel(DECODE_QUEUE);
+        let decoder = Decoder { filter: self.filter.clone(), events: self.events.clone() };
+        instance::spawn(decoder.run(decode_queue));
+        loop {
+            tokio::select! {
+                event = incoming.recv() => match event {
+                    Some(event) => self.on_network_event(event, &fetched_tx, &batches),
+                    None => break,
+                },
+                Some((peer_id, transactions, missing)) = fetched.recv() => {
+                    self.requests.finish(peer_id);
+                    for hash in &missing {
+                        self.seen.remove(hash);
+                    }
+                    emit(&batches, peer_id, transactions);
+                }
+            }
+        }
+    }
+
+    fn on_network_event(
+        &mut self,
+        event: NetworkTransactionEvent<EthNetworkPrimitives>,
+        fetched: &mpsc::UnboundedSender<(PeerId, Vec<TxEnvelope>, Vec<B256>)>,
+        batches: &mpsc::Sender<(PeerId, Vec<TxEnvelope>)>,
+    ) {
+        match event {
+            NetworkTransactionEvent::IncomingTransactions { peer_id, msg } => {
+                trace_wire!(Some(peer_id), Inbound, Transactions, &msg);
+                let transactions = msg
+                    .0
+                    .into_iter()
+                    .map(TxEnvelope::from)
+                    .filter(|tx| self.seen.insert(*tx.tx_hash()))
+                    .collect();
+                emit(batches, peer_id, transactions);
+            }
+            NetworkTransactionEvent::IncomingPooledTransactionHashes { peer_id, msg } => {
+                if !self.fetch_announced {
+                    return;
+                }
+                // the hashes of a dropped announcement aren't marked seen, so another peer's
+                // announcement of them is still requested
+                if !self.requests.start(peer_id) {
+                    counter!("bscpeer_pooled_transaction_announcements_dropped_total").increment(1);
+                    return;
+                }
+                let hashes: Vec<_> =
+                    msg.into_hashes().into_iter().filter(|hash| self.seen.insert(*hash)).collect();
+                if hashes.is_empty() {
+                    self.requests.finish(peer_id);
+                    return;
+                }
+                let network = self.network.clone();
+                let fetched = fetched.clone();
+                instance::spawn(async move {
+                    // the hashes not delivered are forgotten, to be requested again if announced
+                    let requested = hashes.clone();
+                    match fetch::fetch_pooled_transactions(&network, peer_id, hashes).await {
+                        Ok(transactions) => {
+                            let transactions: Vec<TxEnvelope> =
+                                transactions.into_iter().map(Into::into).collect();
+                            let delivered: HashSet<_> =
+                                transactions.iter().map(|tx| *tx.tx_hash()).collect();
+                            let missing = requested
+                                .into_iter()
+                                .filter(|hash| !delivered.contains(hash))
+                                .collect();
+                            let _ = fetched.send((peer_id, transactions, missing));
+                        }
+                        Err(e) => {
+                            debug!(%peer_id, "failed to fetch pooled transactions: {}", e);
+                            let _ = fetched.send((peer_id, Vec::new(), requested));
+                        }
+                    }
+                });
+            }
+            // there is no pool to serve transactions from
+            NetworkTransactionEvent::GetPooledTransactions { response, .. } => {
+                let _ = response.send(Ok(PooledTransactions(Vec::new())));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Queues received transactions for the decoder, dropping them if it falls behind.
+fn emit(
+    batches: &mpsc::Sender<(PeerId, Vec<TxEnvelope>)>,
+    peer_id: PeerId,
+    transactions: Vec<TxEnvelope>,
+) {
+    if transactions.is_empty() {
+        return;
+    }
+    let count = transactions.len();
+    if batches.try_send((peer_id, transactions)).is_err() {
+        counter!("bscpeer_pending_transactions_dropped_total").increment(count as u64);
+    }
+}
+
+/// Pooled transaction requests in flight, per peer.
+#[derive(Debug, Default)]
+struct InFlight {
+    peers: HashMap<PeerId, usize>,
+    total: usize,
+}
+
+impl InFlight {
+    /// Counts a new request to the peer, returning `false` if the peer or all peers together
+    /// already have as many requests in flight as allowed.
+    fn start(&mut self, peer_id: PeerId) -> bool {
+        let requests = self.peers.get(&peer_id).copied().unwrap_or_default();
+        if requests >= MAX_REQUESTS_PER_PEER || self.total >= MAX_REQUESTS {
+            return false;
+        }
+        *self.peers.entry(peer_id).or_default() += 1;
+        self.total += 1;
+        true
+    }
+
+    /// Counts a completed request to the peer.
+    fn finish(&mut self, peer_id: PeerId) {
+        let Some(requests) = self.peers.get_mut(&peer_id) else { return };
+        *requests -= 1;
+        self.total -= 1;
+        if *requests == 0 {
+            self.peers.remove(&peer_id);
+        }
+    }
+}
+
+/// Recovers the senders of the received transactions, one batch at a time, and emits those
+/// matching the filter.
+struct Decoder {
+    filter: Arc<Mutex<Arc<TransactionFilter>>>,
+    events: EventSender,
+}
+
+impl Decoder {
+    async fn run(self, mut batches: mpsc::Receiver<(PeerId, Vec<TxEnvelope>)>) {
+        while let Some((peer_id, transactions)) = batches.recv().await {
+            self.emit(peer_id, transactions).await;
+        }
+    }
+
+    async fn emit(&self, peer_id: PeerId, transactions: Vec<TxEnvelope>) {
+        let received = transactions.len();
+        // the filter is only locked to take the current one, a reload replaces it as a whole
+        let filter = self.filter.lock().unwrap().clone();
+        // the senders are recovered off the async workers
+        let decode = move || {
+            // the cheap checks go first, so filtered transactions are never recovered
+            transactions
+                .into_iter()
+                .filter(|tx| filter.matches(tx))
+                .filter_map(|tx| {
+                    let decoded = PendingTransaction::decode(tx);
+                    if decoded.is_none() {
+                        counter!("bscpeer_pending_transactions_invalid_total").increment(1);
+                    }
+                    decoded
+                })
+                .filter(|tx| filter.matches_sender(tx.sender))
+                .collect::<Vec<_>>()
+        };
+        let transactions = match tokio::task::spawn_blocking(decode).await {
+            Ok(transactions) => transactions,
+            Err(e) => {
+                debug!(%peer_id, "failed to decode pending transactions: {}", e);
+                return;
+            }
+        };
+        let filtered = received - transactions.len();
+        if filtered > 0 {
+            counter!("bscpeer_pending_transactions_filtered_total").increment(filtered as u64);
+        }
+        if transactions.is_empty() {
+            return;
+        }
+        counter!("bscpeer_pending_transactions_total").increment(transactions.len() as u64);
+        self.events.send(BlockEvent::PendingTransactions { peer_id, transactions });
+    }
+}
+
+/// Errors that can occur while sending a transaction.
+#[derive(Debug, thiserror::Error)]
+pub enum SendTransactionError {
+    /// The transaction isn't a valid EIP-2718 encoding.
+    #[error("invalid transaction encoding: {0}")]
+    Decode(#[from] Eip2718Error),
+    /// The signer can't be recovered from the signature.
+    #[error("invalid transaction signature")]
+    InvalidSignature,
+    /// The transaction is replay protected for another chain.
+    #[error("transaction is for chain {got}, expected {expected}")]
+    WrongChain { expected: u64, got: u64 },
+    /// The connected peers couldn't be listed.
+    #[error("network error: {0}")]
+    Network(String),
+    /// No peer is connected to send the transaction to.
+    #[error("no connected peers")]
+    NoPeers,
+}
+
+/// Broadcasts signed transactions to all connected peers.
+#[derive(Debug, Clone)]
+pub struct TransactionSender {
+    network: NetworkHandle<EthNetworkPrimitives>,
+    chain_id: u64,
+}
+
+impl TransactionSender {
+    pub fn new(network: NetworkHandle<EthNetworkPrimitives>, chain_id: u64) -> Self {
+        Self { network, chain_id }
+    }
+
+    /// Decodes an EIP-2718 encoded signed transaction and sends it to every connected peer,
+    /// returning its hash and the number of peers it was sent to.
+    ///
+    /// Delivery is fire-and-forget: the peers validate the transaction on their own and drop
+    /// it silently if it's rejected, e.g. for a nonce too low.
+    pub async fn send_raw_transaction(
+        &self,
+        raw: &[u8],
+    ) -> Result<(B256, usize), SendTransactionError> {
+        let tx = TransactionSigned::decode_2718(&mut &raw[..])?;
+        tx.recover_signer().map_err(|_| SendTransactionError::InvalidSignature)?;
+        if let Some(chain_id) = tx.chain_id().filter(|chain_id| *chain_id != self.chain_id) {
+            return Err(SendTransactionError::WrongChain { expected: self.chain_id, got: chain_id });
+        }
+
+        let peers = self
+            .network
+            .get_all_peers()
+            .await
+            .map_err(|e| SendTransactionError::Network(e.to_string()))?;
+        if peers.is_empty() {
+            return Err(SendTransactionError::NoPeers);
+        }
+        let hash = *tx.tx_hash();
+        let tx = Arc::new(tx);
+        for peer in &peers {
+            self.network.send_transactions(peer.remote_id, vec![tx.clone()]);
+        }
+        counter!("bscpeer_transactions_sent_total").increment(1);
+        debug!(%hash, peers = peers.len(), "sent transaction");
+        Ok((hash, peers.len()))
+    }
+}
+
+/// Handle replacing the filter of a running [`TransactionGossip`].
+#[derive(Debug, Clone)]
+pub struct TransactionFilterHandle(Arc<Mutex<Arc<TransactionFilter>>>);
+
+impl TransactionFilterHandle {
+    pub fn set(&self, config: &TransactionFilterConfig) {
+        *self.0.lock().unwrap() = Arc::new(TransactionFilter::new(config));
+    }
+}
+
+/// Filter applied to the pending transactions before they are emitted.
+#[derive(Debug, Default)]
+struct TransactionFilter {
+    to: HashSet<Address>,
+    from: HashSet<Address>,
+    selectors: HashSet<Selector>,
+    min_value: U256,
+}
+
+impl TransactionFilter {
+    fn new(config: &TransactionFilterConfig) -> Self {
+        Self {
+            to: config.to.iter().copied().collect(),
+            from: config.from.iter().copied().collect(),
+            selectors: config.selectors.iter().copied().collect(),
+            min_value: config.min_value,
+        }
+    }
+
+    /// Checks every criterion but the sender, which requires recovering the signature.
+    fn matches(&self, tx: &TxEnvelope) -> bool {
+        let to = tx.to();
+        let selector = tx.input().get(..4).map(Selector::from_slice);
+        tx.value() >= self.min_value
+            && (self.to.is_empty() || to.is_some_and(|to| self.to.contains(&to)))
+            && (self.selectors.is_empty()
+                || selector.is_some_and(|selector| self.selectors.contains(&selector)))
+    }
+
+    fn matches_sender(&self, sender: Address) -> bool {
+        self.from.is_empty() || self.from.contains(&sender)
+    }
+}
+
+/// Set of the most recently inserted hashes.
+#[derive(Debug)]
+pub(crate) struct RecentHashes {
+    hashes: HashSet<B256>,
+    order: VecDeque<B256>,
+    capacity: usize,
+}
+
+impl RecentHashes {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self { hashes: HashSet::new(), order: VecDeque::new(), capacity }
+    }
+
+    /// Inserts the hash, returning `false` if it is already known.
+    pub(crate) fn insert(&mut self, hash: B256) -> bool {
+        if !self.hashes.insert(hash) {
+            return false;
+        }
+        self.order.push_back(hash);
+        while self.order.len() > self.capacity {
+            let Some(oldest) = self.order.pop_front() else { break };
+            self.hashes.remove(&oldest);
+        }
+        true
+    }
+
+    /// Forgets the hash, so it's inserted again.
+    pub(crate) fn remove(&mut self, hash: &B256) {
+        if self.hashes.remove(hash) {
+            self.order.retain(|known| known != hash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        use alloy_consensus::{Signed, TxLegacy};
+        use alloy_primitives::{Signature, TxKind, address};
+
+        let token = address!("0x55d398326f99059ff775485246999027b3197955");
+        let transfer = |to: Address, input: &[u8], value: u64| {
+            let tx = TxLegacy {
+                to: TxKind::Call(to),
+                input: Bytes::copy_from_slice(input),
+                value: U256::from(value),
+                ..Default::default()
+            };
+            let signature = Signature::new(U256::from(1), U256::from(1), false);
+            TxEnvelope::Legacy(Signed::new_unhashed(tx, signature))
+        };
+
+        let filter = TransactionFilter::default();
+        assert!(filter.matches(&transfer(Address::ZERO, &[], 0)));
+        assert!(filter.matches_sender(Address::ZERO));
+
+        let filter = TransactionFilter::new(&TransactionFilterConfig {
+            to: vec![token],
+            selectors: vec![Selector::new([0xa9, 0x05, 0x9c, 0xbb])],
+            min_value: U256::from(10),
+            ..Default::default()
+        });
+        assert!(filter.matches(&transfer(token, &[0xa9, 0x05, 0x9c, 0xbb, 0x00], 10)));
+        assert!(!filter.matches(&transfer(token, &[0xa9, 0x05, 0x9c, 0xbb], 9)));
+        assert!(!filter.matches(&transfer(token, &[0x09, 0x5e, 0xa7, 0xb3], 10)));
+        assert!(!filter.matches(&transfer(token, &[], 10)));
+        assert!(!filter.matches(&transfer(Address::ZERO, &[0xa9, 0x05, 0x9c, 0xbb], 10)));
+    }
+
+    #[test]
+    fn test_in_flight() {
+        let mut requests = InFlight::default();
+        let (a, b) = (PeerId::with_last_byte(1), PeerId::with_last_byte(2));
+        for _ in 0..MAX_REQUESTS_PER_PEER {
+            assert!(requests.start(a));
+        }
+        // a peer with as many requests as allowed is refused, others aren't
+        assert!(!requests.start(a));
+        assert!(requests.start(b));
+        requests.finish(a);
+        assert!(requests.start(a));
+
+        // unknown peers are ignored, and every request finished leaves nothing behind
+        requests.finish(PeerId::with_last_byte(3));
+        for _ in 0..MAX_REQUESTS_PER_PEER {
+            requests.finish(a);
+        }
+        requests.finish(b);
+        assert!(requests.peers.is_empty());
+        assert_eq!(requests.total, 0);
+    }
+
+    #[test]
+    fn test_recent_hashes() {
+        let mut seen = RecentHashes::new(2);
+        assert!(seen.insert(B256::with_last_byte(1)));
+        assert!(!seen.insert(B256::with_last_byte(1)));
+        assert!(seen.insert(B256::with_last_byte(2)));
+        assert!(seen.insert(B256::with_last_byte(3)));
+        // the oldest hash was evicted
+        assert!(seen.insert(B256::with_last_byte(1)));
+        assert!(!seen.insert(B256::with_last_byte(3)));
+
+        // a forgotten hash is inserted again and doesn't evict early
+        seen.remove(&B256::with_last_byte(3));
+        assert!(seen.insert(B256::with_last_byte(3)));
+        assert!(!seen.insert(B256::with_last_byte(1)));
+    }
+}
@@ -0,0 +1,161 @@
+//! Alerts on transfers matching configured rules, e.g. large values or watched counterparties.
+//!
+//! Exchanges and compliance teams would otherwise poll an RPC node for every block. The rules
+//! are checked against the transactions of each imported block, with the senders recovered by the
+//! verification workers.
+use crate::{
+    config::{TransferAlertsConfig, TransferRule},
+    peer::blockstate::BlockEvent,
+};
+use alloy_consensus::Transaction;
+use alloy_primitives::{Address, B256};
+use metrics::counter;
+use reth_ethereum_primitives::TransactionSigned;
+use tracing::debug;
+
+/// Checks block transactions against the transfer rules.
+#[derive(Debug, Clone)]
+pub struct TransferMonitor {
+    rules: Vec<TransferRule>,
+}
+
+impl TransferMonitor {
+    /// Creates the monitor, `None` without rules.
+    pub fn new(config: &TransferAlertsConfig) -> Option<Self> {
+        (!config.rules.is_empty()).then(|| Self { rules: config.rules.clone() })
+    }
+
+    /// Returns an alert for every transaction of the block matching at least one rule, given the
+    /// senders recovered by the verification workers, so rules on the sender alone cost no
+    /// signature recovery here.
+    pub fn scan(
+        &self,
+        block_number: u64,
+        block_hash: B256,
+        transactions: &[TransactionSigned],
+        senders: &[Option<Address>],
+    ) -> Vec<BlockEvent> {
+        let mut alerts = Vec::new();
+        for (tx, sender) in transactions.iter().zip(senders) {
+            let Some(from) = *sender else {
+                debug!(block_number, tx_hash = %tx.tx_hash(), "transfer without sender");
+                continue;
+            };
+            let rules: Vec<String> = self
+                .rules
+                .iter()
+                .filter(|rule| matches(rule, tx, from))
+                .map(|rule| rule.name.clone())
+                .collect();
+            if rules.is_empty() {
+                continue;
+            }
+            for rule in &rules {
+                counter!("bscpeer_transfer_alerts_total", "rule" => rule.clone()).increment(1);
+            }
+            alerts.push(BlockEvent::TransferAlert {
+                rules,
+                block_number,
+                block_hash,
+                tx_hash: *tx.tx_hash(),
+                from,
+                to: tx.to(),
+                value: tx.value(),
+            });
+        }
+        alerts
+    }
+}
+
+/// Checks every criterion of the rule against a transaction sent by `from`.
+fn matches(rule: &TransferRule, tx: &TransactionSigned, from: Address) -> bool {
+    let to = tx.to();
+    tx.value() >= rule.min_value
+        && (rule.from.is_empty() || rule.from.contains(&from))
+        && (rule.to.is_empty() || to.is_some_and(|to| rule.to.contains(&to)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parlia::seal::public_key_address, peer::verify::recover_senders};
+    use alloy_consensus::{SignableTransaction, Signed, TxLegacy};
+    use alloy_primitives::{Address, Signature, TxKind, U256};
+    use secp256k1::{Message, SECP256K1, SecretKey};
+
+    fn transfer(secret_key: &SecretKey, to: Address, value: u64) -> TransactionSigned {
+        let tx = TxLegacy { to: TxKind::Call(to), value: U256::from(value), ..Default::default() };
+        let message = Message::from_digest(tx.signature_hash().0);
+        let (recovery_id, signature) =
+            SECP256K1.sign_ecdsa_recoverable(&message, secret_key).serialize_compact();
+        let signature = Signature::new(
+            U256::from_be_slice(&signature[..32]),
+            U256::from_be_slice(&signature[32..]),
+            i32::from(recovery_id) != 0,
+        );
+        TransactionSigned::Legacy(Signed::new_unhashed(tx, signature))
+    }
+
+    #[test]
+    fn test_transfer_rules() {
+        let (exchange, hot_wallet) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let (key, other_key) =
+            (SecretKey::from_slice(&[1; 32]).unwrap(), SecretKey::from_slice(&[2; 32]).unwrap());
+        let sender = public_key_address(&key.public_key(SECP256K1));
+        let config = TransferAlertsConfig {
+            rules: vec![
+                TransferRule {
+                    name: "large".to_string(),
+                    min_value: U256::from(1000),
+                    ..Default::default()
+                },
+                TransferRule {
+                    name: "exchange_deposit".to_string(),
+                    to: vec![exchange],
+                    ..Default::default()
+                },
+                TransferRule {
+                    name: "hot_wallet".to_string(),
+                    from: vec![sender],
+                    to: vec![hot_wallet],
+                    ..Default::default()
+                },
+            ],
+        };
+        let monitor = TransferMonitor::new(&config).unwrap();
+        let transactions = [
+            transfer(&key, exchange, 5000),
+            transfer(&key, Address::ZERO, 10),
+            transfer(&other_key, hot_wallet, 10),
+            transfer(&key, hot_wallet, 10),
+        ];
+
+        let senders = recover_senders(&transactions);
+        let alerts = monitor.scan(1, B256::ZERO, &transactions, &senders);
+        let rules: Vec<_> = alerts
+            .iter()
+            .map(|alert| match alert {
+                BlockEvent::TransferAlert { rules, from, .. } => {
+                    assert_eq!(*from, sender);
+                    rules.clone()
+                }
+                _ => panic!("unexpected event {alert:?}"),
+            })
+            .collect();
+        assert_eq!(rules, [vec!["large", "exchange_deposit"], vec!["hot_wallet"]]);
+
+        // a rule on the sender alone matches all of its transactions, but not unrecovered ones
+        let config = TransferAlertsConfig {
+            rules: vec![TransferRule {
+                name: "sender".to_string(),
+                from: vec![sender],
+                ..Default::default()
+            }],
+        };
+        let monitor = TransferMonitor::new(&config).unwrap();
+        assert_eq!(monitor.scan(1, B256::ZERO, &transactions, &senders).len(), 3);
+        assert!(monitor.scan(1, B256::ZERO, &transactions, &[None; 4]).is_empty());
+
+        assert!(TransferMonitor::new(&TransferAlertsConfig::default()).is_none());
+    }
+}
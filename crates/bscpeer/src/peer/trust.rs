@@ -0,0 +1,239 @@
+//! The BSC `trust/1` RLPx sub-protocol.
+//!
+//! bsc-geth uses it to ask trusted peers to verify the state root of a block against its diff
+//! layer. The node advertises the capability and decodes the received messages into
+//! [`BlockEvent::TrustMessage`] events, but holds no state to answer root requests with.
+use crate::peer::{blockstate::BlockEvent, events::EventSender};
+use alloy_primitives::B256;
+use alloy_rlp::{Decodable, RlpDecodable, RlpEncodable};
+use bytes::BytesMut;
+use futures::{Stream, StreamExt, future};
+use metrics::counter;
+use reth_eth_wire::{
+    Capability, capability::SharedCapabilities, multiplex::ProtocolConnection, protocol::Protocol,
+};
+use reth_network::protocol::{ConnectionHandler, OnNotSupported, ProtocolHandler};
+use reth_network_api::Direction;
+use reth_network_peers::PeerId;
+use serde::Serialize;
+use std::{net::SocketAddr, pin::Pin};
+use tracing::{debug, warn};
+
+/// Id of the root request message.
+const REQUEST_ROOT: u8 = 0x00;
+/// Id of the root response message.
+const RESPOND_ROOT: u8 = 0x01;
+
+/// Errors decoding a trust message.
+#[derive(Debug, thiserror::Error)]
+pub enum TrustMessageError {
+    #[error("empty message")]
+    Empty,
+    #[error("unknown message id {0:#04x}")]
+    UnknownMessage(u8),
+    #[error(transparent)]
+    Rlp(#[from] alloy_rlp::Error),
+}
+
+/// Request to verify the state root of a block given the hash of its diff layer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, RlpEncodable, RlpDecodable)]
+#[serde(rename_all = "camelCase")]
+pub struct RootRequest {
+    pub request_id: u64,
+    pub block_number: u64,
+    pub block_hash: B256,
+    pub diff_hash: B256,
+}
+
+/// Verification outcome, e.g. `0x100` for a fully verified block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, RlpEncodable, RlpDecodable)]
+pub struct VerifyStatus {
+    pub code: u16,
+    pub msg: String,
+}
+
+/// Answer to a [`RootRequest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RootResponse {
+    pub request_id: u64,
+    pub status: VerifyStatus,
+    pub block_number: u64,
+    pub block_hash: B256,
+    pub root: B256,
+}
+
+impl Decodable for RootResponse {
+    fn decode(buf: &mut &[u8]) -> alloy_rlp::Result<Self> {
+        let mut payload = alloy_rlp::Header::decode_bytes(buf, true)?;
+        // the trailing extra field is reserved and ignored
+        Ok(Self {
+            request_id: Decodable::decode(&mut payload)?,
+            status: Decodable::decode(&mut payload)?,
+            block_number: Decodable::decode(&mut payload)?,
+            block_hash: Decodable::decode(&mut payload)?,
+            root: Decodable::decode(&mut payload)?,
+        })
+    }
+}
+
+/// A message of the trust protocol.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum TrustMessage {
+    RootRequest(RootRequest),
+    RootResponse(RootResponse),
+}
+
+impl TrustMessage {
+    /// Decodes a message prefixed with its id.
+    pub fn decode(buf: &[u8]) -> Result<Self, TrustMessageError> {
+        let (&id, mut payload) = buf.split_first().ok_or(TrustMessageError::Empty)?;
+        match id {
+            REQUEST_ROOT => Ok(Self::RootRequest(RootRequest::decode(&mut payload)?)),
+            RESPOND_ROOT => Ok(Self::RootResponse(RootResponse::decode(&mut payload)?)),
+            id => Err(TrustMessageError::UnknownMessage(id)),
+        }
+    }
+
+    /// Returns the message name used in metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::RootRequest(_) => "root_request",
+            Self::RootResponse(_) => "root_response",
+        }
+    }
+
+    /// Returns the block the message is about.
+    pub fn block_number(&self) -> u64 {
+        match self {
+            Self::RootRequest(request) => request.block_number,
+            Self::RootResponse(response) => response.block_number,
+        }
+    }
+}
+
+/// Handler of the trust protocol, added to the network as an RLPx sub-protocol.
+#[derive(Debug, Clone)]
+pub struct TrustProtocol {
+    events: EventSender,
+}
+
+impl TrustProtocol {
+    pub fn new(events: EventSender) -> Self {
+        Self { events }
+    }
+
+    /// The `trust/1` capability with its two messages.
+    pub fn protocol() -> Protocol {
+        Protocol::new(Capability::new_static("trust", 1), 2)
+    }
+}
+
+impl ProtocolHandler for TrustProtocol {
+    type ConnectionHandler = Self;
+
+    fn on_incoming(&self, _socket_addr: SocketAddr) -> Option<Self::ConnectionHandler> {
+        Some(self.clone())
+    }
+
+    fn on_outgoing(
+        &self,
+        _socket_addr: SocketAddr,
+        _peer_id: PeerId,
+    ) -> Option<Self::ConnectionHandler> {
+        Some(self.clone())
+    }
+}
+
+impl ConnectionHandler for TrustProtocol {
+    type Connection = Pin<Box<dyn Stream<Item = BytesMut> + Send>>;
+
+    fn protocol(&self) -> Protocol {
+        Self::protocol()
+    }
+
+    fn on_unsupported_by_peer(
+        self,
+        _supported: &SharedCapabilities,
+        _direction: Direction,
+        _peer_id: PeerId,
+    ) -> OnNotSupported {
+        OnNotSupported::KeepAlive
+    }
+
+    fn into_connection(
+        self,
+        _direction: Direction,
+        peer_id: PeerId,
+        conn: ProtocolConnection,
+    ) -> Self::Connection {
+        // only receives, the stream never yields a message to send
+        Box::pin(conn.filter_map(move |buf| {
+            match TrustMessage::decode(&buf) {
+                Ok(message) => {
+                    debug!(%peer_id, kind = message.kind(), "received trust message");
+                    counter!("bscpeer_trust_messages_total", "message" => message.kind())
+                        .increment(1);
+                    self.events.send(BlockEvent::TrustMessage { peer_id, message });
+                }
+                Err(e) => {
+                    warn!(%peer_id, "invalid trust message: {}", e);
+                    counter!("bscpeer_trust_messages_invalid_total").increment(1);
+                }
+            }
+            future::ready(None::<BytesMut>)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_rlp::Encodable;
+
+    #[test]
+    fn test_decode_messages() {
+        let request = RootRequest {
+            request_id: 7,
+            block_number: 100,
+            block_hash: B256::with_last_byte(1),
+            diff_hash: B256::with_last_byte(2),
+        };
+        let mut buf = vec![REQUEST_ROOT];
+        request.encode(&mut buf);
+        assert_eq!(TrustMessage::decode(&buf).unwrap(), TrustMessage::RootRequest(request));
+
+        #[derive(RlpEncodable)]
+        struct EncodedResponse {
+            request_id: u64,
+            status: VerifyStatus,
+            block_number: u64,
+            block_hash: B256,
+            root: B256,
+            extra: Vec<u8>,
+        }
+        let status = VerifyStatus { code: 0x100, msg: "verified".to_string() };
+        let mut buf = vec![RESPOND_ROOT];
+        EncodedResponse {
+            request_id: 7,
+            status: status.clone(),
+            block_number: 100,
+            block_hash: B256::with_last_byte(1),
+            root: B256::with_last_byte(3),
+            extra: Vec::new(),
+        }
+        .encode(&mut buf);
+        let TrustMessage::RootResponse(response) = TrustMessage::decode(&buf).unwrap() else {
+            panic!("expected a root response");
+        };
+        assert_eq!(response.status, status);
+        assert_eq!(response.root, B256::with_last_byte(3));
+
+        assert!(matches!(TrustMessage::decode(&[]), Err(TrustMessageError::Empty)));
+        assert!(matches!(
+            TrustMessage::decode(&[0x02]),
+            Err(TrustMessageError::UnknownMessage(0x02))
+        ));
+    }
+}
@@ -0,0 +1,184 @@
+//! Block production of each validator over the latest blocks.
+//!
+//! Every imported block is attributed to the validator that sealed it. Out-of-turn blocks mean
+//! the in-turn validator missed its slot, so a validator producing many of them is covering for
+//! others, while the averages show how full its blocks are. The stats cover the last [`WINDOW`]
+//! blocks, so they follow changes of the validator set; the block counters of the metrics remain
+//! totals.
+use crate::{chain_config::validators::Validator, parlia::snapshot::TurnStatus};
+use alloy_primitives::Address;
+use metrics::{counter, gauge};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+
+/// Blocks the stats cover, about an hour at a 3 second block time.
+pub const WINDOW: u64 = 1200;
+
+/// Production counters of a validator.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidatorProduction {
+    pub address: Address,
+    pub name: Option<String>,
+    pub blocks: u64,
+    /// Blocks produced while another validator was in turn.
+    pub out_of_turn_blocks: u64,
+    pub avg_transactions: f64,
+    pub avg_gas_used: f64,
+    /// Number of the latest block produced.
+    pub last_block: u64,
+    #[serde(skip)]
+    transactions: u64,
+    #[serde(skip)]
+    gas_used: u64,
+}
+
+impl ValidatorProduction {
+    fn update_averages(&mut self) {
+        let blocks = self.blocks.max(1) as f64;
+        self.avg_transactions = self.transactions as f64 / blocks;
+        self.avg_gas_used = self.gas_used as f64 / blocks;
+    }
+}
+
+/// A block counted in the stats.
+#[derive(Debug)]
+struct Produced {
+    address: Address,
+    number: u64,
+    out_of_turn: bool,
+    transactions: u64,
+    gas_used: u64,
+}
+
+/// Production counters of the validators over the last [`WINDOW`] blocks.
+#[derive(Debug, Default)]
+pub struct ValidatorStats {
+    validators: HashMap<Address, ValidatorProduction>,
+    /// Blocks counted, in import order.
+    blocks: VecDeque<Produced>,
+    /// Highest block counted.
+    latest: u64,
+}
+
+impl ValidatorStats {
+    /// Records a block produced by the validator.
+    pub fn record(
+        &mut self,
+        validator: &Validator,
+        number: u64,
+        turn_status: Option<TurnStatus>,
+        transactions: usize,
+        gas_used: u64,
+    ) {
+        let out_of_turn = turn_status == Some(TurnStatus::OutOfTurn);
+        let production = self.validators.entry(validator.address).or_default();
+        production.address = validator.address;
+        production.name.clone_from(&validator.name);
+        production.blocks += 1;
+        if out_of_turn {
+            production.out_of_turn_blocks += 1;
+        }
+        production.transactions += transactions as u64;
+        production.gas_used += gas_used;
+        production.update_averages();
+        production.last_block = production.last_block.max(number);
+
+        let label = validator.label();
+        gauge!("bscpeer_validator_avg_transactions", "validator" => label.clone())
+            .set(production.avg_transactions);
+        gauge!("bscpeer_validator_avg_gas_used", "validator" => label.clone())
+            .set(production.avg_gas_used);
+        gauge!("bscpeer_validator_last_block", "validator" => label.clone()).set(number as f64);
+        counter!("bscpeer_validator_blocks_total", "validator" => label.clone()).increment(1);
+        if out_of_turn {
+            counter!("bscpeer_validator_out_of_turn_blocks_total", "validator" => label)
+                .increment(1);
+        }
+
+        self.blocks.push_back(Produced {
+            address: validator.address,
+            number,
+            out_of_turn,
+            transactions: transactions as u64,
+            gas_used,
+        });
+        self.latest = self.latest.max(number);
+        self.expire();
+    }
+
+    /// Drops the blocks that fell out of the window ending at the highest block.
+    fn expire(&mut self) {
+        let latest = self.latest;
+        while self.blocks.len() as u64 > WINDOW
+            || self.blocks.front().is_some_and(|block| block.number + WINDOW <= latest)
+        {
+            let Some(block) = self.blocks.pop_front() else { break };
+            let Some(production) = self.validators.get_mut(&block.address) else { continue };
+            production.blocks -= 1;
+            if block.out_of_turn {
+                production.out_of_turn_blocks -= 1;
+            }
+            production.transactions -= block.transactions;
+            production.gas_used -= block.gas_used;
+            if production.blocks > 0 {
+                production.update_averages();
+                continue;
+            }
+            // the validator stopped producing, its gauges are reset unless shared by the unknown
+            // validators
+            let Some(production) = self.validators.remove(&block.address) else { continue };
+            if let Some(label) = production.name {
+                gauge!("bscpeer_validator_avg_transactions", "validator" => label.clone()).set(0.0);
+                gauge!("bscpeer_validator_avg_gas_used", "validator" => label).set(0.0);
+            }
+        }
+    }
+
+    /// Returns the counters of all validators, most productive first.
+    pub fn validators(&self) -> Vec<ValidatorProduction> {
+        let mut validators: Vec<_> = self.validators.values().cloned().collect();
+        validators.sort_by(|a, b| b.blocks.cmp(&a.blocks).then(a.address.cmp(&b.address)));
+        validators
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validator_stats() {
+        let mut stats = ValidatorStats::default();
+        let a = Validator { address: Address::repeat_byte(1), name: Some("A".to_string()) };
+        let b = Validator { address: Address::repeat_byte(2), name: None };
+
+        stats.record(&a, 10, Some(TurnStatus::InTurn), 10, 1_000);
+        stats.record(&b, 11, Some(TurnStatus::OutOfTurn), 1, 100);
+        stats.record(&a, 12, None, 20, 3_000);
+
+        let validators = stats.validators();
+        assert_eq!(validators.len(), 2);
+        let first = &validators[0];
+        assert_eq!((first.address, first.name.as_deref()), (a.address, Some("A")));
+        assert_eq!((first.blocks, first.out_of_turn_blocks, first.last_block), (2, 0, 12));
+        assert_eq!((first.avg_transactions, first.avg_gas_used), (15.0, 2_000.0));
+        let second = &validators[1];
+        assert_eq!((second.blocks, second.out_of_turn_blocks), (1, 1));
+
+        // the first blocks fall out of the window
+        stats.record(&b, 10 + WINDOW, Some(TurnStatus::InTurn), 4, 400);
+        let validators = stats.validators();
+        assert_eq!(validators.len(), 2);
+        let first = &validators[0];
+        assert_eq!((first.address, first.blocks, first.out_of_turn_blocks), (b.address, 2, 1));
+        assert_eq!(first.avg_transactions, 2.5);
+        let second = &validators[1];
+        assert_eq!((second.blocks, second.last_block, second.avg_gas_used), (1, 12, 3_000.0));
+
+        stats.record(&b, 12 + WINDOW, Some(TurnStatus::InTurn), 0, 0);
+        let validators = stats.validators();
+        assert_eq!(validators.len(), 1);
+        assert_eq!((validators[0].address, validators[0].blocks), (b.address, 2));
+    }
+}
@@ -0,0 +1,191 @@
+//! Verification of received blocks on the blocking thread pool.
+//!
+//! Recovering the seal and the transaction senders, recomputing the transactions root and
+//! verifying the aggregated BLS signature of the vote attestation make up most of the cost of
+//! importing a block. They depend only on the block and the vote addresses of the validators, so
+//! they run off the network event loop, keeping its latency flat under load. Verifications
+//! complete in any order but are handed back in submission order, so blocks are still imported in
+//! the order they were received.
+use crate::parlia::{
+    Parlia,
+    extra_data::VoteAddress,
+    seal::SealError,
+    validation::{self, HeaderError},
+    vote::VoteAttestation,
+};
+use alloy_consensus::transaction::SignerRecoverable;
+use alloy_primitives::{Address, B256};
+use metrics::{counter, gauge, histogram};
+use reth_ethereum_primitives::{Block, TransactionSigned};
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, ready},
+    time::Instant,
+};
+use tokio::task::{self, JoinHandle};
+use tracing::{debug, error};
+
+/// Blocks verified at once at most, further blocks are dropped until the queue drains.
+pub const MAX_PENDING_VERIFICATIONS: usize = 256;
+
+/// Results of the checks of a block that don't need the consensus state.
+#[derive(Debug, Clone)]
+pub struct Verified {
+    /// Validator recovered from the seal.
+    pub proposer: Result<Address, SealError>,
+    /// Whether the transactions match the root in the header.
+    pub transactions_root: Result<(), HeaderError>,
+    /// Vote addresses the attestation's signature verified against, `None` if the header carries
+    /// no attestation, it didn't verify or no vote addresses were given.
+    pub verified_votes: Option<Vec<Option<VoteAddress>>>,
+    /// Senders of the transactions, in order, `None` where the signature is invalid.
+    pub senders: Vec<Option<Address>>,
+}
+
+impl Verified {
+    /// Runs the checks of the block with the given hash on the calling thread.
+    pub fn new(
+        parlia: &Parlia,
+        block: &Block,
+        hash: B256,
+        vote_addresses: Option<Vec<Option<VoteAddress>>>,
+    ) -> Self {
+        let header = &block.header;
+        let verified_votes = vote_addresses.filter(|vote_addresses| {
+            VoteAttestation::from_extra_data(&header.extra_data, parlia.layout(header))
+                .ok()
+                .flatten()
+                .is_some_and(|attestation| attestation.verify(vote_addresses).is_ok())
+        });
+        Self {
+            proposer: parlia.proposer(header, hash),
+            transactions_root: validation::validate_transactions_root(
+                header,
+                &block.body.transactions,
+            ),
+            verified_votes,
+            senders: recover_senders(&block.body.transactions),
+        }
+    }
+}
+
+/// Recovers the senders of the transactions, in order, `None` where the signature is invalid.
+pub fn recover_senders(transactions: &[TransactionSigned]) -> Vec<Option<Address>> {
+    transactions
+        .iter()
+        .map(|tx| match tx.recover_signer() {
+            Ok(sender) => Some(sender),
+            Err(e) => {
+                debug!(tx_hash = %tx.tx_hash(), "failed to recover sender: {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Blocks being verified, with the item and the hash each was submitted with.
+#[derive(Debug)]
+pub struct VerifyQueue<T> {
+    parlia: Parlia,
+    pending: VecDeque<(T, B256, JoinHandle<Verified>)>,
+}
+
+impl<T> VerifyQueue<T> {
+    pub fn new(parlia: Parlia) -> Self {
+        Self { parlia, pending: VecDeque::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Returns `true` if the block with the hash is being verified.
+    pub fn contains(&self, hash: &B256) -> bool {
+        self.pending.iter().any(|(_, pending, _)| pending == hash)
+    }
+
+    /// Starts verifying a block, checking its attestation against the given vote addresses.
+    ///
+    /// Returns `false` if the block is dropped, because it's already being verified or
+    /// [`MAX_PENDING_VERIFICATIONS`] blocks are.
+    pub fn submit(
+        &mut self,
+        item: T,
+        hash: B256,
+        block: Arc<reth_eth_wire::NewBlock>,
+        vote_addresses: Option<Vec<Option<VoteAddress>>>,
+    ) -> bool {
+        let dropped = if self.contains(&hash) {
+            Some("duplicate")
+        } else if self.pending.len() >= MAX_PENDING_VERIFICATIONS {
+            Some("full")
+        } else {
+            None
+        };
+        if let Some(reason) = dropped {
+            counter!("bscpeer_block_verifications_dropped_total", "reason" => reason).increment(1);
+            return false;
+        }
+        let parlia = self.parlia.clone();
+        let task = task::spawn_blocking(move || {
+            let start = Instant::now();
+            let verified = Verified::new(&parlia, &block.block, hash, vote_addresses);
+            histogram!("bscpeer_block_verification_seconds").record(start.elapsed().as_secs_f64());
+            verified
+        });
+        self.pending.push_back((item, hash, task));
+        gauge!("bscpeer_block_verifications_pending").set(self.pending.len() as f64);
+        true
+    }
+
+    /// Returns the oldest submitted block once verified, `None` if none is pending.
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<(T, Verified)>> {
+        loop {
+            let Some((_, _, task)) = self.pending.front_mut() else { return Poll::Ready(None) };
+            let result = ready!(Pin::new(task).poll(cx));
+            let Some((item, _, _)) = self.pending.pop_front() else { return Poll::Ready(None) };
+            gauge!("bscpeer_block_verifications_pending").set(self.pending.len() as f64);
+            match result {
+                Ok(verified) => return Poll::Ready(Some((item, verified))),
+                // the block is dropped, the next one may still be fine
+                Err(e) => error!("block verification failed: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain_config::bsc::bsc_mainnet;
+    use std::future::poll_fn;
+
+    #[tokio::test]
+    async fn test_submission_order() {
+        let mut queue = VerifyQueue::new(Parlia::new(Arc::new(bsc_mainnet())));
+        for number in 1..=3 {
+            assert!(queue.submit(number, B256::with_last_byte(number), Arc::default(), None));
+        }
+        // a block already being verified is dropped
+        assert!(!queue.submit(4, B256::with_last_byte(2), Arc::default(), None));
+        assert_eq!(queue.len(), 3);
+
+        let mut verified = Vec::new();
+        while let Some((number, result)) = poll_fn(|cx| queue.poll_next(cx)).await {
+            // an empty block carries no seal and no attestation
+            assert!(result.proposer.is_err());
+            assert!(result.transactions_root.is_ok());
+            assert!(result.verified_votes.is_none());
+            assert!(result.senders.is_empty());
+            verified.push(number);
+        }
+        assert_eq!(verified, [1, 2, 3]);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,282 @@
+//! Watchlist of addresses, persisted across restarts.
+//!
+//! Every transaction of an imported block sent from or to a watched address, and every decoded
+//! token transfer touching one, raises a [`BlockEvent::WatchlistHit`]. The list is edited through
+//! the admin API or by editing the file while the node is stopped.
+//!
+//! A block can be both received and fetched, so the hits of a block are raised only the first
+//! time it is checked.
+use crate::peer::{blockstate::BlockEvent, tokens::TokenTransfer, transactions::RecentHashes};
+use alloy_consensus::Transaction;
+use alloy_primitives::{Address, B256};
+use metrics::counter;
+use reth_ethereum_primitives::TransactionSigned;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf};
+
+/// Blocks remembered as checked, to raise their hits once.
+const CHECKED_BLOCKS: usize = 1024;
+
+/// Errors that can occur while loading or persisting the watchlist.
+#[derive(Debug, thiserror::Error)]
+pub enum WatchlistError {
+    /// Reading or writing the watchlist file failed.
+    #[error("watchlist io error: {0}")]
+    Io(#[from] std::io::Error),
+    /// The watchlist file is malformed.
+    #[error("invalid watchlist file: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// A single persisted address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchEntry {
+    pub address: Address,
+    /// Name the hits carry, e.g. the owner of the address.
+    pub label: Option<String>,
+}
+
+/// A watched address with its hits since startup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchedAddress {
+    pub address: Address,
+    pub label: Option<String>,
+    pub hits: u64,
+}
+
+/// How a hit touched the watched address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchRole {
+    Sender,
+    Recipient,
+    TokenSender,
+    TokenRecipient,
+}
+
+/// A transaction or token transfer touching a watched address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchHit {
+    pub address: Address,
+    pub label: Option<String>,
+    pub role: WatchRole,
+    /// Token of a transfer hit.
+    pub token: Option<Address>,
+    pub block_number: u64,
+    pub block_hash: B256,
+    pub tx_hash: B256,
+}
+
+/// Watchlist backed by a JSON file.
+#[derive(Debug)]
+pub struct Watchlist {
+    path: Option<PathBuf>,
+    /// Label and hits by address.
+    entries: HashMap<Address, (Option<String>, u64)>,
+    /// Blocks whose transactions were checked.
+    checked_transactions: RecentHashes,
+    /// Blocks whose token transfers were checked.
+    checked_transfers: RecentHashes,
+}
+
+impl Default for Watchlist {
+    fn default() -> Self {
+        Self {
+            path: None,
+            entries: HashMap::new(),
+            checked_transactions: RecentHashes::new(CHECKED_BLOCKS),
+            checked_transfers: RecentHashes::new(CHECKED_BLOCKS),
+        }
+    }
+}
+
+impl Watchlist {
+    /// Loads the watchlist from the given file, starting empty if it does not exist yet.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, WatchlistError> {
+        let path = path.into();
+        let mut entries = HashMap::new();
+        if path.exists() {
+            for entry in serde_json::from_slice::<Vec<WatchEntry>>(&std::fs::read(&path)?)? {
+                entries.insert(entry.address, (entry.label, 0));
+            }
+        }
+        Ok(Self { path: Some(path), entries, ..Self::default() })
+    }
+
+    /// Watches the address, or updates its label, and persists the list. Returns `false` if it
+    /// was already watched.
+    pub fn add(&mut self, address: Address, label: Option<String>) -> Result<bool, WatchlistError> {
+        let added = match self.entries.get_mut(&address) {
+            Some(entry) => {
+                entry.0 = label;
+                false
+            }
+            None => {
+                self.entries.insert(address, (label, 0));
+                true
+            }
+        };
+        self.save()?;
+        Ok(added)
+    }
+
+    /// Stops watching the address and persists the list. Returns `false` if it was not watched.
+    pub fn remove(&mut self, address: &Address) -> Result<bool, WatchlistError> {
+        if self.entries.remove(address).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the watched addresses, most hit first.
+    pub fn entries(&self) -> Vec<WatchedAddress> {
+        let mut entries: Vec<_> = self
+            .entries
+            .iter()
+            .map(|(address, (label, hits))| WatchedAddress {
+                address: *address,
+                label: label.clone(),
+                hits: *hits,
+            })
+            .collect();
+        entries.sort_by(|a, b| b.hits.cmp(&a.hits).then(a.address.cmp(&b.address)));
+        entries
+    }
+
+    /// Returns a hit for every block transaction sent from or to a watched address, given their
+    /// recovered senders. A block already checked returns no hits.
+    pub fn check_transactions(
+        &mut self,
+        block_number: u64,
+        block_hash: B256,
+        transactions: &[TransactionSigned],
+        senders: &[Option<Address>],
+    ) -> Vec<BlockEvent> {
+        if self.is_empty() || !self.checked_transactions.insert(block_hash) {
+            return Vec::new();
+        }
+        let mut hits = Vec::new();
+        for (tx, sender) in transactions.iter().zip(senders) {
+            let tx_hash = *tx.tx_hash();
+            let mut hit =
+                |address, role| self.hit(address, role, None, block_number, block_hash, tx_hash);
+            hits.extend(sender.and_then(|from| hit(from, WatchRole::Sender)));
+            hits.extend(tx.to().and_then(|to| hit(to, WatchRole::Recipient)));
+        }
+        hits
+    }
+
+    /// Returns a hit for every token transfer from or to a watched address. A block already
+    /// checked returns no hits.
+    pub fn check_token_transfers(
+        &mut self,
+        block_number: u64,
+        block_hash: B256,
+        transfers: &[TokenTransfer],
+    ) -> Vec<BlockEvent> {
+        if self.is_empty() || !self.checked_transfers.insert(block_hash) {
+            return Vec::new();
+        }
+        let mut hits = Vec::new();
+        for transfer in transfers {
+            let mut hit = |address, role| {
+                let token = Some(transfer.token);
+                self.hit(address, role, token, block_number, block_hash, transfer.tx_hash)
+            };
+            hits.extend(hit(transfer.from, WatchRole::TokenSender));
+            hits.extend(hit(transfer.to, WatchRole::TokenRecipient));
+        }
+        hits
+    }
+
+    fn hit(
+        &mut self,
+        address: Address,
+        role: WatchRole,
+        token: Option<Address>,
+        block_number: u64,
+        block_hash: B256,
+        tx_hash: B256,
+    ) -> Option<BlockEvent> {
+        let (label, hits) = self.entries.get_mut(&address)?;
+        *hits += 1;
+        counter!("bscpeer_watchlist_hits_total", "address" => address.to_string()).increment(1);
+        let hit = WatchHit {
+            address,
+            label: label.clone(),
+            role,
+            token,
+            block_number,
+            block_hash,
+            tx_hash,
+        };
+        Some(BlockEvent::WatchlistHit { hit })
+    }
+
+    fn save(&self) -> Result<(), WatchlistError> {
+        let Some(path) = &self.path else { return Ok(()) };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let entries: Vec<_> = self
+            .entries()
+            .into_iter()
+            .map(|entry| WatchEntry { address: entry.address, label: entry.label })
+            .collect();
+        std::fs::write(path, serde_json::to_vec_pretty(&entries)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::U256;
+
+    #[test]
+    fn test_watchlist() {
+        let path =
+            std::env::temp_dir().join(format!("bscpeer-watchlist-{}.json", std::process::id()));
+        let (watched, other) = (Address::repeat_byte(1), Address::repeat_byte(2));
+
+        let mut watchlist = Watchlist::load(&path).unwrap();
+        assert!(watchlist.check_transactions(1, B256::ZERO, &[], &[]).is_empty());
+        assert!(watchlist.add(watched, Some("treasury".to_string())).unwrap());
+        assert!(!watchlist.add(watched, Some("cold wallet".to_string())).unwrap());
+
+        let transfer = TokenTransfer {
+            token: Address::repeat_byte(3),
+            from: other,
+            to: watched,
+            amount: U256::from(1),
+            tx_hash: B256::ZERO,
+            log_index: 0,
+        };
+        let hits = watchlist.check_token_transfers(1, B256::ZERO, &[transfer.clone()]);
+        let [BlockEvent::WatchlistHit { hit }] = &hits[..] else { panic!("expected one hit") };
+        assert_eq!((hit.address, hit.role), (watched, WatchRole::TokenRecipient));
+        assert_eq!(hit.label.as_deref(), Some("cold wallet"));
+        assert_eq!(watchlist.entries()[0].hits, 1);
+
+        // a block received and fetched raises its hits once
+        assert!(watchlist.check_token_transfers(1, B256::ZERO, &[transfer]).is_empty());
+        assert_eq!(watchlist.entries()[0].hits, 1);
+
+        // the label persists, the hits don't
+        let mut watchlist = Watchlist::load(&path).unwrap();
+        let entries = watchlist.entries();
+        assert_eq!((entries[0].label.as_deref(), entries[0].hits), (Some("cold wallet"), 0));
+        assert!(watchlist.remove(&watched).unwrap());
+        assert!(!watchlist.remove(&watched).unwrap());
+        assert!(Watchlist::load(&path).unwrap().is_empty());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
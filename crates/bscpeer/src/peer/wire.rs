@@ -0,0 +1,219 @@
+//! Wire-level tracing of the eth messages the node handles itself.
+//!
+//! With the `trace-wire` feature and `--trace-wire`, every message the node sends, requests or
+//! receives is logged with the peer, the message id, its encoded size and a hex dump truncated to
+//! the configured length, for debugging protocol quirks of bsc-geth. Messages reth handles
+//! internally, e.g. requests served from the provider, aren't seen. Without the feature the
+//! [`trace_wire!`] call sites expand to nothing, so the hot paths don't pay for the check.
+//!
+//! With `--capture`, the same messages are also appended to a [`capture`](super::capture) file,
+//! which tests and tools can read back as fixtures. The file is written by a thread of its own,
+//! so the network tasks never wait on the disk; messages are dropped while it falls behind. The
+//! capture needs the feature as well, without it no file is created.
+//!
+//! The mode is process wide: enabled by any node's config.
+use crate::{config::TraceWireConfig, peer::capture::CaptureWriter};
+use metrics::counter;
+use reth_network_peers::PeerId;
+use std::{
+    fs::File,
+    io,
+    sync::{
+        OnceLock,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, SyncSender, TrySendError},
+    },
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{info, warn};
+
+/// Messages queued for the capture thread before new ones are dropped.
+const CAPTURE_QUEUE: usize = 4096;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static LOG: AtomicBool = AtomicBool::new(false);
+static MAX_PAYLOAD: AtomicUsize = AtomicUsize::new(0);
+static CAPTURE: OnceLock<SyncSender<Frame>> = OnceLock::new();
+
+/// Message queued for the capture thread.
+struct Frame {
+    timestamp_ms: u64,
+    peer_id: Option<PeerId>,
+    direction: Direction,
+    message_id: u8,
+    payload: Vec<u8>,
+}
+
+/// Direction of a traced message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+impl Direction {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Inbound => "in",
+            Self::Outbound => "out",
+        }
+    }
+}
+
+/// eth messages traced, with their ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message {
+    NewBlockHashes,
+    Transactions,
+    GetBlockHeaders,
+    BlockHeaders,
+    GetBlockBodies,
+    BlockBodies,
+    NewBlock,
+    GetPooledTransactions,
+    PooledTransactions,
+    UpgradeStatus,
+    GetReceipts,
+    Receipts,
+}
+
+impl Message {
+    pub const fn id(self) -> u8 {
+        match self {
+            Self::NewBlockHashes => 0x01,
+            Self::Transactions => 0x02,
+            Self::GetBlockHeaders => 0x03,
+            Self::BlockHeaders => 0x04,
+            Self::GetBlockBodies => 0x05,
+            Self::BlockBodies => 0x06,
+            Self::NewBlock => 0x07,
+            Self::GetPooledTransactions => 0x09,
+            Self::PooledTransactions => 0x0a,
+            Self::UpgradeStatus => 0x0b,
+            Self::GetReceipts => 0x0f,
+            Self::Receipts => 0x10,
+        }
+    }
+}
+
+/// Enables the logging and the capture the config asks for. Fails if the capture file can't be
+/// created.
+pub fn configure(config: &TraceWireConfig) -> io::Result<()> {
+    if config.enabled {
+        MAX_PAYLOAD.fetch_max(config.max_payload, Ordering::Relaxed);
+        LOG.store(true, Ordering::Relaxed);
+        ENABLED.store(true, Ordering::Relaxed);
+    }
+    if let Some(path) = &config.capture {
+        if !cfg!(feature = "trace-wire") {
+            warn!(path = %path.display(), "wire capture needs the trace-wire feature, skipped");
+            return Ok(());
+        }
+        if CAPTURE.get().is_none() {
+            let mut writer = CaptureWriter::new(File::create(path)?)?;
+            let (frames, receiver) = mpsc::sync_channel::<Frame>(CAPTURE_QUEUE);
+            thread::Builder::new().name("wire-capture".to_string()).spawn(move || {
+                for frame in receiver {
+                    let Frame { timestamp_ms, peer_id, direction, message_id, payload } = frame;
+                    if let Err(e) =
+                        writer.write(timestamp_ms, peer_id, direction, message_id, &payload)
+                    {
+                        warn!("failed to capture wire message: {}", e);
+                    }
+                }
+            })?;
+            // another node may have won the race, its thread writes the capture
+            if CAPTURE.set(frames).is_ok() {
+                info!(path = %path.display(), "capturing wire messages");
+            }
+        }
+        ENABLED.store(true, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Logs and captures an encoded message. `peer_id` is unknown during the handshake.
+pub fn trace_bytes(
+    peer_id: Option<PeerId>,
+    direction: Direction,
+    message: Message,
+    payload: &[u8],
+) {
+    if LOG.load(Ordering::Relaxed) {
+        log(peer_id, direction, message, payload);
+    }
+    if let Some(capture) = CAPTURE.get() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let frame = Frame {
+            timestamp_ms: now.as_millis() as u64,
+            peer_id,
+            direction,
+            message_id: message.id(),
+            payload: payload.to_vec(),
+        };
+        if let Err(TrySendError::Full(_)) = capture.try_send(frame) {
+            counter!("bscpeer_wire_capture_dropped_total").increment(1);
+        }
+    }
+}
+
+fn log(peer_id: Option<PeerId>, direction: Direction, message: Message, payload: &[u8]) {
+    let max_payload = MAX_PAYLOAD.load(Ordering::Relaxed);
+    let hex = alloy_primitives::hex::encode(&payload[..payload.len().min(max_payload)]);
+    let truncated = payload.len() > max_payload;
+    info!(
+        target: "bscpeer::wire",
+        peer_id = %peer_id.map_or_else(|| "-".to_string(), |peer_id| peer_id.to_string()),
+        direction = direction.as_str(),
+        id = format_args!("{:#04x}", message.id()),
+        message = ?message,
+        size = payload.len(),
+        truncated,
+        payload = %hex,
+    );
+}
+
+/// Logs a decoded message, encoding it again.
+pub fn trace(
+    peer_id: Option<PeerId>,
+    direction: Direction,
+    message: Message,
+    msg: &impl alloy_rlp::Encodable,
+) {
+    trace_bytes(peer_id, direction, message, &alloy_rlp::encode(msg));
+}
+
+/// Traces a message if the mode is enabled, e.g.
+/// `trace_wire!(Some(peer_id), Inbound, NewBlock, &msg)`, or
+/// `trace_wire!(None, Outbound, UpgradeStatus, bytes = &payload)` for encoded ones. Expands to
+/// nothing without the `trace-wire` feature.
+macro_rules! trace_wire {
+    ($peer_id:expr, $direction:ident, $message:ident, bytes = $payload:expr) => {
+        #[cfg(feature = "trace-wire")]
+        if $crate::peer::wire::enabled() {
+            $crate::peer::wire::trace_bytes(
+                $peer_id,
+                $crate::peer::wire::Direction::$direction,
+                $crate::peer::wire::Message::$message,
+                $payload,
+            );
+        }
+    };
+    ($peer_id:expr, $direction:ident, $message:ident, $msg:expr) => {
+        #[cfg(feature = "trace-wire")]
+        if $crate::peer::wire::enabled() {
+            $crate::peer::wire::trace(
+                $peer_id,
+                $crate::peer::wire::Direction::$direction,
+                $crate::peer::wire::Message::$message,
+                $msg,
+            );
+        }
+    };
+}
+pub(crate) use trace_wire;
@@ -0,0 +1,167 @@
+//! Reloading the config of a running node.
+//!
+//! A node built with a [`ConfigSource`] rereads its config on SIGHUP or `admin_reloadConfig` and
+//! applies the settings that can change without dropping sessions: the log filter, shared by the
+//! nodes of the process, the client and transaction filters and the sinks consuming the event
+//! stream. New filters apply to the sessions established from then on. Changes to other
+//! settings are reported and take effect on the next restart.
+//!
+//! The peer limits in `peers` need a restart: reth fixes them when the network starts and offers
+//! no way to change them while it runs.
+use crate::{
+    config::{Config, ConfigError},
+    sink::SinkError,
+};
+use serde::Serialize;
+use std::{collections::BTreeSet, sync::Arc};
+use tokio::sync::{mpsc, oneshot};
+
+/// Rereads the config of a node, with the same overrides applied as at startup.
+pub type ConfigSource = Arc<dyn Fn() -> Result<Config, ConfigError> + Send + Sync>;
+
+/// Replaces the directives of the process-wide log filter.
+pub type LogFilterReloader = Arc<dyn Fn(&str) -> Result<(), String> + Send + Sync>;
+
+/// Errors reloading the config.
+#[derive(Debug, thiserror::Error)]
+pub enum ReloadError {
+    #[error("node was built without a config source")]
+    NoSource,
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error("invalid log filter: {0}")]
+    LogFilter(String),
+    #[error(transparent)]
+    Sink(#[from] SinkError),
+    #[error("failed to compare configs: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("node stopped")]
+    Stopped,
+}
+
+/// Changed settings of a reload, as dotted paths such as `peers.max_inbound`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReloadReport {
+    /// Settings applied to the running node.
+    pub applied: Vec<String>,
+    /// Settings that take effect on the next restart.
+    pub restart_required: Vec<String>,
+}
+
+/// Copies the settings that can change at runtime from `new` into `config`.
+pub fn apply_reloadable(config: &mut Config, new: &Config) {
+    config.log = new.log.clone();
+    config.client_filter = new.client_filter.clone();
+    config.transactions.filter = new.transactions.filter.clone();
+    config.sinks.ndjson = new.sinks.ndjson.clone();
+    config.sinks.nats = new.sinks.nats.clone();
+    config.sinks.redis = new.sinks.redis.clone();
+}
+
+/// Returns the running config with the reloadable settings of `new` applied, and which settings
+/// changed.
+pub fn plan(config: &Config, new: &Config) -> Result<(Config, ReloadReport), ReloadError> {
+    let mut reloaded = config.clone();
+    apply_reloadable(&mut reloaded, new);
+    let report = ReloadReport {
+        applied: changed_settings(config, &reloaded)?,
+        restart_required: changed_settings(&reloaded, new)?,
+    };
+    Ok((reloaded, report))
+}
+
+/// Returns the settings that differ, down to the fields of the top-level sections.
+fn changed_settings(a: &Config, b: &Config) -> Result<Vec<String>, ReloadError> {
+    let (a, b) = (table(a)?, table(b)?);
+    let mut changed = Vec::new();
+    for key in a.keys().chain(b.keys()).collect::<BTreeSet<_>>() {
+        match (a.get(key), b.get(key)) {
+            (Some(toml::Value::Table(a)), Some(toml::Value::Table(b))) => {
+                let fields = a.keys().chain(b.keys()).collect::<BTreeSet<_>>();
+                changed.extend(
+                    fields
+                        .into_iter()
+                        .filter(|field| a.get(*field) != b.get(*field))
+                        .map(|field| format!("{key}.{field}")),
+                );
+            }
+            (a, b) if a != b => changed.push(key.clone()),
+            _ => {}
+        }
+    }
+    Ok(changed)
+}
+
+fn table(config: &Config) -> Result<toml::Table, ReloadError> {
+    Ok(toml::Table::try_from(config)?)
+}
+
+/// Request of the node to reload its config, answered with the outcome.
+pub(crate) type ReloadRequest = oneshot::Sender<Result<ReloadReport, ReloadError>>;
+
+/// Handle triggering config reloads of a running node.
+#[derive(Debug, Clone)]
+pub struct ConfigReloader {
+    requests: mpsc::UnboundedSender<ReloadRequest>,
+}
+
+impl ConfigReloader {
+    pub(crate) fn new() -> (Self, mpsc::UnboundedReceiver<ReloadRequest>) {
+        let (requests, receiver) = mpsc::unbounded_channel();
+        (Self { requests }, receiver)
+    }
+
+    /// Rereads the config and applies it to the node.
+    pub async fn reload(&self) -> Result<ReloadReport, ReloadError> {
+        let (response, rx) = oneshot::channel();
+        self.requests.send(response).map_err(|_| ReloadError::Stopped)?;
+        rx.await.map_err(|_| ReloadError::Stopped)?
+    }
+}
+
+/// Spawns the task reloading the config on every SIGHUP. The outcome is logged by the node.
+#[cfg(unix)]
+pub fn spawn_sighup(reloader: ConfigReloader) -> tokio::task::JoinHandle<()> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    crate::instance::spawn(async move {
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(e) => {
+                tracing::warn!("failed to listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            tracing::info!("SIGHUP received, reloading config");
+            let _ = reloader.reload().await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NdjsonSinkConfig;
+
+    #[test]
+    fn test_plan() {
+        let config = Config::default();
+        let mut new = config.clone();
+        new.log.filter = "debug".to_string();
+        new.peers.max_inbound += 10;
+        new.client_filter.deny = vec!["erigon".to_string()];
+        new.sinks.ndjson = Some(NdjsonSinkConfig::default());
+        new.p2p.port += 1;
+        new.rpc.enabled = !new.rpc.enabled;
+
+        let (reloaded, report) = plan(&config, &new).unwrap();
+        assert_eq!(report.applied, ["client_filter.deny", "log.filter", "sinks.ndjson"]);
+        assert_eq!(report.restart_required, ["p2p.port", "peers.max_inbound", "rpc.enabled"]);
+        assert_eq!(reloaded.peers, config.peers);
+        assert_eq!(reloaded.p2p, config.p2p);
+
+        assert_eq!(plan(&config, &config).unwrap().1, ReloadReport::default());
+    }
+}
@@ -0,0 +1,310 @@
+//! `admin_` namespace for managing the node at runtime.
+use super::{internal_error, invalid_params};
+use crate::{
+    instance,
+    peer::{
+        banlist::{BanEntry, BanList, BanTarget},
+        blockstate::{BlockStateManager, FinalityHeads},
+        disconnects::{DisconnectReport, DisconnectStats},
+        duplicates::{DuplicateTracker, PeerDuplication},
+        geo::{GeoDistribution, PeerGeoTracker},
+        latency::{LatencyTracker, PeerLatency},
+        peer_heads::{PeerHead, PeerHeads},
+        validator_stats::{ValidatorProduction, ValidatorStats},
+        watchlist::{WatchedAddress, Watchlist},
+    },
+    reload::{ConfigReloader, ReloadReport},
+};
+use alloy_primitives::Address;
+use jsonrpsee::{
+    core::{RpcResult, async_trait},
+    proc_macros::rpc,
+};
+use reth_eth_wire_types::DisconnectReason;
+use reth_network::{EthNetworkPrimitives, NetworkHandle};
+use reth_network_api::{Peers, ReputationChangeKind};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::info;
+
+/// Entries in the top lists of a disconnect report, unless requested otherwise.
+const DEFAULT_REPORT_LIMIT: usize = 10;
+
+/// Admin API.
+#[rpc(server, namespace = "admin")]
+pub trait AdminApi {
+    /// Bans a peer id or IP address, permanently or for the given number of seconds.
+    ///
+    /// Banned peer ids are also banned by reth, which then neither dials nor accepts them until
+    /// the ban is lifted or expires.
+    #[method(name = "banPeer")]
+    async fn ban_peer(&self, target: String, duration_secs: Option<u64>) -> RpcResult<bool>;
+
+    /// Lifts a ban on a peer id or IP address. Returns `false` if it was not banned.
+    #[method(name = "unbanPeer")]
+    async fn unban_peer(&self, target: String) -> RpcResult<bool>;
+
+    /// Returns all active bans.
+    #[method(name = "bannedPeers")]
+    fn banned_peers(&self) -> RpcResult<Vec<BanEntry>>;
+
+    /// Returns the distribution of connected peers across countries and autonomous systems.
+    #[method(name = "peerGeography")]
+    fn peer_geography(&self) -> RpcResult<GeoDistribution>;
+
+    /// Returns the round-trip latency of the connected peers, fastest first.
+    #[method(name = "peerLatencies")]
+    fn peer_latencies(&self) -> RpcResult<Vec<PeerLatency>>;
+
+    /// Returns how many of each peer's recent block announcements were duplicates, most
+    /// duplicating first.
+    #[method(name = "announcementDuplicates")]
+    fn announcement_duplicates(&self) -> RpcResult<Vec<PeerDuplication>>;
+
+    /// Returns the best block each connected peer is believed to have, highest first.
+    #[method(name = "peerHeads")]
+    fn peer_heads(&self) -> RpcResult<Vec<PeerHead>>;
+
+    /// Returns the session disconnects since startup by category, with the most frequent
+    /// reasons and the clients most often disconnected for misbehaviour.
+    #[method(name = "disconnectReport")]
+    fn disconnect_report(&self, limit: Option<usize>) -> RpcResult<DisconnectReport>;
+
+    /// Returns the blocks produced by each validator over the latest blocks, most productive
+    /// first.
+    #[method(name = "validatorStats")]
+    fn validator_stats(&self) -> RpcResult<Vec<ValidatorProduction>>;
+
+    /// Watches an address, or updates its label. Returns `false` if it was already watched.
+    #[method(name = "watchAddress")]
+    async fn watch_address(&self, address: Address, label: Option<String>) -> RpcResult<bool>;
+
+    /// Stops watching an address. Returns `false` if it was not watched.
+    #[method(name = "unwatchAddress")]
+    async fn unwatch_address(&self, address: Address) -> RpcResult<bool>;
+
+    /// Returns the watched addresses with their hits since startup, most hit first.
+    #[method(name = "watchlist")]
+    fn watchlist(&self) -> RpcResult<Vec<WatchedAddress>>;
+
+    /// Returns the latest justified and finalized blocks.
+    #[method(name = "finalityHeads")]
+    fn finality_heads(&self) -> RpcResult<FinalityHeads>;
+
+    /// Rereads the config and applies the settings that can change at runtime, returning which
+    /// settings were applied and which require a restart.
+    #[method(name = "reloadConfig")]
+    async fn reload_config(&self) -> RpcResult<ReloadReport>;
+}
+
+/// Implementation of [`AdminApiServer`].
+#[derive(Debug, Clone)]
+pub struct AdminRpc {
+    network: NetworkHandle<EthNetworkPrimitives>,
+    ban_list: Arc<Mutex<BanList>>,
+    geo: Arc<Mutex<PeerGeoTracker>>,
+    latency: Arc<Mutex<LatencyTracker>>,
+    duplicates: Arc<Mutex<DuplicateTracker>>,
+    heads: Arc<Mutex<PeerHeads>>,
+    disconnects: Arc<Mutex<DisconnectStats>>,
+    validators: Arc<Mutex<ValidatorStats>>,
+    watchlist: Arc<Mutex<Watchlist>>,
+    reloader: Option<ConfigReloader>,
+    state: BlockStateManager,
+}
+
+impl AdminRpc {
+    /// Creates the API, with empty peer trackers until the node's are shared with it.
+    pub fn new(
+        network: NetworkHandle<EthNetworkPrimitives>,
+        ban_list: Arc<Mutex<BanList>>,
+        state: BlockStateManager,
+    ) -> Self {
+        Self {
+            network,
+            ban_list,
+            geo: Arc::default(),
+            latency: Arc::default(),
+            duplicates: Arc::default(),
+            heads: Arc::default(),
+            disconnects: Arc::default(),
+            validators: Arc::default(),
+            watchlist: Arc::default(),
+            reloader: None,
+            state,
+        }
+    }
+
+    pub fn with_geo(mut self, geo: Arc<Mutex<PeerGeoTracker>>) -> Self {
+        self.geo = geo;
+        self
+    }
+
+    pub fn with_latency(mut self, latency: Arc<Mutex<LatencyTracker>>) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    pub fn with_duplicates(mut self, duplicates: Arc<Mutex<DuplicateTracker>>) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
+
+    pub fn with_peer_heads(mut self, heads: Arc<Mutex<PeerHeads>>) -> Self {
+        self.heads = heads;
+        self
+    }
+
+    pub fn with_disconnects(mut self, disconnects: Arc<Mutex<DisconnectStats>>) -> Self {
+        self.disconnects = disconnects;
+        self
+    }
+
+    pub fn with_validator_stats(mut self, validators: Arc<Mutex<ValidatorStats>>) -> Self {
+        self.validators = validators;
+        self
+    }
+
+    pub fn with_watchlist(mut self, watchlist: Arc<Mutex<Watchlist>>) -> Self {
+        self.watchlist = watchlist;
+        self
+    }
+
+    pub fn with_reloader(mut self, reloader: ConfigReloader) -> Self {
+        self.reloader = Some(reloader);
+        self
+    }
+}
+
+#[async_trait]
+impl AdminApiServer for AdminRpc {
+    async fn ban_peer(&self, target: String, duration_secs: Option<u64>) -> RpcResult<bool> {
+        let target = target.parse::<BanTarget>().map_err(invalid_params)?;
+        // the list is persisted on every change, off the async workers
+        let ban_list = self.ban_list.clone();
+        let duration = duration_secs.map(Duration::from_secs);
+        tokio::task::spawn_blocking(move || ban_list.lock().unwrap().ban(target, duration))
+            .await
+            .map_err(|e| internal_error(e.to_string()))?
+            .map_err(|e| internal_error(e.to_string()))?;
+        info!(%target, ?duration_secs, "banned via admin api");
+
+        // a protocol breach bans the peer in reth, which drops it from the peers it dials
+        if let BanTarget::Peer(peer_id) = target {
+            self.network.reputation_change(peer_id, ReputationChangeKind::BadProtocol);
+            // reth bans for its own duration, the reputation is reset once this ban expires
+            if let Some(duration) = duration {
+                let (network, ban_list) = (self.network.clone(), self.ban_list.clone());
+                instance::spawn(async move {
+                    tokio::time::sleep(duration + Duration::from_secs(1)).await;
+                    if !ban_list.lock().unwrap().is_banned(&target) {
+                        network.reputation_change(peer_id, ReputationChangeKind::Reset);
+                    }
+                });
+            }
+        }
+        // drop any live sessions with the banned peer, inbound connections from a banned IP are
+        // dropped once established
+        let peers = self.network.get_all_peers().await.map_err(|e| internal_error(e.to_string()))?;
+        for peer in peers {
+            let banned = match target {
+                BanTarget::Peer(peer_id) => peer.remote_id == peer_id,
+                BanTarget::Ip(ip) => peer.remote_addr.ip() == ip,
+            };
+            if banned {
+                self.network.reputation_change(peer.remote_id, ReputationChangeKind::BadProtocol);
+                self.network
+                    .disconnect_peer_with_reason(peer.remote_id, DisconnectReason::UselessPeer);
+            }
+        }
+        Ok(true)
+    }
+
+    async fn unban_peer(&self, target: String) -> RpcResult<bool> {
+        let target = target.parse::<BanTarget>().map_err(invalid_params)?;
+        let ban_list = self.ban_list.clone();
+        let unbanned = tokio::task::spawn_blocking(move || ban_list.lock().unwrap().unban(&target))
+            .await
+            .map_err(|e| internal_error(e.to_string()))?
+            .map_err(|e| internal_error(e.to_string()))?;
+        if unbanned {
+            // resetting the reputation lifts the ban in reth
+            if let BanTarget::Peer(peer_id) = target {
+                self.network.reputation_change(peer_id, ReputationChangeKind::Reset);
+            }
+            info!(%target, "unbanned via admin api");
+        }
+        Ok(unbanned)
+    }
+
+    fn banned_peers(&self) -> RpcResult<Vec<BanEntry>> {
+        Ok(self.ban_list.lock().unwrap().entries())
+    }
+
+    fn peer_geography(&self) -> RpcResult<GeoDistribution> {
+        Ok(self.geo.lock().unwrap().distribution())
+    }
+
+    fn peer_latencies(&self) -> RpcResult<Vec<PeerLatency>> {
+        Ok(self.latency.lock().unwrap().latencies())
+    }
+
+    fn announcement_duplicates(&self) -> RpcResult<Vec<PeerDuplication>> {
+        Ok(self.duplicates.lock().unwrap().peers())
+    }
+
+    fn peer_heads(&self) -> RpcResult<Vec<PeerHead>> {
+        Ok(self.heads.lock().unwrap().heads())
+    }
+
+    fn disconnect_report(&self, limit: Option<usize>) -> RpcResult<DisconnectReport> {
+        Ok(self.disconnects.lock().unwrap().report(limit.unwrap_or(DEFAULT_REPORT_LIMIT)))
+    }
+
+    fn validator_stats(&self) -> RpcResult<Vec<ValidatorProduction>> {
+        Ok(self.validators.lock().unwrap().validators())
+    }
+
+    async fn watch_address(&self, address: Address, label: Option<String>) -> RpcResult<bool> {
+        // the list is persisted on every change, off the async workers
+        let (watchlist, new_label) = (self.watchlist.clone(), label.clone());
+        let added =
+            tokio::task::spawn_blocking(move || watchlist.lock().unwrap().add(address, new_label))
+                .await
+                .map_err(|e| internal_error(e.to_string()))?
+                .map_err(|e| internal_error(e.to_string()))?;
+        info!(%address, ?label, "watched via admin api");
+        Ok(added)
+    }
+
+    async fn unwatch_address(&self, address: Address) -> RpcResult<bool> {
+        let watchlist = self.watchlist.clone();
+        let removed =
+            tokio::task::spawn_blocking(move || watchlist.lock().unwrap().remove(&address))
+                .await
+                .map_err(|e| internal_error(e.to_string()))?
+                .map_err(|e| internal_error(e.to_string()))?;
+        if removed {
+            info!(%address, "unwatched via admin api");
+        }
+        Ok(removed)
+    }
+
+    fn watchlist(&self) -> RpcResult<Vec<WatchedAddress>> {
+        Ok(self.watchlist.lock().unwrap().entries())
+    }
+
+    fn finality_heads(&self) -> RpcResult<FinalityHeads> {
+        Ok(self.state.finality())
+    }
+
+    async fn reload_config(&self) -> RpcResult<ReloadReport> {
+        let reloader =
+            self.reloader.as_ref().ok_or_else(|| internal_error("reload unavailable"))?;
+        let report = reloader.reload().await.map_err(|e| internal_error(e.to_string()))?;
+        info!(applied = ?report.applied, "config reloaded via admin api");
+        Ok(report)
+    }
+}
@@ -0,0 +1,43 @@
+//! `bsc_` namespace for submitting transactions through the connected peers.
+use super::{internal_error, invalid_params};
+use crate::peer::transactions::{SendTransactionError, TransactionSender};
+use alloy_primitives::{B256, Bytes};
+use jsonrpsee::{
+    core::{RpcResult, async_trait},
+    proc_macros::rpc,
+};
+
+/// Transaction submission API.
+#[rpc(server, namespace = "bsc")]
+pub trait BscApi {
+    /// Sends a signed, EIP-2718 encoded transaction to all connected peers and returns its hash.
+    #[method(name = "sendRawTransaction")]
+    async fn send_raw_transaction(&self, raw: Bytes) -> RpcResult<B256>;
+}
+
+/// Implementation of [`BscApiServer`].
+#[derive(Debug, Clone)]
+pub struct BscRpc {
+    sender: TransactionSender,
+}
+
+impl BscRpc {
+    pub fn new(sender: TransactionSender) -> Self {
+        Self { sender }
+    }
+}
+
+#[async_trait]
+impl BscApiServer for BscRpc {
+    async fn send_raw_transaction(&self, raw: Bytes) -> RpcResult<B256> {
+        match self.sender.send_raw_transaction(&raw).await {
+            Ok((hash, _)) => Ok(hash),
+            Err(
+                e @ (SendTransactionError::Decode(_)
+                | SendTransactionError::InvalidSignature
+                | SendTransactionError::WrongChain { .. }),
+            ) => Err(invalid_params(e.to_string())),
+            Err(e) => Err(internal_error(e.to_string())),
+        }
+    }
+}
@@ -0,0 +1,27 @@
+//! JSON-RPC server exposing the admin API.
+pub mod admin;
+pub mod bsc;
+pub mod parlia;
+
+use jsonrpsee::{
+    server::Server,
+    types::{
+        ErrorObjectOwned,
+        error::{INTERNAL_ERROR_CODE, INVALID_PARAMS_CODE},
+    },
+};
+use std::net::SocketAddr;
+
+/// Binds the JSON-RPC server to the given address, to be started with the methods once the node
+/// is wired up.
+pub async fn bind_server(addr: SocketAddr) -> std::io::Result<Server> {
+    Server::builder().build(addr).await
+}
+
+fn invalid_params(msg: impl Into<String>) -> ErrorObjectOwned {
+    ErrorObjectOwned::owned(INVALID_PARAMS_CODE, msg.into(), None::<()>)
+}
+
+fn internal_error(msg: impl Into<String>) -> ErrorObjectOwned {
+    ErrorObjectOwned::owned(INTERNAL_ERROR_CODE, msg.into(), None::<()>)
+}
@@ -0,0 +1,71 @@
+//! `parlia_` namespace exposing consensus state tracked from the network.
+use crate::{
+    parlia::{extra_data::VoteAddress, snapshot::Snapshot},
+    peer::blockstate::BlockStateManager,
+};
+use alloy_primitives::Address;
+use jsonrpsee::{core::RpcResult, proc_macros::rpc};
+use serde::Serialize;
+
+/// Active validator set of the current snapshot.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EpochValidators {
+    /// Number of the last header applied to the snapshot.
+    pub block_number: u64,
+    /// Active validators, sorted ascending.
+    pub validators: Vec<Address>,
+    /// BLS vote addresses of the active validators, in the order of `validators`.
+    pub vote_addresses: Vec<VoteAddress>,
+    /// Number of consecutive blocks each validator produces.
+    pub turn_length: u8,
+}
+
+impl From<&Snapshot> for EpochValidators {
+    fn from(snapshot: &Snapshot) -> Self {
+        Self {
+            block_number: snapshot.number,
+            validators: snapshot.validators.clone(),
+            vote_addresses: snapshot
+                .validators
+                .iter()
+                .filter_map(|validator| snapshot.vote_addresses.get(validator).copied())
+                .collect(),
+            turn_length: snapshot.turn_length,
+        }
+    }
+}
+
+/// Parlia API.
+#[rpc(server, namespace = "parlia")]
+pub trait ParliaApi {
+    /// Returns the currently active validator set.
+    #[method(name = "getValidators")]
+    fn get_validators(&self) -> RpcResult<Option<EpochValidators>>;
+
+    /// Returns the current Parlia snapshot.
+    #[method(name = "getSnapshot")]
+    fn get_snapshot(&self) -> RpcResult<Option<Snapshot>>;
+}
+
+/// Implementation of [`ParliaApiServer`].
+#[derive(Debug, Clone)]
+pub struct ParliaRpc {
+    state: BlockStateManager,
+}
+
+impl ParliaRpc {
+    pub fn new(state: BlockStateManager) -> Self {
+        Self { state }
+    }
+}
+
+impl ParliaApiServer for ParliaRpc {
+    fn get_validators(&self) -> RpcResult<Option<EpochValidators>> {
+        Ok(self.state.current_snapshot().as_ref().map(EpochValidators::from))
+    }
+
+    fn get_snapshot(&self) -> RpcResult<Option<Snapshot>> {
+        Ok(self.state.current_snapshot())
+    }
+}
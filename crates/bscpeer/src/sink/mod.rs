@@ -0,0 +1,307 @@
+//! Output integrations writing the followed chain to external systems.
+//!
+//! Sinks are optional. Those with heavy dependencies are enabled by a cargo feature of the same
+//! name.
+//!
+//! Sinks implement [`BlockSink`] and run on their own task, fed by their own subscriptions to the
+//! event bus, so a slow sink only drops its own events. Sinks needing the full blocks, like the
+//! parquet and postgres sinks, are also handed every imported block.
+use crate::{
+    config::SinksConfig,
+    instance,
+    peer::{
+        blockstate::{BlockEvent, BlockImportHook},
+        events::{EventEnvelope, EventSender, OverflowPolicy, ReceiveTime},
+    },
+};
+use async_trait::async_trait;
+use metrics::counter;
+use queue::{BLOCK_QUEUE_CAPACITY, Queue};
+use reth_ethereum_primitives::Block;
+use reth_network_peers::PeerId;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time::{interval, timeout},
+};
+use tracing::warn;
+
+#[cfg(feature = "nats")]
+pub mod nats;
+pub mod ndjson;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+mod queue;
+#[cfg(feature = "redis")]
+pub mod redis;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+
+/// Interval between two flushes of a running sink.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time a stopping sink is given to write out what it queued.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Errors starting or running a sink.
+#[derive(Debug, thiserror::Error)]
+pub enum SinkError {
+    #[error("failed to open ndjson output: {0}")]
+    Ndjson(#[from] std::io::Error),
+    #[error("failed to serialize event: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[cfg(feature = "nats")]
+    #[error("failed to configure nats client: {0}")]
+    Nats(#[from] async_nats::ConnectError),
+    #[cfg(feature = "nats")]
+    #[error("invalid nats subject template {0:?}")]
+    NatsSubject(String),
+    #[cfg(feature = "redis")]
+    #[error("invalid redis url: {0}")]
+    Redis(#[from] ::redis::RedisError),
+    /// Error of a sink registered by the embedding application.
+    #[error("{0}")]
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Consumer of the block events emitted by the node.
+///
+/// Errors are logged and counted, the sink keeps receiving the following events.
+#[async_trait]
+pub trait BlockSink: Send + 'static {
+    /// Name of the sink in logs and metrics.
+    fn name(&self) -> &str;
+
+    /// What happens to the events while the sink falls behind.
+    fn overflow_policy(&self) -> OverflowPolicy {
+        OverflowPolicy::CoalesceHashes
+    }
+
+    /// Handles an event, in the order they were emitted.
+    async fn handle_event(&mut self, event: &EventEnvelope) -> Result<(), SinkError>;
+
+    /// Whether the sink is handed the full imported blocks, see [`BlockSink::handle_block`].
+    fn receives_blocks(&self) -> bool {
+        false
+    }
+
+    /// Handles a block that passed validation, sent by the peer at the given time, if the sink
+    /// receives blocks.
+    async fn handle_block(
+        &mut self,
+        _peer_id: PeerId,
+        _block: Block,
+        _received: ReceiveTime,
+    ) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    /// Writes out what the sink buffered, called periodically and before the shutdown.
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    /// Releases the resources of the sink once it stops receiving events.
+    async fn shutdown(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+/// Handle to the task feeding a sink. Dropping it stops the sink once the queued events are
+/// handled, [`SinkTask::stop`] also waits for it.
+#[derive(Debug)]
+pub(crate) struct SinkTask {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+    /// Queue of the imported blocks, if the sink receives them.
+    blocks: Option<Queue<(PeerId, Block, ReceiveTime)>>,
+}
+
+impl SinkTask {
+    /// Returns a block import hook handing the imported blocks to the sink, if it receives them.
+    pub(crate) fn hook(&self) -> Option<BlockImportHook> {
+        let blocks = self.blocks.clone()?;
+        Some(Arc::new(move |peer_id, new_block: &reth_eth_wire::NewBlock| {
+            blocks.push((peer_id, new_block.block.clone(), ReceiveTime::now()))
+        }))
+    }
+
+    /// Stops the sink and waits until it wrote out what it queued and shut down.
+    pub(crate) async fn stop(self) {
+        drop(self.stop);
+        if let Err(e) = self.task.await {
+            warn!("sink task failed: {}", e);
+        }
+    }
+}
+
+/// Starts feeding a sink the events of its own subscriptions, queueing up to `capacity` block
+/// events and as many pending transaction events.
+pub(crate) fn spawn(events: &EventSender, sink: Box<dyn BlockSink>, capacity: usize) -> SinkTask {
+    let receiver = events.subscribe(sink.name(), capacity, sink.overflow_policy());
+    let transactions = events.subscribe_transactions(sink.name(), capacity);
+    let (blocks, block_receiver) = Queue::new(sink.name(), BLOCK_QUEUE_CAPACITY);
+    let blocks = sink.receives_blocks().then_some(blocks);
+    let (stop, stopped) = oneshot::channel();
+    let task = instance::spawn(run(sink, receiver, transactions, block_receiver, stopped));
+    SinkTask { stop, task, blocks }
+}
+
+async fn run(
+    mut sink: Box<dyn BlockSink>,
+    mut events: mpsc::Receiver<EventEnvelope>,
+    mut transactions: mpsc::Receiver<EventEnvelope>,
+    mut blocks: queue::Receiver<(PeerId, Block, ReceiveTime)>,
+    mut stopped: oneshot::Receiver<()>,
+) {
+    let name = sink.name().to_string();
+    let mut flush = interval(FLUSH_INTERVAL);
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let Some(event) = event else { break };
+                let result = sink.handle_event(&event).await;
+                report(&name, "handle event", result);
+            }
+            Some(event) = transactions.recv() => {
+                let result = sink.handle_event(&event).await;
+                report(&name, "handle event", result);
+            }
+            Some((peer_id, block, received)) = blocks.recv() => {
+                let result = sink.handle_block(peer_id, block, received).await;
+                report(&name, "handle block", result);
+            }
+            _ = flush.tick() => report(&name, "flush", sink.flush().await),
+            _ = &mut stopped => {
+                events.close();
+                transactions.close();
+                blocks.close();
+                while let Some(event) = events.recv().await {
+                    let result = sink.handle_event(&event).await;
+                    report(&name, "handle event", result);
+                }
+                while let Some(event) = transactions.recv().await {
+                    let result = sink.handle_event(&event).await;
+                    report(&name, "handle event", result);
+                }
+                while let Some((peer_id, block, received)) = blocks.recv().await {
+                    let result = sink.handle_block(peer_id, block, received).await;
+                    report(&name, "handle block", result);
+                }
+                break;
+            }
+        }
+    }
+    let shutdown = async {
+        report(&name, "flush", sink.flush().await);
+        report(&name, "shut down", sink.shutdown().await);
+    };
+    if timeout(SHUTDOWN_TIMEOUT, shutdown).await.is_err() {
+        warn!(sink = name, "sink didn't shut down in time");
+        counter!("bscpeer_sink_errors_total", "sink" => name).increment(1);
+    }
+}
+
+fn report(name: &str, action: &str, result: Result<(), SinkError>) {
+    if let Err(e) = result {
+        warn!(sink = name, "failed to {}: {}", action, e);
+        counter!("bscpeer_sink_errors_total", "sink" => name.to_string()).increment(1);
+    }
+}
+
+/// Creates the configured sinks that only consume the event stream: ndjson, NATS and Redis.
+///
+/// Unlike the parquet and postgres sinks and the webhook, which are wired into the node at
+/// startup, these are replaced when the config is reloaded.
+pub async fn event_sinks(config: &SinksConfig) -> Result<Vec<Box<dyn BlockSink>>, SinkError> {
+    let mut sinks: Vec<Box<dyn BlockSink>> = Vec::new();
+    if let Some(ndjson) = config.ndjson.clone() {
+        sinks.push(Box::new(ndjson::NdjsonSink::spawn(ndjson)?));
+    }
+    #[cfg(feature = "nats")]
+    if let Some(nats) = config.nats.clone() {
+        sinks.push(Box::new(nats::NatsSink::spawn(nats).await?));
+    }
+    #[cfg(feature = "redis")]
+    if let Some(redis) = config.redis.clone() {
+        sinks.push(Box::new(redis::RedisSink::spawn(redis)?));
+    }
+    Ok(sinks)
+}
+
+/// Renders a subject, channel or key template for an event, replacing `{kind}` and `{number}`
+/// by the event kind and block number.
+#[cfg_attr(not(any(feature = "nats", feature = "redis")), allow(dead_code))]
+pub(crate) fn render(template: &str, event: &BlockEvent) -> String {
+    template.replace("{kind}", event.kind()).replace("{number}", &event.block_number().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let event =
+            BlockEvent::NewBlockHashes { peer_id: PeerId::ZERO, block_numbers: vec![12, 13] };
+        assert_eq!(render("bsc.blocks.{number}", &event), "bsc.blocks.13");
+        assert_eq!(render("bsc.{kind}.{number}", &event), "bsc.new_block_hashes.13");
+        assert_eq!(render("bsc:events", &event), "bsc:events");
+    }
+
+    /// Sink reporting what it is called with.
+    struct Recorder(mpsc::UnboundedSender<&'static str>);
+
+    #[async_trait]
+    impl BlockSink for Recorder {
+        fn name(&self) -> &str {
+            "recorder"
+        }
+
+        async fn handle_event(&mut self, event: &EventEnvelope) -> Result<(), SinkError> {
+            let _ = self.0.send(event.kind());
+            Ok(())
+        }
+
+        fn receives_blocks(&self) -> bool {
+            true
+        }
+
+        async fn handle_block(
+            &mut self,
+            _peer_id: PeerId,
+            _block: Block,
+            _received: ReceiveTime,
+        ) -> Result<(), SinkError> {
+            let _ = self.0.send("block");
+            Ok(())
+        }
+
+        async fn shutdown(&mut self) -> Result<(), SinkError> {
+            let _ = self.0.send("shutdown");
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sink_task() {
+        let events = EventSender::default();
+        let (calls, mut received) = mpsc::unbounded_channel();
+        let task = spawn(&events, Box::new(Recorder(calls)), 8);
+        events.send(BlockEvent::NoPeers { idle_secs: 10 });
+        events.send(BlockEvent::NoPeers { idle_secs: 20 });
+        let hook = task.hook().expect("the recorder receives blocks");
+        hook(PeerId::ZERO, &reth_eth_wire::NewBlock::default());
+
+        // the queued events and blocks are handled before the shutdown
+        task.stop().await;
+        let mut recorded = Vec::new();
+        while let Ok(call) = received.try_recv() {
+            recorded.push(call);
+        }
+        assert_eq!(recorded, ["no_peers", "no_peers", "block", "shutdown"]);
+    }
+}
@@ -0,0 +1,174 @@
+//! NATS publisher of the node events, optionally persisted by a JetStream stream.
+//!
+//! Events are published as JSON to a subject rendered from the configured template. The client
+//! reconnects on its own after losing the server and JetStream publishes are retried until
+//! acknowledged, so events survive a broker restart as long as the queue doesn't overflow.
+//!
+//! With a JetStream stream, the template must start with a fixed token, e.g. `bsc.{kind}`, so the
+//! stream captures `bsc.>` and not every subject on the server.
+use crate::{
+    config::NatsSinkConfig,
+    peer::events::EventEnvelope,
+    sink::{
+        BlockSink, SinkError,
+        queue::{QUEUE_CAPACITY, Queue, Receiver},
+    },
+};
+use async_nats::{
+    Client, ConnectOptions,
+    jetstream::{self, stream},
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use metrics::counter;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+/// Delay before the first retry, doubled up to [`MAX_BACKOFF`] on every failure.
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Handle to the NATS publisher task.
+#[derive(Debug, Clone)]
+pub struct NatsSink {
+    subject: String,
+    messages: Queue<(String, Bytes)>,
+}
+
+impl NatsSink {
+    /// Starts the publisher task, connecting to the server in the background.
+    ///
+    /// Fails if the subject template doesn't render to valid subjects, or if a stream is
+    /// configured and the template doesn't start with a fixed token.
+    pub async fn spawn(config: NatsSinkConfig) -> Result<Self, SinkError> {
+        let invalid = || SinkError::NatsSubject(config.subject.clone());
+        if config.subject.contains(['*', '>']) || config.subject.contains(char::is_whitespace) {
+            return Err(invalid());
+        }
+        let stream = match config.stream {
+            Some(name) => Some((name, stream_subject(&config.subject).ok_or_else(invalid)?)),
+            None => None,
+        };
+        let client = ConnectOptions::new()
+            .retry_on_initial_connect()
+            .event_callback(|event| async move { info!(%event, "nats connection event") })
+            .connect(config.url.as_str())
+            .await?;
+        let (messages, _) =
+            Queue::spawn("nats", QUEUE_CAPACITY, |messages| run(client, stream, messages));
+        Ok(Self { subject: config.subject, messages })
+    }
+
+    /// Queues an event, dropping it if the publisher falls behind.
+    pub fn publish(&self, event: &EventEnvelope) -> serde_json::Result<()> {
+        let payload = Bytes::from(serde_json::to_vec(event)?);
+        self.messages.push((super::render(&self.subject, event), payload));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BlockSink for NatsSink {
+    fn name(&self) -> &str {
+        "nats"
+    }
+
+    async fn handle_event(&mut self, event: &EventEnvelope) -> Result<(), SinkError> {
+        Ok(self.publish(event)?)
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        self.messages.flush().await;
+        Ok(())
+    }
+}
+
+/// Publishes the queued events, to JetStream if a stream and its subject filter are given.
+async fn run(
+    client: Client,
+    stream: Option<(String, String)>,
+    mut messages: Receiver<(String, Bytes)>,
+) {
+    let context = match stream {
+        Some((name, subject)) => {
+            let context = jetstream::new(client.clone());
+            create_stream(&context, name, subject).await;
+            Some(context)
+        }
+        None => None,
+    };
+    while let Some((subject, payload)) = messages.recv().await {
+        match &context {
+            Some(context) => publish_persisted(context, subject, payload).await,
+            None => {
+                if let Err(e) = client.publish(subject, payload).await {
+                    warn!("failed to publish to nats: {}", e);
+                    counter!("bscpeer_sink_errors_total", "sink" => "nats").increment(1);
+                }
+            }
+        }
+    }
+}
+
+/// Creates the stream if missing, retrying until the server is reachable.
+async fn create_stream(context: &jetstream::Context, name: String, subject: String) {
+    let config = stream::Config { name, subjects: vec![subject], ..Default::default() };
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        match context.get_or_create_stream(config.clone()).await {
+            Ok(_) => return,
+            Err(e) => {
+                warn!(stream = %config.name, ?backoff, "failed to create jetstream stream: {}", e);
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+/// Publishes an event to JetStream, retrying until the server acknowledges it.
+async fn publish_persisted(context: &jetstream::Context, subject: String, payload: Bytes) {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        let result = match context.publish(subject.clone(), payload.clone()).await {
+            Ok(ack) => ack.await.map(drop),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => return,
+            Err(e) => {
+                warn!(%subject, ?backoff, "failed to publish to jetstream: {}", e);
+                counter!("bscpeer_sink_errors_total", "sink" => "nats").increment(1);
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+/// Returns the subject filter matching every subject the template renders to, `None` if the
+/// first token isn't fixed and the filter would match every subject.
+fn stream_subject(template: &str) -> Option<String> {
+    let Some(placeholder) = template.find('{') else {
+        return Some(template.to_string());
+    };
+    let dot = template[..placeholder].rfind('.')?;
+    Some(format!("{}>", &template[..=dot]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_subject() {
+        assert_eq!(stream_subject("bsc.blocks.{number}").unwrap(), "bsc.blocks.>");
+        assert_eq!(stream_subject("bsc.{kind}.{number}").unwrap(), "bsc.>");
+        assert_eq!(stream_subject("bsc.block{number}").unwrap(), "bsc.>");
+        assert_eq!(stream_subject("bsc.blocks").unwrap(), "bsc.blocks");
+        // a stream capturing every subject is rejected
+        assert_eq!(stream_subject("{kind}"), None);
+        assert_eq!(stream_subject("bsc{kind}.{number}"), None);
+    }
+}
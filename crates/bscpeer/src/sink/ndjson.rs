@@ -0,0 +1,106 @@
+//! Newline-delimited JSON stream of the node events, one object per line.
+//!
+//! Events are serialized on the event loop and appended to a file by a dedicated thread, so a
+//! slow disk only causes events to be dropped. The stream never goes to stdout, where it would
+//! interleave with the logs.
+use crate::{
+    config::NdjsonSinkConfig,
+    peer::events::EventEnvelope,
+    sink::{
+        BlockSink, SinkError,
+        queue::{QUEUE_CAPACITY, Queue, Receiver},
+    },
+};
+use async_trait::async_trait;
+use metrics::counter;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+};
+use tracing::warn;
+
+/// Handle to the NDJSON writer thread.
+#[derive(Debug, Clone)]
+pub struct NdjsonSink {
+    lines: Queue<String>,
+}
+
+impl NdjsonSink {
+    /// Opens the output file and starts the writer thread.
+    pub fn spawn(config: NdjsonSinkConfig) -> io::Result<Self> {
+        if let Some(dir) = config.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let output = OpenOptions::new().create(true).append(true).open(&config.path)?;
+        let (lines, _) =
+            Queue::spawn_thread("ndjson", QUEUE_CAPACITY, move |lines| run(output, lines))?;
+        Ok(Self { lines })
+    }
+
+    /// Queues an event, dropping it if the writer falls behind.
+    pub fn write_event(&self, event: &EventEnvelope) -> serde_json::Result<()> {
+        self.lines.push(serde_json::to_string(event)?);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BlockSink for NdjsonSink {
+    fn name(&self) -> &str {
+        "ndjson"
+    }
+
+    async fn handle_event(&mut self, event: &EventEnvelope) -> Result<(), SinkError> {
+        Ok(self.write_event(event)?)
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        self.lines.flush().await;
+        Ok(())
+    }
+}
+
+fn run(mut output: File, mut lines: Receiver<String>) {
+    while let Some(line) = lines.blocking_recv() {
+        // one write per line, so lines of another writer of the file never interleave, flushed
+        // so consumers tailing the output see events as they happen
+        let result = output.write_all(format!("{line}\n").as_bytes()).and_then(|_| output.flush());
+        if let Err(e) = result {
+            warn!("failed to write ndjson event: {}", e);
+            counter!("bscpeer_sink_errors_total", "sink" => "ndjson").increment(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer::{
+        blockstate::BlockEvent,
+        events::{EventSender, OverflowPolicy},
+    };
+
+    #[tokio::test]
+    async fn test_ndjson() {
+        let dir = std::env::temp_dir().join(format!("bscpeer-ndjson-{}", std::process::id()));
+        let path = dir.join("events.ndjson");
+        let mut sink = NdjsonSink::spawn(NdjsonSinkConfig { path: path.clone() }).unwrap();
+        let events = EventSender::default();
+        let mut receiver = events.subscribe("test", 4, OverflowPolicy::Drop);
+        events.send(BlockEvent::NoPeers { idle_secs: 10 });
+        events.send(BlockEvent::NoPeers { idle_secs: 20 });
+        while let Ok(event) = receiver.try_recv() {
+            sink.handle_event(&event).await.unwrap();
+        }
+        sink.flush().await.unwrap();
+
+        // one object per line, appended in order
+        let output = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> =
+            output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["idleSecs"], 20);
+        assert!(lines[0]["provenance"]["receivedAtMs"].as_u64().unwrap() > 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
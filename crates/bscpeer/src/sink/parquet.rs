@@ -0,0 +1,431 @@
+//! Parquet export of block headers and transaction summaries.
+//!
+//! Rows are batched on a dedicated thread, each batch being written as a self-contained file
+//! `<dir>/<table>/<partition>/<first>-<last>.parquet`. `table` is `blocks` or `transactions`
+//! and `partition` either `date=YYYY-MM-DD` or `blocks=<start>-<end>`, the layout DuckDB and
+//! Spark read as hive partitions. The batch being filled is written once full, when the partition
+//! changes and when the writer is dropped. Existing files are never overwritten: a batch of blocks
+//! already written, e.g. before a restart, goes to `<first>-<last>.<n>.parquet`.
+use crate::{
+    config::{ParquetPartitioning, ParquetSinkConfig},
+    peer::events::{EventEnvelope, ReceiveTime},
+    sink::{
+        BlockSink, SinkError,
+        queue::{BLOCK_QUEUE_CAPACITY, Queue, Receiver},
+    },
+};
+use ::parquet::{
+    arrow::ArrowWriter, basic::Compression, errors::ParquetError,
+    file::properties::WriterProperties,
+};
+use alloy_consensus::{Transaction, transaction::SignerRecoverable};
+use alloy_eips::Typed2718;
+use arrow_array::{
+    ArrayRef, RecordBatch, StringArray, UInt8Array, UInt64Array, builder::StringBuilder,
+};
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+use async_trait::async_trait;
+use metrics::counter;
+use reth_ethereum_primitives::Block;
+use reth_network_peers::PeerId;
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    path::Path,
+    sync::Arc,
+    thread,
+};
+use tracing::warn;
+
+/// Errors that can occur while writing Parquet files.
+#[derive(Debug, thiserror::Error)]
+pub enum ParquetSinkError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("arrow error: {0}")]
+    Arrow(#[from] ArrowError),
+    #[error("parquet error: {0}")]
+    Parquet(#[from] ParquetError),
+}
+
+/// Handle to the Parquet writer thread, writing the imported blocks.
+#[derive(Debug)]
+pub struct ParquetSink {
+    /// Queue of the writer, dropped to stop it.
+    blocks: Option<Queue<Block>>,
+    writer: Option<thread::JoinHandle<()>>,
+}
+
+impl ParquetSink {
+    /// Starts the writer thread.
+    pub fn spawn(config: ParquetSinkConfig) -> Self {
+        let (blocks, writer) =
+            Queue::spawn_thread("parquet", BLOCK_QUEUE_CAPACITY, move |blocks| {
+                Writer::new(config).run(blocks)
+            })
+            .expect("failed to spawn parquet writer thread");
+        Self { blocks: Some(blocks), writer: Some(writer) }
+    }
+
+    /// Queues a block, dropping it if the writer falls behind.
+    pub fn write_block(&self, block: Block) {
+        if let Some(blocks) = &self.blocks {
+            blocks.push(block);
+        }
+    }
+}
+
+#[async_trait]
+impl BlockSink for ParquetSink {
+    fn name(&self) -> &str {
+        "parquet"
+    }
+
+    // the rows are taken from the full blocks
+    async fn handle_event(&mut self, _event: &EventEnvelope) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    fn receives_blocks(&self) -> bool {
+        true
+    }
+
+    async fn handle_block(
+        &mut self,
+        _peer_id: PeerId,
+        block: Block,
+        _received: ReceiveTime,
+    ) -> Result<(), SinkError> {
+        self.write_block(block);
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        if let Some(blocks) = &self.blocks {
+            blocks.flush().await;
+        }
+        Ok(())
+    }
+
+    /// Stops the writer, which writes the batch being filled, and waits for it.
+    async fn shutdown(&mut self) -> Result<(), SinkError> {
+        self.blocks = None;
+        let Some(writer) = self.writer.take() else { return Ok(()) };
+        match tokio::task::spawn_blocking(move || writer.join()).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => Err(SinkError::Other("parquet writer thread panicked".into())),
+            Err(e) => Err(SinkError::Other(Box::new(e))),
+        }
+    }
+}
+
+/// Writes the blocks on the calling thread, stopping at the first failed batch.
+pub fn write_blocks(
+    config: ParquetSinkConfig,
+    blocks: impl IntoIterator<Item = Block>,
+) -> Result<(), ParquetSinkError> {
+    let mut writer = Writer::new(config);
+    for block in blocks {
+        writer.add(&block)?;
+    }
+    writer.flush()
+}
+
+/// Row buffers of the current batch, all belonging to one partition.
+struct Writer {
+    config: ParquetSinkConfig,
+    partition: Option<String>,
+    blocks: Vec<BlockRow>,
+    transactions: Vec<TransactionRow>,
+}
+
+impl Writer {
+    fn new(config: ParquetSinkConfig) -> Self {
+        Self { config, partition: None, blocks: Vec::new(), transactions: Vec::new() }
+    }
+
+    fn run(mut self, mut blocks: Receiver<Block>) {
+        // failed batches are logged and counted by the flush, the last one is written on drop
+        while let Some(block) = blocks.blocking_recv() {
+            let _ = self.add(&block);
+        }
+    }
+
+    /// Buffers the rows of a block, writing the batch once it is full or the partition changes.
+    fn add(&mut self, block: &Block) -> Result<(), ParquetSinkError> {
+        let partition = self.partition_of(block);
+        let mut result = Ok(());
+        if self.partition.as_ref().is_some_and(|current| *current != partition) {
+            result = self.flush();
+        }
+        self.partition = Some(partition);
+        self.push(block);
+        if self.blocks.len() >= self.config.batch_blocks {
+            result = result.and(self.flush());
+        }
+        result
+    }
+
+    fn partition_of(&self, block: &Block) -> String {
+        match self.config.partitioning {
+            ParquetPartitioning::Day => format!("date={}", date(block.header.timestamp)),
+            ParquetPartitioning::BlockRange => {
+                let size = self.config.partition_blocks.max(1);
+                let start = block.header.number / size * size;
+                format!("blocks={}-{}", start, start + size - 1)
+            }
+        }
+    }
+
+    fn push(&mut self, block: &Block) {
+        let header = &block.header;
+        let hash = header.hash_slow();
+        self.blocks.push(BlockRow {
+            number: header.number,
+            hash: hash.to_string(),
+            parent_hash: header.parent_hash.to_string(),
+            timestamp: header.timestamp,
+            miner: header.beneficiary.to_string(),
+            gas_used: header.gas_used,
+            gas_limit: header.gas_limit,
+            base_fee: header.base_fee_per_gas,
+            transaction_count: block.body.transactions.len() as u64,
+        });
+        for (index, tx) in block.body.transactions.iter().enumerate() {
+            let input = tx.input();
+            self.transactions.push(TransactionRow {
+                block_number: header.number,
+                index: index as u64,
+                hash: tx.tx_hash().to_string(),
+                from: tx.recover_signer().ok().map(|from| from.to_string()),
+                to: tx.to().map(|to| to.to_string()),
+                value: tx.value().to_string(),
+                nonce: tx.nonce(),
+                gas_limit: tx.gas_limit(),
+                gas_price: tx
+                    .effective_gas_price(header.base_fee_per_gas)
+                    .try_into()
+                    .unwrap_or(u64::MAX),
+                tx_type: tx.ty(),
+                input_len: input.len() as u64,
+                selector: input.get(..4).map(alloy_primitives::hex::encode_prefixed),
+            });
+        }
+    }
+
+    /// Writes the buffered rows of the current partition.
+    fn flush(&mut self) -> Result<(), ParquetSinkError> {
+        let (Some(partition), Some(first), Some(last)) =
+            (&self.partition, self.blocks.first(), self.blocks.last())
+        else {
+            return Ok(());
+        };
+        let name = format!("{}-{}", first.number, last.number);
+        let blocks = self.config.dir.join("blocks").join(partition);
+        let transactions = self.config.dir.join("transactions").join(partition);
+        let result = block_batch(&self.blocks)
+            .and_then(|batch| write(&blocks, &name, batch))
+            .and_then(|_| transaction_batch(&self.transactions))
+            .and_then(|batch| write(&transactions, &name, batch));
+        match &result {
+            Ok(()) => counter!("bscpeer_sink_blocks_total", "sink" => "parquet")
+                .increment(self.blocks.len() as u64),
+            Err(e) => {
+                warn!(
+                    %partition,
+                    first = first.number,
+                    last = last.number,
+                    "failed to write parquet files: {}",
+                    e
+                );
+                counter!("bscpeer_sink_errors_total", "sink" => "parquet").increment(1);
+            }
+        }
+        self.blocks.clear();
+        self.transactions.clear();
+        result
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+struct BlockRow {
+    number: u64,
+    hash: String,
+    parent_hash: String,
+    timestamp: u64,
+    miner: String,
+    gas_used: u64,
+    gas_limit: u64,
+    base_fee: Option<u64>,
+    transaction_count: u64,
+}
+
+struct TransactionRow {
+    block_number: u64,
+    index: u64,
+    hash: String,
+    from: Option<String>,
+    to: Option<String>,
+    /// Value in wei, as a decimal string since it may exceed 64 bits.
+    value: String,
+    nonce: u64,
+    gas_limit: u64,
+    /// Effective gas price in wei.
+    gas_price: u64,
+    tx_type: u8,
+    input_len: u64,
+    selector: Option<String>,
+}
+
+fn block_batch(rows: &[BlockRow]) -> Result<RecordBatch, ParquetSinkError> {
+    let schema = Schema::new(vec![
+        Field::new("number", DataType::UInt64, false),
+        Field::new("hash", DataType::Utf8, false),
+        Field::new("parent_hash", DataType::Utf8, false),
+        Field::new("timestamp", DataType::UInt64, false),
+        Field::new("miner", DataType::Utf8, false),
+        Field::new("gas_used", DataType::UInt64, false),
+        Field::new("gas_limit", DataType::UInt64, false),
+        Field::new("base_fee", DataType::UInt64, true),
+        Field::new("transaction_count", DataType::UInt64, false),
+    ]);
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.number))),
+        Arc::new(strings(rows.iter().map(|row| Some(&row.hash)))),
+        Arc::new(strings(rows.iter().map(|row| Some(&row.parent_hash)))),
+        Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.timestamp))),
+        Arc::new(strings(rows.iter().map(|row| Some(&row.miner)))),
+        Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.gas_used))),
+        Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.gas_limit))),
+        Arc::new(rows.iter().map(|row| row.base_fee).collect::<UInt64Array>()),
+        Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.transaction_count))),
+    ];
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+fn transaction_batch(rows: &[TransactionRow]) -> Result<RecordBatch, ParquetSinkError> {
+    let schema = Schema::new(vec![
+        Field::new("block_number", DataType::UInt64, false),
+        Field::new("index", DataType::UInt64, false),
+        Field::new("hash", DataType::Utf8, false),
+        Field::new("from", DataType::Utf8, true),
+        Field::new("to", DataType::Utf8, true),
+        Field::new("value", DataType::Utf8, false),
+        Field::new("nonce", DataType::UInt64, false),
+        Field::new("gas_limit", DataType::UInt64, false),
+        Field::new("gas_price", DataType::UInt64, false),
+        Field::new("type", DataType::UInt8, false),
+        Field::new("input_len", DataType::UInt64, false),
+        Field::new("selector", DataType::Utf8, true),
+    ]);
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.block_number))),
+        Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.index))),
+        Arc::new(strings(rows.iter().map(|row| Some(&row.hash)))),
+        Arc::new(strings(rows.iter().map(|row| row.from.as_ref()))),
+        Arc::new(strings(rows.iter().map(|row| row.to.as_ref()))),
+        Arc::new(strings(rows.iter().map(|row| Some(&row.value)))),
+        Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.nonce))),
+        Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.gas_limit))),
+        Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.gas_price))),
+        Arc::new(UInt8Array::from_iter_values(rows.iter().map(|row| row.tx_type))),
+        Arc::new(UInt64Array::from_iter_values(rows.iter().map(|row| row.input_len))),
+        Arc::new(strings(rows.iter().map(|row| row.selector.as_ref()))),
+    ];
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+fn strings<'a>(values: impl Iterator<Item = Option<&'a String>>) -> StringArray {
+    let mut builder = StringBuilder::new();
+    for value in values {
+        builder.append_option(value);
+    }
+    builder.finish()
+}
+
+/// Writes the batch to a new file `<name>.parquet` in the directory.
+fn write(dir: &Path, name: &str, batch: RecordBatch) -> Result<(), ParquetSinkError> {
+    std::fs::create_dir_all(dir)?;
+    let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+    let mut writer =
+        ArrowWriter::try_new(create_new(dir, name)?, batch.schema(), Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+/// Creates `<name>.parquet` in the directory, or `<name>.<n>.parquet` with the lowest `n` not
+/// taken if it exists.
+fn create_new(dir: &Path, name: &str) -> io::Result<File> {
+    let mut path = dir.join(format!("{name}.parquet"));
+    for n in 1.. {
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                path = dir.join(format!("{name}.{n}.parquet"));
+            }
+            result => return result,
+        }
+    }
+    unreachable!("ran out of file names")
+}
+
+/// Formats the UTC date of a unix timestamp as `YYYY-MM-DD`.
+fn date(timestamp: u64) -> String {
+    // civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (timestamp / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_date() {
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(1_751_250_600), "2025-06-30");
+        assert_eq!(date(951_782_400), "2000-02-29");
+    }
+
+    #[test]
+    fn test_write_blocks() {
+        use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let dir = std::env::temp_dir().join(format!("bscpeer-parquet-{}", std::process::id()));
+        let config = ParquetSinkConfig { dir: dir.clone(), ..Default::default() };
+        let blocks = || {
+            (0..2).map(|number| {
+                let mut block = Block::default();
+                block.header.number = number;
+                block
+            })
+        };
+        write_blocks(config.clone(), blocks()).unwrap();
+        // the same blocks written again don't overwrite the first file
+        write_blocks(config, blocks()).unwrap();
+
+        let partition = dir.join("blocks").join("date=1970-01-01");
+        for name in ["0-1.parquet", "0-1.1.parquet"] {
+            let file = File::open(partition.join(name)).unwrap();
+            let reader = ParquetRecordBatchReaderBuilder::try_new(file).unwrap().build().unwrap();
+            let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+            assert_eq!(rows, 2);
+        }
+        assert!(dir.join("transactions/date=1970-01-01/0-1.1.parquet").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,390 @@
+//! PostgreSQL writer of blocks, transaction summaries, per-peer first-seen times and session
+//! history, for SQL analysis of how blocks propagate through the network.
+//!
+//! The schema is created by the migrations in `migrations/`, embedded in the binary and applied
+//! when the writer connects.
+use crate::{
+    config::PostgresSinkConfig,
+    instance,
+    peer::events::{EventEnvelope, ReceiveTime},
+    sink::{
+        BlockSink, SinkError,
+        queue::{BLOCK_QUEUE_CAPACITY, Queue, Receiver},
+    },
+};
+use alloy_consensus::{Transaction, transaction::SignerRecoverable};
+use alloy_eips::Typed2718;
+use alloy_primitives::{Address, B256};
+use async_trait::async_trait;
+use metrics::counter;
+use reth_ethereum_primitives::Block;
+use reth_network::NetworkEvent;
+use reth_network_api::events::{PeerEvent, SessionInfo};
+use reth_network_peers::PeerId;
+use sqlx::{
+    PgPool, Postgres, QueryBuilder,
+    migrate::{MigrateError, Migrator},
+    postgres::PgPoolOptions,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::{task::JoinError, time::sleep};
+use tokio_stream::{Stream, StreamExt};
+use tracing::warn;
+
+/// Delay between two attempts to migrate the schema.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Transactions inserted per statement, keeping below the limit of 65535 bind parameters.
+const TRANSACTIONS_PER_INSERT: usize = 1000;
+
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Errors that can occur while writing to the database.
+#[derive(Debug, thiserror::Error)]
+pub enum PostgresSinkError {
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migrate(#[from] MigrateError),
+    #[error("task error: {0}")]
+    Task(#[from] JoinError),
+}
+
+/// A row, or rows, to write, timestamped in unix milliseconds when observed.
+enum Record {
+    Block { peer_id: PeerId, seen_at: i64, block: Block },
+    SessionOpened { peer_id: PeerId, remote_addr: String, client_version: String, at: i64 },
+    SessionClosed { peer_id: PeerId, reason: Option<String>, at: i64 },
+}
+
+/// Handle to the Postgres writer task, writing the imported blocks.
+#[derive(Debug, Clone)]
+pub struct PostgresSink {
+    records: Queue<Record>,
+}
+
+impl PostgresSink {
+    /// Starts the writer task, connecting to the database in the background.
+    pub fn spawn(config: PostgresSinkConfig) -> Result<Self, PostgresSinkError> {
+        let pool = PgPoolOptions::new().max_connections(1).connect_lazy(&config.url)?;
+        let (records, _) =
+            Queue::spawn("postgres", BLOCK_QUEUE_CAPACITY, |records| run(pool, records));
+        Ok(Self { records })
+    }
+
+    /// Records the sessions opened and closed by the network.
+    pub fn track_sessions(
+        &self,
+        mut events: impl Stream<Item = NetworkEvent> + Send + Unpin + 'static,
+    ) {
+        let sink = self.clone();
+        instance::spawn(async move {
+            while let Some(event) = events.next().await {
+                match event {
+                    NetworkEvent::ActivePeerSession { info, .. } => {
+                        let SessionInfo { peer_id, remote_addr, client_version, .. } = info;
+                        sink.send(Record::SessionOpened {
+                            peer_id,
+                            remote_addr: remote_addr.to_string(),
+                            client_version: client_version.to_string(),
+                            at: now(),
+                        })
+                    }
+                    NetworkEvent::Peer(PeerEvent::SessionClosed { peer_id, reason }) => {
+                        sink.send(Record::SessionClosed {
+                            peer_id,
+                            reason: reason.map(|reason| format!("{reason:?}")),
+                            at: now(),
+                        })
+                    }
+                    _ => {}
+                }
+            }
+        });
+    }
+
+    fn send(&self, record: Record) {
+        self.records.push(record);
+    }
+}
+
+#[async_trait]
+impl BlockSink for PostgresSink {
+    fn name(&self) -> &str {
+        "postgres"
+    }
+
+    // the rows are taken from the full blocks and the network events
+    async fn handle_event(&mut self, _event: &EventEnvelope) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    fn receives_blocks(&self) -> bool {
+        true
+    }
+
+    /// Records the block and the peer it came from.
+    async fn handle_block(
+        &mut self,
+        peer_id: PeerId,
+        block: Block,
+        received: ReceiveTime,
+    ) -> Result<(), SinkError> {
+        self.send(Record::Block { peer_id, seen_at: received.at_ms as i64, block });
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        self.records.flush().await;
+        Ok(())
+    }
+}
+
+async fn run(pool: PgPool, mut records: Receiver<Record>) {
+    while let Err(e) = MIGRATOR.run(&pool).await {
+        warn!("failed to migrate postgres schema: {}", e);
+        sleep(RECONNECT_DELAY).await;
+    }
+    while let Some(record) = records.recv().await {
+        if let Err(e) = write(&pool, record).await {
+            warn!("failed to write to postgres: {}", e);
+            counter!("bscpeer_sink_errors_total", "sink" => "postgres").increment(1);
+        }
+    }
+}
+
+async fn write(pool: &PgPool, record: Record) -> Result<(), PostgresSinkError> {
+    match record {
+        Record::Block { peer_id, seen_at, block } => {
+            write_block(pool, peer_id, seen_at, block).await
+        }
+        Record::SessionOpened { peer_id, remote_addr, client_version, at } => {
+            sqlx::query(
+                "INSERT INTO sessions (peer_id, remote_addr, client_version, connected_at) \
+                 VALUES ($1, $2, $3, $4)",
+            )
+            .bind(peer_id.as_slice())
+            .bind(remote_addr)
+            .bind(client_version)
+            .bind(at)
+            .execute(pool)
+            .await?;
+            Ok(())
+        }
+        Record::SessionClosed { peer_id, reason, at } => {
+            sqlx::query(
+                "UPDATE sessions SET disconnected_at = $2, disconnect_reason = $3 \
+                 WHERE peer_id = $1 AND disconnected_at IS NULL",
+            )
+            .bind(peer_id.as_slice())
+            .bind(at)
+            .bind(reason)
+            .execute(pool)
+            .await?;
+            Ok(())
+        }
+    }
+}
+
+/// Writes the block and its transactions unless already known, and the time the peer sent it.
+async fn write_block(
+    pool: &PgPool,
+    peer_id: PeerId,
+    seen_at: i64,
+    block: Block,
+) -> Result<(), PostgresSinkError> {
+    let header = &block.header;
+    let hash = header.hash_slow();
+    let number = header.number as i64;
+
+    let mut db = pool.begin().await?;
+    let result = sqlx::query(
+        "INSERT INTO blocks (hash, number, parent_hash, timestamp, miner, gas_used, gas_limit, \
+         base_fee, transaction_count) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) \
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(hash.as_slice())
+    .bind(number)
+    .bind(header.parent_hash.as_slice())
+    .bind(header.timestamp as i64)
+    .bind(header.beneficiary.as_slice())
+    .bind(header.gas_used as i64)
+    .bind(header.gas_limit as i64)
+    .bind(header.base_fee_per_gas.map(|fee| fee as i64))
+    .bind(block.body.transactions.len() as i32)
+    .execute(&mut *db)
+    .await?;
+
+    if result.rows_affected() == 1 {
+        // recovering the senders is too expensive to run on the runtime threads
+        let rows = tokio::task::spawn_blocking(move || transaction_rows(&block)).await?;
+        for chunk in rows.chunks(TRANSACTIONS_PER_INSERT) {
+            let mut query = QueryBuilder::<Postgres>::new(
+                "INSERT INTO transactions (block_hash, block_number, index, hash, \"from\", \
+                 \"to\", value, nonce, gas_limit, gas_price, tx_type, input_len, selector) ",
+            );
+            query.push_values(chunk, |mut row, tx| {
+                row.push_bind(hash.as_slice())
+                    .push_bind(number)
+                    .push_bind(tx.index)
+                    .push_bind(tx.hash.as_slice())
+                    .push_bind(tx.from.as_ref().map(Address::as_slice))
+                    .push_bind(tx.to.as_ref().map(Address::as_slice))
+                    .push_bind(tx.value.as_str())
+                    .push_unseparated("::NUMERIC")
+                    .push_bind(tx.nonce)
+                    .push_bind(tx.gas_limit)
+                    .push_bind(tx.gas_price)
+                    .push_bind(tx.tx_type)
+                    .push_bind(tx.input_len)
+                    .push_bind(tx.selector.as_deref());
+            });
+            query.push(" ON CONFLICT DO NOTHING");
+            query.build().execute(&mut *db).await?;
+        }
+        counter!("bscpeer_sink_blocks_total", "sink" => "postgres").increment(1);
+    }
+
+    sqlx::query(
+        "INSERT INTO block_peers (block_hash, peer_id, seen_at) VALUES ($1, $2, $3) \
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(hash.as_slice())
+    .bind(peer_id.as_slice())
+    .bind(seen_at)
+    .execute(&mut *db)
+    .await?;
+    db.commit().await?;
+    Ok(())
+}
+
+struct TransactionRow {
+    index: i32,
+    hash: B256,
+    from: Option<Address>,
+    to: Option<Address>,
+    /// Value in wei, as a decimal string since it may exceed 64 bits.
+    value: String,
+    nonce: i64,
+    gas_limit: i64,
+    /// Effective gas price in wei.
+    gas_price: i64,
+    tx_type: i16,
+    input_len: i32,
+    selector: Option<Vec<u8>>,
+}
+
+fn transaction_rows(block: &Block) -> Vec<TransactionRow> {
+    let base_fee = block.header.base_fee_per_gas;
+    block
+        .body
+        .transactions
+        .iter()
+        .enumerate()
+        .map(|(index, tx)| {
+            let input = tx.input();
+            TransactionRow {
+                index: index as i32,
+                hash: *tx.tx_hash(),
+                from: tx.recover_signer().ok(),
+                to: tx.to(),
+                value: tx.value().to_string(),
+                nonce: tx.nonce() as i64,
+                gas_limit: tx.gas_limit() as i64,
+                gas_price: tx.effective_gas_price(base_fee).try_into().unwrap_or(i64::MAX),
+                tx_type: tx.ty().into(),
+                input_len: input.len() as i32,
+                selector: input.get(..4).map(<[u8]>::to_vec),
+            }
+        })
+        .collect()
+}
+
+/// Returns the current time in unix milliseconds.
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parlia::seal::public_key_address;
+    use alloy_consensus::{SignableTransaction, Signed, TxLegacy};
+    use alloy_primitives::{Bytes, Signature, TxKind, U256};
+    use reth_ethereum_primitives::TransactionSigned;
+    use secp256k1::{Message, SECP256K1, SecretKey};
+
+    fn transaction(secret_key: &SecretKey, input: &[u8]) -> TransactionSigned {
+        let tx = TxLegacy {
+            to: TxKind::Call(Address::repeat_byte(2)),
+            value: U256::from(5),
+            gas_price: 3,
+            input: Bytes::copy_from_slice(input),
+            ..Default::default()
+        };
+        let message = Message::from_digest(tx.signature_hash().0);
+        let (recovery_id, signature) =
+            SECP256K1.sign_ecdsa_recoverable(&message, secret_key).serialize_compact();
+        let signature = Signature::new(
+            U256::from_be_slice(&signature[..32]),
+            U256::from_be_slice(&signature[32..]),
+            i32::from(recovery_id) != 0,
+        );
+        TransactionSigned::Legacy(Signed::new_unhashed(tx, signature))
+    }
+
+    fn block(number: u64) -> Block {
+        let key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let mut block = Block::default();
+        block.header.number = number;
+        block.header.timestamp = now() as u64;
+        block.body.transactions = vec![transaction(&key, &[]), transaction(&key, &[1, 2, 3, 4, 5])];
+        block
+    }
+
+    #[test]
+    fn test_transaction_rows() {
+        let key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let rows = transaction_rows(&block(1));
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].index, 1);
+        assert_eq!(rows[1].from, Some(public_key_address(&key.public_key(SECP256K1))));
+        assert_eq!(rows[1].to, Some(Address::repeat_byte(2)));
+        assert_eq!((rows[1].value.as_str(), rows[1].gas_price), ("5", 3));
+        assert_eq!(rows[1].input_len, 5);
+        assert_eq!(rows[1].selector.as_deref(), Some(&[1, 2, 3, 4][..]));
+        assert_eq!(rows[0].selector, None);
+    }
+
+    /// Writes a block delivered by two peers, against the database at
+    /// `BSCPEER_TEST_POSTGRES_URL`, skipped if unset.
+    #[tokio::test]
+    async fn test_write_block() {
+        let Ok(url) = std::env::var("BSCPEER_TEST_POSTGRES_URL") else { return };
+        let mut sink = PostgresSink::spawn(PostgresSinkConfig { url: url.clone() }).unwrap();
+        let block = block(u64::from(std::process::id()));
+        let hash = block.header.hash_slow();
+        for peer in 1..=2 {
+            let received = ReceiveTime::now();
+            sink.handle_block(PeerId::repeat_byte(peer), block.clone(), received).await.unwrap();
+        }
+        sink.flush().await.unwrap();
+
+        let pool = PgPoolOptions::new().connect(&url).await.unwrap();
+        let count = |table: &'static str, column: &'static str| {
+            let pool = pool.clone();
+            async move {
+                let query = format!("SELECT COUNT(*) FROM {table} WHERE {column} = $1");
+                sqlx::query_scalar::<_, i64>(&query)
+                    .bind(hash.as_slice())
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap()
+            }
+        };
+        // the block and its transactions are written once, the peers each
+        assert_eq!(count("blocks", "hash").await, 1);
+        assert_eq!(count("transactions", "block_hash").await, 2);
+        assert_eq!(count("block_peers", "block_hash").await, 2);
+    }
+}
@@ -0,0 +1,159 @@
+//! Bounded queue between a sink and the task or thread doing its writes.
+//!
+//! Sinks hand their output to a writer that drains the queue in order, so a slow or unreachable
+//! destination never holds back the node. Items that don't fit are dropped and counted. A flush
+//! waits until the writer reached the items queued before it.
+use crate::instance;
+use metrics::counter;
+use std::{future::Future, io, sync::Arc, thread};
+use tokio::{
+    sync::{
+        mpsc::{self, Sender, error::TrySendError},
+        oneshot,
+    },
+    task::JoinHandle,
+};
+
+/// Events queued for the writer of a sink before new events are dropped.
+pub(crate) const QUEUE_CAPACITY: usize = 4096;
+/// Full blocks queued for the writer of a sink, fewer since they are much larger than events.
+pub(crate) const BLOCK_QUEUE_CAPACITY: usize = 1024;
+
+/// An item, or a flush acknowledged once the writer reaches it.
+#[derive(Debug)]
+enum Entry<T> {
+    Item(T),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Sending side of the queue of a sink. The writer stops once every clone is dropped.
+#[derive(Debug)]
+pub(crate) struct Queue<T> {
+    sink: Arc<str>,
+    sender: Sender<Entry<T>>,
+}
+
+impl<T> Clone for Queue<T> {
+    fn clone(&self) -> Self {
+        Self { sink: self.sink.clone(), sender: self.sender.clone() }
+    }
+}
+
+impl<T: Send + 'static> Queue<T> {
+    /// Creates the queue of a sink, holding up to `capacity` items.
+    pub(crate) fn new(sink: impl Into<Arc<str>>, capacity: usize) -> (Self, Receiver<T>) {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        (Self { sink: sink.into(), sender }, Receiver(receiver))
+    }
+
+    /// Starts the writer task of a sink, queueing up to `capacity` items for it.
+    #[cfg_attr(
+        not(any(feature = "nats", feature = "redis", feature = "postgres", feature = "webhook")),
+        allow(dead_code)
+    )]
+    pub(crate) fn spawn<F>(
+        sink: &'static str,
+        capacity: usize,
+        writer: impl FnOnce(Receiver<T>) -> F,
+    ) -> (Self, JoinHandle<()>)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (queue, receiver) = Self::new(sink, capacity);
+        (queue, instance::spawn(writer(receiver)))
+    }
+
+    /// Starts the writer of a sink doing blocking writes on a thread of its own, queueing up to
+    /// `capacity` items for it.
+    pub(crate) fn spawn_thread(
+        sink: &'static str,
+        capacity: usize,
+        writer: impl FnOnce(Receiver<T>) + Send + 'static,
+    ) -> io::Result<(Self, thread::JoinHandle<()>)> {
+        let (queue, receiver) = Self::new(sink, capacity);
+        let thread =
+            thread::Builder::new().name(format!("{sink}-sink")).spawn(move || writer(receiver))?;
+        Ok((queue, thread))
+    }
+
+    /// Queues an item, dropping it if the writer falls behind.
+    pub(crate) fn push(&self, item: T) {
+        if let Err(TrySendError::Full(_)) = self.sender.try_send(Entry::Item(item)) {
+            counter!("bscpeer_sink_dropped_total", "sink" => self.sink.to_string()).increment(1);
+        }
+    }
+
+    /// Waits until the writer handled the items queued so far, or stopped.
+    pub(crate) async fn flush(&self) {
+        let (ack, done) = oneshot::channel();
+        if self.sender.send(Entry::Flush(ack)).await.is_ok() {
+            let _ = done.await;
+        }
+    }
+}
+
+/// Receiving side of the queue of a sink, acknowledging the flushes as it reaches them.
+#[derive(Debug)]
+pub(crate) struct Receiver<T>(mpsc::Receiver<Entry<T>>);
+
+impl<T> Receiver<T> {
+    /// Returns the next item, `None` once every sender is dropped.
+    pub(crate) async fn recv(&mut self) -> Option<T> {
+        loop {
+            match self.0.recv().await? {
+                Entry::Item(item) => return Some(item),
+                Entry::Flush(ack) => drop(ack.send(())),
+            }
+        }
+    }
+
+    /// Returns the next item on a thread outside the runtime, `None` once every sender is
+    /// dropped.
+    pub(crate) fn blocking_recv(&mut self) -> Option<T> {
+        loop {
+            match self.0.blocking_recv()? {
+                Entry::Item(item) => return Some(item),
+                Entry::Flush(ack) => drop(ack.send(())),
+            }
+        }
+    }
+
+    /// Stops accepting items, the queued ones can still be received.
+    pub(crate) fn close(&mut self) {
+        self.0.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_queue() {
+        let (queue, task) = Queue::spawn("test", 1, |mut receiver| async move {
+            let mut items = Vec::new();
+            while let Some(item) = receiver.recv().await {
+                items.push(item);
+            }
+            assert_eq!(items, [1, 3]);
+        });
+        queue.push(1);
+        // the writer hasn't run yet, so the queue is full
+        queue.push(2);
+        // the flush waits for the writer to take the first item
+        queue.flush().await;
+        queue.push(3);
+        drop(queue);
+        task.await.unwrap();
+
+        let (queue, thread) = Queue::spawn_thread("test", 2, |mut receiver| {
+            assert_eq!(receiver.blocking_recv(), Some("line"));
+            assert_eq!(receiver.blocking_recv(), None);
+        })
+        .unwrap();
+        queue.push("line");
+        queue.flush().await;
+        drop(queue);
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+//! Redis publisher of the node events, to pub/sub channels and/or streams.
+//!
+//! `PUBLISH` is fire-and-forget: subscribers that aren't connected miss the event. Streams keep
+//! the events for consumers polling with `XREAD`, trimmed to an approximate maximum length.
+use crate::{
+    config::RedisSinkConfig,
+    peer::events::EventEnvelope,
+    sink::{
+        BlockSink, SinkError,
+        queue::{QUEUE_CAPACITY, Queue, Receiver},
+    },
+};
+use async_trait::async_trait;
+use metrics::counter;
+use redis::{Client, RedisError, aio::ConnectionManager};
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::warn;
+
+/// Delay between two attempts to connect to the server.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// An event and the keys it is written to.
+struct Message {
+    channel: Option<String>,
+    stream: Option<String>,
+    kind: &'static str,
+    number: u64,
+    payload: Vec<u8>,
+}
+
+/// Handle to the Redis publisher task.
+#[derive(Debug, Clone)]
+pub struct RedisSink {
+    channel: Option<String>,
+    stream: Option<String>,
+    messages: Queue<Message>,
+}
+
+impl RedisSink {
+    /// Starts the publisher task, connecting to the server in the background.
+    pub fn spawn(config: RedisSinkConfig) -> Result<Self, RedisError> {
+        let client = Client::open(config.url.as_str())?;
+        let (messages, _) =
+            Queue::spawn("redis", QUEUE_CAPACITY, |messages| run(client, config.max_len, messages));
+        Ok(Self { channel: config.channel, stream: config.stream, messages })
+    }
+
+    /// Queues an event, dropping it if the publisher falls behind.
+    pub fn publish(&self, event: &EventEnvelope) -> serde_json::Result<()> {
+        let payload = serde_json::to_vec(event)?;
+        let message = Message {
+            channel: self.channel.as_deref().map(|channel| super::render(channel, event)),
+            stream: self.stream.as_deref().map(|stream| super::render(stream, event)),
+            kind: event.kind(),
+            number: event.block_number(),
+            payload,
+        };
+        self.messages.push(message);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BlockSink for RedisSink {
+    fn name(&self) -> &str {
+        "redis"
+    }
+
+    async fn handle_event(&mut self, event: &EventEnvelope) -> Result<(), SinkError> {
+        Ok(self.publish(event)?)
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        self.messages.flush().await;
+        Ok(())
+    }
+}
+
+async fn run(client: Client, max_len: Option<usize>, mut messages: Receiver<Message>) {
+    // the connection manager reconnects on its own once the first connection is established
+    let mut connection = loop {
+        match ConnectionManager::new(client.clone()).await {
+            Ok(connection) => break connection,
+            Err(e) => {
+                warn!("failed to connect to redis: {}", e);
+                sleep(RECONNECT_DELAY).await;
+            }
+        }
+    };
+    while let Some(message) = messages.recv().await {
+        let mut pipe = redis::pipe();
+        if let Some(channel) = &message.channel {
+            pipe.cmd("PUBLISH").arg(channel).arg(&message.payload).ignore();
+        }
+        if let Some(stream) = &message.stream {
+            pipe.cmd("XADD").arg(stream);
+            if let Some(max_len) = max_len {
+                pipe.arg("MAXLEN").arg("~").arg(max_len);
+            }
+            pipe.arg("*")
+                .arg("kind")
+                .arg(message.kind)
+                .arg("number")
+                .arg(message.number)
+                .arg("event")
+                .arg(&message.payload)
+                .ignore();
+        }
+        if let Err(e) = pipe.query_async::<()>(&mut connection).await {
+            warn!(kind = message.kind, number = message.number, "failed to write to redis: {}", e);
+            counter!("bscpeer_sink_errors_total", "sink" => "redis").increment(1);
+        }
+    }
+}
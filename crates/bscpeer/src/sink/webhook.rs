@@ -0,0 +1,366 @@
+//! Webhook notifications, POSTed as JSON to a configured url.
+//!
+//! Besides new blocks, notifications are raised for reorgs of the canonical chain, finality
+//! stalls, the peer count dropping below a threshold and transactions touching watched
+//! addresses. Requests carry an `X-Signature-256: sha256=<hex>` HMAC of the body if a secret is
+//! configured, and are retried with exponential backoff.
+use crate::{
+    config::{WebhookEvent, WebhookSinkConfig},
+    instance,
+    peer::{
+        blockstate::{BlockEvent, BlockSummary, FinalityHeads},
+        chain::Reorg,
+        events::EventEnvelope,
+        watchlist::WatchHit,
+    },
+    sink::{
+        BlockSink, SinkError,
+        queue::{QUEUE_CAPACITY, Queue, Receiver},
+    },
+};
+use alloy_primitives::{B256, hex};
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use metrics::counter;
+use reth_network_api::PeersInfo;
+use reth_network_peers::PeerId;
+use serde::Serialize;
+use sha2::Sha256;
+use std::{
+    collections::{BTreeSet, HashSet},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::time::{interval, sleep};
+use tracing::warn;
+
+/// Number of recent block hashes kept to report each block once.
+const RECENT_BLOCKS: usize = 256;
+
+/// Delay before the first retry, doubled up to [`MAX_BACKOFF`] on every failure.
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Interval between two checks of the peer count.
+const PEER_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Notification body, tagged with the event name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case", rename_all_fields = "camelCase")]
+enum Notification {
+    NewBlock {
+        peer_id: PeerId,
+        #[serde(flatten)]
+        block: BlockSummary,
+    },
+    Reorg {
+        #[serde(flatten)]
+        reorg: Reorg,
+    },
+    FinalityStall {
+        finalized: u64,
+        head: u64,
+    },
+    LowPeers {
+        peers: usize,
+        min_peers: usize,
+    },
+    WatchlistHit {
+        #[serde(flatten)]
+        hit: WatchHit,
+    },
+}
+
+impl Notification {
+    fn event(&self) -> WebhookEvent {
+        match self {
+            Self::NewBlock { .. } => WebhookEvent::NewBlock,
+            Self::Reorg { .. } => WebhookEvent::Reorg,
+            Self::FinalityStall { .. } => WebhookEvent::FinalityStall,
+            Self::LowPeers { .. } => WebhookEvent::LowPeers,
+            Self::WatchlistHit { .. } => WebhookEvent::WatchlistHit,
+        }
+    }
+}
+
+/// Handle to the webhook delivery task.
+#[derive(Debug, Clone)]
+pub struct WebhookSink {
+    events: HashSet<WebhookEvent>,
+    finality_stall_blocks: u64,
+    min_peers: usize,
+    notifications: Queue<Notification>,
+}
+
+impl WebhookSink {
+    /// Starts the delivery task.
+    pub fn spawn(config: WebhookSinkConfig) -> Result<Self, reqwest::Error> {
+        let client =
+            reqwest::Client::builder().timeout(Duration::from_secs(config.timeout_secs)).build()?;
+        let delivery = Delivery {
+            client,
+            url: config.url,
+            secret: config.secret,
+            max_retries: config.max_retries,
+        };
+        let (notifications, _) =
+            Queue::spawn("webhook", QUEUE_CAPACITY, |notifications| delivery.run(notifications));
+        Ok(Self {
+            events: config.events.into_iter().collect(),
+            finality_stall_blocks: config.finality_stall_blocks,
+            min_peers: config.min_peers,
+            notifications,
+        })
+    }
+
+    /// Returns a sink notifying about new blocks, reorgs, finality stalls and watchlist hits.
+    pub fn sink(self) -> Box<dyn BlockSink> {
+        let detector = Detector::new(self.finality_stall_blocks);
+        Box::new(Notifier { webhook: self, detector })
+    }
+
+    /// Periodically checks the peer count, notifying once when it drops below the threshold.
+    pub fn watch_peers(&self, network: impl PeersInfo + Send + 'static) {
+        let sink = self.clone();
+        instance::spawn(async move {
+            let mut interval = interval(PEER_CHECK_INTERVAL);
+            // armed once enough peers are connected, so startup doesn't count as a drop
+            let mut armed = false;
+            loop {
+                interval.tick().await;
+                let peers = network.num_connected_peers();
+                if peers >= sink.min_peers {
+                    armed = true;
+                } else if armed {
+                    armed = false;
+                    sink.notify(Notification::LowPeers { peers, min_peers: sink.min_peers });
+                }
+            }
+        });
+    }
+
+    /// Queues a notification if subscribed to, dropping it if delivery falls behind.
+    fn notify(&self, notification: Notification) {
+        if !self.events.contains(&notification.event()) {
+            return;
+        }
+        self.notifications.push(notification);
+    }
+}
+
+/// Sink queueing the notifications derived from the events.
+struct Notifier {
+    webhook: WebhookSink,
+    detector: Detector,
+}
+
+#[async_trait]
+impl BlockSink for Notifier {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    async fn handle_event(&mut self, event: &EventEnvelope) -> Result<(), SinkError> {
+        for notification in self.detector.on_event(event) {
+            self.webhook.notify(notification);
+        }
+        Ok(())
+    }
+
+    // deliveries are retried with backoff, so only the shutdown waits for them
+    async fn shutdown(&mut self) -> Result<(), SinkError> {
+        self.webhook.notifications.flush().await;
+        Ok(())
+    }
+}
+
+/// Derives notifications from the imported blocks.
+#[derive(Debug)]
+struct Detector {
+    /// Numbers and hashes of the recently imported blocks.
+    hashes: BTreeSet<(u64, B256)>,
+    head: u64,
+    finalized: u64,
+    stall_blocks: u64,
+    stalled: bool,
+}
+
+impl Detector {
+    fn new(stall_blocks: u64) -> Self {
+        Self { hashes: BTreeSet::new(), head: 0, finalized: 0, stall_blocks, stalled: false }
+    }
+
+    fn on_event(&mut self, event: &BlockEvent) -> Vec<Notification> {
+        let (peer_id, block, finality) = match event {
+            BlockEvent::NewBlock { peer_id, block, finality, .. } => (peer_id, block, finality),
+            BlockEvent::Reorg { reorg } => {
+                return vec![Notification::Reorg { reorg: reorg.clone() }];
+            }
+            BlockEvent::WatchlistHit { hit } => {
+                return vec![Notification::WatchlistHit { hit: hit.clone() }];
+            }
+            _ => return Vec::new(),
+        };
+        // the same block is announced by many peers, only the first import is reported
+        if !self.hashes.insert((block.number, block.hash)) {
+            return Vec::new();
+        }
+        while self.hashes.len() > RECENT_BLOCKS {
+            self.hashes.pop_first();
+        }
+        let mut notifications =
+            vec![Notification::NewBlock { peer_id: *peer_id, block: block.clone() }];
+        notifications.extend(self.check_finality(block.number, finality));
+        notifications
+    }
+
+    /// Reports once when the head runs too far ahead of the finalized block.
+    fn check_finality(&mut self, number: u64, finality: &FinalityHeads) -> Option<Notification> {
+        self.head = self.head.max(number);
+        let finalized = finality.finalized.map_or(0, |block| block.number);
+        if finalized > self.finalized {
+            self.finalized = finalized;
+            self.stalled = false;
+        }
+        // nothing to compare to until the first finalized block is known, and the finalized block
+        // may be ahead of the blocks seen here, e.g. after events were dropped
+        if self.stalled
+            || self.finalized == 0
+            || self.head.saturating_sub(self.finalized) <= self.stall_blocks
+        {
+            return None;
+        }
+        self.stalled = true;
+        Some(Notification::FinalityStall { finalized: self.finalized, head: self.head })
+    }
+}
+
+/// Delivers the notifications one at a time, in order.
+struct Delivery {
+    client: reqwest::Client,
+    url: String,
+    secret: Option<String>,
+    max_retries: u32,
+}
+
+impl Delivery {
+    async fn run(self, mut notifications: Receiver<Notification>) {
+        while let Some(notification) = notifications.recv().await {
+            self.deliver(&notification).await;
+        }
+    }
+
+    async fn deliver(&self, notification: &Notification) {
+        #[derive(Serialize)]
+        struct Payload<'a> {
+            timestamp: u64,
+            #[serde(flatten)]
+            notification: &'a Notification,
+        }
+        let timestamp =
+            SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        let body = match serde_json::to_vec(&Payload { timestamp, notification }) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("failed to serialize webhook notification: {}", e);
+                counter!("bscpeer_sink_errors_total", "sink" => "webhook").increment(1);
+                return;
+            }
+        };
+
+        let mut backoff = MIN_BACKOFF;
+        for attempt in 0..=self.max_retries {
+            if attempt > 0 {
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            match self.post(body.clone()).await {
+                Ok(response) if response.status().is_success() => return,
+                // client errors other than rate limiting won't go away by retrying
+                Ok(response)
+                    if response.status().is_client_error()
+                        && response.status() != reqwest::StatusCode::TOO_MANY_REQUESTS =>
+                {
+                    warn!(status = %response.status(), "webhook rejected notification");
+                    break;
+                }
+                Ok(response) => warn!(status = %response.status(), attempt, "webhook failed"),
+                Err(e) => warn!(attempt, "webhook request failed: {}", e),
+            }
+        }
+        counter!("bscpeer_sink_errors_total", "sink" => "webhook").increment(1);
+    }
+
+    async fn post(&self, body: Vec<u8>) -> reqwest::Result<reqwest::Response> {
+        let mut request = self.client.post(&self.url).header("Content-Type", "application/json");
+        if let Some(secret) = &self.secret {
+            request = request.header("X-Signature-256", signature(secret.as_bytes(), &body));
+        }
+        request.body(body).send().await
+    }
+}
+
+/// Returns the `sha256=<hex>` HMAC of the body.
+fn signature(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("hmac accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer::blockstate::BlockRef;
+    use alloy_consensus::Header;
+
+    fn new_block(number: u64, hash: u8, finalized: u64) -> BlockEvent {
+        let header = Header { number, ..Default::default() };
+        BlockEvent::NewBlock {
+            peer_id: PeerId::ZERO,
+            block: BlockSummary::new(&header, B256::with_last_byte(hash), 0),
+            turn_status: None,
+            attestation: None,
+            finality: FinalityHeads {
+                justified: None,
+                finalized: Some(BlockRef { number: finalized, hash: B256::ZERO }),
+            },
+            validators: None,
+        }
+    }
+
+    #[test]
+    fn test_detector() {
+        let mut detector = Detector::new(2);
+        assert_eq!(detector.on_event(&new_block(10, b'a', 9)).len(), 1);
+        assert!(detector.on_event(&new_block(10, b'a', 9)).is_empty());
+
+        // siblings are new blocks, reorgs are reported by the chain tracker
+        assert_eq!(detector.on_event(&new_block(10, b'b', 9)).len(), 1);
+        let reorg = Reorg {
+            common_ancestor: None,
+            dropped: vec![BlockRef { number: 10, hash: B256::with_last_byte(b'a') }],
+            added: vec![BlockRef { number: 10, hash: B256::with_last_byte(b'b') }],
+        };
+        let notifications = detector.on_event(&BlockEvent::Reorg { reorg: reorg.clone() });
+        assert_eq!(notifications, [Notification::Reorg { reorg }]);
+
+        detector.on_event(&new_block(11, b'c', 9));
+        let notifications = detector.on_event(&new_block(12, b'd', 9));
+        assert_eq!(notifications[1], Notification::FinalityStall { finalized: 9, head: 12 });
+        assert_eq!(detector.on_event(&new_block(13, b'e', 9)).len(), 1);
+        detector.on_event(&new_block(14, b'f', 13));
+        assert_eq!(detector.on_event(&new_block(16, b'g', 13)).len(), 2);
+
+        // a finalized block ahead of the head isn't a stall
+        let mut detector = Detector::new(2);
+        assert_eq!(detector.on_event(&new_block(5, b'h', 20)).len(), 1);
+    }
+
+    #[test]
+    fn test_signature() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            signature(b"Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! Periodic status report of a running node.
+//!
+//! [`StatusReporter`] takes a [`StatusReport`] at the configured interval, logs it as structured
+//! fields and publishes it to the receivers of [`BscPeerHandle::subscribe_status`], e.g. for a
+//! dashboard.
+//!
+//! [`BscPeerHandle::subscribe_status`]: crate::BscPeerHandle::subscribe_status
+use crate::peer::{blockstate::BlockStateManager, events::EventSender};
+use reth_network::{EthNetworkPrimitives, NetworkHandle, PeersInfo};
+use serde::Serialize;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{sync::watch, time::interval_at};
+use tracing::info;
+
+/// Snapshot of the node's progress.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusReport {
+    /// Number of the canonical head, or the sync height until the first block arrived.
+    pub height: u64,
+    /// Connected peers.
+    pub peers: usize,
+    /// Block requests in flight.
+    pub in_flight_requests: usize,
+    /// Block events sent per second since the previous report.
+    pub events_per_sec: f64,
+}
+
+/// Task reporting the status, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct StatusReporter {
+    network: NetworkHandle<EthNetworkPrimitives>,
+    state: BlockStateManager,
+    events: EventSender,
+    interval: Duration,
+    reports: Arc<watch::Sender<StatusReport>>,
+}
+
+impl StatusReporter {
+    pub fn new(
+        network: NetworkHandle<EthNetworkPrimitives>,
+        state: BlockStateManager,
+        events: EventSender,
+        interval: Duration,
+    ) -> Self {
+        let reports = Arc::new(watch::Sender::new(StatusReport::default()));
+        Self { network, state, events, interval, reports }
+    }
+
+    /// Returns a receiver of the latest report.
+    pub fn subscribe(&self) -> watch::Receiver<StatusReport> {
+        self.reports.subscribe()
+    }
+
+    pub async fn run(self) {
+        let mut ticks = interval_at(tokio::time::Instant::now() + self.interval, self.interval);
+        let mut last = (Instant::now(), self.events.sent());
+        loop {
+            ticks.tick().await;
+            let (now, sent) = (Instant::now(), self.events.sent());
+            let report = StatusReport {
+                height: self.state.get_current_height(),
+                peers: self.network.num_connected_peers(),
+                in_flight_requests: self.state.in_flight_requests(),
+                events_per_sec: rate(sent - last.1, now - last.0),
+            };
+            last = (now, sent);
+            info!(
+                height = report.height,
+                peers = report.peers,
+                in_flight_requests = report.in_flight_requests,
+                events_per_sec = format_args!("{:.1}", report.events_per_sec),
+                "status"
+            );
+            self.reports.send_replace(report);
+        }
+    }
+}
+
+/// Returns the rate of `count` occurrences over `elapsed`, zero for an empty interval.
+fn rate(count: u64, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() { 0.0 } else { count as f64 / elapsed.as_secs_f64() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate() {
+        assert_eq!(rate(50, Duration::from_secs(10)), 5.0);
+        assert_eq!(rate(3, Duration::from_millis(500)), 6.0);
+        assert_eq!(rate(10, Duration::ZERO), 0.0);
+    }
+}
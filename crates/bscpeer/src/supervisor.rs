@@ -0,0 +1,275 @@
+//! Supervision of the background tasks of a node.
+//!
+//! A panicking or returning task would otherwise die silently, leaving the node running without,
+//! say, its sync ticks. The supervisor logs the failure with the task name and instance, counts
+//! it, reports it to Sentry with the `sentry` feature, and restarts the task or stops the node
+//! per the [`FailurePolicy`]. Tasks that can't be recreated, like the network manager, always stop
+//! the node. A stopping node aborts all its tasks with [`Supervisor::abort_all`].
+use crate::{
+    config::{FailurePolicy, SupervisorConfig},
+    instance,
+};
+use metrics::counter;
+use std::{
+    any::Any,
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc,
+    task::{AbortHandle, JoinError, JoinHandle},
+    time::Instant,
+};
+use tracing::{error, warn};
+
+/// Delay before a failed task is restarted, growing with each recent restart.
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+/// Upper bound of the restart delay.
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+/// Period the restarts of a task are counted over against the limit.
+pub const RESTART_WINDOW: Duration = Duration::from_secs(3600);
+
+/// Failure of a task that stops the node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskFailure {
+    pub task: &'static str,
+    /// Panic message, or why the task ended.
+    pub error: String,
+}
+
+/// Spawns and watches the background tasks of a node.
+#[derive(Debug, Clone)]
+pub struct Supervisor {
+    policy: FailurePolicy,
+    max_restarts: u32,
+    failures: mpsc::UnboundedSender<TaskFailure>,
+    /// Tasks spawned or watched, including the watchers.
+    tasks: Arc<Mutex<Vec<AbortHandle>>>,
+}
+
+impl Supervisor {
+    /// Creates a supervisor, returning the receiver of the failures that stop the node.
+    pub fn new(config: &SupervisorConfig) -> (Self, mpsc::UnboundedReceiver<TaskFailure>) {
+        let (failures, receiver) = mpsc::unbounded_channel();
+        let supervisor = Self {
+            policy: config.on_failure,
+            max_restarts: config.max_restarts,
+            failures,
+            tasks: Arc::default(),
+        };
+        (supervisor, receiver)
+    }
+
+    /// Watches a task that can't be restarted: it stops the node when it fails or ends.
+    pub fn critical(&self, task: &'static str, handle: JoinHandle<()>) {
+        self.track(&handle);
+        let failures = self.failures.clone();
+        let watcher = instance::spawn(async move {
+            let Some(error) = outcome(handle.await) else { return };
+            report(task, &error);
+            let _ = failures.send(TaskFailure { task, error });
+        });
+        self.track(&watcher);
+    }
+
+    /// Spawns a task with `spawn` and spawns it again whenever it fails or ends, unless the policy
+    /// or the restart limit says to stop the node instead.
+    pub fn restartable<F>(&self, task: &'static str, spawn: F)
+    where
+        F: Fn() -> JoinHandle<()> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        let watcher = instance::spawn(async move {
+            // restarts within the window, oldest first
+            let mut restarts = VecDeque::new();
+            loop {
+                let handle = spawn();
+                supervisor.track(&handle);
+                let Some(error) = outcome(handle.await) else { return };
+                report(task, &error);
+                let now = Instant::now();
+                while restarts.front().is_some_and(|restart| now - *restart >= RESTART_WINDOW) {
+                    restarts.pop_front();
+                }
+                if supervisor.policy == FailurePolicy::Shutdown
+                    || (supervisor.max_restarts > 0
+                        && restarts.len() >= supervisor.max_restarts as usize)
+                {
+                    let _ = supervisor.failures.send(TaskFailure { task, error });
+                    return;
+                }
+                restarts.push_back(now);
+                let delay = (RESTART_DELAY * restarts.len() as u32).min(MAX_RESTART_DELAY);
+                warn!(task, restarts = restarts.len(), ?delay, "restarting task");
+                counter!("bscpeer_task_restarts_total", "task" => task).increment(1);
+                tokio::time::sleep(delay).await;
+            }
+        });
+        self.track(&watcher);
+    }
+
+    /// Aborts every task of the node, e.g. once it stops after a failure.
+    pub fn abort_all(&self) {
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+    }
+
+    fn track<T>(&self, handle: &JoinHandle<T>) {
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(handle.abort_handle());
+    }
+}
+
+/// Returns why the task stopped, `None` if it was aborted on purpose.
+fn outcome(result: Result<(), JoinError>) -> Option<String> {
+    match result {
+        Ok(()) => Some("task ended".to_string()),
+        Err(e) if e.is_cancelled() => None,
+        Err(e) => Some(panic_message(&*e.into_panic())),
+    }
+}
+
+/// Logs, counts and reports a task failure.
+fn report(task: &'static str, error: &str) {
+    error!(task, "task failed: {}", error);
+    counter!("bscpeer_task_failures_total", "task" => task).increment(1);
+    #[cfg(feature = "sentry")]
+    sentry::with_scope(
+        |scope| {
+            scope.set_tag("task", task);
+            if let Some(instance) = instance::current() {
+                scope.set_tag("instance", instance);
+            }
+        },
+        || sentry::capture_message(&format!("task {task} failed: {error}"), sentry::Level::Error),
+    );
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "panic with a non-string payload".to_string()
+    }
+}
+
+/// Logs panics through `tracing`, with the instance and thread they occurred in, instead of
+/// printing them to stderr.
+///
+/// Tokio catches the panics of tasks, so without the hook they only show up once the supervisor
+/// notices, if the task is supervised at all.
+pub fn install_panic_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        let location = info.location().map(ToString::to_string);
+        let thread = std::thread::current();
+        error!(
+            instance = ?instance::current(),
+            thread = ?thread.name(),
+            location = ?location,
+            "panic: {}",
+            panic_message(info.payload())
+        );
+        counter!("bscpeer_panics_total").increment(1);
+    }));
+}
+
+/// Keeps the Sentry client alive, flushing the pending reports when dropped.
+#[derive(Debug)]
+pub struct ReportingGuard {
+    #[cfg(feature = "sentry")]
+    _sentry: sentry::ClientInitGuard,
+}
+
+/// Starts reporting panics and task failures to the configured Sentry DSN, if any. Call after
+/// [`install_panic_hook`], whose hook Sentry's chains to.
+#[cfg(feature = "sentry")]
+pub fn init_reporting(config: &SupervisorConfig) -> Option<ReportingGuard> {
+    let dsn = config.sentry_dsn.as_deref()?;
+    let options = sentry::ClientOptions { release: sentry::release_name!(), ..Default::default() };
+    let guard = sentry::init((dsn, options));
+    if !guard.is_enabled() {
+        warn!("invalid sentry dsn, not reporting failures");
+        return None;
+    }
+    Some(ReportingGuard { _sentry: guard })
+}
+
+/// Warns if a Sentry DSN is configured, the `sentry` feature is disabled.
+#[cfg(not(feature = "sentry"))]
+pub fn init_reporting(config: &SupervisorConfig) -> Option<ReportingGuard> {
+    if config.sentry_dsn.is_some() {
+        warn!("sentry dsn configured, but the `sentry` feature is disabled");
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_restart_limit() {
+        let config = SupervisorConfig { max_restarts: 2, ..Default::default() };
+        let (supervisor, mut failures) = Supervisor::new(&config);
+        let spawned = std::sync::Arc::new(std::sync::atomic::AtomicU32::new(0));
+        tokio::time::pause();
+        supervisor.restartable("test", {
+            let spawned = spawned.clone();
+            move || {
+                spawned.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                tokio::spawn(async { panic!("boom") })
+            }
+        });
+
+        let failure = failures.recv().await.unwrap();
+        assert_eq!(failure, TaskFailure { task: "test", error: "boom".to_string() });
+        assert_eq!(spawned.load(std::sync::atomic::Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn test_restart_window() {
+        let config = SupervisorConfig { max_restarts: 2, ..Default::default() };
+        let (supervisor, mut failures) = Supervisor::new(&config);
+        let spawned = std::sync::Arc::new(std::sync::atomic::AtomicU32::new(0));
+        tokio::time::pause();
+        // failures further apart than the window never reach the limit
+        supervisor.restartable("test", {
+            let spawned = spawned.clone();
+            move || {
+                spawned.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                tokio::spawn(async {
+                    tokio::time::sleep(RESTART_WINDOW).await;
+                    panic!("boom")
+                })
+            }
+        });
+        let wait = tokio::time::timeout(RESTART_WINDOW * 5, failures.recv()).await;
+        assert!(wait.is_err());
+        let count = spawned.load(std::sync::atomic::Ordering::Relaxed);
+        assert!(count >= 4);
+
+        // aborted tasks are neither restarted nor reported
+        supervisor.abort_all();
+        let wait = tokio::time::timeout(RESTART_WINDOW * 2, failures.recv()).await;
+        assert!(wait.is_err());
+        assert_eq!(spawned.load(std::sync::atomic::Ordering::Relaxed), count);
+    }
+
+    #[tokio::test]
+    async fn test_critical_abort() {
+        let (supervisor, mut failures) = Supervisor::new(&SupervisorConfig::default());
+        let task = tokio::spawn(std::future::pending());
+        task.abort();
+        supervisor.critical("aborted", task);
+        supervisor.critical("ended", tokio::spawn(async {}));
+
+        let failure = failures.recv().await.unwrap();
+        assert_eq!(failure.task, "ended");
+        assert_eq!(failure.error, "task ended");
+    }
+}
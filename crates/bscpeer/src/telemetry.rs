@@ -0,0 +1,65 @@
+//! OpenTelemetry export of the tracing spans over OTLP, with the `otel` feature.
+//!
+//! The eth handshake, the block requests and the block imports run in spans carrying the peer id
+//! and the block number and hash, so their latency can be followed in Jaeger or Tempo next to the
+//! Prometheus metrics. The spans pass the same filter as the logs.
+use crate::config::OtlpConfig;
+use reth_tracing::tracing_subscriber::{Layer, Registry};
+
+/// Errors starting the span export.
+#[derive(Debug, thiserror::Error)]
+pub enum TelemetryError {
+    #[cfg(feature = "otel")]
+    #[error("failed to build the OTLP exporter: {0}")]
+    Exporter(#[from] opentelemetry_otlp::ExporterBuildError),
+}
+
+/// Flushes and stops the span export when dropped.
+#[derive(Debug)]
+pub struct TelemetryGuard {
+    #[cfg(feature = "otel")]
+    provider: opentelemetry_sdk::trace::SdkTracerProvider,
+}
+
+#[cfg(feature = "otel")]
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            tracing::warn!("failed to flush spans: {}", e);
+        }
+    }
+}
+
+/// Returns the layer exporting the spans, if enabled, and the guard flushing them.
+#[cfg(feature = "otel")]
+pub fn layer(
+    config: &OtlpConfig,
+) -> Result<Option<(impl Layer<Registry> + Send + Sync, TelemetryGuard)>, TelemetryError> {
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::WithExportConfig;
+
+    if !config.enabled {
+        return Ok(None);
+    }
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(config.endpoint.clone())
+        .build()?;
+    let resource = opentelemetry_sdk::Resource::builder()
+        .with_service_name(config.service_name.clone())
+        .build();
+    let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("bscpeer"));
+    Ok(Some((layer, TelemetryGuard { provider })))
+}
+
+/// Returns no layer, the `otel` feature is disabled.
+#[cfg(not(feature = "otel"))]
+pub fn layer(
+    _config: &OtlpConfig,
+) -> Result<Option<(impl Layer<Registry> + Send + Sync, TelemetryGuard)>, TelemetryError> {
+    Ok(None::<(reth_tracing::tracing_subscriber::layer::Identity, _)>)
+}
@@ -0,0 +1,227 @@
+//! Helpers to exercise the sync path against in-process peers.
+//!
+//! [`MockPeer`] runs a network with the BSC handshake that serves a canned chain from a
+//! [`MockEthProvider`], so fetches and the sync actor are tested by `cargo test` instead of
+//! against mainnet peers. [`canned_blocks`] builds that chain: empty blocks on top of the genesis,
+//! shaped like BSC blocks and sealed by [`CANNED_SIGNER`], so they pass the header and seal checks
+//! as long as there is no snapshot whose validator set they are checked against.
+use crate::{
+    config::ForkIdConfig,
+    instance,
+    parlia::{
+        extra_data::{EXTRA_SEAL_LEN, EXTRA_VANITY_LEN},
+        seal::{public_key_address, seal_hash},
+        snapshot::DIFF_INTURN,
+    },
+    peer::{forkid::ForkIdPolicy, handshake::BscHandshake},
+};
+use alloy_consensus::{EMPTY_OMMER_ROOT_HASH, EMPTY_ROOT_HASH, Header};
+use alloy_primitives::{Address, B256, Bytes};
+use reth_chainspec::{ChainSpec, Head};
+use reth_ethereum_primitives::{Block, BlockBody};
+use reth_network::{
+    EthNetworkPrimitives, NetworkConfig, NetworkConfigBuilder, NetworkEvent,
+    NetworkEventListenerProvider, NetworkHandle, NetworkManager, PeersInfo,
+};
+use reth_network_api::Peers;
+use reth_network_peers::PeerId;
+use reth_provider::{noop::NoopProvider, test_utils::MockEthProvider};
+use secp256k1::{Message, SECP256K1, SecretKey, rand};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+use tokio_stream::StreamExt;
+
+/// Seconds between canned blocks.
+const BLOCK_TIME: u64 = 3;
+/// Secret key sealing the canned blocks.
+pub const CANNED_SIGNER: [u8; 32] = [7; 32];
+
+/// Seals the header with the secret key, replacing the seal in its `extraData`.
+pub fn seal_header(header: &mut Header, secret_key: &SecretKey, chain_id: u64) {
+    let message = Message::from_digest(seal_hash(header, chain_id).0);
+    let (recovery_id, signature) =
+        SECP256K1.sign_ecdsa_recoverable(&message, secret_key).serialize_compact();
+    let mut extra_data = header.extra_data.to_vec();
+    let start = extra_data.len() - EXTRA_SEAL_LEN;
+    extra_data[start..start + 64].copy_from_slice(&signature);
+    extra_data[start + 64] = i32::from(recovery_id) as u8;
+    header.extra_data = extra_data.into();
+}
+
+/// Returns the address sealing the canned blocks.
+pub fn canned_signer() -> Address {
+    let secret_key = SecretKey::from_slice(&CANNED_SIGNER).expect("valid secret key");
+    public_key_address(&secret_key.public_key(SECP256K1))
+}
+
+/// Builds `count` empty blocks on top of the genesis of the chain, oldest first, with their
+/// hashes.
+pub fn canned_blocks(chain_spec: &ChainSpec, count: u64) -> Vec<(B256, Block)> {
+    let mut parent = chain_spec.genesis_header().clone();
+    let mut parent_hash = chain_spec.genesis_hash();
+    let secret_key = SecretKey::from_slice(&CANNED_SIGNER).expect("valid secret key");
+    let mut blocks = Vec::new();
+    for _ in 0..count {
+        let mut header = Header {
+            parent_hash,
+            beneficiary: canned_signer(),
+            ommers_hash: EMPTY_OMMER_ROOT_HASH,
+            state_root: parent.state_root,
+            transactions_root: EMPTY_ROOT_HASH,
+            receipts_root: EMPTY_ROOT_HASH,
+            difficulty: DIFF_INTURN,
+            number: parent.number + 1,
+            gas_limit: parent.gas_limit,
+            timestamp: parent.timestamp + BLOCK_TIME,
+            extra_data: Bytes::from(vec![0; EXTRA_VANITY_LEN + EXTRA_SEAL_LEN]),
+            ..Default::default()
+        };
+        seal_header(&mut header, &secret_key, chain_spec.chain.id());
+        parent_hash = header.hash_slow();
+        parent = header.clone();
+        blocks.push((parent_hash, Block { header, body: BlockBody::default() }));
+    }
+    blocks
+}
+
+/// Network settings of the test nodes: a loopback listener on a free port, no discovery and the
+/// BSC handshake.
+fn network_builder(
+    chain_spec: &ChainSpec,
+    head: Head,
+) -> NetworkConfigBuilder<EthNetworkPrimitives> {
+    let fork_id_policy = ForkIdPolicy::new(chain_spec, head, &ForkIdConfig::default());
+    NetworkConfig::builder(SecretKey::new(&mut rand::thread_rng()))
+        .listener_addr(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .disable_discovery()
+        .set_head(head)
+        .with_pow()
+        .eth_rlpx_handshake(Arc::new(BscHandshake::new(fork_id_policy)))
+}
+
+/// Starts a network serving no data, like the node's own.
+pub async fn local_network(
+    chain_spec: Arc<ChainSpec>,
+    head: Head,
+) -> NetworkHandle<EthNetworkPrimitives> {
+    let config = network_builder(&chain_spec, head).build(NoopProvider::eth(chain_spec));
+    let manager =
+        NetworkManager::<EthNetworkPrimitives>::new(config).await.expect("failed to start network");
+    let network = manager.handle().clone();
+    instance::spawn(manager);
+    network
+}
+
+/// A peer serving a canned chain.
+#[derive(Debug)]
+pub struct MockPeer {
+    network: NetworkHandle<EthNetworkPrimitives>,
+    provider: MockEthProvider,
+}
+
+impl MockPeer {
+    /// Starts the peer, serving the given blocks.
+    pub async fn spawn(
+        chain_spec: Arc<ChainSpec>,
+        head: Head,
+        blocks: impl IntoIterator<Item = (B256, Block)>,
+    ) -> Self {
+        let provider = MockEthProvider::default().with_chain_spec((*chain_spec).clone());
+        provider.extend_blocks(blocks);
+        let config = network_builder(&chain_spec, head).build(provider.clone());
+        let (manager, _, request_handler) = NetworkManager::<EthNetworkPrimitives>::new(config)
+            .await
+            .expect("failed to start network")
+            .into_builder()
+            .request_handler(provider.clone())
+            .split();
+        let network = manager.handle().clone();
+        instance::spawn(manager);
+        instance::spawn(request_handler);
+        Self { network, provider }
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        *self.network.peer_id()
+    }
+
+    pub fn network(&self) -> &NetworkHandle<EthNetworkPrimitives> {
+        &self.network
+    }
+
+    /// Adds blocks to the served chain.
+    pub fn extend(&self, blocks: impl IntoIterator<Item = (B256, Block)>) {
+        self.provider.extend_blocks(blocks);
+    }
+
+    /// Connects the network to the peer, returning once the session is established.
+    pub async fn connect(&self, network: &NetworkHandle<EthNetworkPrimitives>) {
+        let mut events = network.event_listener();
+        network.add_peer(self.peer_id(), self.network.local_addr());
+        while let Some(event) = events.next().await {
+            if let NetworkEvent::ActivePeerSession { info, .. } = event {
+                if info.peer_id == self.peer_id() {
+                    return;
+                }
+            }
+        }
+        panic!("network event stream ended");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chain_config::{BscNetwork, custom::genesis_head},
+        parlia::{Parlia, snapshot::SnapshotStore},
+        peer::{
+            blockstate::{BlockStateManager, ConsensusState},
+            fetch,
+        },
+    };
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sync_from_mock_peer() {
+        let chain_spec = Arc::new(BscNetwork::Chapel.chain_spec(None));
+        let head = genesis_head(&chain_spec);
+        let blocks = canned_blocks(&chain_spec, 8);
+        let tip = blocks[7].0;
+        let peer = MockPeer::spawn(chain_spec.clone(), head, blocks).await;
+        let network = local_network(chain_spec, head).await;
+        timeout(Duration::from_secs(10), peer.connect(&network)).await.unwrap();
+
+        let header = fetch::fetch_header(&network, peer.peer_id(), 3).await.unwrap();
+        let hash = header.hash_slow();
+        let body = fetch::fetch_body(&network, peer.peer_id(), hash, &header).await.unwrap();
+        assert!(body.transactions.is_empty());
+        let ancestors = fetch::fetch_ancestors(&network, peer.peer_id(), hash, 2).await.unwrap();
+        assert_eq!(ancestors[1].number, 2);
+        let headers = fetch::fetch_headers(&network, peer.peer_id(), 2..=4).await.unwrap();
+        assert_eq!(headers.iter().map(|header| header.number).collect::<Vec<_>>(), [2, 3, 4]);
+        let hashes = headers.iter().map(Header::hash_slow).collect();
+        let bodies = fetch::fetch_bodies(&network, peer.peer_id(), hashes).await.unwrap();
+        assert_eq!(bodies.len(), 3);
+
+        // the sync follows the announced blocks up to the tip
+        let consensus = ConsensusState::new(SnapshotStore::default());
+        let parlia = Parlia::new(chain_spec.clone());
+        let (state, actor) = BlockStateManager::new(0, parlia, consensus.subscribe());
+        let mut chain_head = state.subscribe_head();
+        actor.spawn(network);
+        state.add_peer(peer.peer_id());
+        state.block_hashes((1..=8).collect());
+        timeout(
+            Duration::from_secs(10),
+            chain_head.wait_for(|head| head.is_some_and(|head| head.hash == tip)),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(state.get_current_height(), 8);
+    }
+}
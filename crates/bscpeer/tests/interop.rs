@@ -0,0 +1,140 @@
+//! Interop test against a bsc-geth container, run with `cargo test --features interop`.
+//!
+//! Starts bsc-geth in docker with a fixed node key, checks that the handshake with it, including
+//! the BSC upgrade status, succeeds, and connects the node to it as its only trusted peer. geth
+//! only announces blocks once it follows the head, which a freshly started testnet node doesn't,
+//! so waiting for an announced block is left to runs against a devnet.
+//!
+//! Environment:
+//! - `BSC_GETH_IMAGE`: image to run, `ghcr.io/bnb-chain/bsc:1.5.19` by default.
+//! - `BSC_GETH_ARGS`: extra geth arguments, `--chapel` by default.
+//! - `BSC_INTEROP_CHAIN`: network the node follows, `chapel` by default.
+//! - `BSC_INTEROP_BLOCK_TIMEOUT_SECS`: if set, how long to wait for a block announced by geth.
+#![cfg(feature = "interop")]
+
+use bscpeer::{
+    BscPeerBuilder,
+    chain_config::BscNetwork,
+    config::Config,
+    peer::ping::{PingError, ping},
+};
+use clap::ValueEnum;
+use futures::StreamExt;
+use reth_network::{NetworkEvent, NetworkEventListenerProvider};
+use reth_network_peers::{NodeRecord, pk2id};
+use secp256k1::{SECP256K1, SecretKey};
+use std::{
+    net::{Ipv4Addr, SocketAddr, TcpListener},
+    process::Command,
+    time::Duration,
+};
+use tokio::time::{Instant, timeout};
+
+const DEFAULT_IMAGE: &str = "ghcr.io/bnb-chain/bsc:1.5.19";
+/// Node key of the geth container.
+const GETH_KEY: [u8; 32] = [0x42; 32];
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(120);
+/// Timeout of a single ping, retried until geth listens.
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn env_or(name: &str, default: &str) -> String {
+    std::env::var(name).unwrap_or_else(|_| default.to_string())
+}
+
+/// Returns a free local TCP port.
+fn free_port() -> u16 {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap().local_addr().unwrap().port()
+}
+
+/// A running container, removed on drop.
+struct Container {
+    name: String,
+}
+
+impl Container {
+    fn start_geth(port: u16) -> Self {
+        let name = format!("bscpeer-interop-{}", std::process::id());
+        let image = env_or("BSC_GETH_IMAGE", DEFAULT_IMAGE);
+        let args = env_or("BSC_GETH_ARGS", "--chapel");
+        let status = Command::new("docker")
+            .args(["run", "-d", "--rm", "--name", &name, "--entrypoint", "geth"])
+            .args(["-p", &format!("127.0.0.1:{port}:30311")])
+            .arg(&image)
+            .args(["--port", "30311", "--nat", "none"])
+            .args(["--nodekeyhex", &alloy_primitives::hex::encode(GETH_KEY)])
+            .args(args.split_whitespace())
+            .status()
+            .expect("failed to run docker");
+        assert!(status.success(), "failed to start {image}");
+        Self { name }
+    }
+}
+
+impl Drop for Container {
+    fn drop(&mut self) {
+        let _ = Command::new("docker").args(["rm", "-f", &self.name]).status();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_bsc_geth_interop() {
+    let geth_port = free_port();
+    let _geth = Container::start_geth(geth_port);
+    let geth_id = pk2id(&SecretKey::from_slice(&GETH_KEY).unwrap().public_key(SECP256K1));
+    let enode = format!("enode://{geth_id:x}@127.0.0.1:{geth_port}");
+
+    let mut config = Config::default();
+    let chain = env_or("BSC_INTEROP_CHAIN", "chapel");
+    config.chain.network = BscNetwork::from_str(&chain, true).expect("unknown chain");
+    let genesis = config.chain.network.chain_spec(None).genesis_hash();
+    config.datadir = std::env::temp_dir().join(format!("bscpeer-interop-{}", std::process::id()));
+    config.p2p.addr = Ipv4Addr::LOCALHOST.into();
+    config.p2p.port = free_port();
+    config.p2p.discovery_port = free_port();
+    config.rpc.enabled = false;
+    config.metrics.enabled = false;
+    config.bootnodes.nodes = vec![enode.clone()];
+    config.peers.trusted_nodes = vec![enode];
+    config.peers.trusted_nodes_only = true;
+
+    // geth takes a moment to start listening
+    let record = NodeRecord::new(SocketAddr::from((Ipv4Addr::LOCALHOST, geth_port)), geth_id);
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    let report = loop {
+        match ping(&config, record, PING_TIMEOUT).await {
+            Ok(report) => break report,
+            Err(PingError::Timeout(_)) if Instant::now() < deadline => {
+                tokio::time::sleep(Duration::from_secs(1)).await
+            }
+            Err(e) => panic!("failed to ping bsc-geth: {e}"),
+        }
+    };
+    assert_eq!(report.handshake.error, None);
+    assert!(report.handshake.upgrade_status.is_some(), "no upgrade status exchanged");
+    assert_eq!(report.handshake.status.expect("no status exchanged").genesis, genesis);
+
+    let node = BscPeerBuilder::new(config).build().await.expect("failed to start node");
+    let mut events = node.network().event_listener();
+    let mut head = node.subscribe_head();
+
+    // the trusted peer is redialed until the session is established
+    let status = timeout(HANDSHAKE_TIMEOUT, async {
+        while let Some(event) = events.next().await {
+            if let NetworkEvent::ActivePeerSession { info, .. } = event {
+                if info.peer_id == geth_id {
+                    return info.status;
+                }
+            }
+        }
+        panic!("network event stream ended");
+    })
+    .await
+    .expect("no session with bsc-geth");
+    assert_eq!(status.genesis, genesis);
+
+    let Ok(block_timeout) = std::env::var("BSC_INTEROP_BLOCK_TIMEOUT_SECS") else { return };
+    let block_timeout = Duration::from_secs(block_timeout.parse().unwrap());
+    timeout(block_timeout, head.changed()).await.expect("no block announced by bsc-geth").unwrap();
+    let head = head.borrow().clone().unwrap();
+    assert!(head.number > 0);
+}